@@ -0,0 +1,448 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{Client, Error, Request};
+
+pub const USAGE: &str = "\
+usage: bench [options] <url>
+
+options:
+  -c, --connections <n>   concurrent keep-alive connections (default: 8)
+  -d, --duration <dur>    run for a fixed time, e.g. 10s or 500ms (default: 10s)
+  -n, --requests <n>      send a fixed number of requests instead of running for a duration
+  -t, --timeout <dur>     connect and read timeout for each request (default: 5s)
+  -m, --method <method>   request method (default: GET)
+  -H, --header <name: value>
+                          extra request header, repeatable
+      --json              print the report as JSON
+  -h, --help              print this help
+
+options accept both `--flag value` and `--flag=value`.";
+
+/*
+    压测的结束条件
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    // 持续发送请求直到时间用完
+    Duration(Duration),
+    // 所有连接一共发送的请求数
+    Requests(u64),
+}
+
+/*
+    压测参数
+ */
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub url: String,
+    pub connections: usize,
+    pub limit: Limit,
+    pub timeout: Duration,
+    pub method: String,
+    pub headers: Vec<(String, String)>,
+    pub json: bool,
+}
+
+impl Options {
+    pub fn new(url: &str) -> Options {
+        Options {
+            url: url.to_string(),
+            connections: 8,
+            limit: Limit::Duration(Duration::from_secs(10)),
+            timeout: Duration::from_secs(5),
+            method: String::from("GET"),
+            headers: Vec::new(),
+            json: false,
+        }
+    }
+}
+
+/*
+    命令行解析结果
+ */
+#[derive(Debug)]
+pub enum Command {
+    Run(Options),
+    Help,
+}
+
+/// 解析命令行参数（不包含程序名）。
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut url = None;
+    let mut options = Options::new("");
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // --flag=value 与 --flag value 两种形式
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |name: &str| -> Result<String, String> {
+            inline.clone().or_else(|| args.next()).ok_or_else(|| format!("{} requires a value", name))
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--json" => options.json = true,
+            "-c" | "--connections" => {
+                options.connections = match value(&flag)?.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("{} expects a positive integer", flag)),
+                }
+            }
+            "-d" | "--duration" => options.limit = Limit::Duration(duration(&flag, &value(&flag)?)?),
+            "-n" | "--requests" => {
+                options.limit = match value(&flag)?.parse() {
+                    Ok(n) if n > 0 => Limit::Requests(n),
+                    _ => return Err(format!("{} expects a positive integer", flag)),
+                }
+            }
+            "-t" | "--timeout" => options.timeout = duration(&flag, &value(&flag)?)?,
+            "-m" | "--method" => options.method = value(&flag)?.to_ascii_uppercase(),
+            "-H" | "--header" => {
+                let header = value(&flag)?;
+                let (name, value) = header.split_once(':').ok_or_else(|| format!("invalid header: {:?}", header))?;
+                options.headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+            other if other.starts_with('-') => return Err(format!("unknown argument: {}", other)),
+            _ if url.is_some() => return Err(format!("unexpected argument: {}", arg)),
+            _ => url = Some(arg),
+        }
+    }
+    options.url = url.ok_or("missing <url>")?;
+    crate::Url::parse(&options.url).map_err(|err| err.to_string())?;
+    Ok(Command::Run(options))
+}
+
+// 时长：整数表示秒，也可以带单位，例如 "500ms"、"5s"、"2m"
+fn duration(flag: &str, value: &str) -> Result<Duration, String> {
+    let invalid = || format!("{} expects a duration such as 10s or 500ms, got {:?}", flag, value);
+    let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let duration = match unit {
+        "ms" => Duration::from_millis(number),
+        "" | "s" => Duration::from_secs(number),
+        "m" => Duration::from_secs(number * 60),
+        _ => return Err(invalid()),
+    };
+    if duration.is_zero() {
+        return Err(invalid());
+    }
+    Ok(duration)
+}
+
+/*
+    压测结果
+
+    延迟只统计收到完整响应的请求（任意状态码），出错的请求按照错误类型单独计数
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub url: String,
+    pub connections: usize,
+    pub elapsed: Duration,
+    // 收到完整响应的请求数
+    pub requests: u64,
+    // 收到的响应体字节数
+    pub bytes: u64,
+    pub status: BTreeMap<u16, u64>,
+    pub errors: Errors,
+    pub latency: Latency,
+}
+
+/*
+    按照类型统计的错误数
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Errors {
+    pub timeout: u64,
+    // 连接失败、连接被重置等
+    pub io: u64,
+    // 响应格式错误或者重定向次数过多
+    pub invalid: u64,
+}
+
+impl Errors {
+    pub fn total(&self) -> u64 {
+        self.timeout + self.io + self.invalid
+    }
+
+    fn record(&mut self, err: &Error) {
+        match err {
+            Error::Timeout => self.timeout += 1,
+            Error::Io(_) => self.io += 1,
+            _ => self.invalid += 1,
+        }
+    }
+
+    fn merge(&mut self, other: &Errors) {
+        self.timeout += other.timeout;
+        self.io += other.io;
+        self.invalid += other.invalid;
+    }
+}
+
+/*
+    延迟分布
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Latency {
+    pub min: Duration,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub p999: Duration,
+    pub max: Duration,
+}
+
+impl Latency {
+    /// 由全部样本计算延迟分布，分位数取最近秩：第 ⌈q·n⌉ 小的样本。
+    pub fn from_samples(mut samples: Vec<Duration>) -> Latency {
+        if samples.is_empty() {
+            return Latency::default();
+        }
+        samples.sort_unstable();
+        let n = samples.len();
+        let quantile = |q: f64| samples[((q * n as f64).ceil() as usize).clamp(1, n) - 1];
+        let total: Duration = samples.iter().sum();
+        Latency {
+            min: samples[0],
+            mean: total / n as u32,
+            p50: quantile(0.5),
+            p90: quantile(0.9),
+            p99: quantile(0.99),
+            p999: quantile(0.999),
+            max: samples[n - 1],
+        }
+    }
+}
+
+impl Report {
+    /// 每秒完成的请求数。
+    pub fn throughput(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            0.0
+        } else {
+            self.requests as f64 / self.elapsed.as_secs_f64()
+        }
+    }
+
+    /// 以JSON格式输出，延迟单位为毫秒，便于记录并比较不同版本的结果。
+    pub fn to_json(&self) -> String {
+        let ms = |duration: Duration| format!("{:.3}", duration.as_secs_f64() * 1000.0);
+        let status = self.status.iter().map(|(code, count)| format!("\"{}\":{}", code, count)).collect::<Vec<_>>().join(",");
+        let latency = &self.latency;
+        format!(
+            concat!(
+                "{{\"url\":{},\"connections\":{},\"duration_secs\":{:.3},\"requests\":{},\"throughput\":{:.1},\"bytes\":{},",
+                "\"latency_ms\":{{\"min\":{},\"mean\":{},\"p50\":{},\"p90\":{},\"p99\":{},\"p999\":{},\"max\":{}}},",
+                "\"status\":{{{}}},\"errors\":{{\"timeout\":{},\"io\":{},\"invalid\":{},\"total\":{}}}}}"
+            ),
+            json_string(&self.url),
+            self.connections,
+            self.elapsed.as_secs_f64(),
+            self.requests,
+            self.throughput(),
+            self.bytes,
+            ms(latency.min),
+            ms(latency.mean),
+            ms(latency.p50),
+            ms(latency.p90),
+            ms(latency.p99),
+            ms(latency.p999),
+            ms(latency.max),
+            status,
+            self.errors.timeout,
+            self.errors.io,
+            self.errors.invalid,
+            self.errors.total(),
+        )
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let latency = &self.latency;
+        writeln!(f, "{} connections to {} for {:.2}s", self.connections, self.url, self.elapsed.as_secs_f64())?;
+        writeln!(f, "  requests    {} ({:.1}/s), {} bytes received", self.requests, self.throughput(), self.bytes)?;
+        writeln!(
+            f,
+            "  latency     min {:?}  mean {:?}  max {:?}",
+            latency.min, latency.mean, latency.max
+        )?;
+        writeln!(
+            f,
+            "  percentiles p50 {:?}  p90 {:?}  p99 {:?}  p99.9 {:?}",
+            latency.p50, latency.p90, latency.p99, latency.p999
+        )?;
+        let status = self.status.iter().map(|(code, count)| format!("{}: {}", code, count)).collect::<Vec<_>>().join(", ");
+        writeln!(f, "  status      {}", if status.is_empty() { "-" } else { &status })?;
+        write!(
+            f,
+            "  errors      {} (timeout {}, io {}, invalid {})",
+            self.errors.total(),
+            self.errors.timeout,
+            self.errors.io,
+            self.errors.invalid
+        )
+    }
+}
+
+// 单个连接的统计结果
+#[derive(Default)]
+struct Sample {
+    latencies: Vec<Duration>,
+    bytes: u64,
+    status: BTreeMap<u16, u64>,
+    errors: Errors,
+}
+
+/// 按照参数执行压测：每个连接一个线程，各自使用一个保持连接的客户端顺序发送请求。
+pub fn run(options: &Options) -> Report {
+    // 所有线程就绪后同时开始计时
+    let barrier = Arc::new(Barrier::new(options.connections + 1));
+    let stop = Arc::new(AtomicBool::new(false));
+    let issued = Arc::new(AtomicU64::new(0));
+
+    let workers: Vec<_> = (0..options.connections)
+        .map(|_| {
+            let (options, barrier, stop, issued) = (options.clone(), barrier.clone(), stop.clone(), issued.clone());
+            thread::spawn(move || {
+                let client = Client::new().timeout(options.timeout).connect_timeout(options.timeout).max_redirects(0);
+                let mut sample = Sample::default();
+                barrier.wait();
+                while !stop.load(Ordering::Relaxed) {
+                    if let Limit::Requests(total) = options.limit {
+                        if issued.fetch_add(1, Ordering::Relaxed) >= total {
+                            break;
+                        }
+                    }
+                    let request = options
+                        .headers
+                        .iter()
+                        .fold(Request::new(&options.method, &options.url), |request, (name, value)| request.header(name, value));
+                    let start = Instant::now();
+                    match client.send(request) {
+                        Ok(response) => {
+                            sample.latencies.push(start.elapsed());
+                            sample.bytes += response.body.len() as u64;
+                            *sample.status.entry(response.status).or_default() += 1;
+                        }
+                        Err(err) => sample.errors.record(&err),
+                    }
+                }
+                sample
+            })
+        })
+        .collect();
+
+    barrier.wait();
+    let start = Instant::now();
+    if let Limit::Duration(duration) = options.limit {
+        thread::sleep(duration);
+        stop.store(true, Ordering::Relaxed);
+    }
+
+    let mut report = Report { url: options.url.clone(), connections: options.connections, ..Report::default() };
+    let mut latencies = Vec::new();
+    for worker in workers {
+        let sample = worker.join().expect("load generator thread panicked");
+        latencies.extend(sample.latencies);
+        report.bytes += sample.bytes;
+        for (code, count) in sample.status {
+            *report.status.entry(code).or_default() += count;
+        }
+        report.errors.merge(&sample.errors);
+    }
+    report.elapsed = start.elapsed();
+    report.requests = latencies.len() as u64;
+    report.latency = Latency::from_samples(latencies);
+    report
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn percentiles() {
+        let samples = (1..=1000).rev().map(Duration::from_millis).collect();
+        let latency = Latency::from_samples(samples);
+        assert_eq!(latency.min, Duration::from_millis(1));
+        assert_eq!(latency.p50, Duration::from_millis(500));
+        assert_eq!(latency.p99, Duration::from_millis(990));
+        assert_eq!(latency.p999, Duration::from_millis(999));
+        assert_eq!(latency.max, Duration::from_millis(1000));
+        assert_eq!(latency.mean, Duration::from_micros(500_500));
+        assert_eq!(Latency::from_samples(Vec::new()), Latency::default());
+    }
+
+    #[test]
+    fn json_report() {
+        let mut report = Report {
+            url: "http://127.0.0.1:7878/\"q\"".to_string(),
+            connections: 2,
+            elapsed: Duration::from_secs(2),
+            requests: 10,
+            bytes: 100,
+            ..Report::default()
+        };
+        report.status.insert(200, 9);
+        report.status.insert(404, 1);
+        report.errors.timeout = 1;
+        report.latency = Latency::from_samples(vec![Duration::from_micros(1500)]);
+        assert_eq!(
+            report.to_json(),
+            concat!(
+                "{\"url\":\"http://127.0.0.1:7878/\\\"q\\\"\",\"connections\":2,\"duration_secs\":2.000,\"requests\":10,",
+                "\"throughput\":5.0,\"bytes\":100,\"latency_ms\":{\"min\":1.500,\"mean\":1.500,\"p50\":1.500,\"p90\":1.500,",
+                "\"p99\":1.500,\"p999\":1.500,\"max\":1.500},\"status\":{\"200\":9,\"404\":1},",
+                "\"errors\":{\"timeout\":1,\"io\":0,\"invalid\":0,\"total\":1}}"
+            )
+        );
+    }
+
+    #[test]
+    fn command_line() {
+        let options = match parse_args(args(&["-c", "4", "--requests=100", "-H", "Accept: text/html", "--json", "http://localhost/"])) {
+            Ok(Command::Run(options)) => options,
+            other => panic!("unexpected command: {:?}", other),
+        };
+        assert_eq!((options.connections, options.limit, options.json), (4, Limit::Requests(100), true));
+        assert_eq!(options.headers, vec![("Accept".to_string(), "text/html".to_string())]);
+
+        assert_eq!(duration("-d", "250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(duration("-d", "2m"), Ok(Duration::from_secs(120)));
+        assert!(duration("-d", "0s").is_err() && duration("-d", "1h").is_err());
+        assert!(parse_args(args(&["-c", "0", "http://localhost/"])).is_err());
+        assert!(parse_args(args(&["--connections"])).is_err());
+        assert!(parse_args(args(&["https://localhost/"])).is_err());
+        assert!(parse_args(args(&[])).is_err());
+    }
+}
@@ -0,0 +1,34 @@
+use std::{env, process};
+
+use client::bench::{self, Command};
+
+/*
+    压测工具：对同一个地址保持N个并发连接，持续一段时间或者发送固定数量的请求，
+    输出吞吐量、延迟分位数（p50/p99/p99.9）以及错误数，例如比较三个版本的WebServer：
+        cargo run --release --bin bench -- -c 16 -d 10s http://127.0.0.1:7878/
+        cargo run --release --bin bench -- -n 10000 --json http://127.0.0.1:7878/ >> results.jsonl
+ */
+fn main() {
+    let options = match bench::parse_args(env::args().skip(1)) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) => {
+            println!("{}", bench::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("bench: {}\n\n{}", err, bench::USAGE);
+            process::exit(2);
+        }
+    };
+
+    let report = bench::run(&options);
+    if options.json {
+        println!("{}", report.to_json());
+    } else {
+        println!("{}", report);
+    }
+    // 一个请求都没有完成时视为失败，便于脚本判断
+    if report.requests == 0 {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,52 @@
+use std::fmt;
+use std::io;
+
+/*
+    客户端错误
+ */
+#[derive(Debug)]
+pub enum Error {
+    // URL格式错误
+    InvalidUrl(String),
+    // 只支持http
+    UnsupportedScheme(String),
+    // 连接、读取或写入超时
+    Timeout,
+    // 服务端返回的响应不符合HTTP/1.1格式
+    InvalidResponse(String),
+    // 重定向次数超过上限
+    TooManyRedirects(usize),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidUrl(url) => write!(f, "invalid url: {:?}", url),
+            Error::UnsupportedScheme(scheme) => write!(f, "unsupported scheme: {:?}", scheme),
+            Error::Timeout => write!(f, "timed out"),
+            Error::InvalidResponse(reason) => write!(f, "invalid response: {}", reason),
+            Error::TooManyRedirects(max) => write!(f, "more than {} redirects", max),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// 套接字超时在不同平台上分别表现为WouldBlock或TimedOut
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::Timeout,
+            _ => Error::Io(err),
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub mod bench;
+pub mod error;
+pub mod response;
+pub mod url;
+
+pub use error::Error;
+pub use response::Response;
+pub use url::Url;
+
+// 每个host:port最多保留的空闲连接数
+const MAX_IDLE_PER_HOST: usize = 8;
+
+/*
+    阻塞式HTTP/1.1客户端
+
+    - 连接复用：响应允许保持连接时，连接放回空闲连接池供同一个host:port的后续请求使用；
+      复用的连接已经被服务端关闭时自动换一个新连接重试
+    - 响应体支持Content-Length、chunked以及读到连接关闭三种形式
+    - 自动跟随重定向：301/302/303把POST改为GET并丢弃请求体，307/308保持方法与请求体
+    - 连接超时以及单次读写超时，超时统一返回 `Error::Timeout`
+
+    客户端可以在多个线程之间共享，例如：
+        let client = Client::new().timeout(Duration::from_secs(5));
+        let response = client.get("http://127.0.0.1:7878/")?;
+ */
+pub struct Client {
+    connect_timeout: Duration,
+    timeout: Option<Duration>,
+    max_redirects: usize,
+    keep_alive: bool,
+    user_agent: String,
+    // "host:port" -> 空闲连接
+    idle: Mutex<HashMap<String, Vec<TcpStream>>>,
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client {
+            connect_timeout: Duration::from_secs(10),
+            timeout: Some(Duration::from_secs(30)),
+            max_redirects: 10,
+            keep_alive: true,
+            user_agent: concat!("client/", env!("CARGO_PKG_VERSION")).to_string(),
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 建立TCP连接的超时时间，默认10秒。
+    pub fn connect_timeout(mut self, timeout: Duration) -> Client {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// 单次读写操作的超时时间，默认30秒，None表示一直等待。
+    pub fn timeout(mut self, timeout: impl Into<Option<Duration>>) -> Client {
+        self.timeout = timeout.into();
+        self
+    }
+
+    /// 最多跟随的重定向次数，默认10次；为0时不跟随，直接返回3xx响应。
+    pub fn max_redirects(mut self, max_redirects: usize) -> Client {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// 是否复用连接，默认复用；关闭后每个请求都带上 `Connection: close`。
+    pub fn keep_alive(mut self, keep_alive: bool) -> Client {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    pub fn get(&self, url: &str) -> Result<Response, Error> {
+        self.send(Request::get(url))
+    }
+
+    pub fn post(&self, url: &str, body: impl Into<Vec<u8>>) -> Result<Response, Error> {
+        self.send(Request::post(url, body))
+    }
+
+    /// 发送请求并跟随重定向，返回最终的响应。
+    pub fn send(&self, request: Request) -> Result<Response, Error> {
+        let Request { mut method, url, mut headers, mut body } = request;
+        let mut url = Url::parse(&url)?;
+        let mut redirects = 0;
+        loop {
+            let response = self.execute(&method, &url, &headers, &body)?;
+            let location = match (response.status, response.header("Location")) {
+                (301 | 302 | 303 | 307 | 308, Some(location)) if self.max_redirects > 0 => location,
+                _ => return Ok(response),
+            };
+            if redirects == self.max_redirects {
+                return Err(Error::TooManyRedirects(self.max_redirects));
+            }
+            redirects += 1;
+
+            let next = url.join(location)?;
+            if response.status == 303 || (matches!(response.status, 301 | 302) && method == "POST") {
+                method = String::from("GET");
+                body.clear();
+                headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Content-Type"));
+            }
+            // 跳转到其他主机时不再携带凭据
+            if next.authority() != url.authority() {
+                headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Authorization") && !name.eq_ignore_ascii_case("Cookie"));
+            }
+            url = next;
+        }
+    }
+
+    /// 当前空闲连接池中的连接数。
+    pub fn idle_connections(&self) -> usize {
+        self.idle.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).values().map(Vec::len).sum()
+    }
+
+    // 完成一次请求-响应交换，不处理重定向
+    fn execute(&self, method: &str, url: &Url, headers: &[(String, String)], body: &[u8]) -> Result<Response, Error> {
+        let message = self.serialize(method, url, headers, body);
+        let key = format!("{}:{}", url.host, url.port);
+        loop {
+            let (stream, reused) = match self.checkout(&key) {
+                Some(stream) => (stream, true),
+                None => (self.connect(url)?, false),
+            };
+            let mut reader = BufReader::new(stream);
+            // 复用的连接可能已经被服务端关闭：写入失败或者还没读到任何数据就遇到EOF/RST时换新连接重试
+            let sent = reader.get_mut().write_all(&message).and_then(|_| reader.fill_buf().map(|buf| !buf.is_empty()));
+            match sent {
+                Ok(true) => {}
+                Ok(false) | Err(_) if reused => continue,
+                Ok(false) => return Err(Error::InvalidResponse("connection closed before the status line".to_string())),
+                Err(err) => return Err(err.into()),
+            }
+            let (response, keep_alive) = response::read_response(&mut reader, url.clone(), method == "HEAD")?;
+            // 缓冲中还有多余的数据说明连接状态不可知，不再复用
+            if self.keep_alive && keep_alive && reader.buffer().is_empty() {
+                self.checkin(key, reader.into_inner());
+            }
+            return Ok(response);
+        }
+    }
+
+    fn serialize(&self, method: &str, url: &Url, headers: &[(String, String)], body: &[u8]) -> Vec<u8> {
+        let has = |name: &str| headers.iter().any(|(key, _)| key.eq_ignore_ascii_case(name));
+        let mut head = format!("{} {} HTTP/1.1\r\n", method, url.target);
+        if !has("Host") {
+            head.push_str(&format!("Host: {}\r\n", url.authority()));
+        }
+        if !has("User-Agent") {
+            head.push_str(&format!("User-Agent: {}\r\n", self.user_agent));
+        }
+        if !has("Accept") {
+            head.push_str("Accept: */*\r\n");
+        }
+        if !self.keep_alive && !has("Connection") {
+            head.push_str("Connection: close\r\n");
+        }
+        if !body.is_empty() || matches!(method, "POST" | "PUT" | "PATCH") {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        let mut message = head.into_bytes();
+        message.extend_from_slice(body);
+        message
+    }
+
+    // 依次尝试解析出的每个地址
+    fn connect(&self, url: &Url) -> Result<TcpStream, Error> {
+        let host = url.host.trim_start_matches('[').trim_end_matches(']');
+        let addrs = (host, url.port).to_socket_addrs().map_err(Error::Io)?;
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, format!("cannot resolve {}", url.host));
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, self.connect_timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(self.timeout)?;
+                    stream.set_write_timeout(self.timeout)?;
+                    stream.set_nodelay(true)?;
+                    return Ok(stream);
+                }
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err.into())
+    }
+
+    // 取出一个仍然可用的空闲连接
+    fn checkout(&self, key: &str) -> Option<TcpStream> {
+        let mut idle = self.idle.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let streams = idle.get_mut(key)?;
+        while let Some(stream) = streams.pop() {
+            if is_open(&stream) {
+                return Some(stream);
+            }
+        }
+        None
+    }
+
+    fn checkin(&self, key: String, stream: TcpStream) {
+        let mut idle = self.idle.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let streams = idle.entry(key).or_default();
+        if streams.len() < MAX_IDLE_PER_HOST {
+            streams.push(stream);
+        }
+    }
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client::new()
+    }
+}
+
+// 空闲连接上不应该有任何可读数据：读到EOF说明服务端已经关闭连接，读到数据说明连接状态异常
+fn is_open(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let open = matches!(stream.peek(&mut [0; 1]), Err(err) if err.kind() == io::ErrorKind::WouldBlock);
+    stream.set_nonblocking(false).is_ok() && open
+}
+
+/*
+    请求
+ */
+#[derive(Debug, Clone)]
+pub struct Request {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(method: &str, url: &str) -> Request {
+        Request { method: method.to_ascii_uppercase(), url: url.to_string(), headers: Vec::new(), body: Vec::new() }
+    }
+
+    pub fn get(url: &str) -> Request {
+        Request::new("GET", url)
+    }
+
+    pub fn post(url: &str, body: impl Into<Vec<u8>>) -> Request {
+        Request::new("POST", url).body(body)
+    }
+
+    /// 追加一个请求头部；Host、User-Agent、Accept会覆盖客户端的默认值，Content-Length由客户端计算。
+    pub fn header(mut self, name: &str, value: &str) -> Request {
+        if !name.eq_ignore_ascii_case("Content-Length") {
+            self.headers.push((name.to_string(), value.to_string()));
+        }
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Request {
+        self.body = body.into();
+        self
+    }
+}
@@ -0,0 +1,214 @@
+use std::io::{BufRead, Read};
+
+use crate::{Error, Url};
+
+// 响应头部（状态行加上所有头部）的最大字节数
+const MAX_HEAD_BYTES: usize = 64 * 1024;
+
+/*
+    HTTP响应，响应体已经完整读取并解码（chunked）
+ */
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    pub version: String,
+    // 按照收到的顺序保存，同名头部可能出现多次
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    // 最终的地址，跟随重定向之后与请求地址不同
+    pub url: Url,
+}
+
+impl Response {
+    /// 获取第一个同名头部的值，名称不区分大小写。
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+
+    /// 以UTF-8解码响应体，无效的字节替换为U+FFFD。
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+}
+
+/// 读取一个完整的响应，返回响应以及连接是否可以复用。
+///
+/// `head_only` 为true时（HEAD请求）响应没有响应体；1xx中间响应会被跳过。
+pub(crate) fn read_response<R: BufRead>(reader: &mut R, url: Url, head_only: bool) -> Result<(Response, bool), Error> {
+    let Head { status, reason, version, headers } = loop {
+        let head = read_head(reader)?;
+        if !(100..200).contains(&head.status) {
+            break head;
+        }
+    };
+
+    let connection = header(&headers, "Connection").map(str::to_ascii_lowercase).unwrap_or_default();
+    let mut keep_alive = if version == "HTTP/1.0" { connection.contains("keep-alive") } else { !connection.contains("close") };
+
+    let chunked = header(&headers, "Transfer-Encoding").is_some_and(|value| value.to_ascii_lowercase().contains("chunked"));
+    let mut body = Vec::new();
+    if head_only || status == 204 || status == 304 {
+        // 没有响应体
+    } else if chunked {
+        read_chunked(reader, &mut body)?;
+    } else if let Some(length) = header(&headers, "Content-Length") {
+        let length: u64 = length
+            .trim()
+            .parse()
+            .map_err(|_| Error::InvalidResponse(format!("invalid Content-Length: {:?}", length)))?;
+        reader.by_ref().take(length).read_to_end(&mut body)?;
+        if (body.len() as u64) < length {
+            return Err(Error::InvalidResponse(format!("body ended after {} of {} bytes", body.len(), length)));
+        }
+    } else {
+        // 没有长度信息：响应体一直持续到连接关闭，连接也就无法复用
+        reader.read_to_end(&mut body)?;
+        keep_alive = false;
+    }
+
+    Ok((Response { status, reason, version, headers, body, url }, keep_alive))
+}
+
+// 状态行与头部
+struct Head {
+    status: u16,
+    reason: String,
+    version: String,
+    headers: Vec<(String, String)>,
+}
+
+fn read_head<R: BufRead>(reader: &mut R) -> Result<Head, Error> {
+    let mut size = 0;
+    let status_line = read_line(reader, &mut size)?
+        .ok_or_else(|| Error::InvalidResponse("connection closed before the status line".to_string()))?;
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or_default().to_string();
+    let status = parts.next().and_then(|code| code.parse::<u16>().ok());
+    let status = match status {
+        Some(status) if version.starts_with("HTTP/1.") && (100..600).contains(&status) => status,
+        _ => return Err(Error::InvalidResponse(format!("invalid status line: {:?}", status_line))),
+    };
+    let reason = parts.next().unwrap_or_default().to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader, &mut size)?
+            .ok_or_else(|| Error::InvalidResponse("connection closed inside the headers".to_string()))?;
+        if line.is_empty() {
+            return Ok(Head { status, reason, version, headers });
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| Error::InvalidResponse(format!("invalid header line: {:?}", line)))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+}
+
+// 读取一行并去掉行尾的CRLF，连接在行首关闭时返回None；size累计头部的总字节数
+fn read_line<R: BufRead>(reader: &mut R, size: &mut usize) -> Result<Option<String>, Error> {
+    let mut line = Vec::new();
+    let limit = MAX_HEAD_BYTES.saturating_sub(*size) as u64;
+    let n = reader.by_ref().take(limit).read_until(b'\n', &mut line)?;
+    *size += n;
+    if n == 0 {
+        return if limit == 0 { Err(Error::InvalidResponse("response head is too large".to_string())) } else { Ok(None) };
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(Error::InvalidResponse(if *size >= MAX_HEAD_BYTES {
+            "response head is too large".to_string()
+        } else {
+            "connection closed in the middle of a line".to_string()
+        }));
+    }
+    let line = String::from_utf8(line).map_err(|_| Error::InvalidResponse("header is not utf-8".to_string()))?;
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+// 解码chunked响应体：每个块为十六进制长度行、数据与CRLF，长度为0的块之后是可选的尾部头部与空行
+fn read_chunked<R: BufRead>(reader: &mut R, body: &mut Vec<u8>) -> Result<(), Error> {
+    let mut size = 0;
+    loop {
+        let line = read_line(reader, &mut size)?
+            .ok_or_else(|| Error::InvalidResponse("connection closed inside a chunked body".to_string()))?;
+        // 忽略块扩展：1a;name=value
+        let length = line.split(';').next().unwrap_or_default().trim();
+        let length = usize::from_str_radix(length, 16)
+            .map_err(|_| Error::InvalidResponse(format!("invalid chunk size: {:?}", line)))?;
+        if length == 0 {
+            break;
+        }
+        let start = body.len();
+        reader.by_ref().take(length as u64).read_to_end(body)?;
+        if body.len() - start < length {
+            return Err(Error::InvalidResponse("connection closed inside a chunk".to_string()));
+        }
+        let mut crlf = [0; 2];
+        reader.read_exact(&mut crlf)?;
+        if &crlf != b"\r\n" {
+            return Err(Error::InvalidResponse("chunk is not terminated by CRLF".to_string()));
+        }
+        // 块大小行不计入头部大小限制
+        size = 0;
+    }
+    // 尾部头部直到空行
+    while let Some(line) = read_line(reader, &mut size)? {
+        if line.is_empty() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &[u8], head_only: bool) -> Result<(Response, bool), Error> {
+        read_response(&mut &raw[..], Url::parse("http://localhost/").unwrap(), head_only)
+    }
+
+    #[test]
+    fn content_length_and_keep_alive() {
+        let (response, keep_alive) =
+            parse(b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-A: 1\r\n\r\nhello", false).unwrap();
+        assert_eq!((response.status, response.reason.as_str()), (200, "OK"));
+        assert_eq!(response.header("x-a"), Some("1"));
+        assert_eq!(response.text(), "hello");
+        assert!(keep_alive);
+
+        let (_, keep_alive) = parse(b"HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 0\r\n\r\n", false).unwrap();
+        assert!(!keep_alive);
+        let (_, keep_alive) = parse(b"HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n", false).unwrap();
+        assert!(!keep_alive);
+        // 没有长度信息时读到连接关闭
+        let (response, keep_alive) = parse(b"HTTP/1.1 200 OK\r\n\r\nuntil eof", false).unwrap();
+        assert_eq!((response.text().as_str(), keep_alive), ("until eof", false));
+        // HEAD响应的Content-Length不代表后面有数据
+        let (response, keep_alive) = parse(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n", true).unwrap();
+        assert!(response.body.is_empty() && keep_alive);
+    }
+
+    #[test]
+    fn decode_chunked_body() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nX-Trailer: 1\r\n\r\n";
+        let (response, keep_alive) = parse(raw, false).unwrap();
+        assert_eq!(response.text(), "hello, world");
+        assert!(keep_alive);
+
+        assert!(matches!(parse(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n", false), Err(Error::InvalidResponse(_))));
+        assert!(matches!(parse(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort", false), Err(Error::InvalidResponse(_))));
+        assert!(matches!(parse(b"SSH-2.0-OpenSSH\r\n\r\n", false), Err(Error::InvalidResponse(_))));
+    }
+}
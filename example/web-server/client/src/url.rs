@@ -0,0 +1,118 @@
+use std::fmt;
+
+use crate::Error;
+
+/*
+    HTTP地址
+
+    只支持 http://host[:port][/path][?query] 形式，片段（#fragment）会被丢弃；
+    IPv6地址保留方括号，例如 http://[::1]:7878/ 的host为 "[::1]"
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    // 请求目标：路径以及查询字符串，总是以 '/' 开头
+    pub target: String,
+}
+
+impl Url {
+    pub fn parse(text: &str) -> Result<Url, Error> {
+        let invalid = || Error::InvalidUrl(text.to_string());
+        let (scheme, rest) = text.split_once("://").ok_or_else(invalid)?;
+        if !scheme.eq_ignore_ascii_case("http") {
+            return Err(Error::UnsupportedScheme(scheme.to_string()));
+        }
+        let rest = rest.split('#').next().unwrap_or_default();
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = if authority.starts_with('[') {
+            // [::1]:7878
+            let end = authority.find(']').ok_or_else(invalid)?;
+            (&authority[..end + 1], authority[end + 1..].strip_prefix(':'))
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            }
+        };
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid())?,
+            None => 80,
+        };
+        if host.is_empty() || host.contains(['@', ' ']) {
+            return Err(invalid());
+        }
+        let target = if target.starts_with('?') { format!("/{}", target) } else { target.to_string() };
+        Ok(Url { host: host.to_ascii_lowercase(), port, target })
+    }
+
+    /// 按照当前地址解析重定向的Location，支持绝对地址、`//host/path`、绝对路径以及相对路径。
+    pub fn join(&self, location: &str) -> Result<Url, Error> {
+        if location.contains("://") {
+            return Url::parse(location);
+        }
+        if let Some(rest) = location.strip_prefix("//") {
+            return Url::parse(&format!("http://{}", rest));
+        }
+        let target = if location.starts_with('/') {
+            location.to_string()
+        } else {
+            // 相对路径：替换当前路径的最后一段
+            let path = self.target.split('?').next().unwrap_or("/");
+            let dir = &path[..path.rfind('/').map(|index| index + 1).unwrap_or(0)];
+            format!("{}{}", dir, location)
+        };
+        Ok(Url { host: self.host.clone(), port: self.port, target: target.split('#').next().unwrap_or_default().to_string() })
+    }
+
+    /// Host头部的值，默认端口省略。
+    pub fn authority(&self) -> String {
+        if self.port == 80 {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}{}", self.authority(), self.target)
+    }
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_urls() {
+        let url = Url::parse("http://Example.com:8080/a/b?x=1#top").unwrap();
+        assert_eq!((url.host.as_str(), url.port, url.target.as_str()), ("example.com", 8080, "/a/b?x=1"));
+        assert_eq!(url.to_string(), "http://example.com:8080/a/b?x=1");
+
+        let url = Url::parse("http://[::1]:7878").unwrap();
+        assert_eq!((url.host.as_str(), url.port, url.target.as_str()), ("[::1]", 7878, "/"));
+        assert_eq!(Url::parse("http://localhost?q").unwrap().target, "/?q");
+
+        assert!(matches!(Url::parse("https://example.com/"), Err(Error::UnsupportedScheme(_))));
+        assert!(matches!(Url::parse("example.com/"), Err(Error::InvalidUrl(_))));
+        assert!(matches!(Url::parse("http://host:port/"), Err(Error::InvalidUrl(_))));
+        assert!(matches!(Url::parse("http:///path"), Err(Error::InvalidUrl(_))));
+    }
+
+    #[test]
+    fn join_locations() {
+        let base = Url::parse("http://localhost:7878/docs/guide.html?page=2").unwrap();
+        assert_eq!(base.join("/login").unwrap().to_string(), "http://localhost:7878/login");
+        assert_eq!(base.join("intro.html").unwrap().to_string(), "http://localhost:7878/docs/intro.html");
+        assert_eq!(base.join("http://other/").unwrap().to_string(), "http://other/");
+        assert_eq!(base.join("//cdn.example.com/x").unwrap().to_string(), "http://cdn.example.com/x");
+    }
+}
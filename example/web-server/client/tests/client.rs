@@ -0,0 +1,166 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use client::bench::{self, Limit, Options};
+use client::{Client, Error, Request};
+
+// 简单的测试服务端：每个连接一个线程，按照 (方法, 路径) 生成原始响应；
+// close为true时每个连接只处理一个请求（但响应中不声明Connection: close）
+fn start(route: fn(&str, &str) -> String, close: bool) -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&accepted);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            thread::spawn(move || handle(stream, route, close));
+        }
+    });
+    (addr, accepted)
+}
+
+fn handle(stream: TcpStream, route: fn(&str, &str) -> String, close: bool) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+            return;
+        }
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("Content-Length") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+
+        let mut parts = request_line.split(' ');
+        let (method, path) = (parts.next().unwrap(), parts.next().unwrap());
+        if writer.write_all(route(method, path).as_bytes()).is_err() || close {
+            return;
+        }
+    }
+}
+
+fn ok(body: &str) -> String {
+    format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn url(addr: SocketAddr, path: &str) -> String {
+    format!("http://{}{}", addr, path)
+}
+
+#[test]
+fn reuse_keep_alive_connections() {
+    let (addr, accepted) = start(|_, path| ok(path), false);
+    let client = Client::new();
+    for path in ["/a", "/b", "/c"] {
+        let response = client.get(&url(addr, path)).unwrap();
+        assert_eq!((response.status, response.text().as_str()), (200, path));
+    }
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    assert_eq!(client.idle_connections(), 1);
+
+    // 关闭keep-alive后每个请求都使用新连接
+    let client = Client::new().keep_alive(false);
+    client.get(&url(addr, "/")).unwrap();
+    client.get(&url(addr, "/")).unwrap();
+    assert_eq!(accepted.load(Ordering::SeqCst), 3);
+    assert_eq!(client.idle_connections(), 0);
+}
+
+#[test]
+fn decode_chunked_response() {
+    let (addr, _) = start(
+        |_, _| "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nhello,\r\n6\r\n world\r\n0\r\n\r\n".to_string(),
+        false,
+    );
+    let client = Client::new();
+    assert_eq!(client.get(&url(addr, "/")).unwrap().text(), "hello, world");
+    // chunked响应结束后连接仍然可以复用
+    assert_eq!(client.get(&url(addr, "/")).unwrap().text(), "hello, world");
+    assert_eq!(client.idle_connections(), 1);
+}
+
+#[test]
+fn follow_redirects() {
+    fn route(method: &str, path: &str) -> String {
+        match path {
+            "/old" => "HTTP/1.1 301 Moved Permanently\r\nLocation: new\r\nContent-Length: 0\r\n\r\n".to_string(),
+            "/submit" => "HTTP/1.1 303 See Other\r\nLocation: /result\r\nContent-Length: 0\r\n\r\n".to_string(),
+            "/keep" => "HTTP/1.1 307 Temporary Redirect\r\nLocation: /result\r\nContent-Length: 0\r\n\r\n".to_string(),
+            "/loop" => "HTTP/1.1 302 Found\r\nLocation: /loop\r\nContent-Length: 0\r\n\r\n".to_string(),
+            _ => ok(&format!("{} {}", method, path)),
+        }
+    }
+    let (addr, _) = start(route, false);
+    let client = Client::new().max_redirects(5);
+
+    let response = client.get(&url(addr, "/old")).unwrap();
+    assert_eq!(response.text(), "GET /new");
+    assert_eq!(response.url.to_string(), url(addr, "/new"));
+    // 303把POST改为GET，307保持原来的方法
+    assert_eq!(client.post(&url(addr, "/submit"), "x=1").unwrap().text(), "GET /result");
+    assert_eq!(client.post(&url(addr, "/keep"), "x=1").unwrap().text(), "POST /result");
+
+    assert!(matches!(client.get(&url(addr, "/loop")), Err(Error::TooManyRedirects(5))));
+    // 不跟随重定向时返回3xx响应本身
+    let response = Client::new().max_redirects(0).get(&url(addr, "/loop")).unwrap();
+    assert_eq!((response.status, response.header("Location")), (302, Some("/loop")));
+}
+
+#[test]
+fn read_timeout() {
+    // 接受连接但从不响应
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let _streams: Vec<_> = listener.incoming().collect();
+    });
+
+    let client = Client::new().timeout(Duration::from_millis(200));
+    let start = Instant::now();
+    assert!(matches!(client.get(&url(addr, "/")), Err(Error::Timeout)));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn retry_closed_idle_connection() {
+    // 服务端处理一个请求后关闭连接，客户端放回连接池的连接随后失效
+    let (addr, accepted) = start(|_, path| ok(path), true);
+    let client = Client::new();
+    assert_eq!(client.get(&url(addr, "/first")).unwrap().text(), "/first");
+    thread::sleep(Duration::from_millis(100));
+    let response = client.send(Request::get(&url(addr, "/second")).header("X-Test", "1")).unwrap();
+    assert_eq!(response.text(), "/second");
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn load_generator() {
+    let (addr, accepted) = start(|_, path| ok(path), false);
+    let options = Options { connections: 2, limit: Limit::Requests(50), ..Options::new(&url(addr, "/bench")) };
+    let report = bench::run(&options);
+    assert_eq!(report.requests, 50);
+    assert_eq!(report.bytes, 50 * "/bench".len() as u64);
+    assert_eq!(report.status.get(&200), Some(&50));
+    assert_eq!(report.errors.total(), 0);
+    assert!(report.latency.p50 <= report.latency.p999 && report.latency.p999 <= report.latency.max);
+    // 每个并发连接在整个压测过程中保持复用
+    assert_eq!(accepted.load(Ordering::SeqCst), 2);
+}
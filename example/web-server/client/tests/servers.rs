@@ -0,0 +1,119 @@
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use client::Client;
+
+/*
+    集成测试：在临时端口上启动每个WebServer，通过客户端检查 `/`、`/sleep` 以及404的行为
+
+    服务端通过cargo构建，在各自的目录下运行（index.html与404.html从当前目录读取），
+    监听地址为127.0.0.1:0，实际端口从服务端输出的第一个 127.0.0.1:PORT 中获取
+ */
+struct Server {
+    child: Child,
+    addr: String,
+    dir: PathBuf,
+}
+
+impl Server {
+    // dir为相对于web-server目录的服务端目录，bin为可执行文件名
+    fn start(dir: &str, bin: &str, args: &[&str]) -> Server {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join(dir);
+        let status = Command::new(env!("CARGO"))
+            .args(["build", "--quiet", "--bin", bin, "--manifest-path"])
+            .arg(dir.join("Cargo.toml"))
+            .status()
+            .unwrap();
+        assert!(status.success(), "failed to build {}", dir.display());
+
+        let target = std::env::var_os("CARGO_TARGET_DIR").map(PathBuf::from).unwrap_or_else(|| dir.join("target"));
+        let mut child = Command::new(target.join("debug").join(bin))
+            .args(args)
+            .current_dir(&dir)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        // 持续读取标准输出，避免管道写满阻塞服务端
+        let stdout = child.stdout.take().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut sender = Some(sender);
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if let Some(addr) = line.split([' ', ',', '(', ')']).find(|word| word.starts_with("127.0.0.1:")) {
+                    if let Some(sender) = sender.take() {
+                        let _ = sender.send(addr.to_string());
+                    }
+                }
+            }
+        });
+        let addr = receiver.recv_timeout(Duration::from_secs(30));
+        let mut server = Server { child, addr: String::new(), dir };
+        server.addr = addr.unwrap_or_else(|_| panic!("{} did not report its address", bin));
+        server
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    fn page(&self, name: &str) -> String {
+        std::fs::read_to_string(self.dir.join(name)).unwrap()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn check_pages(server: &Server, client: &Client) {
+    let response = client.get(&server.url("/")).unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), server.page("index.html"));
+
+    let response = client.get(&server.url("/missing")).unwrap();
+    assert_eq!(response.status, 404);
+    assert_eq!(response.text(), server.page("404.html"));
+}
+
+// /sleep在返回欢迎页面之前阻塞10秒
+fn check_sleep(server: &Server, client: &Client) {
+    let start = Instant::now();
+    let response = client.get(&server.url("/sleep")).unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), server.page("index.html"));
+    assert!(start.elapsed() >= Duration::from_secs(9), "{:?}", start.elapsed());
+}
+
+#[test]
+fn stm_server() {
+    let server = Server::start("stm/server", "server", &["127.0.0.1:0"]);
+    let client = Client::new().timeout(Duration::from_secs(30));
+    check_pages(&server, &client);
+    // 单线程版本没有/sleep路由
+    assert_eq!(client.get(&server.url("/sleep")).unwrap().status, 404);
+}
+
+#[test]
+fn mt_server() {
+    let server = Server::start("mt/server", "main", &["127.0.0.1:0"]);
+    let client = Client::new().timeout(Duration::from_secs(30));
+    check_pages(&server, &client);
+    check_sleep(&server, &client);
+}
+
+#[test]
+fn optimized_server() {
+    let server = Server::start("mt/server-optimize", "main", &["--listen", "127.0.0.1:0", "--access-log", "off"]);
+    let client = Client::new().timeout(Duration::from_secs(30));
+    check_pages(&server, &client);
+    check_sleep(&server, &client);
+}
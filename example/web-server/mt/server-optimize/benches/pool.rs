@@ -0,0 +1,177 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use server_optimize::ThreadPool;
+
+// 每轮提交的任务数，可以通过第一个命令行参数修改
+const JOBS: usize = 200_000;
+// 每种组合重复的轮数，取最快的一轮
+const ROUNDS: usize = 5;
+
+/*
+    对比基准：工作窃取之前的实现，所有Worker争用同一个 Mutex<Receiver>；
+    保留同样的状态计数与panic捕获，只有调度方式不同
+ */
+struct ChannelPool {
+    workers: Vec<JoinHandle<()>>,
+    sender: Option<mpsc::Sender<Box<dyn FnOnce() + Send>>>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl ChannelPool {
+    fn new(size: usize) -> ChannelPool {
+        let (sender, receiver) = mpsc::channel::<Box<dyn FnOnce() + Send>>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let queued = Arc::new(AtomicUsize::new(0));
+        let active = Arc::new(AtomicUsize::new(0));
+        let workers = (0..size)
+            .map(|_| {
+                let (receiver, queued, active) = (Arc::clone(&receiver), Arc::clone(&queued), Arc::clone(&active));
+                thread::spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => {
+                            queued.fetch_sub(1, Ordering::SeqCst);
+                            active.fetch_add(1, Ordering::SeqCst);
+                            let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                            active.fetch_sub(1, Ordering::SeqCst);
+                        }
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+        ChannelPool { workers, sender: Some(sender), queued }
+    }
+
+    fn execute<F: FnOnce() + Send + 'static>(&self, f: F) {
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+    }
+}
+
+impl Drop for ChannelPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+// 两种线程池的共同接口
+trait Pool: Send + Sync + 'static {
+    fn submit(&self, job: Box<dyn FnOnce() + Send>);
+}
+
+impl Pool for ThreadPool {
+    fn submit(&self, job: Box<dyn FnOnce() + Send>) {
+        self.execute(job)
+    }
+}
+
+impl Pool for ChannelPool {
+    fn submit(&self, job: Box<dyn FnOnce() + Send>) {
+        self.execute(job)
+    }
+}
+
+// 计数归零时唤醒等待的线程
+struct Latch {
+    remaining: AtomicUsize,
+    done: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Latch {
+    fn new(count: usize) -> Arc<Latch> {
+        Arc::new(Latch { remaining: AtomicUsize::new(count), done: Mutex::new(false), condvar: Condvar::new() })
+    }
+
+    fn count_down(&self) {
+        if self.remaining.fetch_sub(1, Ordering::AcqRel) == 1 {
+            *self.done.lock().unwrap() = true;
+            self.condvar.notify_all();
+        }
+    }
+
+    fn wait(&self) {
+        let done = self.done.lock().unwrap();
+        drop(self.condvar.wait_while(done, |done| !*done).unwrap());
+    }
+}
+
+// 从线程池外部提交全部任务
+fn external<P: Pool>(pool: &Arc<P>, jobs: usize) -> Duration {
+    let latch = Latch::new(jobs);
+    let start = Instant::now();
+    for _ in 0..jobs {
+        let latch = Arc::clone(&latch);
+        pool.submit(Box::new(move || latch.count_down()));
+    }
+    latch.wait();
+    start.elapsed()
+}
+
+// 由线程池中的任务提交任务（fork式的负载），外层任务数与Worker数相同
+fn nested<P: Pool>(pool: &Arc<P>, jobs: usize, workers: usize) -> Duration {
+    let latch = Latch::new(jobs);
+    let start = Instant::now();
+    for _ in 0..workers {
+        let (inner, latch) = (Arc::clone(pool), Arc::clone(&latch));
+        pool.submit(Box::new(move || {
+            for _ in 0..jobs / workers {
+                let latch = Arc::clone(&latch);
+                inner.submit(Box::new(move || latch.count_down()));
+            }
+        }));
+    }
+    latch.wait();
+    start.elapsed()
+}
+
+fn best<F: FnMut() -> Duration>(mut round: F) -> Duration {
+    (0..ROUNDS).map(|_| round()).min().unwrap()
+}
+
+fn main() {
+    // cargo bench 会传入 --bench 参数
+    let jobs = std::env::args().skip(1).find_map(|arg| arg.parse().ok()).unwrap_or(JOBS);
+    let cores = thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+    let mut sizes = vec![1, 2, 4, cores, cores * 2];
+    sizes.sort();
+    sizes.dedup();
+
+    let mut rows = Vec::new();
+    for &workers in &sizes {
+        let jobs = jobs - jobs % workers;
+        let channel = Arc::new(ChannelPool::new(workers));
+        let stealing = Arc::new(ThreadPool::new(workers));
+        rows.push((
+            workers,
+            jobs,
+            best(|| external(&channel, jobs)),
+            best(|| external(&stealing, jobs)),
+            best(|| nested(&channel, jobs, workers)),
+            best(|| nested(&stealing, jobs, workers)),
+        ));
+    }
+
+    let rate = |jobs: usize, elapsed: Duration| jobs as f64 / elapsed.as_secs_f64() / 1_000_000.0;
+    println!();
+    println!("{} tiny jobs per round, best of {} rounds, million jobs per second", jobs, ROUNDS);
+    println!("{:>8} {:>18} {:>18} {:>18} {:>18}", "workers", "external/channel", "external/stealing", "nested/channel", "nested/stealing");
+    for (workers, jobs, external_channel, external_stealing, nested_channel, nested_stealing) in rows {
+        println!(
+            "{:>8} {:>18.2} {:>18.2} {:>18.2} {:>18.2}",
+            workers,
+            rate(jobs, external_channel),
+            rate(jobs, external_stealing),
+            rate(jobs, nested_channel),
+            rate(jobs, nested_stealing)
+        );
+    }
+}
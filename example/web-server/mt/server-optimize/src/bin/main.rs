@@ -1,51 +1,377 @@
-use std::{fs, thread};
-use std::io::prelude::*;
-use std::net::{TcpListener, TcpStream};
+use std::env;
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 
+use server_optimize::config::{self, Command, Config, ConfigError, Mode};
+use server_optimize::form::{self, Form, UploadedFile};
+use server_optimize::http::{Request, Response, StatusCode};
+use server_optimize::limiter::Limiter;
+use server_optimize::logfile::RotatingFile;
+use server_optimize::metrics::Metrics;
+use server_optimize::middleware::{AccessLog, Chain, Compression, Cors, Handler, Reloadable, RequestId};
+#[cfg(target_os = "linux")]
+use server_optimize::reactor::EventLoop;
+use server_optimize::restart::{Inherited, Upgrader};
+use server_optimize::router::Router;
+use server_optimize::server::{self, Shutdown};
+use server_optimize::signal::{Signal, Signals};
+use server_optimize::static_files;
+use server_optimize::tls;
+use server_optimize::vhost::VirtualHosts;
+use server_optimize::websocket;
 use server_optimize::ThreadPool;
+#[cfg(target_os = "linux")]
+use server_optimize::Priority;
 
 /*
     多线程WebServer-服务端
+
+    配置来自TOML配置文件（默认读取当前目录下的server.toml，不存在时使用内置默认值）以及命令行参数，
+    命令行参数优先，完整的参数列表见 `server-optimize --help`：
+        blocking  阻塞模式（默认），每个连接由一个Worker负责
+        event     事件驱动模式，由epoll统一监听连接，只把完整的请求交给Worker
+        --tls     额外启动HTTPS监听（阻塞模式），与HTTP共用同一个线程池和处理器
+
+    运行期间通过信号控制（见 `Control`）：
+        kill -HUP  <pid>   重新加载配置，已有连接不受影响
+        kill -USR2 <pid>   零停机升级：以相同参数启动新的二进制并交出监听套接字，旧进程处理完已有连接后退出
+        kill -TERM <pid>   停止接受新连接，处理完已有连接后退出
  */
 fn main() {
-    let listner = TcpListener::bind("127.0.0.1:7878").unwrap(); // listner: TcpListener
-    // 初始化线程池，默认设置4个初始线程，这样可以同时接受4个请求处理
-    let pool = ThreadPool::new(4);
+    // 控制信号由专门的线程同步处理，必须在创建任何线程之前屏蔽
+    let signals = Signals::block().unwrap_or_else(|err| fail(err));
+    // 热升级启动的新进程从环境变量中取回旧进程的监听套接字
+    let mut inherited = Inherited::from_env().unwrap_or_else(|err| fail(err));
+    let upgrader = Upgrader::new().unwrap_or_else(|err| fail(err));
+
+    let started = match config::parse_args(env::args().skip(1)) {
+        Ok(Command::Run(config)) => config,
+        Ok(Command::Check(config)) => {
+            println!("configuration ok: {}", summary(&config));
+            return;
+        }
+        Ok(Command::Help) => {
+            println!("{}", config::USAGE);
+            return;
+        }
+        Err(err @ ConfigError::Usage(_)) => {
+            eprintln!("{}\n\n{}", err, config::USAGE);
+            process::exit(2);
+        }
+        Err(err) => fail(err),
+    };
+    let mut config = started.clone();
+    let tls_config = config.tls.as_ref().map(|tls| tls::load_config(&tls.cert, &tls.key).unwrap_or_else(|err| fail(err)));
 
-    for result in listner.incoming().take(2) { // result: Result<TcpStream, Error>
-        let stream = result.unwrap(); // stream: TcpStream
-        // thread::spawn(|| handle_connection(stream));
-        pool.execute(|| handle_connection(stream));
+    // 启动前绑定全部监听地址（或者认领继承的监听套接字），任何一个失败都直接退出
+    if !inherited.is_empty() {
+        println!("[Upgrade] inherited {} listening sockets", inherited.len());
+    }
+    let listeners: Vec<TcpListener> = config.listen.iter().map(|addr| listen(&mut inherited, *addr)).collect();
+    let tls_listeners: Vec<TcpListener> = match &config.tls {
+        Some(tls) => tls.listen.iter().map(|addr| listen(&mut inherited, *addr)).collect(),
+        None => Vec::new(),
+    };
+    // 端口为0时由系统分配端口，日志中输出实际监听的地址
+    config.listen = listeners.iter().map(local_addr).collect();
+    if let Some(tls) = &mut config.tls {
+        tls.listen = tls_listeners.iter().map(local_addr).collect();
     }
+    // 热升级时交给新进程的监听套接字
+    let fds: Vec<RawFd> = listeners.iter().chain(&tls_listeners).map(AsRawFd::as_raw_fd).collect();
 
+    let pool = ThreadPool::builder()
+        .core_threads(config.workers)
+        .event_listener(|event| println!("{}", event))
+        .build()
+        .unwrap_or_else(|err| fail(err));
+    // 准入控制在所有监听之间共享：连接数上限、队列长度上限以及按客户端地址的限速
+    let limiter = Limiter::new(config.limiter);
+    // 重新加载配置时保留的中间件，请求编号与各项指标不会因为重新加载而清零
+    let shared = Shared {
+        limiter: limiter.clone(),
+        request_id: Arc::new(RequestId::new()),
+        metrics: Arc::new(Metrics::new().with_pool(pool.monitor()).with_limiter(limiter.clone())),
+    };
+    // 处理器需要在多个Worker之间共享，收到SIGHUP时整体替换
+    let app = Arc::new(Reloadable::new(build_app(&config, &shared).unwrap_or_else(|err| fail(err))));
+    println!("listening: {}", summary(&config));
+
+    let shutdown = Shutdown::new();
+    let control = Control { started, app: Arc::clone(&app), shared, upgrader, listeners: fds, shutdown: shutdown.clone() };
+    thread::spawn(move || control.run(signals));
+    // 通知旧进程（如果有）可以停止accept了
+    inherited.ready().unwrap_or_else(|err| fail(format!("cannot notify the old process: {}", err)));
+
+    let options = config.options;
+    thread::scope(|scope| {
+        for listener in tls_listeners {
+            let (pool, app, limiter, shutdown) = (&pool, Arc::clone(&app) as Arc<dyn Handler>, &limiter, &shutdown);
+            let tls_config = Arc::clone(tls_config.as_ref().unwrap());
+            scope.spawn(move || tls::serve_until(listener, pool, app, tls_config, options, limiter, shutdown));
+        }
+        for listener in listeners {
+            let (pool, app, limiter, shutdown) = (&pool, Arc::clone(&app) as Arc<dyn Handler>, &limiter, &shutdown);
+            scope.spawn(move || match config.mode {
+                Mode::Blocking => server::serve_until(listener, pool, app, options, limiter, shutdown),
+                #[cfg(target_os = "linux")]
+                Mode::Event => EventLoop::new(listener)
+                    .options(options)
+                    .limiter(limiter.clone())
+                    .shutdown(shutdown.clone())
+                    // 健康检查与指标请求不排在批量请求之后
+                    .prioritize(|request| match request.path.as_str() {
+                        "/healthz" | "/metrics" => Priority::High,
+                        _ => Priority::Normal,
+                    })
+                    .run(pool, app)
+                    .unwrap_or_else(|err| fail(err)),
+                #[cfg(not(target_os = "linux"))]
+                Mode::Event => unreachable!("event mode is rejected by config validation"),
+            });
+        }
+    });
+
+    // 所有监听都已经停止，等待线程池处理完已经接受的连接（每个连接最多再处理一个请求，空闲连接等待keep_alive_timeout）
+    drop(pool);
     println!("shutting down!")
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let mut buffer = [0; 1024];
-    stream.read(&mut buffer).unwrap();
+/*
+    跨越配置重新加载的共享状态
+ */
+struct Shared {
+    limiter: Limiter,
+    request_id: Arc<RequestId>,
+    metrics: Arc<Metrics>,
+}
+
+// 按照配置组装中间件链
+fn build_app(config: &Config, shared: &Shared) -> io::Result<Arc<dyn Handler>> {
+    // 按照Host头部分发到各个站点，未知的主机名由默认站点处理
+    let mut chain = Chain::new(VirtualHosts::from_config(config, routes(config)));
+    if let Some(log) = &config.access_log {
+        // 访问日志单个文件超过max_bytes时滚动，最多保留max_files个历史文件
+        let file = RotatingFile::open(&log.path, log.max_bytes, log.max_files)?;
+        chain = chain.with(AccessLog::new(log.format, file));
+    }
+    // 限速位于访问日志内侧，被拒绝的请求（429）同样会记录在访问日志中
+    Ok(Arc::new(
+        chain
+            .with(shared.limiter.clone())
+            .with(Arc::clone(&shared.request_id))
+            .with(Arc::clone(&shared.metrics))
+            .with(Cors::any())
+            .with(Compression::new()),
+    ))
+}
 
-    let get_body_prefix = b"GET / HTTP/1.1\r\n";
-    let sleep_body_prefix = b"GET /sleep HTTP/1.1\r\n";
+/*
+    信号控制线程
 
-    // 匹配预期的请求是GET请求 并且请求路径是/     则返回欢迎页面，
-    // 匹配预期的请求是GET请求 并且请求路径是/sleep则返回欢迎页面，并且在返回前通过睡眠堵住后需请求（在单线程环境下模拟请求阻塞）
-    // 则否返回404页面
-    let (status_line, resource_filename) = if buffer.starts_with(get_body_prefix) {
-        ("HTTP/1.1 200 OK", "index.html")
-    } else if buffer.starts_with(sleep_body_prefix) {
-        thread::sleep(Duration::from_secs(10));
-        ("HTTP/1.1 200 OK", "index.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND", "404.html")
+    - SIGHUP           重新读取配置文件与命令行参数并替换处理器，失败时保留当前配置
+    - SIGUSR2          启动新的二进制并交出监听套接字，新进程就绪后停止accept并排空线程池
+    - SIGTERM/SIGINT   停止accept并排空线程池，再次收到时立即退出
+    排空期间已经接受的连接写完当前响应（带有Connection: close）后关闭，不会被持续使用的keep-alive连接拖住
+ */
+struct Control {
+    // 启动时的配置，只能通过重启生效的配置项与它比较
+    started: Config,
+    app: Arc<Reloadable>,
+    shared: Shared,
+    upgrader: Upgrader,
+    listeners: Vec<RawFd>,
+    shutdown: Shutdown,
+}
+
+impl Control {
+    fn run(self, signals: Signals) {
+        loop {
+            let signal = match signals.wait() {
+                Ok(signal) => signal,
+                Err(err) => {
+                    eprintln!("[Signal] wait failed: {}", err);
+                    return;
+                }
+            };
+            match signal {
+                Signal::Reload => self.reload(),
+                Signal::Upgrade if self.shutdown.is_triggered() => println!("[Upgrade] ignored, already shutting down"),
+                Signal::Upgrade => match self.upgrader.spawn(&self.listeners) {
+                    Ok(pid) => {
+                        self.shutdown.trigger();
+                        println!("[Upgrade] new process {} is ready, draining", pid);
+                    }
+                    Err(err) => println!("[Upgrade] failed, keep serving: {}", err),
+                },
+                Signal::Terminate if self.shutdown.is_triggered() => {
+                    println!("[Shutdown] terminated before draining finished");
+                    process::exit(1);
+                }
+                Signal::Terminate => {
+                    println!("[Shutdown] draining, signal again to exit immediately");
+                    self.shutdown.trigger();
+                }
+            }
+        }
+    }
+
+    fn reload(&self) {
+        let config = match config::parse_args(env::args().skip(1)) {
+            Ok(Command::Run(config)) => config,
+            Ok(_) => return,
+            Err(err) => {
+                println!("[Reload] failed, keeping the current configuration: {}", err);
+                return;
+            }
+        };
+        match build_app(&config, &self.shared) {
+            Ok(app) => self.app.replace(app),
+            Err(err) => {
+                println!("[Reload] failed, keeping the current configuration: {}", err);
+                return;
+            }
+        }
+        println!("[Reload] configuration reloaded: {}", summary(&config));
+        let pending = self.started.restart_required(&config);
+        if !pending.is_empty() {
+            println!("[Reload] changes to {} take effect after a restart or upgrade", pending.join(", "));
+        }
+    }
+}
+
+// 优先使用从旧进程继承的监听套接字，没有时重新绑定
+fn listen(inherited: &mut Inherited, addr: SocketAddr) -> TcpListener {
+    inherited.take_listener(addr).unwrap_or_else(|| bind(addr))
+}
+
+fn bind(addr: SocketAddr) -> TcpListener {
+    TcpListener::bind(addr).unwrap_or_else(|err| fail(format!("cannot listen on {}: {}", addr, err)))
+}
+
+fn local_addr(listener: &TcpListener) -> SocketAddr {
+    listener.local_addr().unwrap_or_else(|err| fail(err))
+}
+
+fn fail<E: std::fmt::Display>(err: E) -> ! {
+    eprintln!("server-optimize: {}", err);
+    process::exit(1);
+}
+
+fn summary(config: &Config) -> String {
+    let addrs = |addrs: &[SocketAddr]| addrs.iter().map(|addr| addr.to_string()).collect::<Vec<_>>().join(", ");
+    let mut summary = format!(
+        "http on {} ({} mode, {} workers), document root {}, {} virtual hosts",
+        addrs(&config.listen),
+        config.mode.as_str(),
+        config.workers,
+        config.site.document_root.display(),
+        config.virtual_hosts.len()
+    );
+    if let Some(tls) = &config.tls {
+        summary.push_str(&format!(", https on {}", addrs(&tls.listen)));
+    }
+    summary
+}
+
+// 默认站点的内置路由，其余请求按照配置中的路由表以及文档根目录下的静态文件处理：
+// 匹配预期的请求是GET请求 并且请求路径是/sleep则返回欢迎页面，并且在返回前通过睡眠堵住后需请求（在单线程环境下模拟请求阻塞）
+// 匹配预期的请求是GET请求 并且请求路径是/ws   则升级为WebSocket连接，原样返回客户端发送的消息
+// 匹配预期的请求是GET请求 并且请求路径是/upload则返回上传表单，POST请求则把上传的文件保存到文档根目录下的uploads目录
+fn routes(config: &Config) -> Router {
+    let index = config.site.index_path();
+    let uploads = config.site.document_root.join("uploads");
+    // 一次上传的总大小与单个文件的大小都以max_body_bytes为上限，阻塞模式与事件驱动模式的限制一致
+    let max_upload = config.options.limits.max_body_bytes as u64;
+    let limits = form::Limits { max_file_bytes: max_upload, max_total_bytes: max_upload, ..form::Limits::default() };
+    Router::new()
+        .get("/healthz", |_: Request| Response::text(StatusCode::OK, "ok"))
+        .get("/upload", |_: Request| Response::html(StatusCode::OK, UPLOAD_PAGE))
+        .post("/upload", move |mut request: Request| upload(&mut request, &uploads, limits))
+        .get("/ws", |request: Request| {
+            websocket::upgrade(&request, |mut socket| {
+                let sender = socket.sender();
+                for message in socket.messages() {
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+            })
+        })
+        .get("/sleep", move |request: Request| {
+            thread::sleep(Duration::from_secs(10));
+            // 读取本地html资源文件构建响应，存在预压缩的.gz文件并且客户端支持gzip时优先使用
+            static_files::serve_file(&request, StatusCode::OK, &index)
+                .unwrap_or_else(|_| Response::from(StatusCode::INTERNAL_SERVER_ERROR))
+        })
+}
+
+const UPLOAD_PAGE: &str = "<!DOCTYPE html>
+<html>
+<head><meta charset=\"utf-8\"><title>Upload</title></head>
+<body>
+<form method=\"post\" action=\"/upload\" enctype=\"multipart/form-data\">
+<input type=\"file\" name=\"file\" multiple>
+<button type=\"submit\">Upload</button>
+</form>
+</body>
+</html>
+";
+
+// 保存上传的文件，保存后可以通过 /uploads/<文件名> 访问；请求体以流的方式写入临时文件，超过 `limits` 时返回413
+fn upload(request: &mut Request, dir: &Path, limits: form::Limits) -> Response {
+    let files = match Form::parse_with_limits(request, limits) {
+        Ok(form) => form.into_files(),
+        Err(err) => return err.response(),
     };
+    if files.is_empty() {
+        return Response::text(StatusCode::BAD_REQUEST, "no file uploaded");
+    }
+    let mut saved = String::new();
+    for mut file in files {
+        match save(&mut file, dir) {
+            Ok(name) => saved.push_str(&format!("/uploads/{} ({} bytes)\n", name, file.size())),
+            Err(err) => {
+                eprintln!("[Upload] cannot save {:?}: {}", file.filename(), err);
+                return Response::from(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+    Response::text(StatusCode::CREATED, saved)
+}
 
-    // 读取本地html资源文件
-    let content = fs::read_to_string(resource_filename).unwrap();
-    // 构建响应体
-    let reponse = format!("{}\r\nContent-Length: {}\r\n\r\n{}", status_line, content.len(), content);
-    // 返回响应
-    stream.write(reponse.as_bytes()).unwrap();
-    stream.flush().unwrap();   
+// 只保留文件名中的字母、数字以及 . - _，同名文件已经存在时在扩展名前加上序号
+fn save(file: &mut UploadedFile, dir: &Path) -> io::Result<String> {
+    let name: String = file
+        .filename()
+        .unwrap_or("upload")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    // 不允许以点开头，避免隐藏文件以及 ..
+    let name = match name.trim_start_matches('.') {
+        "" => String::from("upload"),
+        name => name.to_string(),
+    };
+    let (stem, extension) = match name.rfind('.') {
+        Some(index) => name.split_at(index),
+        None => (name.as_str(), ""),
+    };
+    fs::create_dir_all(dir)?;
+    for n in 0..1000 {
+        let candidate = if n == 0 { name.clone() } else { format!("{}-{}{}", stem, n, extension) };
+        match file.persist(dir.join(&candidate)) {
+            Ok(()) => return Ok(candidate),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("too many files named {}", name)))
 }
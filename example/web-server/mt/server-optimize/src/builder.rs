@@ -0,0 +1,268 @@
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::event::PoolEvent;
+use crate::rejection::RejectionPolicy;
+use crate::{JobPanic, ThreadPool};
+
+// 超过核心线程数的Worker空闲多久之后退出
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+pub const DEFAULT_NAME_PREFIX: &str = "worker";
+// 等待多久之后任务的有效优先级提升一级
+pub const DEFAULT_AGING_INTERVAL: Duration = Duration::from_millis(100);
+
+pub(crate) type Hook = Arc<dyn Fn(usize) + Send + Sync>;
+pub(crate) type PanicHandler = Box<dyn Fn(&JobPanic) + Send + Sync>;
+pub(crate) type Listener = Box<dyn Fn(&PoolEvent<'_>) + Send + Sync>;
+
+/*
+    线程池构建器
+
+    由 `ThreadPool::builder` 创建，例如：
+        let pool = ThreadPool::builder()
+            .core_threads(4)
+            .max_threads(16)
+            .keep_alive(Duration::from_secs(30))
+            .name_prefix("http")
+            .build()?;
+
+    - 核心线程：线程池创建时启动，一直存在
+    - 任务积压（排队与执行中的任务数超过线程数）时按需增加线程，最多到最大线程数；
+      超过核心线程数的线程空闲 keep_alive 之后退出
+    - 队列默认不限长度；设置了容量时，队列已满后按照拒绝策略处理新提交的任务
+    - 高、低优先级的任务每等待一个老化间隔有效优先级提升一级
+ */
+pub struct Builder {
+    core_threads: Option<usize>,
+    max_threads: Option<usize>,
+    keep_alive: Duration,
+    name_prefix: String,
+    stack_size: Option<usize>,
+    queue_capacity: Option<usize>,
+    rejection_policy: RejectionPolicy,
+    aging_interval: Duration,
+    on_thread_start: Option<Hook>,
+    on_thread_stop: Option<Hook>,
+    panic_handler: Option<PanicHandler>,
+    listener: Option<Listener>,
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder {
+            core_threads: None,
+            max_threads: None,
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            name_prefix: DEFAULT_NAME_PREFIX.to_string(),
+            stack_size: None,
+            queue_capacity: None,
+            rejection_policy: RejectionPolicy::default(),
+            aging_interval: DEFAULT_AGING_INTERVAL,
+            on_thread_start: None,
+            on_thread_stop: None,
+            panic_handler: None,
+            listener: None,
+        }
+    }
+
+    /// 核心线程数，默认为CPU核数。
+    pub fn core_threads(mut self, threads: usize) -> Builder {
+        self.core_threads = Some(threads);
+        self
+    }
+
+    /// 最大线程数，默认与核心线程数相同（固定大小的线程池）。
+    pub fn max_threads(mut self, threads: usize) -> Builder {
+        self.max_threads = Some(threads);
+        self
+    }
+
+    /// 超过核心线程数的线程空闲多久之后退出，默认60秒。
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Builder {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// 线程名前缀，线程名为 "{前缀}-{Worker编号}"，默认 "worker"。
+    pub fn name_prefix(mut self, prefix: &str) -> Builder {
+        self.name_prefix = prefix.to_string();
+        self
+    }
+
+    /// 线程栈大小（字节），默认使用标准库的默认值。
+    pub fn stack_size(mut self, bytes: usize) -> Builder {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    /// 队列中最多等待执行的任务数，默认不限。
+    pub fn queue_capacity(mut self, capacity: usize) -> Builder {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// 队列已满时的处理方式，默认阻塞提交者。
+    pub fn rejection_policy(mut self, policy: RejectionPolicy) -> Builder {
+        self.rejection_policy = policy;
+        self
+    }
+
+    /// 任务等待多久之后有效优先级提升一级，默认100毫秒：低优先级任务最多等待一个间隔就会与普通任务竞争。
+    pub fn aging_interval(mut self, interval: Duration) -> Builder {
+        self.aging_interval = interval;
+        self
+    }
+
+    /// 每个Worker线程启动后、开始取任务之前在该线程中调用，参数为Worker编号。
+    pub fn on_thread_start<F>(mut self, hook: F) -> Builder
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.on_thread_start = Some(Arc::new(hook));
+        self
+    }
+
+    /// 每个Worker线程退出之前（包括空闲退出以及panic后被替换）在该线程中调用，参数为Worker编号。
+    pub fn on_thread_stop<F>(mut self, hook: F) -> Builder
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.on_thread_stop = Some(Arc::new(hook));
+        self
+    }
+
+    /// 任务panic时在执行该任务的Worker线程中调用；监听器同样会收到 `PoolEvent::JobPanicked`。
+    pub fn panic_handler<F>(mut self, handler: F) -> Builder
+    where
+        F: Fn(&JobPanic) + Send + Sync + 'static,
+    {
+        self.panic_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// 接收线程池事件（线程启动与退出、任务panic、关闭等），例如输出日志：
+    /// `.event_listener(|event| println!("{}", event))`；默认不输出任何日志。
+    pub fn event_listener<F>(mut self, listener: F) -> Builder
+    where
+        F: Fn(&PoolEvent<'_>) + Send + Sync + 'static,
+    {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    /// 校验配置并启动核心线程。
+    pub fn build(self) -> Result<ThreadPool, BuildError> {
+        let core = match self.core_threads {
+            Some(core) => core,
+            None => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        };
+        let max = self.max_threads.unwrap_or(core);
+        if core == 0 {
+            return Err(BuildError::ZeroThreads);
+        }
+        if core > max {
+            return Err(BuildError::CoreExceedsMax { core, max });
+        }
+        if self.stack_size == Some(0) {
+            return Err(BuildError::ZeroStackSize);
+        }
+        if self.queue_capacity == Some(0) {
+            return Err(BuildError::ZeroQueueCapacity);
+        }
+        ThreadPool::start(Settings {
+            core_threads: core,
+            max_threads: max,
+            keep_alive: self.keep_alive,
+            name_prefix: self.name_prefix,
+            stack_size: self.stack_size,
+            queue_capacity: self.queue_capacity,
+            rejection_policy: self.rejection_policy,
+            aging_interval: self.aging_interval,
+            on_thread_start: self.on_thread_start,
+            on_thread_stop: self.on_thread_stop,
+            panic_handler: self.panic_handler,
+            listener: self.listener,
+        })
+        .map_err(BuildError::Spawn)
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+// 校验之后的配置，由所有Worker共享
+pub(crate) struct Settings {
+    pub(crate) core_threads: usize,
+    pub(crate) max_threads: usize,
+    pub(crate) keep_alive: Duration,
+    pub(crate) name_prefix: String,
+    pub(crate) stack_size: Option<usize>,
+    pub(crate) queue_capacity: Option<usize>,
+    pub(crate) rejection_policy: RejectionPolicy,
+    pub(crate) aging_interval: Duration,
+    pub(crate) on_thread_start: Option<Hook>,
+    pub(crate) on_thread_stop: Option<Hook>,
+    pub(crate) panic_handler: Option<PanicHandler>,
+    pub(crate) listener: Option<Listener>,
+}
+
+impl Settings {
+    // 超过核心线程数的线程才会空闲退出
+    pub(crate) fn idle_timeout(&self) -> Option<Duration> {
+        (self.max_threads > self.core_threads).then_some(self.keep_alive)
+    }
+
+    pub(crate) fn thread(&self, id: usize) -> thread::Builder {
+        let builder = thread::Builder::new().name(format!("{}-{}", self.name_prefix, id));
+        match self.stack_size {
+            Some(size) => builder.stack_size(size),
+            None => builder,
+        }
+    }
+}
+
+/*
+    创建线程池失败的原因
+ */
+#[derive(Debug)]
+pub enum BuildError {
+    // 核心线程数为0
+    ZeroThreads,
+    // 核心线程数大于最大线程数
+    CoreExceedsMax { core: usize, max: usize },
+    // 线程栈大小为0
+    ZeroStackSize,
+    // 队列容量为0
+    ZeroQueueCapacity,
+    // 启动核心线程失败
+    Spawn(io::Error),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::ZeroThreads => f.write_str("thread pool needs at least one thread"),
+            BuildError::CoreExceedsMax { core, max } => {
+                write!(f, "core threads ({}) must not exceed max threads ({})", core, max)
+            }
+            BuildError::ZeroStackSize => f.write_str("thread stack size must not be zero"),
+            BuildError::ZeroQueueCapacity => f.write_str("queue capacity must not be zero"),
+            BuildError::Spawn(err) => write!(f, "cannot spawn worker thread: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BuildError::Spawn(err) => Some(err),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use std::env;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::http::{Body, HeaderMap, Request, Response, StatusCode};
+use crate::middleware::Handler;
+
+// 单个请求的默认最长执行时间
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+// 响应体的默认大小上限
+pub const DEFAULT_MAX_OUTPUT: u64 = 10 * 1024 * 1024;
+
+// 看门狗检查脚本是否已经退出的间隔
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// 脚本输出的头部块的最大字节数
+const MAX_HEADER_BLOCK: usize = 64 * 1024;
+
+const SERVER_SOFTWARE: &str = concat!("server-optimize/", env!("CARGO_PKG_VERSION"));
+
+/*
+    CGI处理器
+
+    每个请求启动一次配置的可执行文件（RFC 3875）：
+    - 请求信息通过环境变量传给脚本，请求头部转换为HTTP_*变量
+    - 请求体从连接直接复制到脚本的标准输入（chunked请求体先读入内存以确定CONTENT_LENGTH），
+      脚本的标准输出由头部块、空行以及响应体组成
+    - 头部块中的Status指定状态码，只有Location时返回302
+    - 响应体以流的方式转发给客户端
+    - 超过timeout仍未结束、或者输出超过max_output字节的脚本会被杀死：
+      头部块之前超时返回504，之后则中断响应（客户端会收到不完整的chunked响应）
+ */
+pub struct Cgi {
+    program: PathBuf,
+    // SCRIPT_NAME，请求路径中剩余的部分作为PATH_INFO；为None时整个请求路径都是SCRIPT_NAME
+    script_name: Option<String>,
+    timeout: Duration,
+    max_output: u64,
+}
+
+impl Cgi {
+    pub fn new<P: Into<PathBuf>>(program: P) -> Cgi {
+        Cgi { program: program.into(), script_name: None, timeout: DEFAULT_TIMEOUT, max_output: DEFAULT_MAX_OUTPUT }
+    }
+
+    /// 设置脚本在URL中的路径，例如脚本挂载在 `/cgi/hello` 时，请求 `/cgi/hello/a/b` 的PATH_INFO为 `/a/b`。
+    pub fn script_name(mut self, script_name: &str) -> Cgi {
+        self.script_name = Some(script_name.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// 单个请求的最长执行时间，默认30秒。
+    pub fn timeout(mut self, timeout: Duration) -> Cgi {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 响应体的最大字节数，默认10MiB。
+    pub fn max_output(mut self, max_output: u64) -> Cgi {
+        self.max_output = max_output;
+        self
+    }
+
+    // 组装RFC 3875定义的环境变量
+    fn environment(&self, request: &Request) -> Vec<(String, String)> {
+        let (script_name, path_info) = match &self.script_name {
+            Some(script_name) => match request.path.strip_prefix(script_name.as_str()) {
+                Some(rest) if rest.is_empty() || rest.starts_with('/') => (script_name.clone(), rest.to_string()),
+                _ => (request.path.clone(), String::new()),
+            },
+            None => (request.path.clone(), String::new()),
+        };
+        let (server_name, server_port) = match request.header("Host") {
+            Some(host) => match host.rsplit_once(':') {
+                Some((name, port)) if !name.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => {
+                    (name.to_string(), port.to_string())
+                }
+                _ => (host.to_string(), String::from("80")),
+            },
+            None => (String::from("localhost"), String::from("80")),
+        };
+
+        let mut vars = vec![
+            ("GATEWAY_INTERFACE".to_string(), "CGI/1.1".to_string()),
+            ("SERVER_SOFTWARE".to_string(), SERVER_SOFTWARE.to_string()),
+            ("SERVER_PROTOCOL".to_string(), request.version.clone()),
+            ("SERVER_NAME".to_string(), server_name),
+            ("SERVER_PORT".to_string(), server_port),
+            ("REQUEST_METHOD".to_string(), request.method.clone()),
+            ("SCRIPT_NAME".to_string(), script_name),
+            ("PATH_INFO".to_string(), path_info),
+            ("QUERY_STRING".to_string(), request.query.clone().unwrap_or_default()),
+        ];
+        if let Some(addr) = request.remote_addr {
+            vars.push(("REMOTE_ADDR".to_string(), addr.ip().to_string()));
+            vars.push(("REMOTE_PORT".to_string(), addr.port().to_string()));
+        }
+        let length = request.body_length().filter(|length| *length > 0 || request.header("Content-Length").is_some());
+        if let Some(length) = length {
+            vars.push(("CONTENT_LENGTH".to_string(), length.to_string()));
+        }
+        if let Some(content_type) = request.header("Content-Type") {
+            vars.push(("CONTENT_TYPE".to_string(), content_type.to_string()));
+        }
+        for (name, value) in request.headers.iter() {
+            // Content-Length/Content-Type已经单独传递；Proxy头部会被很多程序当作HTTP_PROXY代理设置（httpoxy）
+            if ["Content-Length", "Content-Type", "Proxy"].iter().any(|skip| name.eq_ignore_ascii_case(skip)) {
+                continue;
+            }
+            let name = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+            match vars.iter_mut().find(|(existing, _)| *existing == name) {
+                // 同名头部按照HTTP的规则用逗号合并
+                Some((_, existing)) => {
+                    existing.push_str(", ");
+                    existing.push_str(value);
+                }
+                None => vars.push((name, value.to_string())),
+            }
+        }
+        vars
+    }
+
+    fn spawn(&self, request: &Request) -> io::Result<Child> {
+        let mut command = Command::new(&self.program);
+        command.env_clear();
+        // 保留PATH，脚本解释器（例如#!/usr/bin/env python3）需要它
+        if let Some(path) = env::var_os("PATH") {
+            command.env("PATH", path);
+        }
+        command
+            .envs(self.environment(request))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+        if let Some(dir) = self.program.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            command.current_dir(dir);
+        }
+        // 脚本启动的子进程（例如shell脚本中的命令）会继承标准输出，放在单独的进程组中才能一起杀死
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        command.spawn()
+    }
+}
+
+impl Handler for Cgi {
+    fn handle(&self, mut request: Request) -> Response {
+        // CONTENT_LENGTH必须与请求体一致，长度未知的chunked请求体先读入内存（受max_body_bytes限制）
+        if request.body_length().is_none() {
+            if let Err(err) = request.buffer_body() {
+                return Response::from(err.status());
+            }
+        }
+        let mut child = match self.spawn(&request) {
+            Ok(child) => child,
+            Err(err) => {
+                println!("[CGI] spawn {} failed: {}", self.program.display(), err);
+                return Response::from(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let process = Arc::new(Process { child: Mutex::new(child), killed: AtomicBool::new(false) });
+
+        // 看门狗：超时仍未结束时杀死脚本；脚本已经退出或者输出读完（发送端被丢弃）后不再计时
+        let (done, finished) = mpsc::channel::<()>();
+        let watched = Arc::clone(&process);
+        let deadline = Instant::now() + self.timeout;
+        thread::spawn(move || loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match finished.recv_timeout(remaining.min(EXIT_POLL_INTERVAL)) {
+                Err(RecvTimeoutError::Timeout) if watched.has_exited() => return,
+                Err(RecvTimeoutError::Timeout) if remaining.is_zero() => {
+                    watched.kill();
+                    return;
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+                _ => return,
+            }
+        });
+
+        // 在单独的线程中把请求体从连接复制到标准输入，避免脚本先写输出再读输入时双方互相等待
+        if let Some(mut stdin) = stdin {
+            let mut body = request.take_body();
+            thread::spawn(move || {
+                // 脚本不读取标准输入就退出时会写入失败，忽略即可
+                let _ = io::copy(&mut body, &mut stdin);
+            });
+        }
+
+        let mut output =
+            Output { stdout: BufReader::new(stdout), process: Arc::clone(&process), remaining: self.max_output, _done: done };
+        let headers = match read_header_block(&mut output.stdout) {
+            Ok(headers) => headers,
+            Err(err) if process.killed.load(Ordering::SeqCst) => {
+                println!("[CGI] {} timed out: {}", self.program.display(), err);
+                return Response::from(StatusCode::GATEWAY_TIMEOUT);
+            }
+            Err(err) => {
+                println!("[CGI] {} produced an invalid response: {}", self.program.display(), err);
+                return Response::from(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+        response(headers, output).unwrap_or_else(|err| {
+            println!("[CGI] {} produced an invalid response: {}", self.program.display(), err);
+            Response::from(StatusCode::INTERNAL_SERVER_ERROR)
+        })
+    }
+}
+
+/*
+    正在运行的脚本进程
+ */
+struct Process {
+    child: Mutex<Child>,
+    // 是否因为超时或者输出过多被杀死
+    killed: AtomicBool,
+}
+
+impl Process {
+    // 脚本已经退出（进程被回收）之后pid可能被复用，只向仍在运行的脚本所在的进程组发送信号；
+    // 返回是否真的杀死了脚本，只有这种情况才记为killed
+    fn kill(&self) -> bool {
+        let mut child = self.child.lock().unwrap();
+        if !matches!(child.try_wait(), Ok(None)) {
+            return false;
+        }
+        self.killed.store(true, Ordering::SeqCst);
+        kill_group(&mut child);
+        true
+    }
+
+    fn has_exited(&self) -> bool {
+        !matches!(self.child.lock().unwrap().try_wait(), Ok(None))
+    }
+}
+
+// 杀死脚本所在的整个进程组，脚本启动的子进程同样占用着标准输出
+fn kill_group(child: &mut Child) {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    let _ = child.kill();
+}
+
+/*
+    脚本的标准输出，读取响应体时检查输出大小限制，丢弃时杀死并回收仍在运行的进程
+ */
+struct Output {
+    stdout: BufReader<ChildStdout>,
+    process: Arc<Process>,
+    // 响应体还允许输出的字节数
+    remaining: u64,
+    // 丢弃时通知看门狗退出
+    _done: Sender<()>,
+}
+
+impl Read for Output {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n as u64 > self.remaining {
+            self.process.kill();
+            return Err(io::Error::other("cgi output limit exceeded"));
+        }
+        self.remaining -= n as u64;
+        // 被杀死的进程输出也会结束，此时不能当作正常结束，否则客户端会收到看似完整的响应；
+        // 脚本正常退出之后才超时的不受影响
+        if n == 0 && self.process.killed.load(Ordering::SeqCst) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "cgi script timed out"));
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        self.process.kill();
+        let _ = self.process.child.lock().unwrap().wait();
+    }
+}
+
+// 读取脚本输出的头部块，直到空行；兼容只用LF换行的脚本
+fn read_header_block<R: BufRead>(reader: &mut R) -> io::Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let mut size = 0;
+    loop {
+        let mut line = Vec::new();
+        let n = reader.by_ref().take((MAX_HEADER_BLOCK - size) as u64).read_until(b'\n', &mut line)?;
+        size += n;
+        if n == 0 || line.last() != Some(&b'\n') {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete header block"));
+        }
+        let line = String::from_utf8(line).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "header is not utf-8"))?;
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("invalid header line: {:?}", line)))?;
+        headers.append(name.trim(), value.trim());
+    }
+}
+
+// 根据头部块组装响应，响应体为脚本剩余的输出
+fn response(mut headers: HeaderMap, output: Output) -> io::Result<Response> {
+    let status = match headers.remove("Status") {
+        // Status: 404 Not Found
+        Some(status) => status
+            .split_whitespace()
+            .next()
+            .and_then(|code| code.parse::<u16>().ok())
+            .filter(|code| (200..600).contains(code))
+            .map(StatusCode)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("invalid status: {:?}", status)))?,
+        None if headers.contains("Location") => StatusCode::FOUND,
+        None => StatusCode::OK,
+    };
+    if !headers.contains("Content-Type") && !headers.contains("Location") && status.is_success() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "missing Content-Type"));
+    }
+    // 响应体的长度由本服务确定
+    headers.remove("Content-Length");
+    headers.remove("Transfer-Encoding");
+    headers.remove("Connection");
+    let body = Body::Stream(Box::new(output));
+    Ok(Response { status, headers, body, upgrade: None, route: None, on_complete: None })
+}
+
+/// 脚本文件是否可执行。
+pub fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata().map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0).unwrap_or(false)
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file()
+    }
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_variables() {
+        let mut request = Request::new("POST", "/cgi/hello/extra/path?name=rust&x=1");
+        request.headers.insert("Host", "example.com:8080");
+        request.headers.insert("Content-Type", "text/plain");
+        request.headers.insert("X-Custom-Header", "a");
+        request.headers.append("x-custom-header", "b");
+        request.headers.insert("Proxy", "http://evil");
+        request.body = b"hello".to_vec();
+        request.remote_addr = Some("192.0.2.1:50000".parse().unwrap());
+
+        let vars = Cgi::new("/bin/true").script_name("/cgi/hello").environment(&request);
+        let get = |name: &str| vars.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+        assert_eq!(get("GATEWAY_INTERFACE"), Some("CGI/1.1"));
+        assert_eq!(get("REQUEST_METHOD"), Some("POST"));
+        assert_eq!(get("SCRIPT_NAME"), Some("/cgi/hello"));
+        assert_eq!(get("PATH_INFO"), Some("/extra/path"));
+        assert_eq!(get("QUERY_STRING"), Some("name=rust&x=1"));
+        assert_eq!(get("SERVER_NAME"), Some("example.com"));
+        assert_eq!(get("SERVER_PORT"), Some("8080"));
+        assert_eq!(get("REMOTE_ADDR"), Some("192.0.2.1"));
+        assert_eq!(get("CONTENT_LENGTH"), Some("5"));
+        assert_eq!(get("CONTENT_TYPE"), Some("text/plain"));
+        assert_eq!(get("HTTP_X_CUSTOM_HEADER"), Some("a, b"));
+        assert_eq!(get("HTTP_CONTENT_TYPE"), None);
+        assert_eq!(get("HTTP_PROXY"), None);
+    }
+
+    #[test]
+    fn parse_header_block() {
+        let raw = b"Status: 404 Not Found\nContent-Type: text/plain\r\nX-Script: 1\n\nbody";
+        let mut reader = &raw[..];
+        let headers = read_header_block(&mut reader).unwrap();
+        assert_eq!(headers.get("Status"), Some("404 Not Found"));
+        assert_eq!(headers.get("X-Script"), Some("1"));
+        assert_eq!(reader, b"body");
+
+        assert!(read_header_block(&mut &b"Content-Type: text/plain\n"[..]).is_err());
+        assert!(read_header_block(&mut &b"not a header\n\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,1004 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::cgi;
+use crate::http::{Limits, StatusCode};
+use crate::limiter::{Policy, Rate};
+use crate::middleware::LogFormat;
+use crate::server::{Options, KEEP_ALIVE_TIMEOUT, WRITE_TIMEOUT};
+
+pub mod cli;
+
+pub use cli::{parse_args, Command, USAGE};
+
+// 未通过 --config 指定配置文件时，如果当前目录下存在该文件则自动加载
+pub const DEFAULT_CONFIG_FILE: &str = "server.toml";
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:7878";
+const DEFAULT_TLS_LISTEN: &str = "127.0.0.1:7443";
+const DEFAULT_WORKERS: i64 = 4;
+const DEFAULT_INDEX: &str = "index.html";
+const DEFAULT_NOT_FOUND: &str = "404.html";
+const DEFAULT_ACCESS_LOG: &str = "access.log";
+const DEFAULT_LOG_MAX_BYTES: i64 = 10 * 1024 * 1024;
+const DEFAULT_LOG_MAX_FILES: i64 = 5;
+// 请求头部上限不能小于一个正常的请求行
+const MIN_HEADER_BYTES: i64 = 256;
+const MAX_WORKERS: i64 = 1024;
+const DEFAULT_MAX_CONNECTIONS: i64 = 1024;
+const DEFAULT_MAX_QUEUE: i64 = 1024;
+
+/*
+    服务端配置
+
+    由配置文件（TOML格式）与命令行参数合并后校验得到，所有地址、路径、时长都已经解析完毕，
+    相对路径按照配置文件所在目录解析
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    // HTTP监听地址，可以有多个，支持IPv6（例如 [::1]:7878）
+    pub listen: Vec<SocketAddr>,
+    // 线程池大小
+    pub workers: usize,
+    pub mode: Mode,
+    // 默认站点：没有匹配到虚拟主机的请求都由它处理
+    pub site: Site,
+    // 超时与请求大小限制
+    pub options: Options,
+    // 连接准入与限速策略
+    pub limiter: Policy,
+    // 访问日志，配置为 "off" 时为None
+    pub access_log: Option<AccessLogConfig>,
+    pub tls: Option<TlsConfig>,
+    pub virtual_hosts: Vec<VirtualHost>,
+}
+
+/*
+    连接处理模式
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    // 每个连接由一个Worker负责
+    Blocking,
+    // 由epoll统一监听连接，只把完整的请求交给Worker
+    Event,
+}
+
+/*
+    站点：文档根目录、目录首页文件名、错误页面以及路由表
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Site {
+    pub document_root: PathBuf,
+    // 目录首页的文件名，例如index.html
+    pub index: String,
+    // 按状态码配置的错误页面，路径已经解析完毕；配置文件中的not_found是404页面的简写
+    pub error_pages: BTreeMap<u16, PathBuf>,
+    // 站点自己的路由表，优先于静态文件
+    pub routes: Vec<RouteConfig>,
+}
+
+/*
+    路由表中的一条路由，路径写法与 `Router::route` 一致
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteConfig {
+    pub methods: Vec<String>,
+    pub path: String,
+    pub action: RouteAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteAction {
+    // 返回文档根目录下的某个文件
+    File(PathBuf),
+    // 重定向到另一个地址
+    Redirect { location: String, status: StatusCode },
+    // 反向代理到上游服务器，strip_prefix为转发前去掉的路径前缀
+    Proxy { upstreams: Vec<SocketAddr>, strip_prefix: Option<String> },
+    // 每个请求执行一次CGI脚本，超时或者输出超过max_output字节时杀死脚本
+    Cgi { program: PathBuf, timeout: Duration, max_output: u64 },
+}
+
+/*
+    访问日志配置
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessLogConfig {
+    pub path: PathBuf,
+    pub format: LogFormat,
+    // 单个文件的大小上限，超过后滚动
+    pub max_bytes: u64,
+    // 最多保留的历史文件数
+    pub max_files: usize,
+}
+
+/*
+    HTTPS监听配置
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    pub listen: Vec<SocketAddr>,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/*
+    虚拟主机：按照Host头部匹配的站点，主机名已经统一为小写
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VirtualHost {
+    pub names: Vec<String>,
+    pub site: Site,
+}
+
+/*
+    配置错误
+ */
+#[derive(Debug)]
+pub enum ConfigError {
+    // 读取配置文件失败
+    Io(PathBuf, io::Error),
+    // 配置文件不是合法的TOML，或者包含未知的配置项
+    Parse(PathBuf, String),
+    // 校验失败，包含全部问题而不只是第一个
+    Invalid(Vec<String>),
+    // 命令行参数错误
+    Usage(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "cannot read {}: {}", path.display(), err),
+            ConfigError::Parse(path, message) => write!(f, "cannot parse {}: {}", path.display(), message.trim_end()),
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+            ConfigError::Usage(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// 读取并校验配置文件。
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let path = path.as_ref();
+        let raw = RawConfig::read(path)?;
+        raw.validate(base_dir(path))
+    }
+
+    /// 解析TOML文本，相对路径按照 `base` 解析。
+    pub fn parse(text: &str, base: &Path) -> Result<Config, ConfigError> {
+        let raw: RawConfig = toml::from_str(text).map_err(|err| ConfigError::Parse(PathBuf::from("<string>"), err.to_string()))?;
+        raw.validate(base)
+    }
+
+    /// 与 `other` 相比，哪些发生变化的配置项不能在重新加载时生效，只能通过重启（或者热升级）生效。
+    ///
+    /// 站点、路由、虚拟主机以及访问日志随处理器一起替换；监听地址、线程池、超时、准入策略与证书在启动时确定。
+    pub fn restart_required(&self, other: &Config) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.listen != other.listen {
+            changed.push("listen");
+        }
+        if self.workers != other.workers {
+            changed.push("workers");
+        }
+        if self.mode != other.mode {
+            changed.push("mode");
+        }
+        if self.options != other.options {
+            changed.push("timeouts/limits");
+        }
+        if self.limiter != other.limiter {
+            changed.push("limiter");
+        }
+        if self.tls != other.tls {
+            changed.push("tls");
+        }
+        changed
+    }
+}
+
+impl Default for Config {
+    /// 与此前硬编码的行为一致：监听127.0.0.1:7878，4个Worker，当前目录下的index.html/404.html。
+    fn default() -> Config {
+        Config {
+            listen: vec![DEFAULT_LISTEN.parse().unwrap()],
+            workers: DEFAULT_WORKERS as usize,
+            mode: Mode::Blocking,
+            site: Site {
+                document_root: PathBuf::from("."),
+                index: DEFAULT_INDEX.into(),
+                error_pages: BTreeMap::from([(404, Path::new(".").join(DEFAULT_NOT_FOUND))]),
+                routes: Vec::new(),
+            },
+            options: Options::default(),
+            limiter: Policy {
+                rate: None,
+                max_connections: Some(DEFAULT_MAX_CONNECTIONS as usize),
+                max_connections_per_ip: None,
+                max_queue: Some(DEFAULT_MAX_QUEUE as usize),
+            },
+            access_log: Some(AccessLogConfig {
+                path: PathBuf::from(DEFAULT_ACCESS_LOG),
+                format: LogFormat::Combined,
+                max_bytes: DEFAULT_LOG_MAX_BYTES as u64,
+                max_files: DEFAULT_LOG_MAX_FILES as usize,
+            }),
+            tls: None,
+            virtual_hosts: Vec::new(),
+        }
+    }
+}
+
+impl Mode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Blocking => "blocking",
+            Mode::Event => "event",
+        }
+    }
+}
+
+impl Site {
+    pub fn index_path(&self) -> PathBuf {
+        self.document_root.join(&self.index)
+    }
+
+    /// 状态码对应的错误页面。
+    pub fn error_page(&self, status: StatusCode) -> Option<&Path> {
+        self.error_pages.get(&status.as_u16()).map(PathBuf::as_path)
+    }
+}
+
+/*
+    配置文件的原始结构，所有字段都是可选的，未出现的字段使用默认值
+
+    字段名与配置文件中的键一一对应，未知的键会报错，避免拼写错误被静默忽略
+ */
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RawConfig {
+    listen: Option<Vec<String>>,
+    // 整数先按i64读取，负数也能给出清晰的校验错误
+    workers: Option<i64>,
+    mode: Option<String>,
+    document_root: Option<PathBuf>,
+    index: Option<String>,
+    not_found: Option<String>,
+    error_pages: Option<BTreeMap<String, String>>,
+    #[serde(default, rename = "route")]
+    routes: Vec<RawRoute>,
+    #[serde(default)]
+    timeouts: RawTimeouts,
+    #[serde(default)]
+    limits: RawLimits,
+    #[serde(default)]
+    log: RawLog,
+    tls: Option<RawTls>,
+    #[serde(default, rename = "virtual_host")]
+    virtual_hosts: Vec<RawVirtualHost>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RawTimeouts {
+    keep_alive: Option<RawDuration>,
+    write: Option<RawDuration>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RawLimits {
+    max_header_bytes: Option<i64>,
+    max_body_bytes: Option<i64>,
+    // 以下各项不配置时使用默认值，配置为0表示不限制
+    max_connections: Option<i64>,
+    max_connections_per_ip: Option<i64>,
+    max_queue: Option<i64>,
+    // 每个客户端地址每秒的请求数，以及允许的突发请求数（默认等于rate）
+    rate: Option<i64>,
+    burst: Option<i64>,
+}
+
+impl RawLimits {
+    // 校验连接准入与限速相关的配置项
+    fn policy(&self, problems: &mut Vec<String>) -> Policy {
+        let mut limit = |field: &str, value: Option<i64>, default: Option<i64>| -> Option<usize> {
+            match value.or(default) {
+                Some(value) if value < 0 => {
+                    problems.push(format!("limits.{}: must not be negative, got {}", field, value));
+                    None
+                }
+                Some(0) | None => None,
+                Some(value) => Some(value as usize),
+            }
+        };
+        let max_connections = limit("max_connections", self.max_connections, Some(DEFAULT_MAX_CONNECTIONS));
+        let max_connections_per_ip = limit("max_connections_per_ip", self.max_connections_per_ip, None);
+        let max_queue = limit("max_queue", self.max_queue, Some(DEFAULT_MAX_QUEUE));
+        let per_second = limit("rate", self.rate, None);
+        let burst = limit("burst", self.burst, None);
+        if per_second.is_none() && burst.is_some() {
+            problems.push("limits.burst: only applies together with limits.rate".to_string());
+        }
+        let rate = per_second.map(|per_second| Rate {
+            per_second: per_second.min(u32::MAX as usize) as u32,
+            burst: burst.unwrap_or(per_second).min(u32::MAX as usize) as u32,
+        });
+        Policy { rate, max_connections, max_connections_per_ip, max_queue }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RawLog {
+    // 访问日志路径，"off" 表示关闭
+    access: Option<String>,
+    format: Option<String>,
+    max_bytes: Option<i64>,
+    max_files: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RawTls {
+    listen: Option<Vec<String>>,
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RawVirtualHost {
+    #[serde(default)]
+    names: Vec<String>,
+    document_root: Option<PathBuf>,
+    index: Option<String>,
+    not_found: Option<String>,
+    error_pages: Option<BTreeMap<String, String>>,
+    #[serde(default, rename = "route")]
+    routes: Vec<RawRoute>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RawRoute {
+    path: Option<String>,
+    // 默认只匹配GET，代理路由默认匹配任意方法（"*"），CGI路由默认匹配GET与POST
+    methods: Option<Vec<String>>,
+    // file、redirect、proxy与cgi四选一
+    file: Option<String>,
+    redirect: Option<String>,
+    // 重定向是否为永久重定向（301），默认为302
+    #[serde(default)]
+    permanent: bool,
+    // 上游地址列表（host:port），多个上游之间轮询
+    proxy: Option<Vec<String>>,
+    // 转发前是否去掉路由的路径前缀，例如路由 /api/* 把 /api/users 转发为 /users
+    #[serde(default)]
+    strip_prefix: bool,
+    // CGI脚本路径，相对于配置文件所在目录
+    cgi: Option<String>,
+    // CGI脚本的最长执行时间，默认30秒
+    timeout: Option<RawDuration>,
+    // CGI脚本响应体的最大字节数，默认10MiB
+    max_output_bytes: Option<i64>,
+}
+
+/*
+    时长：整数表示秒，字符串可以带单位，例如 "500ms"、"5s"、"2m"
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum RawDuration {
+    Seconds(i64),
+    Text(String),
+}
+
+impl RawConfig {
+    fn read(path: &Path) -> Result<RawConfig, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        toml::from_str(&text).map_err(|err| ConfigError::Parse(path.to_path_buf(), err.to_string()))
+    }
+
+    // 校验全部配置项，收集所有问题后一次性报告
+    fn validate(self, base: &Path) -> Result<Config, ConfigError> {
+        let mut problems = Vec::new();
+        let defaults = Config::default();
+
+        let listen = parse_addrs("listen", self.listen.as_deref(), DEFAULT_LISTEN, &mut problems);
+
+        let workers = self.workers.unwrap_or(DEFAULT_WORKERS);
+        if !(1..=MAX_WORKERS).contains(&workers) {
+            problems.push(format!("workers: must be between 1 and {}, got {}", MAX_WORKERS, workers));
+        }
+
+        let mode = match self.mode.as_deref().unwrap_or("blocking") {
+            "blocking" => Mode::Blocking,
+            "event" if cfg!(target_os = "linux") => Mode::Event,
+            "event" => {
+                problems.push("mode: \"event\" is only supported on Linux".to_string());
+                Mode::Blocking
+            }
+            other => {
+                problems.push(format!("mode: unknown mode {:?}, expected \"blocking\" or \"event\"", other));
+                Mode::Blocking
+            }
+        };
+
+        let error_pages = self.error_pages.unwrap_or_default();
+        // 没有配置任何404页面时使用文档根目录下的404.html
+        let not_found = self
+            .not_found
+            .or_else(|| (!error_pages.contains_key("404")).then(|| DEFAULT_NOT_FOUND.to_string()));
+        let site = build_site(
+            "",
+            base,
+            resolve(base, self.document_root.as_deref().unwrap_or(&defaults.site.document_root)),
+            self.index.unwrap_or(defaults.site.index),
+            not_found,
+            error_pages,
+            self.routes,
+            &BTreeMap::new(),
+            &mut problems,
+        );
+
+        let keep_alive_timeout = duration("timeouts.keep_alive", self.timeouts.keep_alive, KEEP_ALIVE_TIMEOUT, &mut problems);
+        let write_timeout = duration("timeouts.write", self.timeouts.write, WRITE_TIMEOUT, &mut problems);
+
+        let default_limits = Limits::default();
+        let max_head_bytes = self.limits.max_header_bytes.unwrap_or(default_limits.max_head_bytes as i64);
+        if max_head_bytes < MIN_HEADER_BYTES {
+            problems.push(format!("limits.max_header_bytes: must be at least {}, got {}", MIN_HEADER_BYTES, max_head_bytes));
+        }
+        let max_body_bytes = self.limits.max_body_bytes.unwrap_or(default_limits.max_body_bytes as i64);
+        if max_body_bytes < 0 {
+            problems.push(format!("limits.max_body_bytes: must not be negative, got {}", max_body_bytes));
+        }
+        let options = Options {
+            keep_alive_timeout,
+            write_timeout,
+            limits: Limits { max_head_bytes: max_head_bytes.max(0) as usize, max_body_bytes: max_body_bytes.max(0) as usize },
+        };
+
+        let limiter = self.limits.policy(&mut problems);
+        let access_log = self.log.validate(base, &mut problems);
+        let tls = self.tls.map(|tls| tls.validate(base, &listen, &mut problems));
+
+        let mut virtual_hosts = Vec::new();
+        let mut seen_names = HashSet::new();
+        for (i, raw) in self.virtual_hosts.into_iter().enumerate() {
+            let context = format!("virtual_host[{}]", i);
+            if raw.names.is_empty() {
+                problems.push(format!("{}.names: at least one host name is required", context));
+            }
+            let mut names = Vec::new();
+            for name in raw.names {
+                let name = name.trim().to_ascii_lowercase();
+                // 通配符只能以 "*." 的形式出现在开头
+                let wildcard_ok = !name.contains('*') || (name.starts_with("*.") && name.matches('*').count() == 1);
+                if name.is_empty() || !wildcard_ok || name.contains(|c: char| c.is_whitespace() || c == '/') {
+                    problems.push(format!("{}.names: {:?} is not a valid host name", context, name));
+                } else if !seen_names.insert(name.clone()) {
+                    problems.push(format!("{}.names: host {:?} is already used by another virtual host", context, name));
+                } else {
+                    names.push(name);
+                }
+            }
+            let document_root = match raw.document_root {
+                Some(root) => resolve(base, &root),
+                None => {
+                    problems.push(format!("{}.document_root: is required", context));
+                    PathBuf::new()
+                }
+            };
+            // 未配置的首页与错误页面沿用全局配置
+            let host_site = build_site(
+                &format!("{}.", context),
+                base,
+                document_root,
+                raw.index.unwrap_or_else(|| site.index.clone()),
+                raw.not_found,
+                raw.error_pages.unwrap_or_default(),
+                raw.routes,
+                &site.error_pages,
+                &mut problems,
+            );
+            virtual_hosts.push(VirtualHost { names, site: host_site });
+        }
+
+        if !problems.is_empty() {
+            return Err(ConfigError::Invalid(problems));
+        }
+        Ok(Config {
+            listen,
+            workers: workers as usize,
+            mode,
+            site,
+            options,
+            limiter,
+            access_log,
+            tls,
+            virtual_hosts,
+        })
+    }
+}
+
+impl RawLog {
+    fn validate(self, base: &Path, problems: &mut Vec<String>) -> Option<AccessLogConfig> {
+        let format = match self.format.as_deref().unwrap_or("combined") {
+            "common" => LogFormat::Common,
+            "combined" => LogFormat::Combined,
+            other => {
+                problems.push(format!("log.format: unknown format {:?}, expected \"common\" or \"combined\"", other));
+                LogFormat::Combined
+            }
+        };
+        let max_bytes = self.max_bytes.unwrap_or(DEFAULT_LOG_MAX_BYTES);
+        if max_bytes <= 0 {
+            problems.push(format!("log.max_bytes: must be positive, got {}", max_bytes));
+        }
+        let max_files = self.max_files.unwrap_or(DEFAULT_LOG_MAX_FILES);
+        if max_files < 1 {
+            problems.push(format!("log.max_files: must be at least 1, got {}", max_files));
+        }
+
+        let path = match self.access.as_deref().unwrap_or(DEFAULT_ACCESS_LOG) {
+            "off" => return None,
+            "" => {
+                problems.push("log.access: must be a file path or \"off\"".to_string());
+                return None;
+            }
+            path => resolve(base, Path::new(path)),
+        };
+        match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() && !dir.is_dir() => {
+                problems.push(format!("log.access: directory {} does not exist", dir.display()));
+            }
+            _ => {}
+        }
+        Some(AccessLogConfig { path, format, max_bytes: max_bytes.max(0) as u64, max_files: max_files.max(0) as usize })
+    }
+}
+
+impl RawTls {
+    fn validate(self, base: &Path, http_listen: &[SocketAddr], problems: &mut Vec<String>) -> TlsConfig {
+        let listen = parse_addrs("tls.listen", self.listen.as_deref(), DEFAULT_TLS_LISTEN, problems);
+        for addr in &listen {
+            if http_listen.contains(addr) {
+                problems.push(format!("tls.listen: {} is also used as a plain HTTP listen address", addr));
+            }
+        }
+        let mut file = |name: &str, path: Option<PathBuf>| match path {
+            Some(path) => {
+                let path = resolve(base, &path);
+                if !path.is_file() {
+                    problems.push(format!("tls.{}: file {} does not exist", name, path.display()));
+                }
+                path
+            }
+            None => {
+                problems.push(format!("tls.{}: is required when TLS is enabled", name));
+                PathBuf::new()
+            }
+        };
+        let cert = file("cert", self.cert);
+        let key = file("key", self.key);
+        TlsConfig { listen, cert, key }
+    }
+}
+
+// 解析监听地址列表，未配置时使用默认地址
+fn parse_addrs(field: &str, addrs: Option<&[String]>, default: &str, problems: &mut Vec<String>) -> Vec<SocketAddr> {
+    let addrs = match addrs {
+        Some(addrs) => addrs,
+        None => return vec![default.parse().unwrap()],
+    };
+    if addrs.is_empty() {
+        problems.push(format!("{}: at least one address is required", field));
+    }
+    let mut parsed = Vec::new();
+    for addr in addrs {
+        match addr.trim().parse::<SocketAddr>() {
+            Ok(addr) if parsed.contains(&addr) => problems.push(format!("{}: {} is listed more than once", field, addr)),
+            Ok(addr) => parsed.push(addr),
+            Err(_) => problems.push(format!(
+                "{}: {:?} is not a valid socket address (expected e.g. 127.0.0.1:7878 or [::1]:7878)",
+                field, addr
+            )),
+        }
+    }
+    parsed
+}
+
+// 校验一个站点，错误页面在inherited的基础上覆盖
+#[allow(clippy::too_many_arguments)]
+fn build_site(
+    prefix: &str,
+    base: &Path,
+    document_root: PathBuf,
+    index: String,
+    not_found: Option<String>,
+    error_pages: BTreeMap<String, String>,
+    routes: Vec<RawRoute>,
+    inherited: &BTreeMap<u16, PathBuf>,
+    problems: &mut Vec<String>,
+) -> Site {
+    let mut site = Site { document_root, index, error_pages: inherited.clone(), routes: Vec::new() };
+    // document_root缺失时已经报告过，不再检查其中的文件
+    let root_ok = site.document_root.as_os_str().is_empty() || site.document_root.is_dir();
+    if !root_ok {
+        problems.push(format!("{}document_root: directory {} does not exist", prefix, site.document_root.display()));
+    }
+    let check_file = site.document_root.is_dir();
+    let require_file = |field: &str, path: PathBuf, problems: &mut Vec<String>| {
+        if check_file && !path.is_file() {
+            problems.push(format!("{}{}: file {} does not exist", prefix, field, path.display()));
+        }
+        path
+    };
+
+    if site.index.is_empty() || site.index.contains("..") {
+        problems.push(format!("{}index: {:?} is not a valid file name", prefix, site.index));
+    } else {
+        require_file("index", site.index_path(), problems);
+    }
+
+    if let Some(not_found) = not_found {
+        if error_pages.contains_key("404") {
+            problems.push(format!("{}not_found: conflicts with error_pages.404, set only one of them", prefix));
+        }
+        let path = require_file("not_found", site.document_root.join(not_found), problems);
+        site.error_pages.insert(404, path);
+    }
+    for (status, page) in error_pages {
+        match status.parse::<u16>() {
+            Ok(code) if StatusCode(code).is_error() => {
+                let path = require_file(&format!("error_pages.{}", status), site.document_root.join(page), problems);
+                site.error_pages.insert(code, path);
+            }
+            _ => problems.push(format!("{}error_pages: {:?} is not a 4xx or 5xx status code", prefix, status)),
+        }
+    }
+
+    for (i, raw) in routes.into_iter().enumerate() {
+        let context = format!("{}route[{}]", prefix, i);
+        let path = match raw.path {
+            Some(path) if path.starts_with('/') => path,
+            Some(path) => {
+                problems.push(format!("{}.path: {:?} must start with '/'", context, path));
+                continue;
+            }
+            None => {
+                problems.push(format!("{}.path: is required", context));
+                continue;
+            }
+        };
+        let default_methods: &[&str] = match (&raw.proxy, &raw.cgi) {
+            (Some(_), _) => &["*"],
+            (None, Some(_)) => &["GET", "POST"],
+            (None, None) => &["GET"],
+        };
+        let methods: Vec<String> = raw
+            .methods
+            .unwrap_or_else(|| default_methods.iter().map(|method| method.to_string()).collect())
+            .into_iter()
+            .map(|method| method.to_ascii_uppercase())
+            .collect();
+        let valid_method = |method: &String| method == "*" || (!method.is_empty() && method.chars().all(|c| c.is_ascii_alphabetic()));
+        if methods.is_empty() || !methods.iter().all(valid_method) {
+            problems.push(format!("{}.methods: {:?} is not a list of HTTP methods", context, methods));
+        }
+        if raw.strip_prefix && raw.proxy.is_none() {
+            problems.push(format!("{}.strip_prefix: only applies to proxy routes", context));
+        }
+        if raw.cgi.is_none() && (raw.timeout.is_some() || raw.max_output_bytes.is_some()) {
+            problems.push(format!("{}: timeout and max_output_bytes only apply to cgi routes", context));
+        }
+        let action = match (raw.file, raw.redirect, raw.proxy, raw.cgi) {
+            (Some(file), None, None, None) => {
+                RouteAction::File(require_file(&format!("{}.file", context), site.document_root.join(file), problems))
+            }
+            (None, Some(location), None, None) => {
+                let status = if raw.permanent { StatusCode::MOVED_PERMANENTLY } else { StatusCode::FOUND };
+                RouteAction::Redirect { location, status }
+            }
+            (None, None, Some(upstreams), None) => {
+                if upstreams.is_empty() {
+                    problems.push(format!("{}.proxy: at least one upstream is required", context));
+                }
+                let upstreams = upstreams
+                    .iter()
+                    .filter_map(|upstream| match upstream.to_socket_addrs().map(|mut addrs| addrs.next()) {
+                        Ok(Some(addr)) => Some(addr),
+                        _ => {
+                            problems.push(format!("{}.proxy: cannot resolve upstream {:?} (expected host:port)", context, upstream));
+                            None
+                        }
+                    })
+                    .collect();
+                let strip_prefix = raw.strip_prefix.then(|| path.trim_end_matches('*').trim_end_matches('/').to_string());
+                RouteAction::Proxy { upstreams, strip_prefix }
+            }
+            (None, None, None, Some(program)) => {
+                let program = resolve(base, Path::new(&program));
+                if !cgi::is_executable(&program) {
+                    problems.push(format!("{}.cgi: {} is not an executable file", context, program.display()));
+                }
+                let timeout = duration(&format!("{}.timeout", context), raw.timeout, cgi::DEFAULT_TIMEOUT, problems);
+                let max_output = raw.max_output_bytes.unwrap_or(cgi::DEFAULT_MAX_OUTPUT as i64);
+                if max_output <= 0 {
+                    problems.push(format!("{}.max_output_bytes: must be positive, got {}", context, max_output));
+                }
+                RouteAction::Cgi { program, timeout, max_output: max_output.max(0) as u64 }
+            }
+            _ => {
+                problems.push(format!("{}: exactly one of file, redirect, proxy or cgi is required", context));
+                continue;
+            }
+        };
+        site.routes.push(RouteConfig { methods, path, action });
+    }
+    site
+}
+
+fn duration(field: &str, value: Option<RawDuration>, default: Duration, problems: &mut Vec<String>) -> Duration {
+    let parsed = match value {
+        None => return default,
+        Some(RawDuration::Seconds(secs)) if secs > 0 => Ok(Duration::from_secs(secs as u64)),
+        Some(RawDuration::Seconds(secs)) => Err(format!("must be positive, got {}", secs)),
+        Some(RawDuration::Text(text)) => parse_duration(&text),
+    };
+    parsed.unwrap_or_else(|message| {
+        problems.push(format!("{}: {}", field, message));
+        default
+    })
+}
+
+/// 解析带单位的时长，支持 `ms`、`s`、`m`、`h`，没有单位时按秒处理。
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("{:?} is not a valid duration (expected e.g. 500ms, 5s, 2m)", text))?;
+    let seconds = |scale: u64| number.checked_mul(scale).map(Duration::from_secs);
+    let duration = match unit.trim() {
+        "ms" => Some(Duration::from_millis(number)),
+        "" | "s" => Some(Duration::from_secs(number)),
+        "m" => seconds(60),
+        "h" => seconds(3600),
+        _ => return Err(format!("{:?} has an unknown unit (expected ms, s, m or h)", text)),
+    };
+    let duration = duration.ok_or_else(|| format!("{:?}: duration is too large", text))?;
+    if duration.is_zero() {
+        return Err(format!("{:?} must be positive", text));
+    }
+    Ok(duration)
+}
+
+// 配置文件中的相对路径相对于配置文件所在目录
+fn resolve(base: &Path, path: &Path) -> PathBuf {
+    if path.is_absolute() || base.as_os_str().is_empty() {
+        path.to_path_buf()
+    } else {
+        base.join(path)
+    }
+}
+
+fn base_dir(config_path: &Path) -> &Path {
+    config_path.parent().unwrap_or(Path::new(""))
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 创建一个包含首页与404页面的临时站点目录
+    fn site_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("server-optimize-config-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("index.html"), "index").unwrap();
+        fs::write(dir.join("404.html"), "not found").unwrap();
+        dir
+    }
+
+    #[test]
+    fn parse_full_config() {
+        let dir = site_dir("full");
+        fs::create_dir_all(dir.join("blog")).unwrap();
+        fs::write(dir.join("blog/home.html"), "blog").unwrap();
+        fs::write(dir.join("blog/404.html"), "blog not found").unwrap();
+
+        let config = Config::parse(
+            r#"
+            listen = ["0.0.0.0:8080", "[::1]:8080"]
+            workers = 8
+            mode = "blocking"
+            document_root = "."
+
+            [timeouts]
+            keep_alive = "500ms"
+            write = 10
+
+            [limits]
+            max_header_bytes = 4096
+            max_body_bytes = 65536
+            max_connections = 0
+            max_connections_per_ip = 16
+            rate = 20
+
+            [log]
+            access = "off"
+
+            [[virtual_host]]
+            names = ["Blog.Example.com", "www.blog.example.com"]
+            document_root = "blog"
+            index = "home.html"
+
+            [virtual_host.error_pages]
+            500 = "home.html"
+
+            [[virtual_host.route]]
+            path = "/feed/*"
+            redirect = "https://feeds.example.com/blog"
+            permanent = true
+            "#,
+            &dir,
+        )
+        .unwrap();
+
+        assert_eq!(config.listen, vec!["0.0.0.0:8080".parse().unwrap(), "[::1]:8080".parse().unwrap()]);
+        assert_eq!(config.workers, 8);
+        assert_eq!(config.site.index_path(), dir.join(".").join("index.html"));
+        assert_eq!(config.options.keep_alive_timeout, Duration::from_millis(500));
+        assert_eq!(config.options.write_timeout, Duration::from_secs(10));
+        assert_eq!(config.options.limits, Limits { max_head_bytes: 4096, max_body_bytes: 65536 });
+        assert_eq!(
+            config.limiter,
+            Policy {
+                rate: Some(Rate { per_second: 20, burst: 20 }),
+                max_connections: None,
+                max_connections_per_ip: Some(16),
+                max_queue: Some(DEFAULT_MAX_QUEUE as usize),
+            }
+        );
+        assert_eq!(config.access_log, None);
+        assert_eq!(config.virtual_hosts.len(), 1);
+        let host = &config.virtual_hosts[0];
+        assert_eq!(host.names, vec!["blog.example.com", "www.blog.example.com"]);
+        assert_eq!(host.site.index_path(), dir.join("blog/home.html"));
+        // 未配置的404页面沿用全局配置
+        assert_eq!(host.site.error_page(StatusCode::NOT_FOUND), Some(dir.join(".").join("404.html").as_path()));
+        assert_eq!(host.site.error_page(StatusCode::INTERNAL_SERVER_ERROR), Some(dir.join("blog/home.html").as_path()));
+        assert_eq!(
+            host.site.routes,
+            vec![RouteConfig {
+                methods: vec!["GET".into()],
+                path: "/feed/*".into(),
+                action: RouteAction::Redirect {
+                    location: "https://feeds.example.com/blog".into(),
+                    status: StatusCode::MOVED_PERMANENTLY,
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn report_every_problem() {
+        let dir = site_dir("invalid");
+        let err = Config::parse(
+            r#"
+            listen = ["localhost", "127.0.0.1:1", "127.0.0.1:1"]
+            workers = 0
+            mode = "threads"
+
+            [timeouts]
+            keep_alive = "5 parsecs"
+
+            [error_pages]
+            404 = "index.html"
+            200 = "index.html"
+
+            [[route]]
+            path = "about"
+            file = "index.html"
+
+            [[route]]
+            path = "/cgi"
+            cgi = "index.html"
+            max_output_bytes = 0
+
+            [[route]]
+            path = "/home"
+            file = "index.html"
+            timeout = "1s"
+
+            [[virtual_host]]
+            names = ["a.example.com", "A.example.com", "*.*.example.com"]
+
+            [tls]
+            listen = ["127.0.0.1:1"]
+            cert = "missing.pem"
+            "#,
+            &dir,
+        )
+        .unwrap_err();
+
+        let problems = match err {
+            ConfigError::Invalid(problems) => problems,
+            other => panic!("unexpected error: {}", other),
+        };
+        let expected = [
+            "listen: \"localhost\"",
+            "listen: 127.0.0.1:1 is listed more than once",
+            "workers:",
+            "mode:",
+            "error_pages: \"200\"",
+            "route[0].path:",
+            "route[1].cgi:",
+            "route[1].max_output_bytes:",
+            "route[2]: timeout and max_output_bytes only apply to cgi routes",
+            "timeouts.keep_alive:",
+            "tls.listen: 127.0.0.1:1",
+            "tls.cert:",
+            "tls.key:",
+            "virtual_host[0].names: host \"a.example.com\" is already used",
+            "virtual_host[0].names: \"*.*.example.com\"",
+            "virtual_host[0].document_root:",
+        ];
+        assert_eq!(problems.len(), expected.len(), "{:#?}", problems);
+        for (problem, prefix) in problems.iter().zip(expected) {
+            assert!(problem.starts_with(prefix), "{:?} should start with {:?}", problem, prefix);
+        }
+    }
+
+    #[test]
+    fn reject_unknown_keys() {
+        let err = Config::parse("listen = [\"127.0.0.1:7878\"]\nworker = 4\n", Path::new(".")).unwrap_err();
+        assert!(matches!(err, ConfigError::Parse(..)));
+        assert!(err.to_string().contains("worker"), "{}", err);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("5"), Ok(Duration::from_secs(5)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("soon").is_err());
+        assert_eq!(parse_duration("99999999999999999h"), Err(String::from("\"99999999999999999h\": duration is too large")));
+    }
+
+    #[test]
+    fn changes_requiring_restart() {
+        let config = Config::default();
+        let mut reloaded = config.clone();
+        reloaded.site.document_root = PathBuf::from("/srv/www");
+        reloaded.access_log = None;
+        assert!(config.restart_required(&reloaded).is_empty());
+
+        reloaded.workers += 1;
+        reloaded.options.keep_alive_timeout = Duration::from_secs(1);
+        assert_eq!(config.restart_required(&reloaded), ["workers", "timeouts/limits"]);
+    }
+}
@@ -0,0 +1,267 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use super::{base_dir, Config, ConfigError, RawConfig, RawDuration, RawTls, DEFAULT_CONFIG_FILE};
+
+pub const USAGE: &str = "\
+usage: server-optimize [options] [blocking|event]
+
+options:
+  -c, --config <file>             read configuration from <file> (default: ./server.toml if present)
+  -l, --listen <addr>             HTTP listen address, repeatable, e.g. 0.0.0.0:8080 or [::]:8080
+  -w, --workers <n>               worker thread count
+      --mode <blocking|event>     connection handling mode
+      --root <dir>                document root
+      --index <file>              index page, relative to the document root
+      --not-found <file>          404 page, relative to the document root
+      --access-log <file|off>     access log path, or off to disable it
+      --keep-alive-timeout <dur>  idle keep-alive timeout, e.g. 5s or 500ms
+      --write-timeout <dur>       response write timeout
+      --max-header-bytes <n>      maximum size of the request line and headers
+      --max-body-bytes <n>        maximum size of a request body
+      --max-connections <n>       maximum open connections, 0 for no limit
+      --max-connections-per-ip <n>
+                                  maximum open connections per client address
+      --max-queue <n>             maximum connections waiting for a worker, beyond that reply 503
+      --rate-limit <n>            requests per second per client address, beyond that reply 429
+      --rate-burst <n>            requests a client may burst above the rate (default: the rate)
+      --tls-listen <addr>         HTTPS listen address, repeatable
+      --tls-cert <file>           PEM certificate chain, enables HTTPS
+      --tls-key <file>            PEM private key
+      --tls <cert> <key>          shorthand for --tls-cert <cert> --tls-key <key>
+      --check                     validate the configuration and exit
+  -h, --help                      print this help
+
+options accept both `--flag value` and `--flag=value`; command-line values override the file.";
+
+/*
+    命令行解析结果
+ */
+#[derive(Debug)]
+pub enum Command {
+    // 按照配置启动服务
+    Run(Config),
+    // 只校验配置
+    Check(Config),
+    Help,
+}
+
+/*
+    命令行中出现的覆盖项，路径已经转换为相对于当前目录的绝对路径
+ */
+#[derive(Debug, Default)]
+struct Overrides {
+    config: Option<PathBuf>,
+    listen: Vec<String>,
+    workers: Option<i64>,
+    mode: Option<String>,
+    root: Option<PathBuf>,
+    index: Option<String>,
+    not_found: Option<String>,
+    access_log: Option<String>,
+    keep_alive_timeout: Option<String>,
+    write_timeout: Option<String>,
+    max_header_bytes: Option<i64>,
+    max_body_bytes: Option<i64>,
+    max_connections: Option<i64>,
+    max_connections_per_ip: Option<i64>,
+    max_queue: Option<i64>,
+    rate_limit: Option<i64>,
+    rate_burst: Option<i64>,
+    tls_listen: Vec<String>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    check: bool,
+}
+
+/// 解析命令行参数（不包含程序名），加载配置文件并应用命令行覆盖项，最后校验合并后的配置。
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, ConfigError> {
+    let mut overrides = Overrides::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // --flag=value 与 --flag value 两种形式
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |name: &str| -> Result<String, ConfigError> {
+            match inline.clone().or_else(|| args.next()) {
+                Some(value) => Ok(value),
+                None => Err(ConfigError::Usage(format!("{} requires a value", name))),
+            }
+        };
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--check" => overrides.check = true,
+            "-c" | "--config" => overrides.config = Some(PathBuf::from(value(&flag)?)),
+            "-l" | "--listen" => overrides.listen.push(value(&flag)?),
+            "-w" | "--workers" => overrides.workers = Some(integer(&flag, &value(&flag)?)?),
+            "--mode" => overrides.mode = Some(value(&flag)?),
+            "--root" => overrides.root = Some(absolute(value(&flag)?)),
+            "--index" => overrides.index = Some(value(&flag)?),
+            "--not-found" => overrides.not_found = Some(value(&flag)?),
+            "--access-log" => {
+                let log = value(&flag)?;
+                overrides.access_log = Some(if log == "off" { log } else { absolute(log).to_string_lossy().into_owned() });
+            }
+            "--keep-alive-timeout" => overrides.keep_alive_timeout = Some(value(&flag)?),
+            "--write-timeout" => overrides.write_timeout = Some(value(&flag)?),
+            "--max-header-bytes" => overrides.max_header_bytes = Some(integer(&flag, &value(&flag)?)?),
+            "--max-body-bytes" => overrides.max_body_bytes = Some(integer(&flag, &value(&flag)?)?),
+            "--max-connections" => overrides.max_connections = Some(integer(&flag, &value(&flag)?)?),
+            "--max-connections-per-ip" => overrides.max_connections_per_ip = Some(integer(&flag, &value(&flag)?)?),
+            "--max-queue" => overrides.max_queue = Some(integer(&flag, &value(&flag)?)?),
+            "--rate-limit" => overrides.rate_limit = Some(integer(&flag, &value(&flag)?)?),
+            "--rate-burst" => overrides.rate_burst = Some(integer(&flag, &value(&flag)?)?),
+            "--tls-listen" => overrides.tls_listen.push(value(&flag)?),
+            "--tls-cert" => overrides.tls_cert = Some(absolute(value(&flag)?)),
+            "--tls-key" => overrides.tls_key = Some(absolute(value(&flag)?)),
+            "--tls" => {
+                overrides.tls_cert = Some(absolute(value(&flag)?));
+                let key = args.next().ok_or_else(|| ConfigError::Usage("--tls requires <cert> <key>".to_string()))?;
+                overrides.tls_key = Some(absolute(key));
+            }
+            // 兼容旧的位置参数写法：server-optimize event
+            "blocking" | "event" => overrides.mode = Some(flag),
+            other => return Err(ConfigError::Usage(format!("unknown argument: {}", other))),
+        }
+    }
+
+    let check = overrides.check;
+    let config = overrides.load()?;
+    Ok(if check { Command::Check(config) } else { Command::Run(config) })
+}
+
+impl Overrides {
+    fn load(self) -> Result<Config, ConfigError> {
+        // 显式指定的配置文件必须存在，默认配置文件不存在时使用内置默认值
+        let path = match self.config.clone() {
+            Some(path) => Some(path),
+            None => Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.is_file()),
+        };
+        let (mut raw, base) = match &path {
+            Some(path) => (RawConfig::read(path)?, base_dir(path).to_path_buf()),
+            None => (RawConfig::default(), PathBuf::new()),
+        };
+        self.apply(&mut raw);
+        raw.validate(&base)
+    }
+
+    fn apply(self, raw: &mut RawConfig) {
+        if !self.listen.is_empty() {
+            raw.listen = Some(self.listen);
+        }
+        set(&mut raw.workers, self.workers);
+        set(&mut raw.mode, self.mode);
+        set(&mut raw.document_root, self.root);
+        set(&mut raw.index, self.index);
+        set(&mut raw.not_found, self.not_found);
+        set(&mut raw.log.access, self.access_log);
+        set(&mut raw.timeouts.keep_alive, self.keep_alive_timeout.map(RawDuration::Text));
+        set(&mut raw.timeouts.write, self.write_timeout.map(RawDuration::Text));
+        set(&mut raw.limits.max_header_bytes, self.max_header_bytes);
+        set(&mut raw.limits.max_body_bytes, self.max_body_bytes);
+        set(&mut raw.limits.max_connections, self.max_connections);
+        set(&mut raw.limits.max_connections_per_ip, self.max_connections_per_ip);
+        set(&mut raw.limits.max_queue, self.max_queue);
+        set(&mut raw.limits.rate, self.rate_limit);
+        set(&mut raw.limits.burst, self.rate_burst);
+
+        // 任意一个TLS选项都会启用HTTPS，缺少的证书或私钥会在校验时报告
+        if !self.tls_listen.is_empty() || self.tls_cert.is_some() || self.tls_key.is_some() {
+            let tls = raw.tls.get_or_insert_with(RawTls::default);
+            if !self.tls_listen.is_empty() {
+                tls.listen = Some(self.tls_listen);
+            }
+            set(&mut tls.cert, self.tls_cert);
+            set(&mut tls.key, self.tls_key);
+        }
+    }
+}
+
+fn set<T>(target: &mut Option<T>, value: Option<T>) {
+    if value.is_some() {
+        *target = value;
+    }
+}
+
+fn integer(flag: &str, value: &str) -> Result<i64, ConfigError> {
+    value
+        .parse()
+        .map_err(|_| ConfigError::Usage(format!("{} expects an integer, got {:?}", flag, value)))
+}
+
+// 命令行中的相对路径相对于当前目录，而不是配置文件所在目录
+fn absolute(path: String) -> PathBuf {
+    let path = Path::new(&path);
+    match env::current_dir() {
+        Ok(cwd) if path.is_relative() => cwd.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::config::Mode;
+    use crate::limiter::Rate;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn command_line_overrides_file() {
+        let dir = env::temp_dir().join(format!("server-optimize-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("index.html"), "index").unwrap();
+        fs::write(dir.join("404.html"), "not found").unwrap();
+        let file = dir.join("server.toml");
+        fs::write(&file, "listen = [\"127.0.0.1:9000\"]\nworkers = 2\n[log]\naccess = \"off\"\n").unwrap();
+
+        let command = parse_args(args(&[
+            "--config",
+            file.to_str().unwrap(),
+            "--workers=16",
+            "-l",
+            "[::1]:9001",
+            "--listen=0.0.0.0:9002",
+            "--keep-alive-timeout",
+            "250ms",
+            "--rate-limit=5",
+            "--max-queue",
+            "0",
+            "event",
+            "--check",
+        ]))
+        .unwrap();
+        let config = match command {
+            Command::Check(config) => config,
+            other => panic!("unexpected command: {:?}", other),
+        };
+        assert_eq!(config.listen, vec!["[::1]:9001".parse().unwrap(), "0.0.0.0:9002".parse().unwrap()]);
+        assert_eq!(config.workers, 16);
+        assert_eq!(config.mode, Mode::Event);
+        assert_eq!(config.options.keep_alive_timeout, Duration::from_millis(250));
+        assert_eq!(config.limiter.rate, Some(Rate { per_second: 5, burst: 5 }));
+        assert_eq!(config.limiter.max_queue, None);
+        // 文件中的document_root默认值相对于配置文件所在目录
+        assert_eq!(config.site.index_path(), dir.join(".").join("index.html"));
+        assert_eq!(config.access_log, None);
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert!(matches!(parse_args(args(&["--help"])), Ok(Command::Help)));
+        assert!(matches!(parse_args(args(&["--bogus"])), Err(ConfigError::Usage(_))));
+        assert!(matches!(parse_args(args(&["--workers"])), Err(ConfigError::Usage(_))));
+        assert!(matches!(parse_args(args(&["--workers=many"])), Err(ConfigError::Usage(_))));
+        assert!(matches!(parse_args(args(&["--config", "/nonexistent/server.toml"])), Err(ConfigError::Io(..))));
+    }
+}
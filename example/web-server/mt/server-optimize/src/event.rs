@@ -0,0 +1,64 @@
+use std::fmt;
+use std::io;
+
+use crate::JobPanic;
+
+/*
+    线程池事件
+
+    通过 `Builder::event_listener` 接收，在产生事件的线程中同步回调，监听器应当尽快返回；
+    没有设置监听器时线程池不输出任何日志。Display输出一行日志，例如：
+        [Worker(id = 0)] startup
+        [Worker(id = 0)] was told to terminate!
+ */
+#[derive(Debug)]
+pub enum PoolEvent<'a> {
+    // Worker线程启动（包括按需增加的线程以及替换panic线程的新线程）
+    WorkerStarted { worker: usize },
+    // Worker线程退出
+    WorkerStopped { worker: usize, reason: StopReason },
+    // 任务panic，执行该任务的线程随后被替换（在 `join` 中等待时帮忙执行的任务除外）
+    JobPanicked(&'a JobPanic),
+    // 启动Worker线程失败
+    SpawnFailed { worker: usize, error: &'a io::Error },
+    // 线程启动与退出的回调或者panic处理函数本身panic
+    CallbackPanicked { worker: usize, callback: &'static str },
+    // 开始关闭，`now` 为true时丢弃队列中尚未执行的任务
+    ShutdownStarted { now: bool },
+    // 关闭之后最后一个Worker退出
+    Terminated,
+}
+
+/*
+    Worker线程退出的原因
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    // 线程池关闭
+    Terminated,
+    // 空闲超过存活时间，并且线程数多于核心线程数
+    Idle,
+    // 任务panic，由新的线程替换
+    Panicked,
+}
+
+impl fmt::Display for PoolEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolEvent::WorkerStarted { worker } => write!(f, "[Worker(id = {})] startup", worker),
+            PoolEvent::WorkerStopped { worker, reason: StopReason::Terminated } => {
+                write!(f, "[Worker(id = {})] was told to terminate!", worker)
+            }
+            PoolEvent::WorkerStopped { worker, reason: StopReason::Idle } => write!(f, "[Worker(id = {})] idle, retiring", worker),
+            PoolEvent::WorkerStopped { worker, reason: StopReason::Panicked } => {
+                write!(f, "[Worker(id = {})] replaced after a panic", worker)
+            }
+            PoolEvent::JobPanicked(panic) => write!(f, "[Worker(id = {})] job panicked: {}", panic.worker, panic.message),
+            PoolEvent::SpawnFailed { worker, error } => write!(f, "[Worker(id = {})] cannot spawn thread: {}", worker, error),
+            PoolEvent::CallbackPanicked { worker, callback } => write!(f, "[Worker(id = {})] {} panicked", worker, callback),
+            PoolEvent::ShutdownStarted { now: false } => f.write_str("[ThreadPool] shutting down, draining the queue"),
+            PoolEvent::ShutdownStarted { now: true } => f.write_str("[ThreadPool] shutting down now, discarding the queue"),
+            PoolEvent::Terminated => f.write_str("[ThreadPool] terminated"),
+        }
+    }
+}
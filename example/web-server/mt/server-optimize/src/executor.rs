@@ -0,0 +1,361 @@
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::Instant;
+
+use crate::task::{self, JoinError, Promise, TaskHandle};
+use crate::timer::ScheduledHandle;
+use crate::{Job, ThreadPool, WorkerContext};
+
+// 异步任务的状态
+const IDLE: u8 = 0;
+const SCHEDULED: u8 = 1;
+const RUNNING: u8 = 2;
+const NOTIFIED: u8 = 3;
+const DONE: u8 = 4;
+
+/*
+    异步任务
+
+    在Worker中轮询，返回Pending之后不再占用Worker；被唤醒时作为一条新的任务消息重新放入队列，
+    之后可能由另一个Worker继续轮询。状态变化：
+        IDLE -> SCHEDULED（已经在队列中）-> RUNNING（轮询中）-> IDLE 或者 DONE
+    轮询期间被唤醒时记为NOTIFIED，轮询结束后重新入队；队列中同一个任务最多只有一条消息
+ */
+struct FutureTask<F: Future> {
+    state: AtomicU8,
+    // 完成、panic或者被取消之后立即释放
+    future: Mutex<Option<Pin<Box<F>>>>,
+    promise: Promise<F::Output>,
+    // 线程池已经释放时唤醒不再有任何效果
+    context: Weak<WorkerContext>,
+}
+
+impl<F> FutureTask<F>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    fn run(self: Arc<Self>) {
+        self.state.store(RUNNING, Ordering::SeqCst);
+        let mut future = lock(&self.future);
+        let polled = match future.as_mut() {
+            Some(_) if !self.promise.start() => Ok(Poll::Ready(None)),
+            Some(inner) => {
+                let waker = Waker::from(Arc::clone(&self));
+                panic::catch_unwind(AssertUnwindSafe(|| inner.as_mut().poll(&mut Context::from_waker(&waker)).map(Some)))
+            }
+            None => Ok(Poll::Ready(None)),
+        };
+        match polled {
+            Ok(Poll::Pending) => {
+                drop(future);
+                // 轮询期间被唤醒过，必须再轮询一次
+                if self.state.compare_exchange(RUNNING, IDLE, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+                    self.state.store(SCHEDULED, Ordering::SeqCst);
+                    self.schedule();
+                }
+            }
+            // 句柄已经取消了任务时结果为None，Promise不会覆盖已有的结果
+            Ok(Poll::Ready(output)) => {
+                let finished = future.take();
+                drop(future);
+                self.finish(finished, output.map(Ok));
+            }
+            Err(payload) => {
+                let finished = future.take();
+                drop(future);
+                self.finish(finished, Some(Err(JoinError::Panicked(payload))));
+            }
+        }
+    }
+
+    // 释放已经结束的Future并通知句柄；Future在锁外释放，它的析构可能再次唤醒任务
+    fn finish(&self, future: Option<Pin<Box<F>>>, result: Option<Result<F::Output, JoinError>>) {
+        self.state.store(DONE, Ordering::SeqCst);
+        drop(future);
+        self.promise.complete(result.unwrap_or(Err(JoinError::Cancelled)));
+    }
+
+    // 交给线程池轮询
+    fn schedule(self: Arc<Self>) {
+        if let Some(context) = self.context.upgrade() {
+            context.enqueue(Scheduled::job(self));
+        }
+    }
+}
+
+impl<F> Wake for FutureTask<F>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    fn wake(self: Arc<Self>) {
+        let mut state = self.state.load(Ordering::SeqCst);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                // 已经在队列中、已经记下唤醒或者已经结束
+                _ => return,
+            };
+            match self.state.compare_exchange(state, next, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => break,
+                Err(actual) => state = actual,
+            }
+        }
+        if state == IDLE {
+            self.schedule();
+        }
+    }
+}
+
+// 队列中的一次轮询；没有执行就被丢弃时（例如 `shutdown_now`）结束任务，句柄得到 `JoinError::Cancelled`
+struct Scheduled<F>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    task: Option<Arc<FutureTask<F>>>,
+}
+
+impl<F> Scheduled<F>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    fn job(task: Arc<FutureTask<F>>) -> Job {
+        let mut scheduled = Scheduled { task: Some(task) };
+        Box::new(move || scheduled.task.take().unwrap().run())
+    }
+}
+
+impl<F> Drop for Scheduled<F>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            let future = lock(&task.future).take();
+            task.finish(future, None);
+        }
+    }
+}
+
+pub(crate) fn spawn<F>(pool: &ThreadPool, future: F) -> TaskHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (promise, handle) = task::promise();
+    let task = Arc::new(FutureTask {
+        state: AtomicU8::new(SCHEDULED),
+        future: Mutex::new(Some(Box::pin(future))),
+        promise,
+        context: Arc::downgrade(&pool.context),
+    });
+    // 第一次轮询与普通任务一样按照拒绝策略提交，被拒绝时句柄得到 `JoinError::Cancelled`
+    pool.execute_job(Scheduled::job(task));
+    handle
+}
+
+/// 在当前线程中运行 `future` 直到完成，等待期间阻塞当前线程。
+///
+/// 用于在线程池外部（例如main函数中）等待异步任务；在Worker中调用会占用该Worker直到完成。
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let unparker = Arc::new(Unparker { thread: thread::current(), notified: AtomicBool::new(false) });
+    let waker = Waker::from(Arc::clone(&unparker));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        // park可能被虚假唤醒，以通知标志为准
+        while !unparker.notified.swap(false, Ordering::SeqCst) {
+            thread::park();
+        }
+    }
+}
+
+// block_on的waker：唤醒阻塞中的线程
+struct Unparker {
+    thread: Thread,
+    notified: AtomicBool,
+}
+
+impl Wake for Unparker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.notified.store(true, Ordering::SeqCst);
+        self.thread.unpark();
+    }
+}
+
+/*
+    异步定时器
+
+    由 `ThreadPool::sleep` 或者 `ThreadPool::sleep_until` 创建，创建时就在线程池的定时器线程中登记，
+    到期时定时器线程唤醒等待它的任务，等待期间不占用Worker；线程池关闭后尚未到期的定时器不再唤醒
+ */
+pub struct Sleep {
+    deadline: Instant,
+    shared: Arc<SleepShared>,
+    handle: ScheduledHandle,
+}
+
+struct SleepShared {
+    fired: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    pub fn is_elapsed(&self) -> bool {
+        self.shared.fired.load(Ordering::SeqCst) || Instant::now() >= self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_elapsed() {
+            return Poll::Ready(());
+        }
+        *lock(&self.shared.waker) = Some(cx.waker().clone());
+        // 登记waker之后再检查一次，定时器可能恰好在两者之间到期
+        if self.shared.fired.load(Ordering::SeqCst) {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+// 不再等待时取消定时器中的登记
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.handle.cancel();
+    }
+}
+
+pub(crate) fn sleep_until(pool: &ThreadPool, deadline: Instant) -> Sleep {
+    let shared = Arc::new(SleepShared { fired: AtomicBool::new(false), waker: Mutex::new(None) });
+    let handle = {
+        let shared = Arc::clone(&shared);
+        pool.timer().call(
+            deadline,
+            Box::new(move || {
+                shared.fired.store(true, Ordering::SeqCst);
+                let waker = lock(&shared.waker).take();
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }),
+        )
+    };
+    Sleep { deadline, shared, handle }
+}
+
+// 锁内只做整体替换，锁中毒时数据仍然是完整的
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc};
+    use std::time::{Duration, Instant};
+
+    use super::block_on;
+    use crate::ThreadPool;
+
+    #[test]
+    fn spawn_futures_and_join() {
+        let pool = ThreadPool::new(2);
+        assert_eq!(pool.spawn_future(async { 6 * 7 }).join().unwrap(), 42);
+
+        let err = pool.spawn_future(async { panic!("boom") }).join().unwrap_err();
+        assert_eq!(err.to_string(), "task panicked: boom");
+        assert_eq!(pool.monitor().stats().panicked, 0);
+    }
+
+    #[test]
+    fn await_other_tasks_on_one_worker() {
+        // 只有一个Worker：等待中的任务如果阻塞了Worker，它等待的任务永远不会执行
+        let pool = Arc::new(ThreadPool::new(1));
+        let inner = Arc::clone(&pool);
+        let outer = pool.spawn_future(async move {
+            let first = inner.spawn_future(async { 1 });
+            let second = inner.spawn_future(async { 2 });
+            first.await.unwrap() + second.await.unwrap()
+        });
+        let (sender, result) = mpsc::channel();
+        std::thread::spawn(move || sender.send(outer.join().unwrap()).unwrap());
+        assert_eq!(result.recv_timeout(Duration::from_secs(10)).unwrap(), 3);
+    }
+
+    #[test]
+    fn sleep_without_blocking_workers() {
+        let pool = Arc::new(ThreadPool::new(1));
+        let start = Instant::now();
+        let handles: Vec<_> = (0..5u64)
+            .map(|n| {
+                let inner = Arc::clone(&pool);
+                pool.spawn_future(async move {
+                    inner.sleep(Duration::from_millis(50)).await;
+                    n
+                })
+            })
+            .collect();
+        let sum = block_on(async {
+            let mut sum = 0;
+            for handle in handles {
+                sum += handle.await.unwrap();
+            }
+            sum
+        });
+        assert_eq!(sum, 10);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(50));
+        // 五个任务在同一个Worker上同时等待，而不是依次睡眠
+        assert!(elapsed < Duration::from_millis(250), "sleeps ran one after another: {:?}", elapsed);
+    }
+
+    #[test]
+    fn cancel_futures_before_first_poll() {
+        let pool = ThreadPool::new(1);
+        let (gate, blocked) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = blocked.recv();
+        });
+        let handle = pool.spawn_future(async { 1 });
+        assert!(handle.cancel());
+        drop(gate);
+        assert!(handle.join().unwrap_err().is_cancelled());
+
+        // 丢弃队列中的异步任务时句柄同样得到Cancelled
+        let (gate, blocked) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = blocked.recv();
+        });
+        let handle = pool.spawn_future(async { 1 });
+        drop(pool.shutdown_now());
+        drop(gate);
+        assert!(handle.join().unwrap_err().is_cancelled());
+    }
+}
@@ -0,0 +1,360 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use crate::http::{ParseError, Request, Response, StatusCode};
+
+pub mod multipart;
+
+pub use multipart::UploadedFile;
+
+// 默认的字段数量上限（文件不计入）
+pub const MAX_FIELDS: usize = 1000;
+// 默认的单个字段值的最大字节数
+pub const MAX_FIELD_BYTES: usize = 64 * 1024;
+// 默认的文件数量上限
+pub const MAX_FILES: usize = 16;
+// 默认的单个文件的最大字节数
+pub const MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+// 默认的multipart请求体的最大字节数
+pub const MAX_TOTAL_BYTES: u64 = 64 * 1024 * 1024;
+
+/*
+    表单大小限制
+
+    urlencoded请求体整个读入内存，受 `http::Limits::max_body_bytes` 限制；
+    multipart请求体以流的方式读取，不受该限制，而是在读取过程中检查这里的各项限制
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_fields: usize,
+    pub max_field_bytes: usize,
+    pub max_files: usize,
+    pub max_file_bytes: u64,
+    // multipart请求体的总字节数
+    pub max_total_bytes: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_fields: MAX_FIELDS,
+            max_field_bytes: MAX_FIELD_BYTES,
+            max_files: MAX_FILES,
+            max_file_bytes: MAX_FILE_BYTES,
+            max_total_bytes: MAX_TOTAL_BYTES,
+        }
+    }
+}
+
+/*
+    解析后的表单
+
+    支持两种请求体：
+    - application/x-www-form-urlencoded：name=value&name=value，值经过百分号编码
+    - multipart/form-data：每个部分是一个字段或者一个上传的文件，文件以流的方式写入临时文件
+
+    同名字段可能出现多次，按照请求中的顺序保存；临时文件在 `UploadedFile` 被drop时删除，
+    需要保留的文件通过 `UploadedFile::persist` 移动到目标位置
+ */
+#[derive(Debug, Default)]
+pub struct Form {
+    fields: Vec<(String, String)>,
+    files: Vec<UploadedFile>,
+}
+
+impl Form {
+    /// 按照Content-Type解析请求体，使用默认的大小限制。
+    pub fn parse(request: &mut Request) -> Result<Form, FormError> {
+        Form::parse_with_limits(request, Limits::default())
+    }
+
+    /// 按照Content-Type解析请求体，字段或者文件超过 `limits` 时返回错误，已经写出的临时文件会被删除。
+    ///
+    /// 请求体被读取（multipart请求体被取走），之后 `request` 中不再有请求体。
+    pub fn parse_with_limits(request: &mut Request, limits: Limits) -> Result<Form, FormError> {
+        let content_type = request.header("Content-Type").unwrap_or_default().to_string();
+        let media_type = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        match media_type.as_str() {
+            "application/x-www-form-urlencoded" => {
+                let body = request.buffer_body().map_err(|err| match err {
+                    ParseError::BodyTooLarge => FormError::TooLarge("request body"),
+                    ParseError::Io(err) => FormError::from(err),
+                    _ => FormError::Malformed("invalid request body"),
+                })?;
+                let body = std::str::from_utf8(body).map_err(|_| FormError::Malformed("form body is not valid utf-8"))?;
+                Form::from_urlencoded(body, limits)
+            }
+            "multipart/form-data" => {
+                let boundary = parameter(&content_type, "boundary").ok_or(FormError::Malformed("missing multipart boundary"))?;
+                if request.body_length().is_some_and(|length| length > limits.max_total_bytes) {
+                    return Err(FormError::TooLarge("request body"));
+                }
+                multipart::parse(request.take_body(), &boundary, limits)
+            }
+            _ => Err(FormError::UnsupportedMediaType(content_type)),
+        }
+    }
+
+    /// 解析查询字符串，例如 `request.query` 中的 `a=1&b=2`。
+    pub fn from_query(query: &str) -> Result<Form, FormError> {
+        Form::from_urlencoded(query, Limits::default())
+    }
+
+    fn from_urlencoded(input: &str, limits: Limits) -> Result<Form, FormError> {
+        let mut form = Form::default();
+        for pair in input.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            if value.len() > limits.max_field_bytes {
+                return Err(FormError::TooLarge("form field"));
+            }
+            form.push_field(decode(name)?, decode(value)?, limits)?;
+        }
+        Ok(form)
+    }
+
+    fn push_field(&mut self, name: String, value: String, limits: Limits) -> Result<(), FormError> {
+        if self.fields.len() == limits.max_fields {
+            return Err(FormError::TooLarge("number of form fields"));
+        }
+        self.fields.push((name, value));
+        Ok(())
+    }
+
+    /// 获取第一个同名字段的值。
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    /// 获取所有同名字段的值，例如多选框。
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields.iter().filter(move |(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    /// 把字段值解析为指定类型，字段不存在或者无法解析时返回错误（状态码400）。
+    ///
+    /// ```
+    /// use server_optimize::form::Form;
+    ///
+    /// let form = Form::from_query("page=2&size=ten").unwrap();
+    /// assert_eq!(form.get_as::<u32>("page").unwrap(), 2);
+    /// assert!(form.get_as::<u32>("size").is_err());
+    /// assert!(form.get_as::<u32>("missing").is_err());
+    /// ```
+    pub fn get_as<T: FromStr>(&self, name: &str) -> Result<T, FormError> {
+        let value = self.get(name).ok_or_else(|| FormError::MissingField(name.to_string()))?;
+        value
+            .trim()
+            .parse()
+            .map_err(|_| FormError::InvalidField { name: name.to_string(), value: value.to_string() })
+    }
+
+    /// 按照请求中的顺序遍历所有字段（不包括文件）。
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// 获取第一个同名字段上传的文件。
+    pub fn file(&self, name: &str) -> Option<&UploadedFile> {
+        self.files.iter().find(|file| file.name() == name)
+    }
+
+    pub fn files(&self) -> &[UploadedFile] {
+        &self.files
+    }
+
+    /// 取出所有上传的文件，用于调用 `UploadedFile::persist` 保留文件。
+    pub fn into_files(self) -> Vec<UploadedFile> {
+        self.files
+    }
+}
+
+/// 解码 `application/x-www-form-urlencoded` 中的名称或者值：`+` 表示空格，`%XX` 表示一个字节。
+///
+/// 不完整的百分号序列原样保留，解码后的字节必须是有效的UTF-8。
+pub fn decode(input: &str) -> Result<String, FormError> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = |offset: usize| bytes.get(i + offset).and_then(|b| (*b as char).to_digit(16));
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match (hex(1), hex(2)) {
+                (Some(high), Some(low)) => {
+                    decoded.push((high * 16 + low) as u8);
+                    i += 2;
+                }
+                _ => decoded.push(b'%'),
+            },
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8(decoded).map_err(|_| FormError::Malformed("form field is not valid utf-8"))
+}
+
+/// 获取头部值中 `; name=value` 形式的参数，例如Content-Type中的boundary、Content-Disposition中的filename。
+///
+/// 参数名不区分大小写，带引号的值会去掉引号并处理反斜杠转义。
+pub fn parameter(header: &str, name: &str) -> Option<String> {
+    let mut chars = header.chars().peekable();
+    // 跳过第一个分号之前的主值
+    let mut quoted = false;
+    for c in chars.by_ref() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => break,
+            _ => {}
+        }
+    }
+    while chars.peek().is_some() {
+        let key: String = chars.by_ref().take_while(|c| *c != '=' && *c != ';').collect();
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    c => value.push(c),
+                }
+            }
+            // 跳过引号之后到下一个分号之间的内容
+            chars.by_ref().take_while(|c| *c != ';').for_each(drop);
+        } else {
+            value = chars.by_ref().take_while(|c| *c != ';').collect::<String>().trim().to_string();
+        }
+        if key.trim().eq_ignore_ascii_case(name) {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/*
+    表单解析错误
+ */
+#[derive(Debug)]
+pub enum FormError {
+    // Content-Type既不是urlencoded也不是multipart
+    UnsupportedMediaType(String),
+    // 请求体格式错误
+    Malformed(&'static str),
+    // 超过大小或者数量限制，参数为超出限制的对象
+    TooLarge(&'static str),
+    // 必需的字段不存在
+    MissingField(String),
+    // 字段值无法解析为需要的类型
+    InvalidField { name: String, value: String },
+    // 写入临时文件失败
+    Io(io::Error),
+}
+
+impl FormError {
+    /// 解析失败时返回给客户端的状态码。
+    pub fn status(&self) -> StatusCode {
+        match self {
+            FormError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            FormError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            FormError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// 返回给客户端的错误响应，服务端内部错误不暴露细节。
+    pub fn response(&self) -> Response {
+        match self {
+            FormError::Io(_) => Response::from(self.status()),
+            _ => Response::text(self.status(), self.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormError::UnsupportedMediaType(content_type) => write!(f, "unsupported content type: {:?}", content_type),
+            FormError::Malformed(reason) => write!(f, "malformed form: {}", reason),
+            FormError::TooLarge(what) => write!(f, "{} exceeds the limit", what),
+            FormError::MissingField(name) => write!(f, "missing field: {}", name),
+            FormError::InvalidField { name, value } => write!(f, "invalid value for field {}: {:?}", name, value),
+            FormError::Io(err) => write!(f, "io error: {}", err),
+        }
+    }
+}
+
+impl Error for FormError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FormError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// 请求体提前结束或者编码错误属于客户端的问题，其他IO错误（例如写入临时文件失败）属于服务端
+impl From<io::Error> for FormError {
+    fn from(err: io::Error) -> FormError {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => FormError::Malformed("unexpected end of request body"),
+            io::ErrorKind::InvalidData => FormError::Malformed("invalid request body"),
+            _ => FormError::Io(err),
+        }
+    }
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form_request(content_type: &str, body: &str) -> Request {
+        let mut request = Request::new("POST", "/submit");
+        request.headers.insert("Content-Type", content_type);
+        request.body = body.as_bytes().to_vec();
+        request
+    }
+
+    #[test]
+    fn parse_urlencoded_body() {
+        let mut request = form_request(
+            "application/x-www-form-urlencoded; charset=UTF-8",
+            "name=J%C3%B6rg+M&tag=a&tag=b&empty=&flag&bad=100%&age=42",
+        );
+        let form = Form::parse(&mut request).unwrap();
+        assert_eq!(form.get("name"), Some("Jörg M"));
+        assert_eq!(form.get_all("tag").collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!((form.get("empty"), form.get("flag")), (Some(""), Some("")));
+        assert_eq!(form.get("bad"), Some("100%"));
+        assert_eq!(form.get_as::<u8>("age").unwrap(), 42);
+        assert!(matches!(form.get_as::<u8>("name"), Err(FormError::InvalidField { .. })));
+        assert!(form.files().is_empty());
+
+        let mut request = form_request("text/plain", "a=1");
+        assert_eq!(Form::parse(&mut request).unwrap_err().status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let mut request = form_request("application/x-www-form-urlencoded", "a=%FF");
+        assert_eq!(Form::parse(&mut request).unwrap_err().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn enforce_field_limits() {
+        let limits = Limits { max_fields: 2, max_field_bytes: 4, ..Limits::default() };
+        let mut request = form_request("application/x-www-form-urlencoded", "a=1&b=2&c=3");
+        assert!(matches!(Form::parse_with_limits(&mut request, limits), Err(FormError::TooLarge(_))));
+        let mut request = form_request("application/x-www-form-urlencoded", "a=12345");
+        assert_eq!(Form::parse_with_limits(&mut request, limits).unwrap_err().status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn header_parameters() {
+        let header = "form-data; name=\"file\"; filename=\"a;b \\\"c\\\".txt\"";
+        assert_eq!(parameter(header, "name").as_deref(), Some("file"));
+        assert_eq!(parameter(header, "filename").as_deref(), Some("a;b \"c\".txt"));
+        assert_eq!(parameter("multipart/form-data; BOUNDARY=xyz ", "boundary").as_deref(), Some("xyz"));
+        assert_eq!(parameter("multipart/form-data", "boundary"), None);
+    }
+}
@@ -0,0 +1,395 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::{parameter, Form, FormError, Limits};
+
+// 每个部分的头部块的最大字节数
+const MAX_PART_HEAD: usize = 8 * 1024;
+// 每次从请求体读取的字节数
+const READ_SIZE: usize = 8 * 1024;
+
+// 临时文件名中的序号，同一进程内唯一
+static NEXT_TEMP_FILE: AtomicU64 = AtomicU64::new(0);
+
+/*
+    上传的文件
+
+    内容保存在系统临时目录下的临时文件中，drop时删除；调用 `persist` 后文件归调用方所有
+ */
+#[derive(Debug)]
+pub struct UploadedFile {
+    // 表单字段名
+    name: String,
+    // 客户端提供的文件名，只保留最后一段，去掉目录与控制字符
+    filename: Option<String>,
+    content_type: Option<String>,
+    size: u64,
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl UploadedFile {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 客户端提供的文件名，已经去掉了目录部分；仍然可能包含任意字符，写入文件系统前需要再做校验。
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// 文件当前所在的路径，`persist` 之前是临时文件。
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn open(&self) -> io::Result<File> {
+        File::open(&self.path)
+    }
+
+    /// 把临时文件移动到 `dest`，目标已经存在时返回 `AlreadyExists` 错误，不会覆盖已有文件。
+    pub fn persist<P: AsRef<Path>>(&mut self, dest: P) -> io::Result<()> {
+        let dest = dest.as_ref();
+        if self.persisted {
+            return Err(io::Error::other("upload has already been persisted"));
+        }
+        // 硬链接在目标存在时失败，不会覆盖；跨文件系统时退回到复制
+        match fs::hard_link(&self.path, dest) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => return Err(err),
+            Err(_) => {
+                let mut target = OpenOptions::new().write(true).create_new(true).open(dest)?;
+                if let Err(err) = io::copy(&mut self.open()?, &mut target) {
+                    let _ = fs::remove_file(dest);
+                    return Err(err);
+                }
+            }
+        }
+        let _ = fs::remove_file(&self.path);
+        self.path = dest.to_path_buf();
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+// 在系统临时目录下创建一个新的临时文件，仅当前用户可读写
+fn create_temp_file() -> io::Result<(PathBuf, File)> {
+    let dir = env::temp_dir();
+    loop {
+        let seq = NEXT_TEMP_FILE.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("server-optimize-upload-{}-{}", process::id(), seq));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            // 之前同一个pid的进程遗留的文件
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// 只保留文件名的最后一段，浏览器在Windows上可能发送完整路径
+fn sanitize_filename(filename: &str) -> Option<String> {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+    match name.trim() {
+        "" | "." | ".." => None,
+        name => Some(name.to_string()),
+    }
+}
+
+/*
+    按块读取的输入流，支持在流中查找分隔符
+ */
+struct Stream<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    eof: bool,
+    // 已经从reader读取的字节数，超过limit时停止读取
+    read: u64,
+    limit: u64,
+}
+
+impl<R: Read> Stream<R> {
+    // 再读取一块数据，已经到达末尾时返回false
+    fn fill(&mut self) -> Result<bool, FormError> {
+        if self.eof {
+            return Ok(false);
+        }
+        let start = self.buffer.len();
+        self.buffer.resize(start + READ_SIZE, 0);
+        let n = loop {
+            match self.reader.read(&mut self.buffer[start..]) {
+                Ok(n) => break n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.buffer.truncate(start);
+                    return Err(err.into());
+                }
+            }
+        };
+        self.buffer.truncate(start + n);
+        self.eof = n == 0;
+        self.read += n as u64;
+        if self.read > self.limit {
+            return Err(FormError::TooLarge("request body"));
+        }
+        Ok(n > 0)
+    }
+
+    // 流是否以prefix开头，是则消耗掉
+    fn consume_prefix(&mut self, prefix: &[u8]) -> Result<bool, FormError> {
+        while self.buffer.len() < prefix.len() && self.fill()? {}
+        let matched = self.buffer.starts_with(prefix);
+        if matched {
+            self.buffer.drain(..prefix.len());
+        }
+        Ok(matched)
+    }
+
+    // 把分隔符之前的数据分块交给sink并消耗掉分隔符，数据不会在内存中累积
+    fn read_until<F>(&mut self, delimiter: &[u8], mut sink: F) -> Result<(), FormError>
+    where
+        F: FnMut(&[u8]) -> Result<(), FormError>,
+    {
+        loop {
+            if let Some(index) = self.buffer.windows(delimiter.len()).position(|window| window == delimiter) {
+                sink(&self.buffer[..index])?;
+                self.buffer.drain(..index + delimiter.len());
+                return Ok(());
+            }
+            // 末尾不足一个分隔符长度的数据可能是分隔符的开头，留到下一轮
+            let safe = self.buffer.len().saturating_sub(delimiter.len() - 1);
+            if safe > 0 {
+                sink(&self.buffer[..safe])?;
+                self.buffer.drain(..safe);
+            }
+            if !self.fill()? {
+                return Err(FormError::Malformed("unexpected end of multipart body"));
+            }
+        }
+    }
+}
+
+/// 解析 `multipart/form-data` 请求体（RFC 7578）。
+///
+/// 请求体边读取边解析，文件部分按块写入临时文件，读取的总字节数超过 `limits.max_total_bytes` 时停止读取；
+/// 任何一个部分出错时已经创建的临时文件都会被删除；
+/// 文件名为空并且没有内容的部分（表单中未选择文件）会被忽略。
+pub(crate) fn parse<R: Read>(reader: R, boundary: &str, limits: Limits) -> Result<Form, FormError> {
+    if boundary.is_empty() || boundary.len() > 70 {
+        return Err(FormError::Malformed("invalid multipart boundary"));
+    }
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+    // 第一个分隔符前面没有CRLF，补上之后所有分隔符的形式一致
+    let mut stream = Stream { reader, buffer: b"\r\n".to_vec(), eof: false, read: 0, limit: limits.max_total_bytes };
+    let mut form = Form::default();
+
+    // 跳过第一个分隔符之前的前言
+    stream.read_until(&delimiter, |_| Ok(()))?;
+    // 结束分隔符多出 "--"，之后的尾声忽略
+    while !stream.consume_prefix(b"--")? {
+        // 分隔符所在行的剩余部分只允许空白
+        stream.read_until(b"\r\n", |padding| {
+            if padding.iter().all(|b| *b == b' ' || *b == b'\t') {
+                Ok(())
+            } else {
+                Err(FormError::Malformed("invalid multipart delimiter line"))
+            }
+        })?;
+        let headers = read_part_headers(&mut stream)?;
+        let header = |name: &str| headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str());
+
+        let disposition = header("Content-Disposition").ok_or(FormError::Malformed("part without Content-Disposition"))?;
+        if !disposition.trim_start().to_ascii_lowercase().starts_with("form-data") {
+            return Err(FormError::Malformed("part is not form-data"));
+        }
+        let name = parameter(disposition, "name").ok_or(FormError::Malformed("part without a name"))?;
+        match parameter(disposition, "filename") {
+            Some(filename) => {
+                if form.files.len() == limits.max_files {
+                    return Err(FormError::TooLarge("number of uploaded files"));
+                }
+                let (path, mut file) = create_temp_file()?;
+                // 先创建UploadedFile，出错返回时由它的Drop删除临时文件
+                let mut upload = UploadedFile {
+                    name,
+                    filename: sanitize_filename(&filename),
+                    content_type: header("Content-Type").map(str::to_string),
+                    size: 0,
+                    path,
+                    persisted: false,
+                };
+                let size = &mut upload.size;
+                stream.read_until(&delimiter, |chunk| {
+                    *size += chunk.len() as u64;
+                    if *size > limits.max_file_bytes {
+                        return Err(FormError::TooLarge("uploaded file"));
+                    }
+                    Ok(file.write_all(chunk)?)
+                })?;
+                if !(filename.is_empty() && upload.size == 0) {
+                    form.files.push(upload);
+                }
+            }
+            None => {
+                let mut value = Vec::new();
+                stream.read_until(&delimiter, |chunk| {
+                    if value.len() + chunk.len() > limits.max_field_bytes {
+                        return Err(FormError::TooLarge("form field"));
+                    }
+                    value.extend_from_slice(chunk);
+                    Ok(())
+                })?;
+                let value = String::from_utf8(value).map_err(|_| FormError::Malformed("form field is not valid utf-8"))?;
+                form.push_field(name, value, limits)?;
+            }
+        }
+    }
+    Ok(form)
+}
+
+// 读取一个部分的头部，直到空行
+fn read_part_headers<R: Read>(stream: &mut Stream<R>) -> Result<Vec<(String, String)>, FormError> {
+    if stream.consume_prefix(b"\r\n")? {
+        return Ok(Vec::new());
+    }
+    let mut head = Vec::new();
+    stream.read_until(b"\r\n\r\n", |chunk| {
+        if head.len() + chunk.len() > MAX_PART_HEAD {
+            return Err(FormError::TooLarge("multipart headers"));
+        }
+        head.extend_from_slice(chunk);
+        Ok(())
+    })?;
+    let head = String::from_utf8(head).map_err(|_| FormError::Malformed("multipart headers are not valid utf-8"))?;
+    head.split("\r\n")
+        .map(|line| {
+            line.split_once(':')
+                .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+                .ok_or(FormError::Malformed("invalid multipart header line"))
+        })
+        .collect()
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 每次最多返回n个字节的读取器，用于覆盖分隔符跨越多次读取的情况
+    struct Trickle<'a>(&'a [u8], usize);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.1.min(buf.len()).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    const BODY: &str = "preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        hello\r\nworld\r\n\
+        --XyZ  \r\n\
+        Content-Disposition: form-data; name=\"upload\"; filename=\"C:\\\\docs\\\\notes.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        line one\r\n-XyZ --XyZ\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"empty\"; filename=\"\"\r\n\
+        Content-Type: application/octet-stream\r\n\
+        \r\n\
+        \r\n\
+        --XyZ--\r\n\
+        epilogue";
+
+    #[test]
+    fn parse_fields_and_files() {
+        for step in [1, 3, 7, 4096] {
+            let form = parse(Trickle(BODY.as_bytes(), step), "XyZ", Limits::default()).unwrap();
+            assert_eq!(form.get("title"), Some("hello\r\nworld"));
+            assert_eq!(form.files().len(), 1, "step {}", step);
+
+            let file = form.file("upload").unwrap();
+            assert_eq!(file.filename(), Some("notes.txt"));
+            assert_eq!(file.content_type(), Some("text/plain"));
+            let content = fs::read_to_string(file.path()).unwrap();
+            assert_eq!(content, "line one\r\n-XyZ --XyZ");
+            assert_eq!(file.size(), content.len() as u64);
+
+            // 临时文件在drop时删除
+            let path = file.path().to_path_buf();
+            drop(form);
+            assert!(!path.exists());
+        }
+    }
+
+    #[test]
+    fn persist_upload() {
+        let dir = env::temp_dir().join(format!("server-optimize-persist-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dest = dir.join("notes.txt");
+        let _ = fs::remove_file(&dest);
+
+        let form = parse(BODY.as_bytes(), "XyZ", Limits::default()).unwrap();
+        let mut file = form.into_files().pop().unwrap();
+        let temp = file.path().to_path_buf();
+        file.persist(&dest).unwrap();
+        assert!(!temp.exists());
+        assert_eq!(file.path(), dest);
+        drop(file);
+        assert!(dest.exists());
+
+        // 不覆盖已有文件
+        let mut again = parse(BODY.as_bytes(), "XyZ", Limits::default()).unwrap().into_files().pop().unwrap();
+        assert_eq!(again.persist(&dest).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn enforce_limits_and_reject_malformed() {
+        let limits = Limits { max_file_bytes: 8, ..Limits::default() };
+        assert!(matches!(parse(BODY.as_bytes(), "XyZ", limits), Err(FormError::TooLarge("uploaded file"))));
+        let limits = Limits { max_field_bytes: 4, ..Limits::default() };
+        assert!(matches!(parse(BODY.as_bytes(), "XyZ", limits), Err(FormError::TooLarge("form field"))));
+        let limits = Limits { max_files: 0, ..Limits::default() };
+        assert!(matches!(parse(BODY.as_bytes(), "XyZ", limits), Err(FormError::TooLarge(_))));
+        let limits = Limits { max_total_bytes: 64, ..Limits::default() };
+        assert!(matches!(parse(BODY.as_bytes(), "XyZ", limits), Err(FormError::TooLarge("request body"))));
+
+        let truncated = &BODY[..BODY.find("--XyZ--").unwrap()];
+        assert!(matches!(parse(truncated.as_bytes(), "XyZ", Limits::default()), Err(FormError::Malformed(_))));
+        let no_name = "--b\r\nContent-Disposition: form-data\r\n\r\nx\r\n--b--";
+        assert!(matches!(parse(no_name.as_bytes(), "b", Limits::default()), Err(FormError::Malformed(_))));
+        assert!(matches!(parse(&b""[..], "", Limits::default()), Err(FormError::Malformed(_))));
+    }
+}
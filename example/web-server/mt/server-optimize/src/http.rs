@@ -0,0 +1,16 @@
+// HTTP协议相关的基础类型：请求、响应、头部、状态码以及MIME类型推断
+pub mod body;
+pub mod date;
+pub mod header;
+pub mod mime;
+pub mod request;
+pub mod response;
+pub mod status;
+pub mod upgrade;
+
+pub use body::BodyReader;
+pub use header::HeaderMap;
+pub use request::{Limits, ParseError, Request};
+pub use response::{Body, OnComplete, Response};
+pub use status::StatusCode;
+pub use upgrade::{OnUpgrade, Upgraded};
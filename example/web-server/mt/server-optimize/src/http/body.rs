@@ -0,0 +1,222 @@
+use std::fmt;
+use std::io::{self, BufRead, Read};
+
+use super::{HeaderMap, ParseError};
+
+// chunked编码中块大小行与trailer行的最大字节数
+const MAX_CHUNK_LINE: u64 = 8 * 1024;
+
+/*
+    请求体读取器
+
+    由 `Request::take_body` 取出，只能读取一次。阻塞模式下直接从连接中读取并解码Content-Length或者chunked编码的请求体，
+    请求体不会在内存中累积；连接在请求处理结束之后继续读取下一个请求，此时仍然被持有的读取器会返回错误。
+    提前到达结尾（客户端中途关闭连接）时返回 `UnexpectedEof`
+ */
+pub struct BodyReader {
+    inner: Box<dyn Read + Send>,
+    // Content-Length声明的长度，chunked编码时为None
+    length: Option<u64>,
+}
+
+impl BodyReader {
+    pub fn new<R: Read + Send + 'static>(reader: R, length: Option<u64>) -> BodyReader {
+        BodyReader { inner: Box::new(reader), length }
+    }
+
+    pub fn empty() -> BodyReader {
+        BodyReader::new(io::empty(), Some(0))
+    }
+
+    /// 请求体的总长度，chunked编码的请求体长度未知返回 `None`。
+    pub fn length(&self) -> Option<u64> {
+        self.length
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl fmt::Debug for BodyReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BodyReader").field("length", &self.length).finish()
+    }
+}
+
+/*
+    请求体解码器
+
+    按照请求头决定的分帧方式从字节流中读出请求体，自身只保存解码状态，不持有字节流，
+    这样连接可以在请求体读完之后继续用同一个字节流读取下一个请求
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Decoder {
+    // 剩余的字节数
+    Length(u64),
+    // 等待下一个块大小行
+    ChunkSize,
+    // 当前块剩余的字节数
+    ChunkData(u64),
+    // 块数据之后的CRLF
+    ChunkEnd,
+    Done,
+}
+
+impl Decoder {
+    /// 根据Transfer-Encoding与Content-Length选择分帧方式，两者都没有时请求体为空。
+    pub(crate) fn new(headers: &HeaderMap) -> Result<Decoder, ParseError> {
+        let chunked = headers
+            .get("Transfer-Encoding")
+            .map(|value| value.to_ascii_lowercase().contains("chunked"))
+            .unwrap_or(false);
+        if chunked {
+            return Ok(Decoder::ChunkSize);
+        }
+        match headers.get("Content-Length") {
+            Some(length) => length
+                .parse()
+                .map(Decoder::Length)
+                .map_err(|_| ParseError::Malformed("invalid content-length")),
+            None => Ok(Decoder::Length(0)),
+        }
+    }
+
+    /// 请求体的总长度，只在开始读取之前有意义；chunked编码返回 `None`。
+    pub(crate) fn length(&self) -> Option<u64> {
+        match self {
+            Decoder::Length(length) => Some(*length),
+            Decoder::Done => Some(0),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        matches!(self, Decoder::Done | Decoder::Length(0))
+    }
+
+    /// 从 `reader` 中读取并解码请求体，读完之后返回0。
+    pub(crate) fn read<R: BufRead>(&mut self, reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match *self {
+                Decoder::Length(0) | Decoder::Done => {
+                    *self = Decoder::Done;
+                    return Ok(0);
+                }
+                Decoder::Length(remaining) => {
+                    let n = read_data(reader, buf, remaining)?;
+                    *self = Decoder::Length(remaining - n as u64);
+                    return Ok(n);
+                }
+                Decoder::ChunkSize => {
+                    let line = read_chunk_line(reader)?;
+                    let size = line.trim().split(';').next().unwrap_or("");
+                    let size = u64::from_str_radix(size, 16)
+                        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size"))?;
+                    if size == 0 {
+                        // 跳过可能存在的trailer头部直到空行，空行之前连接关闭说明请求不完整
+                        while !read_chunk_line(reader)?.trim().is_empty() {}
+                        *self = Decoder::Done;
+                    } else {
+                        *self = Decoder::ChunkData(size);
+                    }
+                }
+                Decoder::ChunkData(remaining) => {
+                    let n = read_data(reader, buf, remaining)?;
+                    let remaining = remaining - n as u64;
+                    *self = if remaining == 0 { Decoder::ChunkEnd } else { Decoder::ChunkData(remaining) };
+                    return Ok(n);
+                }
+                Decoder::ChunkEnd => {
+                    if !read_chunk_line(reader)?.trim().is_empty() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "missing CRLF after chunk data"));
+                    }
+                    *self = Decoder::ChunkSize;
+                }
+            }
+        }
+    }
+
+    /// 借用字节流，得到一个实现了 `Read` 的解码视图。
+    pub(crate) fn reader<'a, R: BufRead>(&'a mut self, reader: &'a mut R) -> Decoded<'a, R> {
+        Decoded { decoder: self, reader }
+    }
+}
+
+pub(crate) struct Decoded<'a, R> {
+    decoder: &'a mut Decoder,
+    reader: &'a mut R,
+}
+
+impl<R: BufRead> Read for Decoded<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(self.reader, buf)
+    }
+}
+
+// 最多读取remaining个字节，还没有读完就到达结尾时返回UnexpectedEof
+fn read_data<R: BufRead>(reader: &mut R, buf: &mut [u8], remaining: u64) -> io::Result<usize> {
+    let limit = buf.len().min(usize::try_from(remaining).unwrap_or(usize::MAX));
+    let n = reader.read(&mut buf[..limit])?;
+    if n == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(n)
+}
+
+// 读取chunked编码中的一行（块大小或者trailer），没有读到换行符就遇到结尾时返回UnexpectedEof
+fn read_chunk_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = Vec::new();
+    reader.by_ref().take(MAX_CHUNK_LINE).read_until(b'\n', &mut line)?;
+    if line.last() != Some(&b'\n') {
+        return Err(if line.len() as u64 == MAX_CHUNK_LINE {
+            io::Error::new(io::ErrorKind::InvalidData, "chunk line too long")
+        } else {
+            io::ErrorKind::UnexpectedEof.into()
+        });
+    }
+    String::from_utf8(line).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "chunk line is not valid utf-8"))
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(headers: &[(&str, &str)], raw: &[u8]) -> io::Result<Vec<u8>> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(*name, *value);
+        }
+        let mut decoder = Decoder::new(&map).unwrap();
+        let mut reader = raw;
+        let mut body = Vec::new();
+        decoder.reader(&mut reader).read_to_end(&mut body)?;
+        assert!(decoder.is_done());
+        Ok(body)
+    }
+
+    #[test]
+    fn decode_length_and_chunked() {
+        assert_eq!(decode(&[("Content-Length", "5")], b"helloGET").unwrap(), b"hello");
+        assert_eq!(decode(&[], b"GET").unwrap(), b"");
+        let chunked = [("Transfer-Encoding", "chunked")];
+        assert_eq!(decode(&chunked, b"5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Sum: 1\r\n\r\nGET").unwrap(), b"hello world");
+
+        assert_eq!(decode(&[("Content-Length", "5")], b"hel").unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(decode(&chunked, b"5\r\nhello\r\n0\r\nX-Sum").unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(decode(&chunked, b"zz\r\n").unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(decode(&chunked, b"5\r\nhelloXX0\r\n\r\n").unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Length", "-1");
+        assert!(matches!(Decoder::new(&headers), Err(ParseError::Malformed(_))));
+    }
+}
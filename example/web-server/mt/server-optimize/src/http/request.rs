@@ -0,0 +1,399 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Cursor, Read};
+use std::mem;
+use std::net::SocketAddr;
+
+use super::body::Decoder;
+use super::{BodyReader, HeaderMap, StatusCode};
+
+// 默认的请求行与头部的最大字节数，超过则认为是非法请求
+pub const MAX_HEAD_SIZE: usize = 8 * 1024;
+// 默认的请求体最大字节数
+pub const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/*
+    请求大小限制
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    // 请求行与头部的最大字节数
+    pub max_head_bytes: usize,
+    // 请求体的最大字节数
+    pub max_body_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits { max_head_bytes: MAX_HEAD_SIZE, max_body_bytes: MAX_BODY_SIZE }
+    }
+}
+
+/*
+    HTTP请求
+
+    请求报文大致格式如下：
+    Method Request-URI HTTP-Version CRLF
+    headers CRLF
+    message-body
+
+    阻塞模式下处理器收到请求时请求体还留在连接中，通过 `take_body` 以流的方式读取，或者通过 `buffer_body`
+    读入 `body`（受 `Limits::max_body_bytes` 限制）；事件驱动模式以及 `read_with_limits` 解析出的请求体已经在 `body` 中
+ */
+#[derive(Debug)]
+pub struct Request {
+    // 请求方法，例如GET、POST
+    pub method: String,
+    // 请求路径，不包含查询字符串，例如/sleep
+    pub path: String,
+    // 查询字符串，不包含问号
+    pub query: Option<String>,
+    // 协议版本，例如HTTP/1.1
+    pub version: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    // 客户端地址
+    pub remote_addr: Option<SocketAddr>,
+    // 尚未读取的请求体
+    stream: Option<BodyReader>,
+    // buffer_body读入内存的最大字节数
+    max_body_bytes: usize,
+}
+
+// 克隆的请求不包含尚未读取的请求体
+impl Clone for Request {
+    fn clone(&self) -> Request {
+        Request {
+            method: self.method.clone(),
+            path: self.path.clone(),
+            query: self.query.clone(),
+            version: self.version.clone(),
+            headers: self.headers.clone(),
+            body: self.body.clone(),
+            remote_addr: self.remote_addr,
+            stream: None,
+            max_body_bytes: self.max_body_bytes,
+        }
+    }
+}
+
+impl Request {
+    pub fn new(method: &str, target: &str) -> Request {
+        let (path, query) = split_target(target);
+        Request {
+            method: method.to_string(),
+            path,
+            query,
+            version: String::from("HTTP/1.1"),
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+            remote_addr: None,
+            stream: None,
+            max_body_bytes: MAX_BODY_SIZE,
+        }
+    }
+
+    /// 从字节流中读取并解析一个完整的请求，使用默认的大小限制。
+    ///
+    /// 如果在读取到任何字节之前连接就已经关闭则返回 `Ok(None)`。
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Option<Request>, ParseError> {
+        Request::read_with_limits(reader, Limits::default())
+    }
+
+    /// 从字节流中读取并解析一个完整的请求，头部或者请求体超过 `limits` 时返回错误。
+    pub fn read_with_limits<R: BufRead>(reader: &mut R, limits: Limits) -> Result<Option<Request>, ParseError> {
+        let (mut request, mut decoder) = match Request::read_head(reader, limits)? {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        // 在读取请求体之前检查长度，避免为超大的请求体分配内存
+        if decoder.length().is_some_and(|length| length > limits.max_body_bytes as u64) {
+            return Err(ParseError::BodyTooLarge);
+        }
+        request.body = read_to_limit(decoder.reader(reader), limits.max_body_bytes)?;
+        Ok(Some(request))
+    }
+
+    /// 只解析请求行与头部，返回请求以及读取其请求体所需的解码器，请求体留在字节流中。
+    pub(crate) fn read_head<R: BufRead>(reader: &mut R, limits: Limits) -> Result<Option<(Request, Decoder)>, ParseError> {
+        let mut head_size = 0;
+        let max_head = limits.max_head_bytes;
+
+        // 解析请求行
+        let request_line = match read_line(reader, &mut head_size, max_head)? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        let mut parts = request_line.split_whitespace();
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version), None) => (method, target, version),
+            _ => return Err(ParseError::Malformed("invalid request line")),
+        };
+        if !version.starts_with("HTTP/") {
+            return Err(ParseError::Malformed("invalid http version"));
+        }
+        let mut request = Request::new(method, target);
+        request.version = version.to_string();
+        request.max_body_bytes = limits.max_body_bytes;
+
+        // 解析请求头，直到遇到空行
+        loop {
+            let line = read_line(reader, &mut head_size, max_head)?
+                .ok_or(ParseError::Malformed("unexpected end of headers"))?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(ParseError::Malformed("invalid header line"))?;
+            request.headers.append(name.trim(), value.trim());
+        }
+
+        let decoder = Decoder::new(&request.headers)?;
+        Ok(Some((request, decoder)))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// 设置以流的方式读取的请求体，`body` 中已有的内容被丢弃。
+    pub fn set_body(&mut self, body: BodyReader) {
+        self.body.clear();
+        self.stream = Some(body);
+    }
+
+    /// 取出请求体读取器，请求体只能取出一次，之后 `body` 为空。
+    ///
+    /// 请求体已经在 `body` 中时返回读取这部分内容的读取器。
+    pub fn take_body(&mut self) -> BodyReader {
+        match self.stream.take() {
+            Some(stream) => stream,
+            None => {
+                let body = mem::take(&mut self.body);
+                let length = body.len() as u64;
+                BodyReader::new(Cursor::new(body), Some(length))
+            }
+        }
+    }
+
+    /// 把尚未读取的请求体读入 `body` 并返回，超过 `Limits::max_body_bytes` 时返回 `ParseError::BodyTooLarge`。
+    ///
+    /// 请求体已经被 `take_body` 取走时返回空的 `body`。
+    pub fn buffer_body(&mut self) -> Result<&[u8], ParseError> {
+        if let Some(stream) = self.stream.take() {
+            if stream.length().is_some_and(|length| length > self.max_body_bytes as u64) {
+                return Err(ParseError::BodyTooLarge);
+            }
+            self.body = read_to_limit(stream, self.max_body_bytes)?;
+        }
+        Ok(&self.body)
+    }
+
+    /// 请求体的长度：尚未读取时为Content-Length，chunked编码时为 `None`；已经读入内存时为 `body` 的长度。
+    pub fn body_length(&self) -> Option<u64> {
+        match &self.stream {
+            Some(stream) => stream.length(),
+            None => Some(self.body.len() as u64),
+        }
+    }
+
+    /// 连接在响应之后是否保持打开。
+    ///
+    /// HTTP/1.1默认保持连接，除非声明了 `Connection: close`；HTTP/1.0则需要显式声明 `Connection: keep-alive`。
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("Connection").map(|value| value.to_ascii_lowercase());
+        let has = |token: &str| {
+            connection
+                .as_deref()
+                .map(|value| value.split(',').any(|item| item.trim() == token))
+                .unwrap_or(false)
+        };
+        if self.version == "HTTP/1.0" {
+            has("keep-alive")
+        } else {
+            !has("close")
+        }
+    }
+
+    /// 请求目标，即路径与查询字符串。
+    pub fn target(&self) -> String {
+        match &self.query {
+            Some(query) => format!("{}?{}", self.path, query),
+            None => self.path.clone(),
+        }
+    }
+}
+
+// 拆分请求目标中的路径与查询字符串
+fn split_target(target: &str) -> (String, Option<String>) {
+    match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (target.to_string(), None),
+    }
+}
+
+// 读取一行（去掉结尾的CRLF），同时累计已读取的头部大小
+fn read_line<R: BufRead>(reader: &mut R, head_size: &mut usize, max_head: usize) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    let limit = max_head.saturating_sub(*head_size) as u64 + 1;
+    let n = reader.by_ref().take(limit).read_until(b'\n', &mut line)?;
+    if n == 0 {
+        return if *head_size == 0 {
+            Ok(None)
+        } else {
+            Err(ParseError::Malformed("unexpected end of request"))
+        };
+    }
+    *head_size += n;
+    if *head_size > max_head {
+        return Err(ParseError::HeadTooLarge);
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(ParseError::Malformed("unexpected end of request"));
+    }
+    while matches!(line.last(), Some(b'\n') | Some(b'\r')) {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| ParseError::Malformed("request head is not valid utf-8"))
+}
+
+// 把请求体读入内存，超过max_body个字节时返回BodyTooLarge
+fn read_to_limit<R: Read>(reader: R, max_body: usize) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    reader.take(max_body as u64 + 1).read_to_end(&mut body)?;
+    if body.len() > max_body {
+        return Err(ParseError::BodyTooLarge);
+    }
+    Ok(body)
+}
+
+/*
+    请求解析错误
+ */
+#[derive(Debug)]
+pub enum ParseError {
+    // 底层IO错误
+    Io(io::Error),
+    // 请求行与头部过大
+    HeadTooLarge,
+    // 请求体过大
+    BodyTooLarge,
+    // 报文格式错误
+    Malformed(&'static str),
+}
+
+impl ParseError {
+    /// 解析失败时返回给客户端的状态码。
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ParseError::HeadTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            ParseError::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io(err) => write!(f, "io error: {}", err),
+            ParseError::HeadTooLarge => write!(f, "request head too large"),
+            ParseError::BodyTooLarge => write!(f, "request body too large"),
+            ParseError::Malformed(reason) => write!(f, "malformed request: {}", reason),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> ParseError {
+        ParseError::Io(err)
+    }
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_get_request() {
+        let raw = b"GET /sleep?seconds=1 HTTP/1.1\r\nHost: localhost:7878\r\nAccept: */*\r\n\r\n";
+        let request = Request::read_from(&mut &raw[..]).unwrap().unwrap();
+        assert_eq!("GET", request.method);
+        assert_eq!("/sleep", request.path);
+        assert_eq!(Some("seconds=1"), request.query.as_deref());
+        assert_eq!(Some("localhost:7878"), request.header("host"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn parse_body_with_content_length_and_chunked() {
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        let request = Request::read_from(&mut &raw[..]).unwrap().unwrap();
+        assert_eq!(b"hello".to_vec(), request.body);
+
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+        let request = Request::read_from(&mut &raw[..]).unwrap().unwrap();
+        assert_eq!(b"hello world".to_vec(), request.body);
+    }
+
+    #[test]
+    fn incomplete_chunked_trailers() {
+        let complete = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\nX-Checksum: 1\r\n\r\n";
+        let request = Request::read_from(&mut &complete[..]).unwrap().unwrap();
+        assert_eq!(b"ok".to_vec(), request.body);
+
+        // 在trailer中间、trailer之后的空行之前以及块大小行中间结束的请求都不完整
+        for end in [complete.len() - 10, complete.len() - 2, complete.len() - 1, complete.len() - 19] {
+            match Request::read_from(&mut &complete[..end]) {
+                Err(ParseError::Io(err)) => assert_eq!(io::ErrorKind::UnexpectedEof, err.kind(), "end {}", end),
+                other => panic!("end {}: {:?}", end, other),
+            }
+        }
+    }
+
+    #[test]
+    fn enforce_size_limits() {
+        let limits = Limits { max_head_bytes: 64, max_body_bytes: 4 };
+        let raw = b"GET / HTTP/1.1\r\nX-Padding: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n\r\n";
+        assert!(matches!(Request::read_with_limits(&mut &raw[..], limits), Err(ParseError::HeadTooLarge)));
+
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        assert!(matches!(Request::read_with_limits(&mut &raw[..], limits), Err(ParseError::BodyTooLarge)));
+
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n";
+        assert!(matches!(Request::read_with_limits(&mut &raw[..], limits), Err(ParseError::BodyTooLarge)));
+    }
+
+    #[test]
+    fn keep_alive_depends_on_version() {
+        let mut request = Request::new("GET", "/");
+        assert!(request.keep_alive());
+        request.headers.insert("Connection", "close");
+        assert!(!request.keep_alive());
+        request.version = String::from("HTTP/1.0");
+        request.headers.insert("Connection", "Keep-Alive");
+        assert!(request.keep_alive());
+    }
+
+    #[test]
+    fn reject_malformed_request() {
+        assert!(Request::read_from(&mut &b""[..]).unwrap().is_none());
+        assert!(Request::read_from(&mut &b"GET /\r\n\r\n"[..]).is_err());
+        assert!(Request::read_from(&mut &b"GET / HTTP/1.1\r\nHost"[..]).is_err());
+    }
+}
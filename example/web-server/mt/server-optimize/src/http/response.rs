@@ -0,0 +1,365 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use super::{mime, HeaderMap, OnUpgrade, StatusCode};
+
+/*
+    响应体
+
+    响应体可以是内存中的字节、磁盘上的文件或者任意的字节流，
+    其中文件和字节流在写入TCP流时才会被读取，避免一次性加载到内存中
+ */
+pub enum Body {
+    Empty,
+    Bytes(Vec<u8>),
+    File(File),
+    // 长度未知的字节流，写出时使用chunked编码
+    Stream(Box<dyn Read + Send>),
+}
+
+impl Body {
+    /// 响应体的长度，字节流的长度未知返回 `None`。
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Empty => Some(0),
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::File(file) => file.metadata().ok().map(|metadata| metadata.len()),
+            Body::Stream(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Empty => write!(f, "Body::Empty"),
+            Body::Bytes(bytes) => write!(f, "Body::Bytes({} bytes)", bytes.len()),
+            Body::File(file) => write!(f, "Body::File({:?})", file),
+            Body::Stream(_) => write!(f, "Body::Stream"),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<String> for Body {
+    fn from(content: String) -> Body {
+        Body::Bytes(content.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(content: &str) -> Body {
+        Body::Bytes(content.as_bytes().to_vec())
+    }
+}
+
+impl From<File> for Body {
+    fn from(file: File) -> Body {
+        Body::File(file)
+    }
+}
+
+/*
+    HTTP响应
+ */
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Body,
+    // 协议升级（101 Switching Protocols）时接管底层连接的回调
+    pub upgrade: Option<OnUpgrade>,
+    // 匹配到的路由（例如 `/api/*`），由Router设置，指标按照它而不是请求路径分类
+    pub route: Option<String>,
+    // 响应体写完（或者放弃写出）之后的回调，例如访问日志在这里记录实际的字节数与耗时
+    pub on_complete: Option<OnComplete>,
+}
+
+impl Response {
+    pub fn new(status: StatusCode) -> Response {
+        Response { status, headers: HeaderMap::new(), body: Body::Empty, upgrade: None, route: None, on_complete: None }
+    }
+
+    /// 创建HTML响应。
+    pub fn html(status: StatusCode, content: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(content.into())
+    }
+
+    /// 创建纯文本响应。
+    pub fn text(status: StatusCode, content: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(content.into())
+    }
+
+    /// 创建文件响应，Content-Type根据文件扩展名推断。
+    pub fn file<P: AsRef<Path>>(status: StatusCode, path: P) -> io::Result<Response> {
+        let file = File::open(path.as_ref())?;
+        Ok(Response::new(status)
+            .with_header("Content-Type", mime::from_path(path))
+            .with_body(file))
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Response {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Body>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// 设置协议升级回调，响应写出之后底层连接会交给该回调。
+    pub fn with_upgrade(mut self, upgrade: OnUpgrade) -> Response {
+        self.upgrade = Some(upgrade);
+        self
+    }
+
+    /// 添加响应写完之后的回调，已有的回调先被调用。
+    pub fn with_on_complete(mut self, callback: impl FnOnce(u64) + Send + 'static) -> Response {
+        let previous = self.on_complete.take().and_then(|mut done| done.0.callback.take());
+        let callback: Box<dyn FnOnce(u64) + Send> = match previous {
+            Some(previous) => Box::new(move |written| {
+                previous(written);
+                callback(written);
+            }),
+            None => Box::new(callback),
+        };
+        self.on_complete = Some(OnComplete(Box::new(Callback { callback: Some(callback), written: 0 })));
+        self
+    }
+
+    /// 将响应写入字节流，返回写出的响应体字节数。
+    ///
+    /// 长度已知的响应体使用Content-Length，长度未知的字节流使用chunked编码。
+    pub fn write_to<W: Write>(mut self, writer: &mut W) -> io::Result<u64> {
+        // 回调在函数返回（响应体写出并flush，或者写出失败）时被调用
+        let mut on_complete = self.on_complete.take();
+        writer.write_all(&self.encode_head())?;
+
+        // 写出响应体
+        let written = match self.body {
+            Body::Empty => 0,
+            Body::Bytes(bytes) => {
+                writer.write_all(&bytes)?;
+                bytes.len() as u64
+            }
+            Body::File(mut file) => io::copy(&mut file, writer)?,
+            Body::Stream(mut stream) => write_chunked(&mut stream, writer)?,
+        };
+        writer.flush()?;
+        if let Some(on_complete) = &mut on_complete {
+            on_complete.add(written);
+        }
+        Ok(written)
+    }
+
+    /// 只写出状态行与响应头，用于HEAD请求：分帧头部与对应的GET响应相同，响应体被丢弃。
+    pub fn write_head_to<W: Write>(mut self, writer: &mut W) -> io::Result<()> {
+        let _on_complete = self.on_complete.take();
+        writer.write_all(&self.encode_head())?;
+        writer.flush()
+    }
+
+    /// 按照响应体设置Content-Length或者Transfer-Encoding，返回序列化后的状态行与响应头。
+    ///
+    /// 不允许携带响应体的响应（1xx、204与304）同时清空响应体，之后由调用者按照头部中的分帧方式写出 `body`。
+    pub(crate) fn encode_head(&mut self) -> Vec<u8> {
+        let length = self.body.len();
+        // 1xx、204与304响应不允许携带响应体以及Content-Length/Transfer-Encoding（RFC 9112）
+        let bodiless = self.status.as_u16() < 200 || self.status == StatusCode::NO_CONTENT || self.status.as_u16() == 304;
+        match length {
+            _ if bodiless => {
+                self.headers.remove("Content-Length");
+                self.headers.remove("Transfer-Encoding");
+                self.body = Body::Empty;
+            }
+            Some(length) => {
+                self.headers.remove("Transfer-Encoding");
+                self.headers.insert("Content-Length", length.to_string());
+            }
+            None => {
+                self.headers.remove("Content-Length");
+                self.headers.insert("Transfer-Encoding", "chunked");
+            }
+        }
+        if !self.headers.contains("Connection") {
+            self.headers.insert("Connection", "close");
+        }
+
+        // 写出状态行与响应头
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+        for (name, value) in self.headers.iter() {
+            head.push_str(name);
+            head.push_str(": ");
+            head.push_str(value);
+            head.push_str("\r\n");
+        }
+        head.push_str("\r\n");
+        head.into_bytes()
+    }
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("status", &self.status)
+            .field("headers", &self.headers)
+            .field("body", &self.body)
+            .field("upgrade", &self.upgrade.is_some())
+            .field("route", &self.route)
+            .field("on_complete", &self.on_complete.is_some())
+            .finish()
+    }
+}
+
+/*
+    响应写完之后的回调
+
+    参数为写出的响应体字节数（不包括头部与chunked编码的分帧）。回调在释放时被调用，
+    所以写出失败、连接提前关闭或者响应被丢弃时同样恰好调用一次，此时只计入已知写出的部分
+ */
+// 装箱之后Response只增加一个指针的大小
+pub struct OnComplete(Box<Callback>);
+
+struct Callback {
+    callback: Option<Box<dyn FnOnce(u64) + Send>>,
+    written: u64,
+}
+
+impl OnComplete {
+    // 记录已经写出的响应体字节数
+    pub(crate) fn add(&mut self, written: u64) {
+        self.0.written += written;
+    }
+}
+
+impl Drop for OnComplete {
+    fn drop(&mut self) {
+        if let Some(callback) = self.0.callback.take() {
+            callback(self.0.written);
+        }
+    }
+}
+
+impl From<StatusCode> for Response {
+    fn from(status: StatusCode) -> Response {
+        Response::text(status, status.reason_phrase())
+    }
+}
+
+// 以chunked编码写出字节流：每个块由十六进制长度行、数据以及CRLF组成，最后以长度为0的块结束
+pub(crate) fn write_chunked<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<u64> {
+    let mut buffer = [0; 8 * 1024];
+    let mut written = 0;
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        write!(writer, "{:X}\r\n", n)?;
+        writer.write_all(&buffer[..n])?;
+        writer.write_all(b"\r\n")?;
+        written += n as u64;
+    }
+    writer.write_all(b"0\r\n\r\n")?;
+    Ok(written)
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_bytes_response() {
+        let mut output = Vec::new();
+        let response = Response::html(StatusCode::OK, "<h1>Hello!</h1>");
+        let written = response.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(15, written);
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.contains("Content-Length: 15\r\n"));
+        assert!(output.ends_with("\r\n\r\n<h1>Hello!</h1>"));
+    }
+
+    #[test]
+    fn write_stream_response_with_chunked_encoding() {
+        let mut output = Vec::new();
+        let stream: Box<dyn Read + Send> = Box::new(&b"hello"[..]);
+        Response::new(StatusCode::OK)
+            .with_body(Body::Stream(stream))
+            .write_to(&mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(output.ends_with("\r\n\r\n5\r\nhello\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn bodiless_statuses() {
+        for status in [StatusCode(101), StatusCode::NO_CONTENT, StatusCode(304)] {
+            let mut output = Vec::new();
+            let stream: Box<dyn Read + Send> = Box::new(&b"hello"[..]);
+            let written = Response::new(status).with_body(Body::Stream(stream)).write_to(&mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert_eq!(written, 0);
+            assert!(!output.contains("Content-Length"), "{}", output);
+            assert!(!output.contains("Transfer-Encoding"), "{}", output);
+            assert!(output.ends_with("\r\n\r\n"), "{}", output);
+        }
+    }
+
+    #[test]
+    fn write_head_only() {
+        let mut output = Vec::new();
+        Response::html(StatusCode::OK, "<h1>Hello!</h1>").write_head_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Content-Length: 15\r\n"));
+        assert!(output.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn on_complete_receives_written_body_bytes() {
+        use std::sync::{Arc, Mutex};
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let record = |name: &'static str| {
+            let calls = Arc::clone(&calls);
+            move |written: u64| calls.lock().unwrap().push((name, written))
+        };
+
+        // 字节流的长度在写出之后才知道，回调按照注册顺序调用
+        let stream: Box<dyn Read + Send> = Box::new(io::Cursor::new(vec![b'x'; 20000]));
+        let response = Response::new(StatusCode::OK)
+            .with_body(Body::Stream(stream))
+            .with_on_complete(record("first"))
+            .with_on_complete(record("second"));
+        response.write_to(&mut Vec::new()).unwrap();
+        assert_eq!(*calls.lock().unwrap(), [("first", 20000), ("second", 20000)]);
+
+        // HEAD请求不写出响应体；没有被写出就被丢弃的响应同样会调用回调
+        calls.lock().unwrap().clear();
+        let head = Response::text(StatusCode::OK, "hello").with_on_complete(record("head"));
+        head.write_head_to(&mut Vec::new()).unwrap();
+        drop(Response::text(StatusCode::OK, "hello").with_on_complete(record("dropped")));
+        assert_eq!(*calls.lock().unwrap(), [("head", 0), ("dropped", 0)]);
+    }
+}
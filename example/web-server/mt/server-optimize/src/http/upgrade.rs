@@ -0,0 +1,81 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+/// 协议升级回调：响应写出后服务端将底层连接交给它，之后该连接不再按照HTTP处理。
+pub type OnUpgrade = Box<dyn FnOnce(Upgraded) + Send + 'static>;
+
+/*
+    升级后的连接
+
+    服务端在解析HTTP请求时可能已经多读取了一部分数据（例如客户端在握手之后立刻发送的WebSocket帧），
+    这部分数据保存在buffered中，读取时会先于TCP流中的数据返回
+ */
+pub struct Upgraded {
+    stream: TcpStream,
+    buffered: Vec<u8>,
+    position: usize,
+}
+
+impl Upgraded {
+    pub fn new(stream: TcpStream, buffered: Vec<u8>) -> Upgraded {
+        Upgraded { stream, buffered, position: 0 }
+    }
+
+    /// 底层的TCP流，可以通过 `try_clone` 获得独立的写端。
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+}
+
+impl Read for Upgraded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position < self.buffered.len() {
+            let n = (&self.buffered[self.position..]).read(buf)?;
+            self.position += n;
+            return Ok(n);
+        }
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Upgraded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl fmt::Debug for Upgraded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgraded")
+            .field("stream", &self.stream)
+            .field("buffered", &(self.buffered.len() - self.position))
+            .finish()
+    }
+}
+
+/// 在独立的线程中运行升级回调。
+///
+/// 升级后的连接（例如WebSocket）往往会存活很长时间，如果继续占用线程池中的Worker，
+/// 少量长连接就可以让普通HTTP请求无Worker可用，所以这里为每个升级后的连接单独创建线程。
+pub fn spawn(on_upgrade: OnUpgrade, upgraded: Upgraded) {
+    // 阻塞模式下设置的keep-alive读超时以及事件驱动模式下的非阻塞标记都不适用于升级后的连接
+    let reset = upgraded
+        .stream()
+        .set_read_timeout(None)
+        .and_then(|_| upgraded.stream().set_nonblocking(false));
+    if let Err(err) = reset {
+        println!("[Upgrade] reset connection failed: {}", err);
+        return;
+    }
+    let result = std::thread::Builder::new()
+        .name(String::from("upgraded-connection"))
+        .spawn(move || on_upgrade(upgraded));
+    if let Err(err) = result {
+        println!("[Upgrade] spawn thread failed: {}", err);
+    }
+}
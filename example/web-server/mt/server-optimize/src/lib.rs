@@ -1,6 +1,45 @@
-use std::{thread::{JoinHandle, self}, sync::{mpsc, Arc, Mutex}};
+use std::{any::Any, future::Future, io, panic::{self, AssertUnwindSafe}, thread::{self, JoinHandle}, time::{Duration, Instant}, sync::{Arc, Condvar, Mutex, OnceLock, PoisonError, atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}}};
+
+pub mod builder;
+pub mod cgi;
+pub mod config;
+pub mod event;
+pub mod executor;
+pub mod form;
+pub mod http;
+pub mod limiter;
+pub mod logfile;
+pub mod metrics;
+pub mod middleware;
+pub mod parallel;
+pub mod proxy;
+#[cfg(target_os = "linux")]
+pub mod reactor;
+pub mod rejection;
+pub mod restart;
+pub mod router;
+mod scheduler;
+pub mod server;
+pub mod signal;
+pub mod static_files;
+pub mod task;
+pub mod timer;
+pub mod tls;
+pub mod vhost;
+pub mod websocket;
+
+use builder::{Hook, Settings};
+use event::{PoolEvent, StopReason};
+use executor::Sleep;
+pub use builder::{BuildError, Builder};
+use rejection::Capacity;
+pub use rejection::{RejectionPolicy, Submitted};
+use scheduler::Scheduler;
+use task::{Scope, TaskHandle};
+use timer::{ScheduledHandle, Timer};
 
 // 定义指令枚举
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 enum Message {
     // 新任务请求
     NEW_JOB(Job),
@@ -10,69 +49,516 @@ enum Message {
 
 /*
     线程池结构体
+
+    核心线程在创建时启动；任务积压时按需增加线程直到最大线程数，
+    多出来的线程空闲超过存活时间后退出，通过 `ThreadPool::builder` 配置
  */
 pub struct ThreadPool {
-    // 定义存放线程列表，元素类型可以thread::spawn方法的返回类型
-    workers: Vec<Worker>,
-    // 定义channel通道，所以这里必须要持有一个mpsc的发送端，它需要将执行请求发送给Worker线程进行执行
-    sender: mpsc::Sender<Message>,
+    // Worker槽位、任务队列与配置，由线程池和所有Worker共享，执行请求通过调度器交给Worker线程进行执行
+    context: Arc<WorkerContext>,
+    // 线程池运行状态计数，由线程池和所有Worker共享
+    state: Arc<PoolState>,
+    // 定时器线程，第一次提交定时任务时启动
+    timer: OnceLock<Timer>,
 }
 
 impl ThreadPool {
-    /// 创建线程池。
+    /// 创建固定大小的线程池。
     ///
     /// 线程池中线程的数量。
     ///
     /// # Panics
     ///
-    /// `new` 函数在 size 为 0 时会 panic。
+    /// `new` 函数在 size 为 0 或者无法创建线程时会 panic，需要处理错误时使用 `try_new`。
     pub fn new(size: usize) -> ThreadPool {
-        assert!(size > 0);
+        ThreadPool::try_new(size).unwrap_or_else(|err| panic!("{}", err))
+    }
 
-        // 根据指定大小创建线程列表
-        let mut workers = Vec::with_capacity(size);
+    /// 创建固定大小的线程池，size 为 0 或者无法创建线程时返回错误。
+    pub fn try_new(size: usize) -> Result<ThreadPool, BuildError> {
+        ThreadPool::builder().core_threads(size).build()
+    }
 
-        // 创建channel管道用于Job和Worker的任务收发
-        let (sender, receiver) = mpsc::channel();
+    /// 创建线程池，任务panic时在执行该任务的Worker线程中调用 `handler`。
+    ///
+    /// panic不会影响其他任务：执行该任务的Worker线程会被一个新的线程替换，线程池的容量保持不变。
+    ///
+    /// # Panics
+    ///
+    /// size 为 0 或者无法创建线程时会 panic。
+    pub fn with_panic_handler<F>(size: usize, handler: F) -> ThreadPool
+    where
+        F: Fn(&JobPanic) + Send + Sync + 'static,
+    {
+        ThreadPool::builder().core_threads(size).panic_handler(handler).build().unwrap_or_else(|err| panic!("{}", err))
+    }
 
-        // 创建线程安全并且支持多所有权共享的智能指针来解决下面循环初始化线程时receiver move移入问题
-        // receiver: Arc<Mutex<Receiver<Job>>>
-        let receiver  = Arc::new(Mutex::new(receiver));
+    /// 创建线程池构建器：核心与最大线程数、空闲线程的存活时间、线程名、栈大小以及线程启动与退出的回调。
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
 
-        // 初始化线程，即Worker对象
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
-        }
+    // 由构建器在校验配置之后调用，启动全部核心线程
+    pub(crate) fn start(settings: Settings) -> io::Result<ThreadPool> {
+        let state = Arc::new(PoolState::new(settings.max_threads));
 
-        ThreadPool { workers, sender }
+        // 所有Worker（包括替换panic线程以及按需增加的Worker）共享同一个调度器、状态计数与配置
+        let context = Arc::new(WorkerContext {
+            scheduler: Scheduler::new(settings.max_threads, settings.aging_interval),
+            capacity: Capacity::new(settings.queue_capacity),
+            state: Arc::clone(&state),
+            workers: (0..settings.max_threads).map(|_| Worker::default()).collect(),
+            shutdown: AtomicBool::new(false),
+            terminated: Mutex::new(false),
+            termination: Condvar::new(),
+            settings,
+        });
+        let pool = ThreadPool { context, state, timer: OnceLock::new() };
+
+        // 初始化核心线程；中途失败时已经启动的线程由Drop回收
+        for _ in 0..pool.context.settings.core_threads {
+            pool.context.start_worker().map_err(|(_, err)| err)?;
+        }
+        Ok(pool)
     }
 
     // 该方法签名可以参考thread::spawn方法签名
+    //
+    // 队列已满时按照拒绝策略处理，被拒绝（Abort）的任务直接丢弃，需要知道结果时使用try_execute
     pub fn execute<F>(&self, f: F)
     where 
         F: FnOnce(),
         F: Send + 'static,
     {
-        let job = Box::new(f);
-        // 将执行请求放入channel通道中于后续执行
-        self.sender.send(Message::NEW_JOB(job)).unwrap();
+        let _ = self.try_execute(f);
+    }
+
+    /// 提交任务并返回任务的去向。
+    ///
+    /// 队列未满时任务总是进入队列；队列已满时按照构建时指定的 `RejectionPolicy` 处理，
+    /// 策略为 `Abort` 时以 `Err` 交还任务，由调用者决定如何处理（例如直接回复503）。
+    pub fn try_execute<F>(&self, f: F) -> Result<Submitted, F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.try_execute_with_priority(Priority::Normal, f)
+    }
+
+    /// 按照优先级提交任务：高优先级任务先于普通任务执行，低优先级任务在没有其他任务时执行，
+    /// 等待时间越长有效优先级越高（每个老化间隔提升一级），所以不会一直得不到执行。
+    pub fn execute_with_priority<F>(&self, priority: Priority, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let _ = self.try_execute_with_priority(priority, f);
+    }
+
+    /// 提交一个必须在 `deadline` 之前开始执行的任务。
+    ///
+    /// 开始执行时如果已经超过截止时间，任务不再执行而是在Worker中调用 `expired`
+    /// （例如回复504），由此避免在过载时处理早已没有意义的请求。
+    pub fn execute_before<F, E>(&self, deadline: Instant, f: F, expired: E)
+    where
+        F: FnOnce() + Send + 'static,
+        E: FnOnce() + Send + 'static,
+    {
+        let state = Arc::clone(&self.state);
+        self.execute(move || {
+            if Instant::now() > deadline {
+                state.expired.fetch_add(1, Ordering::SeqCst);
+                expired();
+            } else {
+                f();
+            }
+        });
+    }
+
+    /// 与 `try_execute` 相同，只是按照指定的优先级排队。
+    pub fn try_execute_with_priority<F>(&self, priority: Priority, f: F) -> Result<Submitted, F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.context.submit(priority, f)
+    }
+
+    /// 在 `delay` 之后把任务交给线程池执行，到期之前可以通过返回的句柄取消。
+    pub fn schedule<F>(&self, delay: Duration, f: F) -> ScheduledHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.timer().once(Instant::now() + delay, Box::new(f))
+    }
+
+    /// 在 `initial` 之后第一次执行，之后按照固定频率每隔 `period` 执行一次，直到通过返回的句柄取消。
+    ///
+    /// 到期时上一次执行尚未结束则跳过本次，同一个任务不会并发执行；错过的周期不会补执行。
+    ///
+    /// # Panics
+    ///
+    /// `period` 为0时会 panic。
+    pub fn schedule_at_fixed_rate<F>(&self, initial: Duration, period: Duration, f: F) -> ScheduledHandle
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        assert!(!period.is_zero(), "period must not be zero");
+        self.timer().repeat(Instant::now() + initial, period, Arc::new(f))
     }
+
+    fn timer(&self) -> &Timer {
+        self.timer.get_or_init(|| {
+            let name = format!("{}-timer", self.context.settings.name_prefix);
+            Timer::start(Arc::downgrade(&self.context), name).unwrap_or_else(|err| panic!("cannot spawn timer thread: {}", err))
+        })
+    }
+
+    /// 提交一个有返回值的任务，通过返回的 `TaskHandle` 等待结果，或者取消尚未开始执行的任务。
+    ///
+    /// 任务中的panic由 `TaskHandle::join` 以 `JoinError` 的形式返回，不会交给线程池的panic处理函数。
+    pub fn spawn<F, T>(&self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (task, handle) = task::task(f);
+        self.execute(move || task.run());
+        handle
+    }
+
+    /// 在线程池中执行一个 `Future`，通过返回的 `TaskHandle` 阻塞等待结果，或者在其他异步任务中 `.await`。
+    ///
+    /// 返回 `Pending` 期间不占用Worker；被唤醒时作为一条新的任务消息重新入队，由任意一个Worker继续轮询。
+    /// 第一次轮询之前可以取消；轮询中的panic由句柄以 `JoinError` 返回，不会交给线程池的panic处理函数。
+    pub fn spawn_future<F>(&self, future: F) -> TaskHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        executor::spawn(self, future)
+    }
+
+    /// 返回一个 `duration` 之后完成的 `Future`，由定时器线程唤醒，在异步任务中等待时不占用Worker。
+    pub fn sleep(&self, duration: Duration) -> Sleep {
+        self.sleep_until(Instant::now() + duration)
+    }
+
+    /// 返回一个在 `deadline` 完成的 `Future`。
+    pub fn sleep_until(&self, deadline: Instant) -> Sleep {
+        executor::sleep_until(self, deadline)
+    }
+
+    /// 创建一个作用域，作用域内提交的任务可以借用调用者栈上的数据，`scope` 返回前等待这些任务全部结束。
+    ///
+    /// 与 `std::thread::scope` 类似，只是任务在线程池中执行；不要在线程池的Worker中调用，
+    /// 所有Worker都在等待作用域结束时作用域内的任务将无法执行。
+    pub fn scope<'env, F, R>(&'env self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        task::scope(self, f)
+    }
+
+    /// 并行执行两个闭包并返回两者的结果：`b` 交给线程池，`a` 在当前线程中执行。
+    ///
+    /// 闭包可以借用调用者栈上的数据。可以在Worker中嵌套调用：`b` 还没有被其他线程取走时由当前线程执行，
+    /// 否则等待期间当前Worker帮忙执行队列中的其他任务，所以所有Worker都在 `join` 中等待也不会死锁。
+    /// 任一闭包panic时，在两者都结束之后把panic传递给调用者（`a` 的优先）。
+    pub fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA + Send,
+        B: FnOnce() -> RB + Send,
+        RA: Send,
+        RB: Send,
+    {
+        parallel::join(self, a, b)
+    }
+
+    /// 对每个元素并行执行 `f`，按照输入的顺序返回结果。
+    ///
+    /// 元素先收集起来，再递归地一分为二交给 `join`，直到每一段足够小。
+    pub fn par_map<I, F, R>(&self, items: I, f: F) -> Vec<R>
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(I::Item) -> R + Sync,
+        R: Send,
+    {
+        parallel::map(self, items.into_iter().collect(), &f)
+    }
+
+    /// 对每个元素并行执行 `f`，所有元素处理完后返回。
+    pub fn par_for_each<I, F>(&self, items: I, f: F)
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(I::Item) + Sync,
+    {
+        parallel::map(self, items.into_iter().collect(), &f);
+    }
+
+    /// 把切片按照 `chunk_size` 分块（最后一块可能较短），对每一块并行执行 `f`，按照块的顺序返回结果。
+    ///
+    /// # Panics
+    ///
+    /// `chunk_size` 为0时会 panic。
+    pub fn par_chunks<T, F, R>(&self, data: &[T], chunk_size: usize, f: F) -> Vec<R>
+    where
+        T: Sync,
+        F: Fn(&[T]) -> R + Sync,
+        R: Send,
+    {
+        assert!(chunk_size > 0, "chunk size must not be zero");
+        parallel::map(self, data.chunks(chunk_size).collect(), &f)
+    }
+
+    /// 并行归约：每一段从 `identity()` 开始用 `op` 依次合并，再把相邻的段按照原来的顺序合并。
+    ///
+    /// `op` 需要满足结合律，`identity()` 需要是 `op` 的单位元；没有元素时返回 `identity()`。
+    pub fn par_reduce<I, ID, OP>(&self, items: I, identity: ID, op: OP) -> I::Item
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        ID: Fn() -> I::Item + Sync,
+        OP: Fn(I::Item, I::Item) -> I::Item + Sync,
+    {
+        self.par_map_reduce(items, |item| item, identity, op)
+    }
+
+    /// 先对每个元素执行 `map`，再像 `par_reduce` 一样归约，例如并行求和：
+    /// `pool.par_map_reduce(&lines, |line| line.len(), || 0, |a, b| a + b)`。
+    pub fn par_map_reduce<I, M, R, ID, OP>(&self, items: I, map: M, identity: ID, op: OP) -> R
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        M: Fn(I::Item) -> R + Sync,
+        R: Send,
+        ID: Fn() -> R + Sync,
+        OP: Fn(R, R) -> R + Sync,
+    {
+        parallel::reduce(self, items.into_iter().collect(), &parallel::Reducer { map, identity, op })
+    }
+
+    // 提交已经装箱的任务，与execute一样按照拒绝策略处理
+    fn execute_job(&self, job: Job) {
+        let _ = self.try_execute(job);
+    }
+
+    /// 开始关闭：不再接受新的任务，队列中已有的任务（以及它们在Worker中提交的后续任务）执行完后Worker退出，
+    /// 尚未到期的定时任务不再执行。不会阻塞，配合 `await_termination` 等待关闭完成。
+    ///
+    /// 关闭之后在线程池外部提交的任务被拒绝：`try_execute` 返回 `Err`，`execute` 直接丢弃。
+    pub fn shutdown(&self) {
+        self.stop(false);
+    }
+
+    /// 立即关闭：在 `shutdown` 的基础上取出队列中尚未开始执行的任务并返回，正在执行的任务不受影响。
+    pub fn shutdown_now(&self) -> Vec<Job> {
+        self.stop(true)
+    }
+
+    /// 等待关闭完成（所有Worker都已经退出），最多等待 `timeout`，返回是否已经关闭完成。
+    ///
+    /// 没有调用 `shutdown` 或者 `shutdown_now` 时只会等到超时。
+    pub fn await_termination(&self, timeout: Duration) -> bool {
+        let terminated = self.context.terminated.lock().unwrap_or_else(PoisonError::into_inner);
+        let (terminated, _) = self
+            .context
+            .termination
+            .wait_timeout_while(terminated, timeout, |terminated| !*terminated)
+            .unwrap_or_else(PoisonError::into_inner);
+        *terminated
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.context.shutdown.load(Ordering::SeqCst)
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        *self.context.terminated.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // 关闭线程池，`now` 为true时取出队列中的任务
+    fn stop(&self, now: bool) -> Vec<Job> {
+        let context = &self.context;
+        // 多次调用时只有第一次需要通知Worker退出
+        let first = !context.shutdown.swap(true, Ordering::SeqCst);
+        if first {
+            context.emit(&PoolEvent::ShutdownStarted { now });
+            // 唤醒等待队列空位的提交者，它们的任务被拒绝
+            context.capacity.close();
+            // 不再触发定时任务，尚未到期的定时任务随之丢弃
+            if let Some(timer) = self.timer.get() {
+                timer.shutdown();
+            }
+        }
+        let jobs = if now { context.scheduler.drain() } else { Vec::new() };
+        self.state.queued.fetch_sub(jobs.len(), Ordering::SeqCst);
+        if first {
+            // 发出退出指令，每个运行中的线程一条；排在它之前的任务都会先被执行
+            for _ in 0..self.state.workers.load(Ordering::SeqCst) {
+                context.scheduler.push(Message::TERMINATE);
+            }
+        }
+        jobs
+    }
+
+    /// 获取线程池的监视器，监视器可以被克隆并在其他线程中读取线程池的运行状态。
+    pub fn monitor(&self) -> PoolMonitor {
+        PoolMonitor { state: Arc::clone(&self.state) }
+    }
+}
+
+// 线程池运行状态计数
+struct PoolState {
+    // 当前运行中的Worker线程数
+    workers: AtomicUsize,
+    // 已提交但尚未被Worker取走的任务数
+    queued: AtomicUsize,
+    // 正在执行任务的Worker数
+    active: AtomicUsize,
+    // 执行过程中panic的任务总数
+    panicked: AtomicUsize,
+    // 队列已满时被拒绝或者丢弃的任务总数
+    rejected: AtomicUsize,
+    // 开始执行时已经超过截止时间的任务总数
+    expired: AtomicUsize,
+    // 正常执行完毕的任务总数
+    completed: AtomicUsize,
+    // 每个Worker编号的累计计数，长度为最大线程数
+    per_worker: Box<[WorkerCounters]>,
+}
+
+impl PoolState {
+    fn new(max_threads: usize) -> PoolState {
+        PoolState {
+            workers: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            panicked: AtomicUsize::new(0),
+            rejected: AtomicUsize::new(0),
+            expired: AtomicUsize::new(0),
+            completed: AtomicUsize::new(0),
+            per_worker: (0..max_threads).map(|_| WorkerCounters::default()).collect(),
+        }
+    }
+}
+
+// 单个Worker编号的累计计数，编号上的线程被替换或者重新启动时继续累加
+#[derive(Default)]
+struct WorkerCounters {
+    // 执行任务的累计时间（纳秒）
+    busy: AtomicU64,
+    // 执行完毕（包括panic）的任务数
+    jobs: AtomicUsize,
+}
+
+/*
+    线程池监视器
+
+    只持有运行状态计数而不持有线程池本身，所以不会影响线程池的Drop
+ */
+#[derive(Clone)]
+pub struct PoolMonitor {
+    state: Arc<PoolState>,
+}
+
+impl PoolMonitor {
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            workers: self.state.workers.load(Ordering::SeqCst),
+            queued: self.state.queued.load(Ordering::SeqCst),
+            active: self.state.active.load(Ordering::SeqCst),
+            panicked: self.state.panicked.load(Ordering::SeqCst),
+            rejected: self.state.rejected.load(Ordering::SeqCst),
+            expired: self.state.expired.load(Ordering::SeqCst),
+            completed: self.state.completed.load(Ordering::SeqCst),
+        }
+    }
+
+    /// 每个Worker编号的累计统计，按编号排列，长度为最大线程数。
+    pub fn workers(&self) -> Vec<WorkerStats> {
+        self.state
+            .per_worker
+            .iter()
+            .enumerate()
+            .map(|(id, counters)| WorkerStats {
+                id,
+                busy: Duration::from_nanos(counters.busy.load(Ordering::SeqCst)),
+                jobs: counters.jobs.load(Ordering::SeqCst),
+            })
+            .collect()
+    }
+}
+
+/*
+    单个Worker编号的累计统计
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerStats {
+    pub id: usize,
+    // 执行任务的累计时间
+    pub busy: Duration,
+    // 执行完毕（包括panic）的任务数
+    pub jobs: usize,
+}
+
+/*
+    线程池运行状态快照
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    // 当前运行中的Worker线程数，介于核心线程数与最大线程数之间
+    pub workers: usize,
+    // 队列中等待执行的任务数
+    pub queued: usize,
+    // 正在执行任务的Worker数
+    pub active: usize,
+    // 执行过程中panic的任务总数
+    pub panicked: usize,
+    // 队列已满时被拒绝或者丢弃的任务总数（不包括在提交者线程中执行的任务）
+    pub rejected: usize,
+    // 开始执行时已经超过截止时间而被放弃的任务总数
+    pub expired: usize,
+    // 正常执行完毕的任务总数
+    pub completed: usize,
+}
+
+/*
+    任务优先级，`execute` 提交的任务为Normal
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/*
+    任务panic的信息，交给线程池的panic处理函数
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobPanic {
+    // 执行该任务的Worker编号
+    pub worker: usize,
+    // panic消息，payload不是字符串时为 "Box<dyn Any>"
+    pub message: String,
 }
 
 // 为线程池实现Drop特征用于进行相关的清理工作
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        println!{"Sending terminate message to all workers!"};
-        // 发出退出指令
-        for _ in &self.workers {
-            self.sender.send(Message::TERMINATE);
-        } 
-        println!{"Shutting down all workers!"};
-        // 退出
-        for worker in &mut self.workers {
-            println!("[Worker(id = {})] shutting down!", worker.id);
-            if let Some(thread) =  worker.thread.take() {
-                thread.join().unwrap();
+        // 与shutdown相同：执行完队列中的任务后退出；已经调用过shutdown_now时队列已经是空的
+        self.stop(false);
+        for worker in self.context.workers.iter() {
+            // 任务panic后Worker线程会被新的线程替换，一直等到最新的线程退出为止
+            loop {
+                let thread = worker.thread.lock().unwrap_or_else(PoisonError::into_inner).take();
+                match thread {
+                    Some(thread) => {
+                        let _ = thread.join();
+                    }
+                    None => break,
+                }
             }
         }
     }
@@ -83,41 +569,615 @@ impl Drop for ThreadPool {
     ❓ 为什么需要这个结构体
     因为处理逻辑被封装为闭包一旦被传入则闭包函数就会直接执行
     所以需要一个类似描述线程元数据结构体记录请求的元数据，这里包括被封装闭包，以及等地执行任何的线程
+
+    每个Worker编号对应一个槽位，槽位数等于最大线程数；线程空闲退出后槽位可以被新的线程重新使用
  */
+#[derive(Default)]
 struct Worker {
-    id: usize,
-    thread: Option<JoinHandle<()>>,
+    // 当前的线程句柄，线程被替换时由旧线程写入新线程的句柄
+    thread: Mutex<Option<JoinHandle<()>>>,
+    // 槽位上是否有线程在运行（或者正在启动）
+    running: AtomicBool,
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-        println!("[Worker(id = {})] startup", id);
-        let thread = thread::spawn(move || {
-            // 为了让每个Worker能够不断的执行请求，这里需要将整个逻辑让入死循环中
-            // 如果从channel中能够recv到任务就是执行，否则就阻塞等待
-            loop {
-                // 从通道中获取执行请求进行执行
-                // result: Receiver<Box<..>>
-                let command = receiver.lock().unwrap().recv().unwrap();
-
-                match command {
-                    // 执行请求
-                    Message::NEW_JOB(job) => {
-                        println!("[Worker(id = {})] execute job", id);
-                        // 这里是封装的代码，然后进行真正的执行
-                        job();
+    // 启动Worker线程并记录句柄；持有句柄锁直到记录完成，避免新线程替换自己时被旧句柄覆盖
+    fn spawn(context: &Arc<WorkerContext>, id: usize) -> io::Result<()> {
+        let mut handle = context.workers[id].thread.lock().unwrap_or_else(PoisonError::into_inner);
+        let thread = {
+            let context = Arc::clone(context);
+            context.settings.thread(id).spawn(move || Worker::run(id, context))?
+        };
+        // 旧线程已经退出或者正在退出（被替换的panic线程），不再需要等待
+        *handle = Some(thread);
+        context.emit(&PoolEvent::WorkerStarted { worker: id });
+        Ok(())
+    }
+
+    fn run(id: usize, context: Arc<WorkerContext>) {
+        let state = &context.state;
+        let settings = &context.settings;
+        let counters = &state.per_worker[id];
+        context.scheduler.register(id);
+        context.call_hook(&settings.on_thread_start, id);
+        let idle_timeout = settings.idle_timeout();
+        // 为了让每个Worker能够不断的执行请求，这里需要将整个逻辑让入死循环中
+        // 如果从队列中能够取到任务就是执行，否则就阻塞等待
+        let reason = loop {
+            match context.scheduler.pop(id, idle_timeout) {
+                // 执行请求
+                Some(Message::NEW_JOB(job)) => {
+                    state.queued.fetch_sub(1, Ordering::SeqCst);
+                    context.capacity.release();
+                    state.active.fetch_add(1, Ordering::SeqCst);
+                    let started = Instant::now();
+                    // 这里是封装的代码，然后进行真正的执行；任务panic时在这里捕获，不会波及线程池
+                    let result = panic::catch_unwind(AssertUnwindSafe(job));
+                    counters.busy.fetch_add(started.elapsed().as_nanos() as u64, Ordering::SeqCst);
+                    counters.jobs.fetch_add(1, Ordering::SeqCst);
+                    state.active.fetch_sub(1, Ordering::SeqCst);
+                    match result {
+                        Ok(()) => {
+                            state.completed.fetch_add(1, Ordering::SeqCst);
+                        }
+                        Err(payload) => {
+                            context.report_panic(id, payload);
+                            break StopReason::Panicked;
+                        }
                     }
-                    // 告知终止
-                    Message::TERMINATE => {
-                        println!("[Worker(id = {})] was told to terminate!", id);
-                        break;
+                }
+                // 告知终止
+                Some(Message::TERMINATE) => break StopReason::Terminated,
+                // 空闲超时，线程数多于核心线程数时退出
+                None => {
+                    if context.retire() {
+                        break StopReason::Idle;
                     }
-                } 
+                }
             }
-        });
-        Worker { id, thread: Some(thread) }
+        };
+        context.call_hook(&settings.on_thread_stop, id);
+        context.emit(&PoolEvent::WorkerStopped { worker: id, reason });
+        match reason {
+            // 任务可能留下了不一致的线程局部状态，换一个新的线程继续工作
+            StopReason::Panicked => {
+                if let Err(error) = Worker::spawn(&context, id) {
+                    context.emit(&PoolEvent::SpawnFailed { worker: id, error: &error });
+                    context.release(id);
+                }
+            }
+            // 线程数已经在retire中减少
+            StopReason::Idle => context.workers[id].running.store(false, Ordering::SeqCst),
+            StopReason::Terminated => context.release(id),
+        }
+    }
+}
+
+// Worker之间共享的上下文
+struct WorkerContext {
+    scheduler: Scheduler,
+    capacity: Capacity,
+    state: Arc<PoolState>,
+    workers: Box<[Worker]>,
+    // 线程池正在关闭，不再接受外部提交的任务，也不再增加线程
+    shutdown: AtomicBool,
+    // 关闭之后所有Worker都已经退出
+    terminated: Mutex<bool>,
+    termination: Condvar,
+    settings: Settings,
+}
+
+impl WorkerContext {
+    // 提交任务，队列已满时按照拒绝策略处理
+    fn submit<F>(self: &Arc<Self>, priority: Priority, f: F) -> Result<Submitted, F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let queued = &self.state.queued;
+        // 关闭期间Worker中提交的任务（例如作用域任务）仍然接受，它们属于正在排空的任务
+        if self.shutdown.load(Ordering::SeqCst) && !self.scheduler.is_worker() {
+            self.state.rejected.fetch_add(1, Ordering::SeqCst);
+            return Err(f);
+        }
+        if self.capacity.reserve(queued) {
+            self.push(Box::new(f), priority);
+            return Ok(Submitted::Queued);
+        }
+        match self.settings.rejection_policy {
+            // 在Worker中阻塞等待空位可能导致所有Worker互相等待，改为在当前线程中执行
+            RejectionPolicy::Block if !self.scheduler.is_worker() => {
+                if !self.capacity.wait(queued) {
+                    // 等待期间线程池已经关闭
+                    self.state.rejected.fetch_add(1, Ordering::SeqCst);
+                    return Err(f);
+                }
+                self.push(Box::new(f), priority);
+                Ok(Submitted::Queued)
+            }
+            RejectionPolicy::Block | RejectionPolicy::CallerRuns => {
+                f();
+                Ok(Submitted::RanOnCaller)
+            }
+            RejectionPolicy::DropNewest => {
+                self.state.rejected.fetch_add(1, Ordering::SeqCst);
+                drop(f);
+                Ok(Submitted::Discarded)
+            }
+            RejectionPolicy::DropOldest => {
+                let mut displaced = false;
+                // 腾出的位置可能被其他提交者抢先占用，继续丢弃直到占到位置
+                while !self.capacity.reserve(queued) {
+                    match self.scheduler.evict_oldest() {
+                        Some(job) => {
+                            queued.fetch_sub(1, Ordering::SeqCst);
+                            self.state.rejected.fetch_add(1, Ordering::SeqCst);
+                            displaced = true;
+                            drop(job);
+                        }
+                        // 排队的任务刚刚被Worker取走，位置马上就会归还
+                        None => thread::yield_now(),
+                    }
+                }
+                self.push(Box::new(f), priority);
+                Ok(if displaced { Submitted::DisplacedOldest } else { Submitted::Queued })
+            }
+            RejectionPolicy::Abort => {
+                self.state.rejected.fetch_add(1, Ordering::SeqCst);
+                Err(f)
+            }
+        }
+    }
+
+    // 已经占用了队列中的位置
+    fn push(self: &Arc<Self>, job: Job, priority: Priority) {
+        // 将执行请求放入队列中于后续执行
+        self.scheduler.push_prioritized(job, priority);
+        self.grow();
+    }
+
+    // 提交fork-join中被拆分出去的任务，不受关闭状态与拒绝策略影响，队列已满时交还任务
+    fn fork(self: &Arc<Self>, job: Job) -> Result<(), Job> {
+        if self.capacity.reserve(&self.state.queued) {
+            self.push(job, Priority::Normal);
+            Ok(())
+        } else {
+            Err(job)
+        }
+    }
+
+    // 重新放入被唤醒的异步任务，不受关闭状态、队列容量与拒绝策略影响：丢弃它会让等待者得不到结果
+    fn enqueue(self: &Arc<Self>, job: Job) {
+        self.state.queued.fetch_add(1, Ordering::SeqCst);
+        self.push(job, Priority::Normal);
+    }
+
+    // 等待fork-join任务的第 `id` 个Worker帮忙执行一个本地队列中的或者窃取到的任务，没有任务时返回false；
+    // 这段时间已经计入外层任务的忙碌时间，任务panic时只报告，不替换当前线程（它还在等待被拆分出去的任务）
+    fn help(&self, id: usize) -> bool {
+        let job = match self.scheduler.try_pop_local(id) {
+            Some(job) => job,
+            None => return false,
+        };
+        self.state.queued.fetch_sub(1, Ordering::SeqCst);
+        self.capacity.release();
+        let result = panic::catch_unwind(AssertUnwindSafe(job));
+        self.state.per_worker[id].jobs.fetch_add(1, Ordering::SeqCst);
+        match result {
+            Ok(()) => {
+                self.state.completed.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(payload) => self.report_panic(id, payload),
+        }
+        true
+    }
+
+    // 任务panic：计数、产生事件并调用panic处理函数
+    fn report_panic(&self, id: usize, payload: Box<dyn Any + Send>) {
+        self.state.panicked.fetch_add(1, Ordering::SeqCst);
+        let report = JobPanic { worker: id, message: panic_message(payload.as_ref()) };
+        self.emit(&PoolEvent::JobPanicked(&report));
+        // 处理函数本身panic时同样不能让线程池失去Worker
+        if let Some(handler) = &self.settings.panic_handler {
+            if panic::catch_unwind(AssertUnwindSafe(|| handler(&report))).is_err() {
+                self.emit(&PoolEvent::CallbackPanicked { worker: id, callback: "panic handler" });
+            }
+        }
+    }
+
+    // 在一个空闲槽位上启动新的Worker线程，没有空闲槽位或者线程池正在关闭时返回false
+    fn start_worker(self: &Arc<Self>) -> Result<bool, (usize, io::Error)> {
+        for (id, worker) in self.workers.iter().enumerate() {
+            if worker.running.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                self.state.workers.fetch_add(1, Ordering::SeqCst);
+                // 增加计数之后再检查：关闭时要么看到这个线程并为它发出退出指令，要么这里看到关闭标志
+                if self.shutdown.load(Ordering::SeqCst) {
+                    self.release(id);
+                    return Ok(false);
+                }
+                if let Err(err) = Worker::spawn(self, id) {
+                    self.release(id);
+                    return Err((id, err));
+                }
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // 排队与执行中的任务数超过线程数时增加一个线程，最多到最大线程数
+    fn grow(self: &Arc<Self>) {
+        let workers = self.state.workers.load(Ordering::SeqCst);
+        if workers >= self.settings.max_threads || self.shutdown.load(Ordering::SeqCst) {
+            return;
+        }
+        let busy = self.state.queued.load(Ordering::SeqCst) + self.state.active.load(Ordering::SeqCst);
+        if busy > workers {
+            if let Err((worker, error)) = self.start_worker() {
+                self.emit(&PoolEvent::SpawnFailed { worker, error: &error });
+            }
+        }
+    }
+
+    // 线程数多于核心线程数时减少一个，返回调用的线程是否应当退出
+    fn retire(&self) -> bool {
+        let core = self.settings.core_threads;
+        self.state
+            .workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |workers| (workers > core).then(|| workers - 1))
+            .is_ok()
+    }
+
+    // 槽位上的线程退出，槽位可以被重新使用；关闭之后最后一个线程退出时通知等待者
+    fn release(&self, id: usize) {
+        let remaining = self.state.workers.fetch_sub(1, Ordering::SeqCst) - 1;
+        self.workers[id].running.store(false, Ordering::SeqCst);
+        if remaining == 0 && self.shutdown.load(Ordering::SeqCst) {
+            *self.terminated.lock().unwrap_or_else(PoisonError::into_inner) = true;
+            self.termination.notify_all();
+            self.emit(&PoolEvent::Terminated);
+        }
+    }
+
+    // 监听器panic时忽略，不影响Worker
+    fn emit(&self, event: &PoolEvent<'_>) {
+        if let Some(listener) = &self.settings.listener {
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| listener(event)));
+        }
+    }
+
+    // 线程启动与退出的回调panic时只产生一个事件，不影响Worker
+    fn call_hook(&self, hook: &Option<Hook>, id: usize) {
+        if let Some(hook) = hook {
+            if panic::catch_unwind(AssertUnwindSafe(|| hook(id))).is_err() {
+                self.emit(&PoolEvent::CallbackPanicked { worker: id, callback: "thread hook" });
+            }
+        }
     }
 }
 
-// 定义请求提交任务类型别名，用于代表存储执行请求的闭包类型
-type Job = Box<dyn FnOnce() + Send + 'static>;
\ No newline at end of file
+// panic的payload通常是 &str 或者 String
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("Box<dyn Any>")
+    }
+}
+
+// 定义请求提交任务类型别名，用于代表存储执行请求的闭包类型；`shutdown_now` 以这个类型返回尚未执行的任务
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Barrier};
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn survive_panicking_jobs() {
+        let (reports, received) = mpsc::channel();
+        let reports = Mutex::new(reports);
+        let pool = ThreadPool::with_panic_handler(2, move |panic: &JobPanic| {
+            reports.lock().unwrap().send(panic.clone()).unwrap();
+        });
+        for n in 0..4 {
+            pool.execute(move || panic!("job {} failed", n));
+        }
+        pool.execute(|| std::panic::panic_any(42));
+
+        // 所有Worker都经历过panic之后仍然能够执行任务
+        let (sender, results) = mpsc::channel();
+        for n in 0..8 {
+            let sender = sender.clone();
+            pool.execute(move || sender.send(n).unwrap());
+        }
+        let mut done: Vec<i32> = (0..8).map(|_| results.recv_timeout(Duration::from_secs(10)).unwrap()).collect();
+        done.sort();
+        assert_eq!(done, (0..8).collect::<Vec<_>>());
+
+        let mut messages: Vec<String> = (0..5).map(|_| received.recv_timeout(Duration::from_secs(10)).unwrap().message).collect();
+        messages.sort();
+        assert_eq!(messages, ["Box<dyn Any>", "job 0 failed", "job 1 failed", "job 2 failed", "job 3 failed"]);
+        let stats = pool.monitor().stats();
+        assert_eq!((stats.workers, stats.panicked), (2, 5));
+        // Drop等待被替换后的线程退出
+        drop(pool);
+    }
+
+    #[test]
+    fn survive_panicking_handler() {
+        let pool = ThreadPool::with_panic_handler(1, |_: &JobPanic| panic!("handler failed"));
+        pool.execute(|| panic!("job failed"));
+        let (sender, result) = mpsc::channel();
+        pool.execute(move || sender.send(()).unwrap());
+        assert!(result.recv_timeout(Duration::from_secs(10)).is_ok());
+        assert_eq!(pool.monitor().stats().panicked, 1);
+    }
+
+    #[test]
+    fn grow_and_retire_idle_threads() {
+        let pool = ThreadPool::builder().core_threads(1).max_threads(4).keep_alive(Duration::from_millis(100)).build().unwrap();
+        assert_eq!(pool.monitor().stats().workers, 1);
+        // 4个任务必须同时执行才能通过屏障，线程池需要增加到4个线程
+        let barrier = Arc::new(Barrier::new(5));
+        for _ in 0..4 {
+            let barrier = Arc::clone(&barrier);
+            pool.execute(move || {
+                barrier.wait();
+            });
+        }
+        barrier.wait();
+        assert_eq!(pool.monitor().stats().workers, 4);
+
+        // 空闲超过存活时间后回到核心线程数
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while pool.monitor().stats().workers > 1 {
+            assert!(Instant::now() < deadline, "idle threads were not retired");
+            thread::sleep(Duration::from_millis(20));
+        }
+        // 退出后的槽位可以被重新使用
+        let (sender, result) = mpsc::channel();
+        pool.execute(move || sender.send(()).unwrap());
+        assert!(result.recv_timeout(Duration::from_secs(10)).is_ok());
+    }
+
+    #[test]
+    fn reject_invalid_configuration() {
+        assert!(matches!(ThreadPool::try_new(0), Err(BuildError::ZeroThreads)));
+        let err = ThreadPool::builder().core_threads(4).max_threads(2).build().err().unwrap();
+        assert!(matches!(err, BuildError::CoreExceedsMax { core: 4, max: 2 }));
+        assert_eq!(err.to_string(), "core threads (4) must not exceed max threads (2)");
+        assert!(matches!(ThreadPool::builder().stack_size(0).build(), Err(BuildError::ZeroStackSize)));
+    }
+
+    #[test]
+    fn name_threads_and_call_hooks() {
+        let (events, received) = mpsc::channel();
+        let (start, stop) = (Mutex::new(events.clone()), Mutex::new(events));
+        let pool = ThreadPool::builder()
+            .core_threads(2)
+            .name_prefix("test")
+            .stack_size(256 * 1024)
+            .on_thread_start(move |id| start.lock().unwrap().send(format!("start {}", id)).unwrap())
+            .on_thread_stop(move |id| stop.lock().unwrap().send(format!("stop {}", id)).unwrap())
+            .build()
+            .unwrap();
+        let name = pool.spawn(|| thread::current().name().map(String::from)).join().unwrap().unwrap();
+        assert!(name == "test-0" || name == "test-1", "unexpected thread name {:?}", name);
+        drop(pool);
+
+        let mut events: Vec<String> = received.try_iter().collect();
+        events.sort();
+        assert_eq!(events, ["start 0", "start 1", "stop 0", "stop 1"]);
+    }
+
+    // 唯一的Worker被阻塞，放开返回的发送端之后Worker继续执行
+    fn blocked(builder: Builder) -> (ThreadPool, mpsc::Sender<()>) {
+        let pool = builder.core_threads(1).build().unwrap();
+        let (gate, wait) = mpsc::channel();
+        let (started, running) = mpsc::channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            wait.recv().unwrap();
+        });
+        running.recv_timeout(Duration::from_secs(10)).unwrap();
+        (pool, gate)
+    }
+
+    // 单个Worker被阻塞、容量为2的队列已经排满
+    fn saturated(policy: RejectionPolicy, done: &mpsc::Sender<usize>) -> (ThreadPool, mpsc::Sender<()>) {
+        let (pool, gate) = blocked(ThreadPool::builder().queue_capacity(2).rejection_policy(policy));
+        for n in 0..2 {
+            let done = done.clone();
+            assert_eq!(pool.try_execute(move || done.send(n).unwrap()).ok(), Some(Submitted::Queued));
+        }
+        (pool, gate)
+    }
+
+    #[test]
+    fn apply_rejection_policies() {
+        let (done, finished) = mpsc::channel();
+
+        let (pool, gate) = saturated(RejectionPolicy::Abort, &done);
+        assert!(pool.try_execute(|| ()).is_err());
+        assert_eq!(pool.monitor().stats().rejected, 1);
+        gate.send(()).unwrap();
+        drop(pool);
+        assert_eq!(finished.try_iter().collect::<Vec<_>>(), [0, 1]);
+
+        let (pool, gate) = saturated(RejectionPolicy::DropNewest, &done);
+        let sender = done.clone();
+        assert_eq!(pool.try_execute(move || sender.send(2).unwrap()).ok(), Some(Submitted::Discarded));
+        gate.send(()).unwrap();
+        drop(pool);
+        assert_eq!(finished.try_iter().collect::<Vec<_>>(), [0, 1]);
+
+        let (pool, gate) = saturated(RejectionPolicy::DropOldest, &done);
+        let sender = done.clone();
+        assert_eq!(pool.try_execute(move || sender.send(2).unwrap()).ok(), Some(Submitted::DisplacedOldest));
+        assert_eq!((pool.monitor().stats().queued, pool.monitor().stats().rejected), (2, 1));
+        gate.send(()).unwrap();
+        drop(pool);
+        assert_eq!(finished.try_iter().collect::<Vec<_>>(), [1, 2]);
+
+        let (pool, gate) = saturated(RejectionPolicy::CallerRuns, &done);
+        let caller = thread::current().id();
+        let (sender, ran_on) = mpsc::channel();
+        assert_eq!(pool.try_execute(move || sender.send(thread::current().id()).unwrap()).ok(), Some(Submitted::RanOnCaller));
+        assert_eq!(ran_on.try_recv().unwrap(), caller);
+        gate.send(()).unwrap();
+    }
+
+    #[test]
+    fn block_until_queue_has_space() {
+        let (done, finished) = mpsc::channel();
+        let (pool, gate) = saturated(RejectionPolicy::Block, &done);
+        thread::scope(|scope| {
+            let submitter = scope.spawn(|| {
+                let done = done.clone();
+                pool.try_execute(move || done.send(2).unwrap()).ok()
+            });
+            thread::sleep(Duration::from_millis(50));
+            assert!(!submitter.is_finished());
+            gate.send(()).unwrap();
+            assert_eq!(submitter.join().unwrap(), Some(Submitted::Queued));
+        });
+        drop(pool);
+        assert_eq!(finished.try_iter().collect::<Vec<_>>(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn run_high_priority_jobs_first() {
+        let (done, finished) = mpsc::channel();
+        let (pool, gate) = blocked(ThreadPool::builder().aging_interval(Duration::from_secs(60)));
+        for (n, priority) in [(0, Priority::Normal), (1, Priority::Low), (2, Priority::Normal), (3, Priority::High)] {
+            let done = done.clone();
+            pool.execute_with_priority(priority, move || done.send(n).unwrap());
+        }
+        gate.send(()).unwrap();
+        drop(pool);
+        assert_eq!(finished.try_iter().collect::<Vec<_>>(), [3, 0, 2, 1]);
+    }
+
+    #[test]
+    fn age_low_priority_jobs() {
+        let (done, finished) = mpsc::channel();
+        let (pool, gate) = blocked(ThreadPool::builder().aging_interval(Duration::from_millis(20)));
+        let sender = done.clone();
+        pool.execute_with_priority(Priority::Low, move || sender.send(0).unwrap());
+        // 等待超过一个老化间隔之后，低优先级任务不再排在普通任务之后
+        thread::sleep(Duration::from_millis(50));
+        for n in 1..3 {
+            let done = done.clone();
+            pool.execute(move || done.send(n).unwrap());
+        }
+        gate.send(()).unwrap();
+        drop(pool);
+        assert_eq!(finished.try_iter().collect::<Vec<_>>(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn drop_jobs_past_deadline() {
+        let (done, finished) = mpsc::channel();
+        let (pool, gate) = blocked(ThreadPool::builder());
+        for (n, deadline) in [(0, Duration::from_millis(10)), (1, Duration::from_secs(60))] {
+            let (done, expired) = (done.clone(), done.clone());
+            pool.execute_before(Instant::now() + deadline, move || done.send(n).unwrap(), move || expired.send(n + 10).unwrap());
+        }
+        thread::sleep(Duration::from_millis(30));
+        gate.send(()).unwrap();
+        let monitor = pool.monitor();
+        drop(pool);
+        assert_eq!(finished.try_iter().collect::<Vec<_>>(), [10, 1]);
+        assert_eq!(monitor.stats().expired, 1);
+    }
+
+    #[test]
+    fn shutdown_drains_queue() {
+        let pool = ThreadPool::new(2);
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..10 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(5));
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        assert!(!pool.await_termination(Duration::from_millis(1)));
+        pool.shutdown();
+        assert!(pool.is_shutdown());
+        assert!(pool.try_execute(|| ()).is_err());
+        assert!(pool.await_termination(Duration::from_secs(10)));
+        assert!(pool.is_terminated());
+        assert_eq!(done.load(Ordering::SeqCst), 10);
+        let stats = pool.monitor().stats();
+        assert_eq!((stats.workers, stats.completed, stats.rejected), (0, 10, 1));
+    }
+
+    #[test]
+    fn shutdown_now_returns_queued_jobs() {
+        let (pool, gate) = blocked(ThreadPool::builder());
+        let done = Arc::new(AtomicUsize::new(0));
+        for priority in [Priority::Normal, Priority::High, Priority::Low] {
+            let done = Arc::clone(&done);
+            pool.execute_with_priority(priority, move || {
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        let jobs = pool.shutdown_now();
+        assert_eq!((jobs.len(), pool.monitor().stats().queued), (3, 0));
+        gate.send(()).unwrap();
+        assert!(pool.await_termination(Duration::from_secs(10)));
+        assert_eq!(done.load(Ordering::SeqCst), 0);
+        jobs.into_iter().for_each(|job| job());
+        assert_eq!(done.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn report_events_and_busy_time() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let pool = {
+            let events = Arc::clone(&events);
+            ThreadPool::builder()
+                .core_threads(1)
+                .event_listener(move |event| events.lock().unwrap().push(event.to_string()))
+                .build()
+                .unwrap()
+        };
+        let monitor = pool.monitor();
+        pool.execute(|| thread::sleep(Duration::from_millis(20)));
+        pool.execute(|| panic!("job failed"));
+        let deadline = Instant::now() + Duration::from_secs(10);
+        // 等待替换panic线程的新线程启动
+        while events.lock().unwrap().len() < 4 {
+            assert!(Instant::now() < deadline, "worker was not replaced");
+            thread::sleep(Duration::from_millis(5));
+        }
+        drop(pool);
+
+        let events = events.lock().unwrap().clone();
+        assert_eq!(
+            events,
+            [
+                "[Worker(id = 0)] startup",
+                "[Worker(id = 0)] job panicked: job failed",
+                "[Worker(id = 0)] replaced after a panic",
+                "[Worker(id = 0)] startup",
+                "[ThreadPool] shutting down, draining the queue",
+                "[Worker(id = 0)] was told to terminate!",
+                "[ThreadPool] terminated",
+            ]
+        );
+        let worker = monitor.workers()[0];
+        assert_eq!(worker.jobs, 2);
+        assert!(worker.busy >= Duration::from_millis(20));
+        assert_eq!((monitor.stats().completed, monitor.stats().panicked), (1, 1));
+    }
+}
@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Shutdown, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::http::{Request, Response, StatusCode};
+use crate::middleware::{Handler, Middleware};
+
+// 令牌桶表的容量上限：达到上限时清理已经回满的桶，仍然超过上限的3/4时按照最后一次请求的时间淘汰最早的桶，
+// 大量不同的客户端地址不会让表无限增长，清理的开销也分摊到之后的多次请求上
+const MAX_BUCKETS: usize = 4096;
+// 拒绝日志的最小间隔，间隔内的其他拒绝只计数，在下一行日志中汇总
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/*
+    速率：每个客户端地址每秒补充per_second个令牌，桶中最多积攒burst个令牌，每个请求消耗一个令牌
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    pub per_second: u32,
+    pub burst: u32,
+}
+
+/*
+    准入策略，None表示不限制
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Policy {
+    // 每个客户端地址的请求速率
+    pub rate: Option<Rate>,
+    // 同时打开的连接总数
+    pub max_connections: Option<usize>,
+    // 每个客户端地址同时打开的连接数
+    pub max_connections_per_ip: Option<usize>,
+    // 线程池队列中等待执行的任务数，超过后新的连接（事件驱动模式下为新的请求）直接得到503
+    pub max_queue: Option<usize>,
+}
+
+/*
+    拒绝原因
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    QueueFull,
+    TooManyConnections,
+    TooManyConnectionsPerIp,
+    // 需要等待多久才会有新的令牌
+    RateLimited(Duration),
+}
+
+impl Rejection {
+    /// 服务端整体过载时为503，单个客户端超出限制时为429。
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Rejection::QueueFull | Rejection::TooManyConnections => StatusCode::SERVICE_UNAVAILABLE,
+            Rejection::TooManyConnectionsPerIp | Rejection::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    /// 建议客户端重试前等待的秒数，至少为1。
+    pub fn retry_after(&self) -> u64 {
+        match self {
+            Rejection::RateLimited(wait) => wait.as_secs() + u64::from(wait.subsec_nanos() > 0),
+            _ => 1,
+        }
+        .max(1)
+    }
+
+    pub fn response(&self) -> Response {
+        Response::from(self.status()).with_header("Retry-After", self.retry_after().to_string())
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::QueueFull => write!(f, "accept queue is full"),
+            Rejection::TooManyConnections => write!(f, "too many connections"),
+            Rejection::TooManyConnectionsPerIp => write!(f, "too many connections from this address"),
+            Rejection::RateLimited(_) => write!(f, "rate limit exceeded"),
+        }
+    }
+}
+
+/*
+    准入控制器
+
+    在接受连接时检查连接数上限与线程池队列长度，在处理请求时（作为中间件）按照客户端地址做令牌桶限速；
+    可以廉价地克隆，所有克隆共享同一份计数。拒绝时输出带有当前计数的日志，每LOG_INTERVAL最多一行，
+    期间省略的拒绝次数记在下一行日志中；完整的计数可以通过stats读取
+ */
+#[derive(Clone)]
+pub struct Limiter {
+    policy: Policy,
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    // 每个客户端地址当前打开的连接数
+    connections: Mutex<HashMap<IpAddr, usize>>,
+    // 当前打开的连接总数
+    active: AtomicUsize,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    accepted: AtomicU64,
+    rejected_queue_full: AtomicU64,
+    rejected_connections: AtomicU64,
+    rejected_per_ip: AtomicU64,
+    rate_limited: AtomicU64,
+    // 上一次输出拒绝日志的时间，以及之后省略的拒绝次数
+    last_log: Mutex<Option<Instant>>,
+    suppressed: AtomicU64,
+}
+
+struct Bucket {
+    tokens: f64,
+    // 上一次补充令牌的时间，也就是该地址最后一次请求的时间
+    updated: Instant,
+}
+
+impl Bucket {
+    // 按照经过的时间补充令牌
+    fn refill(&mut self, now: Instant, rate: Rate) {
+        self.tokens = self.tokens_at(now, rate);
+        self.updated = now;
+    }
+
+    fn tokens_at(&self, now: Instant, rate: Rate) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * f64::from(rate.per_second)).min(f64::from(rate.burst))
+    }
+}
+
+// 清理令牌桶表：回满的桶与新建的桶没有区别，直接删除；剩余的桶仍然太多时淘汰最久没有请求的地址
+fn prune(buckets: &mut HashMap<IpAddr, Bucket>, now: Instant, rate: Rate) {
+    buckets.retain(|_, bucket| bucket.tokens_at(now, rate) < f64::from(rate.burst));
+    let keep = MAX_BUCKETS / 4 * 3;
+    if buckets.len() > keep {
+        let mut oldest: Vec<(Instant, IpAddr)> = buckets.iter().map(|(ip, bucket)| (bucket.updated, *ip)).collect();
+        oldest.sort_unstable();
+        for (_, ip) in &oldest[..oldest.len() - keep] {
+            buckets.remove(ip);
+        }
+    }
+}
+
+/*
+    准入控制器计数快照
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LimiterStats {
+    // 当前打开的连接数
+    pub connections: usize,
+    // 累计接受的连接数
+    pub accepted: u64,
+    // 因为队列已满被拒绝的连接（或请求）数
+    pub rejected_queue_full: u64,
+    // 因为连接总数超限被拒绝的连接数
+    pub rejected_connections: u64,
+    // 因为单个地址的连接数超限被拒绝的连接数
+    pub rejected_per_ip: u64,
+    // 因为速率超限被拒绝的请求数
+    pub rate_limited: u64,
+}
+
+impl fmt::Display for LimiterStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "connections={} accepted={} rejected_queue_full={} rejected_connections={} rejected_per_ip={} rate_limited={}",
+            self.connections,
+            self.accepted,
+            self.rejected_queue_full,
+            self.rejected_connections,
+            self.rejected_per_ip,
+            self.rate_limited
+        )
+    }
+}
+
+impl Limiter {
+    /// 速率与桶容量至少为1。
+    pub fn new(mut policy: Policy) -> Limiter {
+        policy.rate = policy.rate.map(|rate| Rate { per_second: rate.per_second.max(1), burst: rate.burst.max(1) });
+        Limiter { policy, shared: Arc::new(Shared::default()) }
+    }
+
+    /// 不做任何限制，只统计连接数。
+    pub fn unlimited() -> Limiter {
+        Limiter::new(Policy::default())
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    /// 检查线程池队列长度，`queued` 为当前等待执行的任务数。
+    pub fn check_queue(&self, queued: usize) -> Result<(), Rejection> {
+        match self.policy.max_queue {
+            Some(max_queue) if queued >= max_queue => Err(self.reject(None, Rejection::QueueFull)),
+            _ => Ok(()),
+        }
+    }
+
+    /// 登记一个新连接，返回的守卫被丢弃时连接数减一。
+    pub fn connect(&self, ip: IpAddr) -> Result<ConnectionGuard, Rejection> {
+        let mut connections = self.shared.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let rejection = match self.policy {
+            Policy { max_connections: Some(max), .. } if self.shared.active.load(Ordering::SeqCst) >= max => {
+                Rejection::TooManyConnections
+            }
+            Policy { max_connections_per_ip: Some(max), .. } if connections.get(&ip).copied().unwrap_or(0) >= max => {
+                Rejection::TooManyConnectionsPerIp
+            }
+            _ => {
+                *connections.entry(ip).or_insert(0) += 1;
+                self.shared.active.fetch_add(1, Ordering::SeqCst);
+                self.shared.accepted.fetch_add(1, Ordering::SeqCst);
+                return Ok(ConnectionGuard { shared: Arc::clone(&self.shared), ip });
+            }
+        };
+        // 释放锁之后再记录拒绝，输出日志时需要读取计数
+        drop(connections);
+        Err(self.reject(Some(ip), rejection))
+    }
+
+    /// 消耗客户端地址的一个令牌，没有令牌时返回需要等待的时间。
+    pub fn check_rate(&self, ip: IpAddr) -> Result<(), Rejection> {
+        let rate = match self.policy.rate {
+            Some(rate) => rate,
+            None => return Ok(()),
+        };
+        let now = Instant::now();
+        let wait = {
+            let mut buckets = self.shared.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&ip) {
+                prune(&mut buckets, now, rate);
+            }
+            let bucket = buckets.entry(ip).or_insert(Bucket { tokens: f64::from(rate.burst), updated: now });
+            bucket.refill(now, rate);
+            if bucket.tokens >= 1.0 {
+                bucket.tokens -= 1.0;
+                return Ok(());
+            }
+            Duration::from_secs_f64((1.0 - bucket.tokens) / f64::from(rate.per_second))
+        };
+        Err(self.reject(Some(ip), Rejection::RateLimited(wait)))
+    }
+
+    pub fn stats(&self) -> LimiterStats {
+        let shared = &self.shared;
+        LimiterStats {
+            connections: shared.active.load(Ordering::SeqCst),
+            accepted: shared.accepted.load(Ordering::SeqCst),
+            rejected_queue_full: shared.rejected_queue_full.load(Ordering::SeqCst),
+            rejected_connections: shared.rejected_connections.load(Ordering::SeqCst),
+            rejected_per_ip: shared.rejected_per_ip.load(Ordering::SeqCst),
+            rate_limited: shared.rate_limited.load(Ordering::SeqCst),
+        }
+    }
+
+    // 累加拒绝计数并输出日志
+    fn reject(&self, ip: Option<IpAddr>, rejection: Rejection) -> Rejection {
+        let counter = match rejection {
+            Rejection::QueueFull => &self.shared.rejected_queue_full,
+            Rejection::TooManyConnections => &self.shared.rejected_connections,
+            Rejection::TooManyConnectionsPerIp => &self.shared.rejected_per_ip,
+            Rejection::RateLimited(_) => &self.shared.rate_limited,
+        };
+        counter.fetch_add(1, Ordering::SeqCst);
+        // 被攻击时拒绝可能非常频繁，逐个输出日志本身就会成为负担
+        if let Some(suppressed) = self.log_slot(Instant::now()) {
+            let client = ip.map(|ip| ip.to_string()).unwrap_or_else(|| String::from("-"));
+            let stats = self.stats();
+            match suppressed {
+                0 => println!("[Limiter] rejected {}: {} ({})", client, rejection, stats),
+                n => println!(
+                    "[Limiter] rejected {}: {} ({}), {} more rejections since the last log",
+                    client, rejection, stats, n
+                ),
+            }
+        }
+        rejection
+    }
+
+    // 距离上一行拒绝日志不到LOG_INTERVAL时只计数并返回None，否则返回期间省略的拒绝次数
+    fn log_slot(&self, now: Instant) -> Option<u64> {
+        let mut last_log = self.shared.last_log.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match *last_log {
+            Some(logged) if now.saturating_duration_since(logged) < LOG_INTERVAL => {
+                self.shared.suppressed.fetch_add(1, Ordering::SeqCst);
+                None
+            }
+            _ => {
+                *last_log = Some(now);
+                Some(self.shared.suppressed.swap(0, Ordering::SeqCst))
+            }
+        }
+    }
+}
+
+impl Default for Limiter {
+    fn default() -> Limiter {
+        Limiter::unlimited()
+    }
+}
+
+// 作为中间件使用时按照请求的客户端地址限速，超限时返回429
+impl Middleware for Limiter {
+    fn handle(&self, request: Request, next: &dyn Handler) -> Response {
+        if let Some(addr) = request.remote_addr {
+            if let Err(rejection) = self.check_rate(addr.ip()) {
+                return rejection.response();
+            }
+        }
+        next.handle(request)
+    }
+}
+
+/*
+    已登记的连接，丢弃时归还连接数
+ */
+pub struct ConnectionGuard {
+    shared: Arc<Shared>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.shared.connections.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+        self.shared.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// 在不阻塞accept线程的前提下向被拒绝的连接写出响应并关闭连接。
+///
+/// 先丢弃已经到达的请求数据，否则关闭时内核会发送RST，客户端可能来不及读到响应。
+pub fn refuse(mut stream: TcpStream, rejection: Rejection) {
+    if stream.set_nonblocking(true).is_err() {
+        return;
+    }
+    let mut buffer = [0; 4096];
+    while let Ok(n) = stream.read(&mut buffer) {
+        if n == 0 {
+            break;
+        }
+    }
+    let mut bytes = Vec::new();
+    if rejection.response().with_header("Connection", "close").write_to(&mut bytes).is_ok() {
+        // 响应很短，通常可以一次写入发送缓冲区；写不完也不再等待
+        let _ = stream.write_all(&bytes);
+    }
+    let _ = stream.shutdown(Shutdown::Write);
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    #[test]
+    fn connection_caps() {
+        let limiter = Limiter::new(Policy { max_connections: Some(3), max_connections_per_ip: Some(2), ..Policy::default() });
+        let a1 = limiter.connect(ip(1)).unwrap();
+        let _a2 = limiter.connect(ip(1)).unwrap();
+        assert_eq!(limiter.connect(ip(1)).err(), Some(Rejection::TooManyConnectionsPerIp));
+        let _b1 = limiter.connect(ip(2)).unwrap();
+        assert_eq!(limiter.connect(ip(3)).err(), Some(Rejection::TooManyConnections));
+
+        // 连接关闭后名额归还
+        drop(a1);
+        let _c1 = limiter.connect(ip(3)).unwrap();
+        let stats = limiter.stats();
+        assert_eq!((stats.connections, stats.accepted), (3, 4));
+        assert_eq!((stats.rejected_per_ip, stats.rejected_connections), (1, 1));
+    }
+
+    #[test]
+    fn token_bucket() {
+        let limiter = Limiter::new(Policy { rate: Some(Rate { per_second: 10, burst: 3 }), ..Policy::default() });
+        for _ in 0..3 {
+            assert!(limiter.check_rate(ip(1)).is_ok());
+        }
+        let rejection = limiter.check_rate(ip(1)).unwrap_err();
+        assert_eq!(rejection.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(rejection.retry_after(), 1);
+        // 不同地址的令牌桶互不影响
+        assert!(limiter.check_rate(ip(2)).is_ok());
+
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(limiter.check_rate(ip(1)).is_ok());
+        assert_eq!(limiter.stats().rate_limited, 1);
+    }
+
+    #[test]
+    fn bounded_bucket_table() {
+        let limiter = Limiter::new(Policy { rate: Some(Rate { per_second: 1, burst: 1 }), ..Policy::default() });
+        // 每个地址请求一次就用完令牌，这些桶短时间内都不会回满
+        let addresses: Vec<IpAddr> = (0..3 * MAX_BUCKETS as u32).map(|n| IpAddr::from((0x0a00_0000 + n).to_be_bytes())).collect();
+        for ip in &addresses {
+            assert!(limiter.check_rate(*ip).is_ok());
+            assert!(limiter.shared.buckets.lock().unwrap().len() <= MAX_BUCKETS);
+        }
+        // 最近的地址仍然受限，最早的地址已经被淘汰
+        assert!(limiter.check_rate(*addresses.last().unwrap()).is_err());
+        assert!(limiter.check_rate(addresses[0]).is_ok());
+    }
+
+    #[test]
+    fn throttle_rejection_logs() {
+        let limiter = Limiter::new(Policy { max_queue: Some(1), ..Policy::default() });
+        let start = Instant::now();
+        assert_eq!(limiter.log_slot(start), Some(0));
+        assert_eq!(limiter.log_slot(start + Duration::from_secs(1)), None);
+        assert_eq!(limiter.log_slot(start + Duration::from_secs(2)), None);
+        assert_eq!(limiter.log_slot(start + LOG_INTERVAL), Some(2));
+        assert_eq!(limiter.log_slot(start + LOG_INTERVAL), None);
+
+        // 日志被省略时拒绝计数照常累加
+        for _ in 0..5 {
+            assert!(limiter.check_queue(1).is_err());
+        }
+        assert_eq!(limiter.stats().rejected_queue_full, 5);
+    }
+
+    #[test]
+    fn bounded_queue() {
+        let limiter = Limiter::new(Policy { max_queue: Some(2), ..Policy::default() });
+        assert!(limiter.check_queue(1).is_ok());
+        let rejection = limiter.check_queue(2).unwrap_err();
+        let response = rejection.response();
+        assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers.get("Retry-After"), Some("1"));
+        assert!(Limiter::unlimited().check_queue(usize::MAX).is_ok());
+    }
+}
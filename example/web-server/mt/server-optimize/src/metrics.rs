@@ -0,0 +1,275 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::http::{Request, Response, StatusCode};
+use crate::limiter::Limiter;
+use crate::middleware::{Handler, Middleware};
+use crate::PoolMonitor;
+
+// 请求耗时直方图的桶上界，单位为秒
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// 没有匹配到任何路由的请求（404、fallback处理的请求等）统一归到这个标签下；
+// 其余请求按照Router匹配到的路由分类，而不是请求路径，避免任意路径导致指标数量无限增长
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/*
+    耗时直方图
+ */
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    // 每个桶的计数（非累计），最后一个元素对应+Inf
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        let index = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[index] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    // (route, method, status) -> 请求数
+    requests: BTreeMap<(String, String, u16), u64>,
+    // route -> 耗时直方图
+    latency: BTreeMap<String, Histogram>,
+}
+
+/*
+    指标中间件
+
+    统计每个路由（`Response::route`）的请求数与耗时直方图，并在GET /metrics上以Prometheus文本格式输出，
+    如果设置了线程池监视器则同时输出线程池的队列长度与忙碌Worker数，设置了准入控制器则输出连接数与拒绝计数
+ */
+pub struct Metrics {
+    path: String,
+    registry: Mutex<Registry>,
+    pool: Option<PoolMonitor>,
+    limiter: Option<Limiter>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics { path: String::from("/metrics"), registry: Mutex::new(Registry::default()), pool: None, limiter: None }
+    }
+
+    /// 修改暴露指标的路径，默认为 `/metrics`。
+    pub fn path(mut self, path: impl Into<String>) -> Metrics {
+        self.path = path.into();
+        self
+    }
+
+    /// 设置线程池监视器，用于输出线程池状态指标。
+    pub fn with_pool(mut self, pool: PoolMonitor) -> Metrics {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// 设置准入控制器，用于输出连接数以及各类拒绝计数。
+    pub fn with_limiter(mut self, limiter: Limiter) -> Metrics {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    fn record(&self, route: String, method: String, status: u16, seconds: f64) {
+        let mut registry = self.registry.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *registry.requests.entry((route.clone(), method, status)).or_insert(0) += 1;
+        registry.latency.entry(route).or_default().observe(seconds);
+    }
+
+    /// 以Prometheus文本格式输出所有指标。
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total Total number of HTTP requests.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((route, method, status), count) in &registry.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{route=\"{}\",method=\"{}\",status=\"{}\"}} {}",
+                escape(route),
+                escape(method),
+                status,
+                count
+            );
+        }
+
+        out.push_str("# HELP http_request_duration_seconds HTTP request latency in seconds.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for (route, histogram) in &registry.latency {
+            let route = escape(route);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    route, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+                route, histogram.count
+            );
+            let _ = writeln!(out, "http_request_duration_seconds_sum{{route=\"{}\"}} {}", route, histogram.sum);
+            let _ = writeln!(out, "http_request_duration_seconds_count{{route=\"{}\"}} {}", route, histogram.count);
+        }
+
+        if let Some(pool) = &self.pool {
+            let stats = pool.stats();
+            out.push_str("# HELP threadpool_workers Number of worker threads.\n");
+            out.push_str("# TYPE threadpool_workers gauge\n");
+            let _ = writeln!(out, "threadpool_workers {}", stats.workers);
+            out.push_str("# HELP threadpool_queue_depth Number of jobs waiting in the queue.\n");
+            out.push_str("# TYPE threadpool_queue_depth gauge\n");
+            let _ = writeln!(out, "threadpool_queue_depth {}", stats.queued);
+            out.push_str("# HELP threadpool_busy_workers Number of workers executing a job.\n");
+            out.push_str("# TYPE threadpool_busy_workers gauge\n");
+            let _ = writeln!(out, "threadpool_busy_workers {}", stats.active);
+            out.push_str("# HELP threadpool_panicked_jobs_total Total number of jobs that panicked.\n");
+            out.push_str("# TYPE threadpool_panicked_jobs_total counter\n");
+            let _ = writeln!(out, "threadpool_panicked_jobs_total {}", stats.panicked);
+            out.push_str("# HELP threadpool_rejected_jobs_total Total number of jobs rejected or dropped because the queue was full.\n");
+            out.push_str("# TYPE threadpool_rejected_jobs_total counter\n");
+            let _ = writeln!(out, "threadpool_rejected_jobs_total {}", stats.rejected);
+            out.push_str("# HELP threadpool_expired_jobs_total Total number of jobs dropped because their deadline passed before they started.\n");
+            out.push_str("# TYPE threadpool_expired_jobs_total counter\n");
+            let _ = writeln!(out, "threadpool_expired_jobs_total {}", stats.expired);
+            out.push_str("# HELP threadpool_completed_jobs_total Total number of jobs that ran to completion.\n");
+            out.push_str("# TYPE threadpool_completed_jobs_total counter\n");
+            let _ = writeln!(out, "threadpool_completed_jobs_total {}", stats.completed);
+            out.push_str("# HELP threadpool_worker_busy_seconds_total Time each worker spent executing jobs.\n");
+            out.push_str("# TYPE threadpool_worker_busy_seconds_total counter\n");
+            for worker in pool.workers() {
+                let _ = writeln!(out, "threadpool_worker_busy_seconds_total{{worker=\"{}\"}} {}", worker.id, worker.busy.as_secs_f64());
+            }
+        }
+
+        if let Some(limiter) = &self.limiter {
+            let stats = limiter.stats();
+            out.push_str("# HELP limiter_open_connections Number of admitted connections that are still open.\n");
+            out.push_str("# TYPE limiter_open_connections gauge\n");
+            let _ = writeln!(out, "limiter_open_connections {}", stats.connections);
+            out.push_str("# HELP limiter_accepted_connections_total Total number of admitted connections.\n");
+            out.push_str("# TYPE limiter_accepted_connections_total counter\n");
+            let _ = writeln!(out, "limiter_accepted_connections_total {}", stats.accepted);
+            out.push_str("# HELP limiter_rejected_total Total number of rejected connections and requests by reason.\n");
+            out.push_str("# TYPE limiter_rejected_total counter\n");
+            for (reason, count) in [
+                ("queue_full", stats.rejected_queue_full),
+                ("max_connections", stats.rejected_connections),
+                ("max_connections_per_ip", stats.rejected_per_ip),
+                ("rate", stats.rate_limited),
+            ] {
+                let _ = writeln!(out, "limiter_rejected_total{{reason=\"{}\"}} {}", reason, count);
+            }
+        }
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+impl Middleware for Metrics {
+    fn handle(&self, request: Request, next: &dyn Handler) -> Response {
+        if request.method == "GET" && request.path == self.path {
+            return Response::new(StatusCode::OK)
+                .with_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+                .with_body(self.render());
+        }
+
+        let start = Instant::now();
+        let method = request.method.clone();
+
+        let response = next.handle(request);
+
+        let route = response.route.clone().unwrap_or_else(|| String::from(UNMATCHED_ROUTE));
+        self.record(route, method, response.status.as_u16(), start.elapsed().as_secs_f64());
+        response
+    }
+}
+
+// 转义标签值中的反斜杠、引号和换行
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::router::Router;
+
+    #[test]
+    fn render_counters_and_histograms() {
+        let metrics = Metrics::new();
+        let app = Router::new().get("/", |_: Request| Response::from(StatusCode::OK));
+        metrics.handle(Request::new("GET", "/"), &app);
+        metrics.handle(Request::new("GET", "/"), &app);
+        metrics.handle(Request::new("GET", "/missing"), &app);
+
+        let output = metrics.render();
+        assert!(output.contains("http_requests_total{route=\"/\",method=\"GET\",status=\"200\"} 2\n"));
+        assert!(output.contains("http_requests_total{route=\"unmatched\",method=\"GET\",status=\"404\"} 1\n"));
+        assert!(output.contains("http_request_duration_seconds_bucket{route=\"/\",le=\"+Inf\"} 2\n"));
+        assert!(output.contains("http_request_duration_seconds_count{route=\"unmatched\"} 1\n"));
+    }
+
+    #[test]
+    fn bounded_route_labels() {
+        let metrics = Metrics::new();
+        let app = Router::new()
+            // 状态码取决于路径的最后一位数字
+            .route("*", "/api/*", |request: Request| match request.path.as_bytes().last().unwrap() % 3 {
+                0 => Response::from(StatusCode::OK),
+                1 => Response::from(StatusCode::FORBIDDEN),
+                _ => Response::from(StatusCode::BAD_GATEWAY),
+            })
+            .get("/about", |_: Request| Response::from(StatusCode::OK))
+            .fallback(|_: Request| Response::from(StatusCode::FORBIDDEN));
+        let labels = |metrics: &Metrics| {
+            metrics.render().lines().filter(|line| line.starts_with("http_requests_total{")).count()
+        };
+
+        let paths = |n: usize| vec![format!("/api/items/{}", n), format!("/about/{}", n), format!("/files/{}", n)];
+        for n in 0..10 {
+            for path in paths(n) {
+                metrics.handle(Request::new("GET", &path), &app);
+            }
+            metrics.handle(Request::new("POST", "/about"), &app);
+        }
+        let before = labels(&metrics);
+        // 更多不同的路径不会产生新的标签
+        for n in 10..200 {
+            for path in paths(n) {
+                metrics.handle(Request::new("GET", &path), &app);
+            }
+            metrics.handle(Request::new("POST", "/about"), &app);
+        }
+        assert_eq!(labels(&metrics), before);
+        let output = metrics.render();
+        assert!(output.contains("route=\"/api/*\",method=\"GET\",status=\"502\""), "{}", output);
+        assert!(output.contains("route=\"/about\",method=\"POST\",status=\"405\""), "{}", output);
+        assert!(output.contains("route=\"unmatched\",method=\"GET\",status=\"403\""), "{}", output);
+    }
+}
@@ -0,0 +1,181 @@
+use std::sync::{Arc, RwLock};
+
+use crate::http::{Request, Response};
+
+pub mod access_log;
+pub mod compression;
+pub mod cors;
+pub mod logger;
+pub mod request_id;
+
+pub use access_log::{AccessLog, LogFormat};
+pub use compression::Compression;
+pub use cors::Cors;
+pub use logger::Logger;
+pub use request_id::RequestId;
+
+/*
+    请求处理器特征
+
+    处理器会在线程池的多个Worker之间共享，所以要求实现Send + Sync
+ */
+pub trait Handler: Send + Sync {
+    fn handle(&self, request: Request) -> Response;
+}
+
+// 为闭包和普通函数实现Handler特征，这样fn(Request) -> Response可以直接作为处理器使用
+impl<F> Handler for F
+where
+    F: Fn(Request) -> Response + Send + Sync,
+{
+    fn handle(&self, request: Request) -> Response {
+        self(request)
+    }
+}
+
+/*
+    中间件特征
+
+    中间件包裹在处理器外层，可以在调用next之前修改请求，也可以在调用next之后修改响应，
+    甚至可以不调用next直接返回响应（例如CORS的预检请求）
+ */
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: Request, next: &dyn Handler) -> Response;
+}
+
+// 共享的中间件，例如重新加载配置时需要保留计数的指标中间件
+impl<M: Middleware + ?Sized> Middleware for Arc<M> {
+    fn handle(&self, request: Request, next: &dyn Handler) -> Response {
+        self.as_ref().handle(request, next)
+    }
+}
+
+/*
+    中间件链
+
+    先添加的中间件位于外层，即先处理请求、后处理响应：
+    Chain::new(handler).with(a).with(b) 的调用顺序为 a -> b -> handler -> b -> a
+ */
+pub struct Chain {
+    middlewares: Vec<Box<dyn Middleware>>,
+    handler: Box<dyn Handler>,
+}
+
+impl Chain {
+    pub fn new<H: Handler + 'static>(handler: H) -> Chain {
+        Chain { middlewares: Vec::new(), handler: Box::new(handler) }
+    }
+
+    /// 在链的最内层（紧挨着处理器的外侧）追加一个中间件。
+    pub fn with<M: Middleware + 'static>(mut self, middleware: M) -> Chain {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+}
+
+impl Handler for Chain {
+    fn handle(&self, request: Request) -> Response {
+        Next { middlewares: &self.middlewares, handler: self.handler.as_ref() }.handle(request)
+    }
+}
+
+/*
+    可替换的处理器
+
+    重新加载配置时整体替换内部的处理器，新请求使用新的处理器，
+    正在处理中的请求持有旧处理器的引用计数，处理完毕后旧处理器才会被释放
+ */
+pub struct Reloadable {
+    current: RwLock<Arc<dyn Handler>>,
+}
+
+impl Reloadable {
+    pub fn new(handler: Arc<dyn Handler>) -> Reloadable {
+        Reloadable { current: RwLock::new(handler) }
+    }
+
+    pub fn replace(&self, handler: Arc<dyn Handler>) {
+        *self.current.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = handler;
+    }
+
+    pub fn current(&self) -> Arc<dyn Handler> {
+        Arc::clone(&self.current.read().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+}
+
+impl Handler for Reloadable {
+    fn handle(&self, request: Request) -> Response {
+        // 先取出当前处理器再调用，处理请求期间不持有锁
+        self.current().handle(request)
+    }
+}
+
+// 链中剩余的部分，每经过一个中间件就向内移动一层
+struct Next<'a> {
+    middlewares: &'a [Box<dyn Middleware>],
+    handler: &'a dyn Handler,
+}
+
+impl<'a> Handler for Next<'a> {
+    fn handle(&self, request: Request) -> Response {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => {
+                middleware.handle(request, &Next { middlewares: rest, handler: self.handler })
+            }
+            None => self.handler.handle(request),
+        }
+    }
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::StatusCode;
+
+    // 在请求头和响应头上记录经过的顺序
+    struct Trace(&'static str);
+
+    impl Middleware for Trace {
+        fn handle(&self, mut request: Request, next: &dyn Handler) -> Response {
+            request.headers.append("X-Trace", self.0);
+            let mut response = next.handle(request);
+            response.headers.append("X-Trace", self.0);
+            response
+        }
+    }
+
+    #[test]
+    fn middlewares_wrap_in_order() {
+        let chain = Chain::new(|request: Request| {
+            let trace: Vec<&str> = request.headers.get_all("X-Trace").collect();
+            Response::text(StatusCode::OK, trace.join(","))
+        })
+        .with(Trace("a"))
+        .with(Trace("b"));
+
+        let response = chain.handle(Request::new("GET", "/"));
+        let trace: Vec<&str> = response.headers.get_all("X-Trace").collect();
+        assert_eq!(vec!["b", "a"], trace);
+        match response.body {
+            crate::http::Body::Bytes(bytes) => assert_eq!(b"a,b".to_vec(), bytes),
+            body => panic!("unexpected body {:?}", body),
+        }
+    }
+
+    #[test]
+    fn replace_reloadable_handler() {
+        let reloadable = Reloadable::new(Arc::new(|_: Request| Response::text(StatusCode::OK, "old")));
+        let old = reloadable.current();
+        reloadable.replace(Arc::new(|_: Request| Response::text(StatusCode::OK, "new")));
+        let body = |response: Response| match response.body {
+            crate::http::Body::Bytes(bytes) => String::from_utf8(bytes).unwrap(),
+            body => panic!("unexpected body {:?}", body),
+        };
+        assert_eq!(body(reloadable.handle(Request::new("GET", "/"))), "new");
+        // 替换之前取出的处理器仍然可用
+        assert_eq!(body(old.handle(Request::new("GET", "/"))), "old");
+    }
+}
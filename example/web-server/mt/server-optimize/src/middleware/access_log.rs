@@ -0,0 +1,89 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+use super::{Handler, Middleware};
+use crate::http::{date, Request, Response};
+use crate::logfile::RotatingFile;
+
+/*
+    访问日志格式
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    // host ident authuser [date] "request" status bytes
+    Common,
+    // 在Common的基础上追加 "referer" "user-agent"
+    Combined,
+}
+
+/*
+    访问日志中间件
+
+    按照Common/Combined Log Format写入日志文件，并在行尾追加以微秒为单位的耗时，例如：
+    127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET / HTTP/1.1" 200 2326 "-" "curl/8.0" 1520
+
+    日志行在响应体写完之后才写入（Response::on_complete），字节数是实际写出的响应体字节数，
+    耗时包括处理与传输；连接中途断开时只记录已经写出的部分
+ */
+#[derive(Debug)]
+pub struct AccessLog {
+    format: LogFormat,
+    file: Arc<Mutex<RotatingFile>>,
+}
+
+impl AccessLog {
+    pub fn new(format: LogFormat, file: RotatingFile) -> AccessLog {
+        AccessLog { format, file: Arc::new(Mutex::new(file)) }
+    }
+}
+
+impl Middleware for AccessLog {
+    fn handle(&self, request: Request, next: &dyn Handler) -> Response {
+        let start = Instant::now();
+        let time = SystemTime::now();
+        let host = request
+            .remote_addr
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_else(|| String::from("-"));
+        let request_line = format!("{} {} {}", request.method, request.target(), request.version);
+        let referer = request.header("Referer").unwrap_or("-").to_string();
+        let user_agent = request.header("User-Agent").unwrap_or("-").to_string();
+
+        let response = next.handle(request);
+
+        let mut line = format!(
+            "{} - - [{}] \"{}\" {}",
+            host,
+            date::format_clf(time),
+            escape(&request_line),
+            response.status.as_u16()
+        );
+        let (format, file) = (self.format, Arc::clone(&self.file));
+        response.with_on_complete(move |written| {
+            // 没有写出响应体时按照CLF的约定记为"-"
+            if written > 0 {
+                line.push_str(&format!(" {}", written));
+            } else {
+                line.push_str(" -");
+            }
+            if format == LogFormat::Combined {
+                line.push_str(&format!(" \"{}\" \"{}\"", escape(&referer), escape(&user_agent)));
+            }
+            line.push_str(&format!(" {}", start.elapsed().as_micros()));
+
+            // 日志写入失败不应该影响请求处理，这里只在控制台输出错误
+            let result = match file.lock() {
+                Ok(mut file) => file.write_line(&line),
+                Err(poisoned) => poisoned.into_inner().write_line(&line),
+            };
+            if let Err(err) = result {
+                println!("[AccessLog] write failed: {}", err);
+            }
+        })
+    }
+}
+
+// 转义引号和反斜杠，避免客户端构造的内容破坏日志格式
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,248 @@
+use std::io::{Read, Write};
+
+use flate2::read::{GzEncoder as GzReadEncoder, ZlibEncoder as ZlibReadEncoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use super::{Handler, Middleware};
+use crate::http::{Body, HeaderMap, Request, Response};
+
+/*
+    内容编码
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    // HTTP中的deflate实际为zlib格式（RFC 1950）
+    Deflate,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+/// 根据Accept-Encoding选择一个服务端支持的编码，支持q值，例如 `gzip;q=0.8, deflate, *;q=0`。
+///
+/// q值相同时按照 `supported` 中的顺序优先，客户端不接受任何支持的编码时返回 `None`。
+pub fn negotiate(accept_encoding: &str, supported: &[Encoding]) -> Option<Encoding> {
+    // 解析出(编码名称, q值)列表
+    let codings: Vec<(String, f32)> = accept_encoding
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let name = params.next()?.trim().to_ascii_lowercase();
+            if name.is_empty() {
+                return None;
+            }
+            let q = params
+                .filter_map(|param| param.trim().strip_prefix("q=").or_else(|| param.trim().strip_prefix("Q=")))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            Some((name, q))
+        })
+        .collect();
+
+    // 没有明确列出的编码使用通配符*的q值
+    let wildcard = codings.iter().find(|(name, _)| name == "*").map(|(_, q)| *q);
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in supported {
+        let q = codings
+            .iter()
+            .find(|(name, _)| name == encoding.as_str() || (*encoding == Encoding::Gzip && name == "x-gzip"))
+            .map(|(_, q)| *q)
+            .or(wildcard)
+            .unwrap_or(0.0);
+        if q > 0.0 && best.map(|(_, best_q)| q > best_q).unwrap_or(true) {
+            best = Some((*encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// 判断Content-Type是否为文本类，图片、压缩包等二进制内容再次压缩没有意义。
+pub fn is_text_like(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime == "application/json"
+        || mime == "application/javascript"
+        || mime == "application/xml"
+        || mime == "application/wasm"
+        || mime == "image/svg+xml"
+}
+
+/// 在Vary头部中追加Accept-Encoding，已存在时不重复追加。
+pub fn add_vary_accept_encoding(headers: &mut HeaderMap) {
+    let present = headers.get_all("Vary").any(|value| {
+        value
+            .split(',')
+            .any(|item| item.trim() == "*" || item.trim().eq_ignore_ascii_case("Accept-Encoding"))
+    });
+    if !present {
+        headers.append("Vary", "Accept-Encoding");
+    }
+}
+
+/*
+    响应压缩中间件
+
+    根据请求的Accept-Encoding协商gzip或者deflate编码，对超过大小阈值的文本类响应体进行压缩，
+    并设置Content-Encoding与Vary头部。已经设置了Content-Encoding的响应（例如预压缩的.gz文件）不会被再次压缩
+ */
+#[derive(Debug, Clone)]
+pub struct Compression {
+    // 小于该字节数的响应体不压缩，压缩收益抵不过额外开销
+    min_size: u64,
+    level: u32,
+    supported: Vec<Encoding>,
+}
+
+impl Compression {
+    pub fn new() -> Compression {
+        Compression { min_size: 1024, level: 6, supported: vec![Encoding::Gzip, Encoding::Deflate] }
+    }
+
+    /// 设置压缩的最小响应体大小，默认为1024字节。
+    pub fn min_size(mut self, bytes: u64) -> Compression {
+        self.min_size = bytes;
+        self
+    }
+
+    /// 设置压缩级别（0-9），默认为6。
+    pub fn level(mut self, level: u32) -> Compression {
+        self.level = level.min(9);
+        self
+    }
+
+    fn compress(&self, body: Body, encoding: Encoding) -> Body {
+        let level = flate2::Compression::new(self.level);
+        match body {
+            // 内存中的字节直接压缩，压缩后长度已知
+            Body::Bytes(bytes) => {
+                let compressed = match encoding {
+                    Encoding::Gzip => {
+                        let mut encoder = GzEncoder::new(Vec::new(), level);
+                        encoder.write_all(&bytes).and_then(|_| encoder.finish())
+                    }
+                    Encoding::Deflate => {
+                        let mut encoder = ZlibEncoder::new(Vec::new(), level);
+                        encoder.write_all(&bytes).and_then(|_| encoder.finish())
+                    }
+                };
+                // 内存写入不会失败，这里保险起见失败时退回原始内容
+                compressed.map(Body::Bytes).unwrap_or(Body::Bytes(bytes))
+            }
+            // 文件与字节流边读边压缩，压缩后长度未知，写出时使用chunked编码
+            Body::File(file) => Body::Stream(encode_stream(file, encoding, level)),
+            Body::Stream(stream) => Body::Stream(encode_stream(stream, encoding, level)),
+            Body::Empty => Body::Empty,
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, request: Request, next: &dyn Handler) -> Response {
+        let encoding = request
+            .header("Accept-Encoding")
+            .and_then(|accept| negotiate(accept, &self.supported));
+
+        let mut response = next.handle(request);
+        if response.headers.contains("Content-Encoding") {
+            return response;
+        }
+        let compressible = response.headers.get("Content-Type").map(is_text_like).unwrap_or(false);
+        if !compressible {
+            return response;
+        }
+        // 同一个URL的响应内容取决于Accept-Encoding，需要告知缓存
+        add_vary_accept_encoding(&mut response.headers);
+
+        let encoding = match encoding {
+            Some(encoding) => encoding,
+            None => return response,
+        };
+        if response.body.len().map(|len| len < self.min_size).unwrap_or(false) {
+            return response;
+        }
+
+        let body = std::mem::replace(&mut response.body, Body::Empty);
+        response.body = self.compress(body, encoding);
+        response.headers.insert("Content-Encoding", encoding.as_str());
+        response
+    }
+}
+
+fn encode_stream<R: Read + Send + 'static>(
+    reader: R,
+    encoding: Encoding,
+    level: flate2::Compression,
+) -> Box<dyn Read + Send> {
+    match encoding {
+        Encoding::Gzip => Box::new(GzReadEncoder::new(reader, level)),
+        Encoding::Deflate => Box::new(ZlibReadEncoder::new(reader, level)),
+    }
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::StatusCode;
+
+    const BOTH: [Encoding; 2] = [Encoding::Gzip, Encoding::Deflate];
+
+    #[test]
+    fn negotiate_with_q_values() {
+        assert_eq!(Some(Encoding::Gzip), negotiate("gzip, deflate, br", &BOTH));
+        assert_eq!(Some(Encoding::Deflate), negotiate("gzip;q=0.5, deflate;q=0.8", &BOTH));
+        assert_eq!(Some(Encoding::Deflate), negotiate("gzip;q=0, *", &BOTH));
+        assert_eq!(None, negotiate("br, identity", &BOTH));
+        assert_eq!(None, negotiate("*;q=0", &BOTH));
+    }
+
+    #[test]
+    fn compress_text_above_threshold() {
+        let compression = Compression::new().min_size(16);
+        let app = |_: Request| Response::text(StatusCode::OK, "hello ".repeat(100));
+        let mut request = Request::new("GET", "/");
+        request.headers.insert("Accept-Encoding", "deflate;q=0.9, gzip;q=0.1");
+
+        let response = compression.handle(request, &app);
+        assert_eq!(Some("deflate"), response.headers.get("Content-Encoding"));
+        assert_eq!(Some("Accept-Encoding"), response.headers.get("Vary"));
+        let compressed = match response.body {
+            Body::Bytes(bytes) => bytes,
+            body => panic!("unexpected body {:?}", body),
+        };
+        let mut decoded = String::new();
+        flate2::read::ZlibDecoder::new(&compressed[..]).read_to_string(&mut decoded).unwrap();
+        assert_eq!("hello ".repeat(100), decoded);
+    }
+
+    #[test]
+    fn skip_small_and_binary_bodies() {
+        let compression = Compression::new();
+        let mut request = Request::new("GET", "/");
+        request.headers.insert("Accept-Encoding", "gzip");
+        let small = compression.handle(request.clone(), &|_: Request| Response::text(StatusCode::OK, "hi"));
+        assert!(!small.headers.contains("Content-Encoding"));
+        assert!(small.headers.contains("Vary"));
+
+        let binary = compression.handle(request, &|_: Request| {
+            Response::new(StatusCode::OK).with_header("Content-Type", "image/png").with_body(vec![0; 4096])
+        });
+        assert!(!binary.headers.contains("Content-Encoding"));
+        assert!(!binary.headers.contains("Vary"));
+    }
+}
@@ -0,0 +1,90 @@
+use super::{Handler, Middleware};
+use crate::http::{Request, Response, StatusCode};
+
+/*
+    跨域资源共享（CORS）中间件
+
+    对于预检请求（OPTIONS + Access-Control-Request-Method）直接返回204，
+    对于普通跨域请求在响应中追加Access-Control-Allow-Origin等头部
+ */
+#[derive(Debug, Clone)]
+pub struct Cors {
+    // 允许的来源，为空表示允许任意来源
+    allow_origins: Vec<String>,
+    allow_methods: String,
+    allow_headers: String,
+    max_age: u32,
+}
+
+impl Cors {
+    /// 允许任意来源的跨域请求。
+    pub fn any() -> Cors {
+        Cors {
+            allow_origins: Vec::new(),
+            allow_methods: String::from("GET, HEAD, POST, PUT, DELETE, OPTIONS"),
+            allow_headers: String::from("Content-Type, Authorization, X-Request-Id"),
+            max_age: 86400,
+        }
+    }
+
+    /// 只允许指定来源的跨域请求。
+    pub fn allow_origins<I, S>(origins: I) -> Cors
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Cors { allow_origins: origins.into_iter().map(Into::into).collect(), ..Cors::any() }
+    }
+
+    pub fn allow_methods(mut self, methods: impl Into<String>) -> Cors {
+        self.allow_methods = methods.into();
+        self
+    }
+
+    pub fn allow_headers(mut self, headers: impl Into<String>) -> Cors {
+        self.allow_headers = headers.into();
+        self
+    }
+
+    pub fn max_age(mut self, seconds: u32) -> Cors {
+        self.max_age = seconds;
+        self
+    }
+
+    // 返回写入Access-Control-Allow-Origin的值，来源不被允许时返回None
+    fn allowed_origin(&self, origin: &str) -> Option<String> {
+        if self.allow_origins.is_empty() {
+            Some(String::from("*"))
+        } else if self.allow_origins.iter().any(|allowed| allowed == origin) {
+            Some(origin.to_string())
+        } else {
+            None
+        }
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, request: Request, next: &dyn Handler) -> Response {
+        let allowed = match request.header("Origin").and_then(|origin| self.allowed_origin(origin)) {
+            Some(allowed) => allowed,
+            // 非跨域请求或者来源不被允许，不追加任何CORS头部
+            None => return next.handle(request),
+        };
+
+        let preflight = request.method == "OPTIONS" && request.headers.contains("Access-Control-Request-Method");
+        let mut response = if preflight {
+            Response::new(StatusCode::NO_CONTENT)
+                .with_header("Access-Control-Allow-Methods", self.allow_methods.clone())
+                .with_header("Access-Control-Allow-Headers", self.allow_headers.clone())
+                .with_header("Access-Control-Max-Age", self.max_age.to_string())
+        } else {
+            next.handle(request)
+        };
+
+        if allowed != "*" {
+            response.headers.append("Vary", "Origin");
+        }
+        response.headers.insert("Access-Control-Allow-Origin", allowed);
+        response
+    }
+}
@@ -0,0 +1,29 @@
+use std::time::Instant;
+
+use super::{Handler, Middleware};
+use crate::http::{Request, Response};
+
+/*
+    请求日志中间件，输出请求方法、路径、响应状态以及处理耗时
+ */
+#[derive(Debug, Default)]
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn handle(&self, request: Request, next: &dyn Handler) -> Response {
+        let start = Instant::now();
+        let method = request.method.clone();
+        let target = request.target();
+
+        let response = next.handle(request);
+
+        println!(
+            "[Access] {} {} -> {} ({:?})",
+            method,
+            target,
+            response.status.as_u16(),
+            start.elapsed()
+        );
+        response
+    }
+}
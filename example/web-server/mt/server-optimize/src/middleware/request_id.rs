@@ -0,0 +1,53 @@
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::{Handler, Middleware};
+use crate::http::{Request, Response};
+
+/*
+    请求ID中间件
+
+    如果客户端（或者上游代理）已经携带了请求ID则沿用，否则生成一个新的ID，
+    ID会同时写入请求头（供后续处理器使用）以及响应头（供客户端排查问题）
+ */
+#[derive(Debug)]
+pub struct RequestId {
+    header: String,
+    counter: AtomicU64,
+}
+
+impl RequestId {
+    pub fn new() -> RequestId {
+        RequestId::with_header("X-Request-Id")
+    }
+
+    pub fn with_header(header: impl Into<String>) -> RequestId {
+        RequestId { header: header.into(), counter: AtomicU64::new(0) }
+    }
+
+    // 进程ID加自增序号，在单个进程内保证唯一
+    fn next_id(&self) -> String {
+        let seq = self.counter.fetch_add(1, Ordering::Relaxed);
+        format!("{:x}-{:08x}", process::id(), seq)
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> RequestId {
+        RequestId::new()
+    }
+}
+
+impl Middleware for RequestId {
+    fn handle(&self, mut request: Request, next: &dyn Handler) -> Response {
+        let id = match request.header(&self.header) {
+            Some(id) if !id.is_empty() => id.to_string(),
+            _ => self.next_id(),
+        };
+        request.headers.insert(self.header.clone(), id.clone());
+
+        let mut response = next.handle(request);
+        response.headers.insert(self.header.clone(), id);
+        response
+    }
+}
@@ -0,0 +1,280 @@
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use crate::{Job, ThreadPool};
+
+// 每个线程平均分到的段数，多分几段便于先完成的线程窃取剩下的工作
+const SPLITS_PER_THREAD: usize = 4;
+// Worker在join中找不到可以帮忙的任务时，等待被拆分出去的任务结束的最长时间，之后再去找任务
+const HELP_INTERVAL: Duration = Duration::from_millis(1);
+
+/*
+    fork-join中被拆分出去的一半
+
+    闭包由先取到它的一方执行：线程池中的Worker，或者发现它还没有开始执行的 `join` 调用者；
+    排队的任务被丢弃（例如 `shutdown_now` 或者 `DropOldest`）时闭包留在这里，由调用者执行
+ */
+struct Forked<F, R> {
+    f: Mutex<Option<F>>,
+    result: Mutex<Option<thread::Result<R>>>,
+    done: Condvar,
+}
+
+impl<F, R> Forked<F, R>
+where
+    F: FnOnce() -> R,
+{
+    fn take(&self) -> Option<F> {
+        self.f.lock().unwrap_or_else(PoisonError::into_inner).take()
+    }
+
+    // 由线程池执行，闭包已经被调用者取回时什么也不做
+    fn run(&self) {
+        if let Some(f) = self.take() {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            *self.lock() = Some(result);
+            self.done.notify_all();
+        }
+    }
+
+    fn try_result(&self) -> Option<thread::Result<R>> {
+        self.lock().take()
+    }
+
+    fn wait(&self, timeout: Option<Duration>) -> Option<thread::Result<R>> {
+        let result = self.lock();
+        let mut result = match timeout {
+            None => self.done.wait_while(result, |result| result.is_none()).unwrap_or_else(PoisonError::into_inner),
+            Some(timeout) => {
+                self.done.wait_timeout_while(result, timeout, |result| result.is_none()).unwrap_or_else(PoisonError::into_inner).0
+            }
+        };
+        result.take()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<thread::Result<R>>> {
+        self.result.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub(crate) fn join<'a, A, B, RA, RB>(pool: &ThreadPool, a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send + 'a,
+    RA: Send,
+    RB: Send + 'a,
+{
+    let context = &pool.context;
+    let forked = Arc::new(Forked { f: Mutex::new(Some(b)), result: Mutex::new(None), done: Condvar::new() });
+    let job: Box<dyn FnOnce() + Send + 'a> = {
+        let forked = Arc::clone(&forked);
+        Box::new(move || forked.run())
+    };
+    // SAFETY: 返回之前闭包要么由当前线程取回，要么已经执行完毕，任务借用的数据在此之前一直有效；
+    // 之后仍然留在队列中（或者被 `shutdown_now` 交给调用者）的任务只剩下空的Forked，执行时什么也不做
+    let job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'a>, Job>(job) };
+    // 队列已满时b留在Forked中，在a之后由当前线程执行
+    let _ = context.fork(job);
+
+    // a panic时同样要等待b结束，之后再继续panic
+    let ra = panic::catch_unwind(AssertUnwindSafe(a));
+    let rb = match forked.take() {
+        // b还没有开始执行，与其等待不如自己执行
+        Some(b) => panic::catch_unwind(AssertUnwindSafe(b)),
+        None => match context.scheduler.current() {
+            // Worker不能只是阻塞：执行b的线程可能又拆分出了任务，所有Worker都在等待时没有人执行它们
+            Some(id) => loop {
+                if let Some(result) = forked.try_result() {
+                    break result;
+                }
+                if !context.help(id) {
+                    if let Some(result) = forked.wait(Some(HELP_INTERVAL)) {
+                        break result;
+                    }
+                }
+            },
+            None => forked.wait(None).unwrap(),
+        },
+    };
+    match (ra, rb) {
+        (Ok(ra), Ok(rb)) => (ra, rb),
+        (Err(payload), _) | (_, Err(payload)) => panic::resume_unwind(payload),
+    }
+}
+
+// 把元素数为 `len` 的工作拆分到每段最多多少个元素
+fn min_len(pool: &ThreadPool, len: usize) -> usize {
+    len.div_ceil(pool.context.settings.max_threads * SPLITS_PER_THREAD).max(1)
+}
+
+pub(crate) fn map<T, F, R>(pool: &ThreadPool, items: Vec<T>, f: &F) -> Vec<R>
+where
+    T: Send,
+    F: Fn(T) -> R + Sync,
+    R: Send,
+{
+    let mut input: Vec<Option<T>> = items.into_iter().map(Some).collect();
+    let mut output: Vec<Option<R>> = (0..input.len()).map(|_| None).collect();
+    let min_len = min_len(pool, input.len());
+    map_range(pool, &mut input, &mut output, min_len, f);
+    output.into_iter().map(Option::unwrap).collect()
+}
+
+// 输入与输出在同一个位置拆分，结果写回元素原来的位置，由此保持顺序
+fn map_range<T, F, R>(pool: &ThreadPool, input: &mut [Option<T>], output: &mut [Option<R>], min_len: usize, f: &F)
+where
+    T: Send,
+    F: Fn(T) -> R + Sync,
+    R: Send,
+{
+    if input.len() <= min_len {
+        for (item, slot) in input.iter_mut().zip(output) {
+            *slot = item.take().map(f);
+        }
+        return;
+    }
+    let mid = input.len() / 2;
+    let (left_input, right_input) = input.split_at_mut(mid);
+    let (left_output, right_output) = output.split_at_mut(mid);
+    join(
+        pool,
+        || map_range(pool, left_input, left_output, min_len, f),
+        || map_range(pool, right_input, right_output, min_len, f),
+    );
+}
+
+// 归约所需的三个闭包
+pub(crate) struct Reducer<M, ID, OP> {
+    pub(crate) map: M,
+    pub(crate) identity: ID,
+    pub(crate) op: OP,
+}
+
+pub(crate) fn reduce<T, M, R, ID, OP>(pool: &ThreadPool, items: Vec<T>, reducer: &Reducer<M, ID, OP>) -> R
+where
+    T: Send,
+    M: Fn(T) -> R + Sync,
+    R: Send,
+    ID: Fn() -> R + Sync,
+    OP: Fn(R, R) -> R + Sync,
+{
+    let mut input: Vec<Option<T>> = items.into_iter().map(Some).collect();
+    let min_len = min_len(pool, input.len());
+    reduce_range(pool, &mut input, min_len, reducer)
+}
+
+// 左半部分的结果总是作为op的第一个参数，不满足交换律的op也能得到与顺序归约相同的结果
+fn reduce_range<T, M, R, ID, OP>(pool: &ThreadPool, input: &mut [Option<T>], min_len: usize, reducer: &Reducer<M, ID, OP>) -> R
+where
+    T: Send,
+    M: Fn(T) -> R + Sync,
+    R: Send,
+    ID: Fn() -> R + Sync,
+    OP: Fn(R, R) -> R + Sync,
+{
+    if input.len() <= min_len {
+        return input.iter_mut().filter_map(Option::take).fold((reducer.identity)(), |acc, item| (reducer.op)(acc, (reducer.map)(item)));
+    }
+    let (left, right) = input.split_at_mut(input.len() / 2);
+    let (left, right) =
+        join(pool, || reduce_range(pool, left, min_len, reducer), || reduce_range(pool, right, min_len, reducer));
+    (reducer.op)(left, right)
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::time::Duration;
+
+    use crate::ThreadPool;
+
+    fn fib(pool: &ThreadPool, n: u64) -> u64 {
+        if n < 2 {
+            return n;
+        }
+        let (a, b) = pool.join(|| fib(pool, n - 1), || fib(pool, n - 2));
+        a + b
+    }
+
+    #[test]
+    fn join_borrowed_data() {
+        let pool = ThreadPool::new(2);
+        let data: Vec<u64> = (1..=100).collect();
+        let (left, right) = pool.join(|| data[..50].iter().sum::<u64>(), || data[50..].iter().sum::<u64>());
+        assert_eq!(left + right, 5050);
+    }
+
+    #[test]
+    fn nested_joins_do_not_deadlock() {
+        // 在线程池外部调用
+        let pool = Arc::new(ThreadPool::new(2));
+        assert_eq!(fib(&pool, 20), 6765);
+
+        // 在Worker中调用：唯一的Worker在等待时必须自己执行被拆分出去的任务
+        for threads in [1, 2] {
+            let pool = Arc::new(ThreadPool::new(threads));
+            let (sender, result) = mpsc::channel();
+            let inner = Arc::clone(&pool);
+            pool.execute(move || sender.send(fib(&inner, 18)).unwrap());
+            assert_eq!(result.recv_timeout(Duration::from_secs(30)).unwrap(), 2584);
+        }
+    }
+
+    #[test]
+    fn join_panics_after_both_finish() {
+        let pool = ThreadPool::new(2);
+        let finished = AtomicUsize::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.join(
+                || -> () { panic!("left failed") },
+                || {
+                    std::thread::sleep(Duration::from_millis(20));
+                    finished.fetch_add(1, Ordering::SeqCst);
+                },
+            )
+        }));
+        assert_eq!(*result.unwrap_err().downcast::<&str>().unwrap(), "left failed");
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+        // panic传递给调用者，不计入线程池的panic次数
+        assert_eq!(pool.monitor().stats().panicked, 0);
+    }
+
+    #[test]
+    fn parallel_iteration_preserves_order() {
+        let pool = ThreadPool::new(3);
+        let squares = pool.par_map(0..1000u64, |n| n * n);
+        assert_eq!(squares, (0..1000u64).map(|n| n * n).collect::<Vec<_>>());
+        assert!(pool.par_map(Vec::<u64>::new(), |n| n).is_empty());
+
+        let data: Vec<u32> = (0..103).collect();
+        let sums = pool.par_chunks(&data, 10, |chunk| chunk.iter().sum::<u32>());
+        assert_eq!(sums.len(), 11);
+        assert_eq!(sums[0], 45);
+        assert_eq!(sums[10], 100 + 101 + 102);
+
+        let count = AtomicUsize::new(0);
+        pool.par_for_each(&data, |n| {
+            count.fetch_add(*n as usize, Ordering::SeqCst);
+        });
+        assert_eq!(count.load(Ordering::SeqCst), 103 * 102 / 2);
+    }
+
+    #[test]
+    fn reduce_in_order() {
+        let pool = ThreadPool::new(3);
+        assert_eq!(pool.par_reduce(1..=1000u64, || 0, |a, b| a + b), 500500);
+        assert_eq!(pool.par_reduce(Vec::<u64>::new(), || 7, |a, b| a + b), 7);
+        // 字符串拼接不满足交换律，结果与顺序拼接相同
+        let words: Vec<String> = (0..200).map(|n| n.to_string()).collect();
+        let joined = pool.par_map_reduce(&words, |word| word.clone(), String::new, |a, b| a + &b);
+        assert_eq!(joined, words.concat());
+    }
+}
@@ -0,0 +1,486 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::http::response::write_chunked;
+use crate::http::{Body, BodyReader, HeaderMap, Request, Response, StatusCode};
+use crate::middleware::Handler;
+
+// 逐跳（hop-by-hop）头部只对单个连接有意义，转发时需要去掉
+const HOP_BY_HOP: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+// 上游响应头部的最大字节数
+const MAX_RESPONSE_HEAD: usize = 64 * 1024;
+
+/*
+    反向代理处理器
+
+    把请求转发给上游HTTP服务器，并把上游的响应原样返回给客户端：
+    - 去掉逐跳头部（包括Connection中列出的头部），追加X-Forwarded-For/X-Forwarded-Host
+    - 请求体与上游响应体都以流的方式转发，不在内存中缓冲；长度未知的请求体以chunked编码发给上游
+    - 多个上游之间轮询（round-robin）
+    - 被动健康检查：连接或读取失败max_fails次后，该上游在fail_timeout内不再被选中；
+      连接失败时会换下一个上游重试，已经发出请求之后的失败不会重试（请求可能不是幂等的）
+    - 上游超时返回504，其他失败返回502
+ */
+pub struct Proxy {
+    upstreams: Vec<Upstream>,
+    // 下一次轮询的起点
+    next: AtomicUsize,
+    connect_timeout: Duration,
+    timeout: Duration,
+    max_fails: u32,
+    fail_timeout: Duration,
+    strip_prefix: Option<String>,
+}
+
+struct Upstream {
+    addr: SocketAddr,
+    health: Mutex<Health>,
+}
+
+#[derive(Default)]
+struct Health {
+    // 连续失败次数
+    fails: u32,
+    // 在此之前不再选中该上游
+    down_until: Option<Instant>,
+}
+
+/*
+    一次转发失败的原因
+ */
+#[derive(Debug)]
+enum Failure {
+    // 连接或读取超时，对应504
+    Timeout,
+    // 连接被拒绝、连接中断或者响应格式错误，对应502
+    Bad,
+    // 读取客户端的请求体失败（例如客户端中途断开），对应400，不计入上游的失败次数
+    Client,
+}
+
+impl Proxy {
+    /// 创建转发到 `upstreams` 的代理，至少需要一个上游。
+    pub fn new(upstreams: &[SocketAddr]) -> Proxy {
+        assert!(!upstreams.is_empty(), "proxy requires at least one upstream");
+        Proxy {
+            upstreams: upstreams
+                .iter()
+                .map(|addr| Upstream { addr: *addr, health: Mutex::new(Health::default()) })
+                .collect(),
+            next: AtomicUsize::new(0),
+            connect_timeout: Duration::from_secs(5),
+            timeout: Duration::from_secs(30),
+            max_fails: 1,
+            fail_timeout: Duration::from_secs(10),
+            strip_prefix: None,
+        }
+    }
+
+    /// 连接上游的超时时间，默认5秒。
+    pub fn connect_timeout(mut self, timeout: Duration) -> Proxy {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// 等待上游响应（以及读取响应体时每次读操作）的超时时间，默认30秒。
+    pub fn timeout(mut self, timeout: Duration) -> Proxy {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 连续失败多少次后暂时摘除该上游，默认1次。
+    pub fn max_fails(mut self, max_fails: u32) -> Proxy {
+        self.max_fails = max_fails.max(1);
+        self
+    }
+
+    /// 上游被摘除后多长时间内不再被选中，默认10秒。
+    pub fn fail_timeout(mut self, fail_timeout: Duration) -> Proxy {
+        self.fail_timeout = fail_timeout;
+        self
+    }
+
+    /// 转发前去掉路径前缀，例如前缀为/api时 /api/users 转发为 /users。
+    pub fn strip_prefix(mut self, prefix: &str) -> Proxy {
+        self.strip_prefix = Some(prefix.trim_end_matches('/').to_string());
+        self
+    }
+
+    // 按照轮询顺序排列的候选上游，健康的在前；全部被摘除时仍然按顺序尝试，避免永久返回502
+    fn candidates(&self) -> Vec<&Upstream> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let (mut healthy, down): (Vec<&Upstream>, Vec<&Upstream>) = (0..self.upstreams.len())
+            .map(|i| &self.upstreams[(start + i) % self.upstreams.len()])
+            .partition(|upstream| upstream.available(now));
+        if healthy.is_empty() {
+            healthy = down;
+        }
+        healthy
+    }
+
+    fn connect(&self, upstream: &Upstream) -> Result<TcpStream, Failure> {
+        let stream = TcpStream::connect_timeout(&upstream.addr, self.connect_timeout).map_err(|err| failure(&err))?;
+        stream.set_read_timeout(Some(self.timeout)).map_err(|err| failure(&err))?;
+        stream.set_write_timeout(Some(self.timeout)).map_err(|err| failure(&err))?;
+        Ok(stream)
+    }
+
+    // 向上游写出请求并读取响应头部，响应体作为流返回
+    fn forward(&self, mut stream: TcpStream, request: &Request, body: &mut BodyReader) -> Result<Response, Failure> {
+        let head = self.request_head(request, body.length());
+        stream.write_all(head.as_bytes()).map_err(|err| failure(&err))?;
+        send_body(body, &mut stream)?;
+        stream.flush().map_err(|err| failure(&err))?;
+
+        let mut reader = BufReader::new(stream);
+        loop {
+            let (status, mut headers) = read_head(&mut reader)?;
+            // 1xx是中间响应（例如100 Continue），跳过后继续读取最终响应；请求中的Upgrade已经去掉，上游不应该返回101
+            match status.as_u16() {
+                101 => return Err(Failure::Bad),
+                100..=199 => continue,
+                _ => {}
+            }
+
+            let chunked = headers
+                .get("Transfer-Encoding")
+                .map(|value| value.to_ascii_lowercase().contains("chunked"))
+                .unwrap_or(false);
+            let length = match headers.get("Content-Length") {
+                Some(value) => Some(value.parse::<u64>().map_err(|_| Failure::Bad)?),
+                None => None,
+            };
+            let bodiless = request.method == "HEAD" || status == StatusCode::NO_CONTENT || status.as_u16() == 304;
+
+            // 响应体的长度由本服务重新确定（Content-Length或者chunked）
+            strip_hop_by_hop(&mut headers);
+            headers.remove("Content-Length");
+            let body = match length {
+                _ if bodiless => Body::Empty,
+                _ if chunked => Body::Stream(Box::new(ChunkedReader::new(reader))),
+                Some(0) => Body::Empty,
+                Some(length) => Body::Stream(Box::new(reader.take(length))),
+                // 既没有长度也不是chunked：响应体一直持续到上游关闭连接
+                None => Body::Stream(Box::new(reader)),
+            };
+            return Ok(Response { status, headers, body, upgrade: None, route: None, on_complete: None });
+        }
+    }
+
+    // 组装转发给上游的请求头部，`length` 为请求体的长度，长度未知时使用chunked编码
+    fn request_head(&self, request: &Request, length: Option<u64>) -> String {
+        let mut target = request.target();
+        if let Some(prefix) = &self.strip_prefix {
+            // 只在路径分段的边界上去掉前缀：/api/users -> /users，/apix不变
+            match target.strip_prefix(prefix.as_str()) {
+                Some(rest) if rest.starts_with('/') => target = rest.to_string(),
+                Some(rest) if rest.is_empty() || rest.starts_with('?') => target = format!("/{}", rest),
+                _ => {}
+            }
+        }
+
+        let mut headers = request.headers.clone();
+        strip_hop_by_hop(&mut headers);
+        headers.remove("Content-Length");
+        if let Some(addr) = request.remote_addr {
+            let forwarded = match request.header("X-Forwarded-For") {
+                Some(existing) => format!("{}, {}", existing, addr.ip()),
+                None => addr.ip().to_string(),
+            };
+            headers.insert("X-Forwarded-For", forwarded);
+        }
+        if let Some(host) = request.header("Host") {
+            headers.insert("X-Forwarded-Host", host);
+        }
+        // 每个请求使用一个新连接，上游写完响应后关闭连接
+        headers.insert("Connection", "close");
+        match length {
+            Some(length) if length > 0 || matches!(request.method.as_str(), "POST" | "PUT" | "PATCH") => {
+                headers.insert("Content-Length", length.to_string());
+            }
+            Some(_) => {}
+            None => headers.insert("Transfer-Encoding", "chunked"),
+        }
+
+        let mut head = format!("{} {} HTTP/1.1\r\n", request.method, target);
+        for (name, value) in headers.iter() {
+            head.push_str(name);
+            head.push_str(": ");
+            head.push_str(value);
+            head.push_str("\r\n");
+        }
+        head.push_str("\r\n");
+        head
+    }
+}
+
+impl Handler for Proxy {
+    fn handle(&self, mut request: Request) -> Response {
+        let mut body = request.take_body();
+        let mut last_failure = Failure::Bad;
+        for upstream in self.candidates() {
+            // 连接失败时请求还没有发出，可以安全地换下一个上游重试
+            let stream = match self.connect(upstream) {
+                Ok(stream) => stream,
+                Err(err) => {
+                    upstream.failed(self.max_fails, self.fail_timeout);
+                    last_failure = err;
+                    continue;
+                }
+            };
+            return match self.forward(stream, &request, &mut body) {
+                Ok(response) => {
+                    upstream.succeeded();
+                    response
+                }
+                Err(Failure::Client) => Failure::Client.response(),
+                Err(err) => {
+                    upstream.failed(self.max_fails, self.fail_timeout);
+                    println!("[Proxy] upstream {} failed: {:?}", upstream.addr, err);
+                    err.response()
+                }
+            };
+        }
+        println!("[Proxy] no upstream available: {:?}", last_failure);
+        last_failure.response()
+    }
+}
+
+impl Upstream {
+    fn available(&self, now: Instant) -> bool {
+        match self.health.lock().unwrap().down_until {
+            Some(until) => now >= until,
+            None => true,
+        }
+    }
+
+    fn failed(&self, max_fails: u32, fail_timeout: Duration) {
+        let mut health = self.health.lock().unwrap();
+        health.fails += 1;
+        if health.fails >= max_fails {
+            health.fails = 0;
+            health.down_until = Some(Instant::now() + fail_timeout);
+        }
+    }
+
+    fn succeeded(&self) {
+        let mut health = self.health.lock().unwrap();
+        health.fails = 0;
+        health.down_until = None;
+    }
+}
+
+impl Failure {
+    fn response(&self) -> Response {
+        match self {
+            Failure::Timeout => Response::from(StatusCode::GATEWAY_TIMEOUT),
+            Failure::Bad => Response::from(StatusCode::BAD_GATEWAY),
+            Failure::Client => Response::from(StatusCode::BAD_REQUEST).with_header("Connection", "close"),
+        }
+    }
+}
+
+// 把客户端的请求体复制到上游：长度已知时原样复制，否则按照chunked编码逐块写出
+fn send_body(body: &mut BodyReader, stream: &mut TcpStream) -> Result<(), Failure> {
+    let chunked = body.length().is_none();
+    let mut client = ClientBody { body, failed: false };
+    let copied = if chunked { write_chunked(&mut client, stream) } else { io::copy(&mut client, stream) };
+    match copied {
+        Ok(_) => Ok(()),
+        Err(_) if client.failed => Err(Failure::Client),
+        Err(err) => Err(failure(&err)),
+    }
+}
+
+// 记录错误是否来自客户端一侧的请求体
+struct ClientBody<'a> {
+    body: &'a mut BodyReader,
+    failed: bool,
+}
+
+impl Read for ClientBody<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.body.read(buf);
+        self.failed = result.is_err();
+        result
+    }
+}
+
+fn failure(err: &io::Error) -> Failure {
+    match err.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Failure::Timeout,
+        _ => Failure::Bad,
+    }
+}
+
+// 去掉逐跳头部，以及Connection头部中列出的头部
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<String> = headers
+        .get_all("Connection")
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect();
+    for name in listed.iter().map(String::as_str).chain(HOP_BY_HOP) {
+        while headers.remove(name).is_some() {}
+    }
+}
+
+// 读取上游响应的状态行与头部
+fn read_head<R: BufRead>(reader: &mut R) -> Result<(StatusCode, HeaderMap), Failure> {
+    let mut head_size = 0;
+    let status_line = read_line(reader, &mut head_size)?;
+    let status = parse_status_line(&status_line).ok_or(Failure::Bad)?;
+    let mut headers = HeaderMap::new();
+    loop {
+        let line = read_line(reader, &mut head_size)?;
+        if line.is_empty() {
+            return Ok((status, headers));
+        }
+        let (name, value) = line.split_once(':').ok_or(Failure::Bad)?;
+        headers.append(name.trim(), value.trim());
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R, head_size: &mut usize) -> Result<String, Failure> {
+    let mut line = Vec::new();
+    let limit = (MAX_RESPONSE_HEAD - *head_size) as u64;
+    let n = reader.take(limit).read_until(b'\n', &mut line).map_err(|err| failure(&err))?;
+    *head_size += n;
+    if n == 0 || line.last() != Some(&b'\n') {
+        return Err(Failure::Bad);
+    }
+    while matches!(line.last(), Some(b'\n') | Some(b'\r')) {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| Failure::Bad)
+}
+
+// HTTP/1.1 200 OK
+fn parse_status_line(line: &str) -> Option<StatusCode> {
+    let mut parts = line.splitn(3, ' ');
+    let version = parts.next()?;
+    if !version.starts_with("HTTP/1.") {
+        return None;
+    }
+    let code: u16 = parts.next()?.parse().ok()?;
+    if !(100..600).contains(&code) {
+        return None;
+    }
+    Some(StatusCode(code))
+}
+
+/*
+    chunked编码的解码器，读出去掉分块格式之后的原始数据
+ */
+struct ChunkedReader<R> {
+    inner: R,
+    // 当前块剩余的字节数
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    fn new(inner: R) -> ChunkedReader<R> {
+        ChunkedReader { inner, remaining: 0, done: false }
+    }
+
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let mut line = String::new();
+        if self.inner.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let size = line.trim().split(';').next().unwrap_or("");
+        self.remaining = u64::from_str_radix(size, 16)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size"))?;
+        if self.remaining == 0 {
+            // 跳过trailer直到空行
+            loop {
+                let mut trailer = String::new();
+                if self.inner.read_line(&mut trailer)? == 0 || trailer.trim().is_empty() {
+                    break;
+                }
+            }
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            self.next_chunk()?;
+            if self.done {
+                return Ok(0);
+            }
+        }
+        let max = buf.len().min(self.remaining as usize);
+        let n = self.inner.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n as u64;
+        if self.remaining == 0 {
+            // 每个块的数据之后跟着CRLF
+            let mut crlf = [0; 2];
+            self.inner.read_exact(&mut crlf)?;
+        }
+        Ok(n)
+    }
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_hop_by_hop_headers() {
+        let mut headers = HeaderMap::new();
+        headers.append("Connection", "keep-alive, X-Session");
+        headers.append("Keep-Alive", "timeout=5");
+        headers.append("X-Session", "secret");
+        headers.append("Transfer-Encoding", "chunked");
+        headers.append("Upgrade", "websocket");
+        headers.append("Accept", "text/html");
+        strip_hop_by_hop(&mut headers);
+        assert_eq!(headers.iter().collect::<Vec<_>>(), vec![("Accept", "text/html")]);
+    }
+
+    #[test]
+    fn decode_chunked_body() {
+        let raw = b"5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Trailer: 1\r\n\r\n";
+        let mut body = String::new();
+        ChunkedReader::new(&raw[..]).read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello world");
+    }
+
+    #[test]
+    fn parse_status() {
+        assert_eq!(parse_status_line("HTTP/1.1 404 Not Found"), Some(StatusCode::NOT_FOUND));
+        assert_eq!(parse_status_line("HTTP/1.0 200"), Some(StatusCode::OK));
+        assert_eq!(parse_status_line("SSH-2.0-OpenSSH"), None);
+        assert_eq!(parse_status_line("HTTP/1.1 abc"), None);
+    }
+}
@@ -0,0 +1,969 @@
+use std::collections::HashMap;
+use std::io::{self, Cursor, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::http::body::Decoder;
+use crate::http::{upgrade, Body, Limits, OnComplete, OnUpgrade, ParseError, Request, Response, StatusCode, Upgraded};
+use crate::limiter::{self, ConnectionGuard, Limiter};
+use crate::middleware::Handler;
+use crate::server::{self, Options, Shutdown};
+use crate::{PoolMonitor, Priority, Submitted, ThreadPool};
+
+// epoll事件中的token：0代表监听套接字，1代表唤醒器，其余为连接
+const LISTENER: u64 = 0;
+const WAKER: u64 = 1;
+const FIRST_CONNECTION: u64 = 2;
+// 文件与字节流响应体每次在Worker中读取的最大字节数
+const BODY_PIECE_SIZE: usize = 64 * 1024;
+
+// 决定请求在线程池中的优先级
+type Classifier = Arc<dyn Fn(&Request) -> Priority + Send + Sync>;
+
+/*
+    事件驱动（epoll）模式
+
+    由少量的Reactor线程通过epoll同时监听大量连接，只有在一个请求完整读取之后才提交给线程池执行处理器，
+    处理器生成的响应再交回Reactor线程以非阻塞的方式写出。
+    这样空闲的keep-alive连接或者发送缓慢的客户端只占用一个文件描述符，而不会长期占用线程池中的Worker。
+    请求体同样在提交之前完整读入内存，受 `Limits::max_body_bytes` 限制；需要接收大请求体（例如大文件上传）时使用阻塞模式
+ */
+pub struct EventLoop {
+    listener: TcpListener,
+    threads: usize,
+    options: Options,
+    limiter: Limiter,
+    shutdown: Shutdown,
+    classifier: Option<Classifier>,
+}
+
+impl EventLoop {
+    pub fn new(listener: TcpListener) -> EventLoop {
+        EventLoop {
+            listener,
+            threads: 2,
+            options: Options::default(),
+            limiter: Limiter::unlimited(),
+            shutdown: Shutdown::new(),
+            classifier: None,
+        }
+    }
+
+    /// 设置Reactor线程数，默认为2。
+    pub fn threads(mut self, threads: usize) -> EventLoop {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// 设置连接处理选项：空闲连接按照keep-alive超时关闭，写出停滞的连接按照写超时关闭。
+    pub fn options(mut self, options: Options) -> EventLoop {
+        self.options = options;
+        self
+    }
+
+    /// 设置准入控制：新连接检查连接数上限，每个完整的请求在提交给线程池之前检查队列长度。
+    pub fn limiter(mut self, limiter: Limiter) -> EventLoop {
+        self.limiter = limiter;
+        self
+    }
+
+    /// 设置停机信号：触发后不再接受新连接，空闲连接立即关闭，正在处理的请求写出响应后关闭连接，
+    /// 所有连接都关闭后 `run` 返回。
+    pub fn shutdown(mut self, shutdown: Shutdown) -> EventLoop {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// 设置请求的优先级，例如让健康检查不必排在批量请求之后；默认所有请求都是普通优先级。
+    pub fn prioritize<F>(mut self, classifier: F) -> EventLoop
+    where
+        F: Fn(&Request) -> Priority + Send + Sync + 'static,
+    {
+        self.classifier = Some(Arc::new(classifier));
+        self
+    }
+
+    /// 启动所有Reactor线程并阻塞当前线程，处理器在 `pool` 中执行。
+    pub fn run(&self, pool: &ThreadPool, handler: Arc<dyn Handler>) -> io::Result<()> {
+        self.listener.set_nonblocking(true)?;
+
+        // 使用作用域线程，这样Reactor线程可以直接借用监听套接字和线程池
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..self.threads)
+                .map(|id| {
+                    let handler = Arc::clone(&handler);
+                    scope.spawn(move || -> io::Result<()> {
+                        Reactor::new(id, &self.listener, pool, handler, self.options, self.limiter.clone())?
+                            .classifier(self.classifier.clone())
+                            .shutdown(self.shutdown.clone())
+                            .run()
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(io::Error::other("reactor thread panicked")))?;
+            }
+            Ok(())
+        })
+    }
+}
+
+// 连接状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    // 等待并读取请求
+    Reading,
+    // 请求已提交给线程池，等待响应
+    Processing,
+    // 正在写出响应
+    Writing,
+}
+
+struct Connection {
+    stream: TcpStream,
+    addr: SocketAddr,
+    state: State,
+    read_buf: Vec<u8>,
+    // 读缓冲中还没有完整到达的请求的解析状态
+    parser: Parser,
+    write_buf: Vec<u8>,
+    written: usize,
+    keep_alive: bool,
+    // 客户端已经关闭了写方向，处理完缓冲中的请求后关闭连接
+    read_closed: bool,
+    // 响应写出后需要移交连接的升级回调
+    upgrade: Option<OnUpgrade>,
+    // 写缓冲写完之后还需要继续读取的响应体
+    body: Option<Pending>,
+    // 当前响应写完（或者连接关闭）时调用的回调，同时累计已经交给写缓冲的响应体字节数
+    on_complete: Option<OnComplete>,
+    last_active: Instant,
+    // 连接关闭（或者移交给升级回调）时归还连接数
+    _guard: ConnectionGuard,
+}
+
+impl Connection {
+    // 读取数据直到没有更多数据可读，返回是否读到了EOF，缓冲超过max_buffered字节时返回错误
+    fn fill(&mut self, max_buffered: usize) -> io::Result<bool> {
+        let mut chunk = [0; 16 * 1024];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Ok(true),
+                Ok(n) => {
+                    self.read_buf.extend_from_slice(&chunk[..n]);
+                    self.last_active = Instant::now();
+                    if self.read_buf.len() > max_buffered {
+                        return Err(io::Error::other("request too large"));
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+// 线程池中处理完成的响应，或者响应体的下一段
+struct Completion {
+    token: u64,
+    bytes: Vec<u8>,
+    keep_alive: bool,
+    upgrade: Option<OnUpgrade>,
+    body: Option<Pending>,
+    on_complete: Option<OnComplete>,
+}
+
+impl Completion {
+    // 写出bytes之后就关闭连接的响应
+    fn closing(token: u64, bytes: Vec<u8>) -> Completion {
+        Completion { token, bytes, keep_alive: false, upgrade: None, body: None, on_complete: None }
+    }
+}
+
+/*
+    分段写出的响应体（文件或者字节流）
+
+    读取可能阻塞，所以每一段都在Worker中读取，Reactor写完上一段之后才提交下一段的读取，
+    每个连接最多只缓冲一段，客户端读取缓慢时也不会在内存中累积整个响应体
+ */
+struct Pending {
+    reader: Box<dyn Read + Send>,
+    // 字节流使用chunked编码，文件按照Content-Length原样写出
+    chunked: bool,
+    // 按照Content-Length还需要写出的字节数
+    remaining: u64,
+}
+
+impl Pending {
+    // 把不能一次放进写缓冲的响应体转换为分段读取，其余的响应体直接追加到头部之后
+    fn split(body: Body, bytes: &mut Vec<u8>) -> Option<Pending> {
+        match body {
+            Body::Empty => None,
+            Body::Bytes(body) => {
+                bytes.extend_from_slice(&body);
+                None
+            }
+            Body::File(file) => {
+                let remaining = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+                Some(Pending { reader: Box::new(file), chunked: false, remaining })
+            }
+            Body::Stream(stream) => Some(Pending { reader: stream, chunked: true, remaining: 0 }),
+        }
+    }
+
+    // 读取并编码下一段追加到bytes中，返回是否还有后续的段
+    fn next(&mut self, bytes: &mut Vec<u8>, on_complete: &mut Option<OnComplete>) -> io::Result<bool> {
+        let limit = if self.chunked { BODY_PIECE_SIZE } else { BODY_PIECE_SIZE.min(self.remaining as usize) };
+        if limit == 0 {
+            return Ok(false);
+        }
+        let mut piece = vec![0; limit];
+        let n = loop {
+            match self.reader.read(&mut piece) {
+                Ok(n) => break n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        };
+        if let Some(on_complete) = on_complete {
+            on_complete.add(n as u64);
+        }
+        if self.chunked {
+            if n == 0 {
+                bytes.extend_from_slice(b"0\r\n\r\n");
+                return Ok(false);
+            }
+            bytes.extend_from_slice(format!("{:X}\r\n", n).as_bytes());
+            bytes.extend_from_slice(&piece[..n]);
+            bytes.extend_from_slice(b"\r\n");
+            return Ok(true);
+        }
+        // 文件在写出期间被截断，已经发出的Content-Length无法兑现
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        bytes.extend_from_slice(&piece[..n]);
+        self.remaining -= n as u64;
+        Ok(self.remaining > 0)
+    }
+}
+
+// 在Worker中读取下一段响应体，出错时返回false，已经写出的部分无法撤回，只能关闭连接
+fn read_piece(body: &mut Option<Pending>, bytes: &mut Vec<u8>, on_complete: &mut Option<OnComplete>) -> bool {
+    let pending = match body {
+        Some(pending) => pending,
+        None => return true,
+    };
+    match pending.next(bytes, on_complete) {
+        Ok(true) => true,
+        Ok(false) => {
+            *body = None;
+            true
+        }
+        Err(err) => {
+            println!("[Reactor] read response body failed: {}", err);
+            *body = None;
+            false
+        }
+    }
+}
+
+/*
+    把Worker中生成的响应交回Reactor
+
+    任务被线程池的拒绝策略丢弃（或者处理器panic）时没有发送响应就被释放，
+    此时回复503并关闭连接，否则连接会一直停留在处理中状态；
+    读取响应体后续段的任务被丢弃时响应已经写出了一部分，只能直接关闭连接
+ */
+struct Reply {
+    token: u64,
+    sender: Option<mpsc::Sender<Completion>>,
+    waker: Arc<Waker>,
+    // 响应头部已经写出
+    started: bool,
+}
+
+impl Reply {
+    fn send(
+        mut self,
+        bytes: Vec<u8>,
+        keep_alive: bool,
+        upgrade: Option<OnUpgrade>,
+        body: Option<Pending>,
+        on_complete: Option<OnComplete>,
+    ) {
+        let sender = self.sender.take().unwrap();
+        let completion = Completion { token: self.token, bytes, keep_alive, upgrade, body, on_complete };
+        // Reactor可能已经因为连接关闭而不再存在，这里忽略发送失败
+        if sender.send(completion).is_ok() {
+            self.waker.wake();
+        }
+    }
+}
+
+impl Drop for Reply {
+    fn drop(&mut self) {
+        if let Some(sender) = self.sender.take() {
+            let mut bytes = Vec::new();
+            if !self.started {
+                let _ = Response::from(StatusCode::SERVICE_UNAVAILABLE).with_header("Connection", "close").write_to(&mut bytes);
+            }
+            if sender.send(Completion::closing(self.token, bytes)).is_ok() {
+                self.waker.wake();
+            }
+        }
+    }
+}
+
+// 从读缓冲中解析请求的结果
+enum Parsed {
+    Incomplete,
+    Request(Request),
+    Invalid(ParseError),
+}
+
+/*
+    读缓冲的增量解析器
+
+    请求可能分多次到达，解析状态在两次读取之间保留：头部结束标记只在新到达的字节中查找，
+    头部解析完成后记下请求体在读缓冲中的解码位置以及解码器状态，之后每次只解码新的字节，
+    整个请求的解析开销与请求大小成线性关系；请求完整之后才从读缓冲中移除，状态随之重置
+ */
+#[derive(Default)]
+struct Parser {
+    // 已经查找过头部结束标记的字节数
+    scanned: usize,
+    // 已经解析出头部的请求以及请求体的解码器
+    head: Option<(Request, Decoder)>,
+    // 读缓冲中已经解码的字节数（头部以及已经解码的请求体）
+    consumed: usize,
+}
+
+impl Parser {
+    fn parse(&mut self, buffer: &mut Vec<u8>, limits: Limits) -> Parsed {
+        if self.head.is_none() {
+            // 结束标记可能跨越上一次查找的边界，往回多查3个字节
+            let from = self.scanned.saturating_sub(3);
+            let end = match buffer[from..].windows(4).position(|window| window == b"\r\n\r\n") {
+                Some(index) => from + index + 4,
+                None => {
+                    self.scanned = buffer.len();
+                    return if buffer.len() > limits.max_head_bytes {
+                        Parsed::Invalid(ParseError::HeadTooLarge)
+                    } else {
+                        Parsed::Incomplete
+                    };
+                }
+            };
+            let mut cursor = Cursor::new(&buffer[..end]);
+            let (request, decoder) = match Request::read_head(&mut cursor, limits) {
+                Ok(Some(head)) => head,
+                Ok(None) => return Parsed::Incomplete,
+                Err(err) => return Parsed::Invalid(err),
+            };
+            // 在读取请求体之前检查长度，避免为超大的请求体分配内存
+            if decoder.length().is_some_and(|length| length > limits.max_body_bytes as u64) {
+                return Parsed::Invalid(ParseError::BodyTooLarge);
+            }
+            self.consumed = cursor.position() as usize;
+            self.head = Some((request, decoder));
+        }
+
+        let (request, decoder) = self.head.as_mut().unwrap();
+        let mut cursor = Cursor::new(&buffer[self.consumed..]);
+        let mut piece = [0; 16 * 1024];
+        loop {
+            // 块大小行或者trailer只到达了一部分时，解码器回到这一次读取之前的状态，等待更多数据后重新读取
+            let saved = *decoder;
+            let n = match decoder.read(&mut cursor, &mut piece) {
+                Ok(0) => break,
+                Ok(n) => n,
+                // 请求体还没有完整到达
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    *decoder = saved;
+                    return Parsed::Incomplete;
+                }
+                Err(err) => return Parsed::Invalid(ParseError::Io(err)),
+            };
+            request.body.extend_from_slice(&piece[..n]);
+            if request.body.len() > limits.max_body_bytes {
+                return Parsed::Invalid(ParseError::BodyTooLarge);
+            }
+            self.consumed += cursor.position() as usize;
+            cursor = Cursor::new(&buffer[self.consumed..]);
+        }
+        let consumed = self.consumed + cursor.position() as usize;
+        let (request, _) = self.head.take().unwrap();
+        buffer.drain(..consumed);
+        *self = Parser::default();
+        Parsed::Request(request)
+    }
+}
+
+/*
+    单个Reactor线程，拥有独立的epoll实例以及连接表
+ */
+struct Reactor<'a> {
+    id: usize,
+    listener: &'a TcpListener,
+    pool: &'a ThreadPool,
+    handler: Arc<dyn Handler>,
+    options: Options,
+    limiter: Limiter,
+    classifier: Option<Classifier>,
+    monitor: PoolMonitor,
+    epoll: Epoll,
+    waker: Arc<Waker>,
+    sender: mpsc::Sender<Completion>,
+    completions: mpsc::Receiver<Completion>,
+    connections: HashMap<u64, Connection>,
+    next_token: u64,
+    // 触发之后不再接受新连接，之后写出的响应都带有Connection: close
+    shutdown: Shutdown,
+}
+
+impl<'a> Reactor<'a> {
+    fn new(
+        id: usize,
+        listener: &'a TcpListener,
+        pool: &'a ThreadPool,
+        handler: Arc<dyn Handler>,
+        options: Options,
+        limiter: Limiter,
+    ) -> io::Result<Reactor<'a>> {
+        let epoll = Epoll::new()?;
+        let waker = Arc::new(Waker::new()?);
+        // 多个Reactor共同监听同一个套接字，EPOLLEXCLUSIVE避免新连接到来时唤醒所有Reactor
+        epoll.add(listener.as_raw_fd(), LISTENER, (libc::EPOLLIN | libc::EPOLLEXCLUSIVE) as u32)?;
+        epoll.add(waker.fd, WAKER, libc::EPOLLIN as u32)?;
+        let (sender, completions) = mpsc::channel();
+        println!("[Reactor(id = {})] startup", id);
+        Ok(Reactor {
+            id,
+            listener,
+            pool,
+            handler,
+            options,
+            limiter,
+            classifier: None,
+            monitor: pool.monitor(),
+            epoll,
+            waker,
+            sender,
+            completions,
+            connections: HashMap::new(),
+            next_token: FIRST_CONNECTION,
+            shutdown: Shutdown::new(),
+        })
+    }
+
+    fn classifier(mut self, classifier: Option<Classifier>) -> Reactor<'a> {
+        self.classifier = classifier;
+        self
+    }
+
+    fn shutdown(mut self, shutdown: Shutdown) -> Reactor<'a> {
+        self.shutdown = shutdown;
+        self
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        let mut events = vec![libc::epoll_event { events: 0, u64: 0 }; 1024];
+        let mut last_sweep = Instant::now();
+        let mut draining = false;
+        loop {
+            if self.shutdown.is_triggered() {
+                if !draining {
+                    // 停止接受新连接，监听套接字留给其他Reactor或者热升级后的新进程
+                    let _ = self.epoll.delete(self.listener.as_raw_fd());
+                    draining = true;
+                    println!("[Reactor(id = {})] draining {} connections", self.id, self.connections.len());
+                }
+                self.close_idle();
+                if self.connections.is_empty() {
+                    return Ok(());
+                }
+            }
+            // 最多等待200毫秒，保证空闲连接能够被及时清理、停机信号能够被及时响应
+            let n = self.epoll.wait(&mut events, 200)?;
+            for event in &events[..n] {
+                let (token, flags) = (event.u64, event.events);
+                match token {
+                    LISTENER => self.accept(),
+                    WAKER => self.complete(),
+                    token => self.ready(token, flags),
+                }
+            }
+            if last_sweep.elapsed() >= Duration::from_secs(1) {
+                self.sweep();
+                last_sweep = Instant::now();
+            }
+        }
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    if let Err(err) = self.register(stream, addr) {
+                        println!("[Reactor(id = {})] register connection failed: {}", self.id, err);
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    // 例如文件描述符耗尽，留到下一轮事件再重试
+                    println!("[Reactor(id = {})] accept failed: {}", self.id, err);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn register(&mut self, stream: TcpStream, addr: SocketAddr) -> io::Result<()> {
+        let guard = match self.limiter.connect(addr.ip()) {
+            Ok(guard) => guard,
+            Err(rejection) => {
+                limiter::refuse(stream, rejection);
+                return Ok(());
+            }
+        };
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        let token = self.next_token;
+        self.next_token += 1;
+        self.epoll.add(stream.as_raw_fd(), token, READ_INTEREST)?;
+        self.connections.insert(
+            token,
+            Connection {
+                stream,
+                addr,
+                state: State::Reading,
+                read_buf: Vec::new(),
+                parser: Parser::default(),
+                write_buf: Vec::new(),
+                written: 0,
+                keep_alive: true,
+                read_closed: false,
+                upgrade: None,
+                body: None,
+                on_complete: None,
+                last_active: Instant::now(),
+                _guard: guard,
+            },
+        );
+        Ok(())
+    }
+
+    fn ready(&mut self, token: u64, flags: u32) {
+        let max_buffered = self.max_buffered();
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+        if flags & (libc::EPOLLERR | libc::EPOLLHUP) as u32 != 0 {
+            self.close(token);
+            return;
+        }
+        match connection.state {
+            State::Reading if flags & libc::EPOLLIN as u32 != 0 => match connection.fill(max_buffered) {
+                Ok(eof) => {
+                    connection.read_closed = eof;
+                    self.dispatch(token);
+                }
+                Err(_) => self.close(token),
+            },
+            State::Writing if flags & libc::EPOLLOUT as u32 != 0 => self.flush(token),
+            _ => {}
+        }
+    }
+
+    // 尝试从读缓冲中解析出一个完整请求并提交给线程池
+    fn dispatch(&mut self, token: u64) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+        let mut request = match connection.parser.parse(&mut connection.read_buf, self.options.limits) {
+            Parsed::Request(request) => request,
+            Parsed::Incomplete => {
+                if connection.read_closed {
+                    self.close(token);
+                }
+                return;
+            }
+            Parsed::Invalid(err) => {
+                println!("[Reactor(id = {})] bad request: {}", self.id, err);
+                let mut bytes = Vec::new();
+                let _ = Response::from(err.status())
+                    .with_header("Connection", "close")
+                    .write_to(&mut bytes);
+                self.respond(Completion::closing(token, bytes));
+                return;
+            }
+        };
+
+        // 线程池队列已满：不再提交，直接返回503并关闭连接
+        if let Err(rejection) = self.limiter.check_queue(self.monitor.stats().queued) {
+            let mut bytes = Vec::new();
+            let _ = rejection.response().with_header("Connection", "close").write_to(&mut bytes);
+            self.respond(Completion::closing(token, bytes));
+            return;
+        }
+
+        // 处理期间不再关注读事件，也就不会读取流水线中的后续请求
+        connection.state = State::Processing;
+        if self.epoll.modify(connection.stream.as_raw_fd(), token, 0).is_err() {
+            self.close(token);
+            return;
+        }
+
+        request.remote_addr = Some(connection.addr);
+        // 停机期间（包括Reactor还没有在事件循环开头发现停机信号时）写完当前响应就关闭连接
+        let keep_alive = request.keep_alive() && !connection.read_closed && !self.shutdown.is_triggered();
+        let head_only = request.method == "HEAD";
+        let priority = self.classifier.as_ref().map_or(Priority::Normal, |classify| classify(&request));
+        let handler = Arc::clone(&self.handler);
+        let reply = self.reply(token, false);
+        let job = move || {
+            let mut response = handler.handle(request);
+            let upgrade = response.upgrade.take();
+            let mut on_complete = response.on_complete.take();
+            let keep_alive = keep_alive && !server::closes_connection(&response);
+            if upgrade.is_none() {
+                response = response.with_header("Connection", if keep_alive { "keep-alive" } else { "close" });
+            }
+            // 头部与内存中的响应体一次交给Reactor；文件与字节流先读取第一段，之后每写完一段再读取下一段
+            // HEAD请求的响应与GET相同，只是不写出响应体
+            let mut bytes = response.encode_head();
+            let body = std::mem::replace(&mut response.body, Body::Empty);
+            let (head, body) = (bytes.len(), if head_only { Body::Empty } else { body });
+            let mut body = Pending::split(body, &mut bytes);
+            if let Some(on_complete) = &mut on_complete {
+                on_complete.add((bytes.len() - head) as u64);
+            }
+            let keep_alive = read_piece(&mut body, &mut bytes, &mut on_complete) && keep_alive;
+            reply.send(bytes, keep_alive, upgrade, body, on_complete);
+        };
+        // 被拒绝的任务由Reply回复503
+        match self.pool.try_execute_with_priority(priority, job) {
+            Ok(Submitted::Queued) | Ok(Submitted::RanOnCaller) => {}
+            Ok(Submitted::DisplacedOldest) => println!("[Reactor(id = {})] thread pool queue is full, dropped the oldest queued request", self.id),
+            Ok(Submitted::Discarded) | Err(_) => println!("[Reactor(id = {})] thread pool queue is full, request rejected", self.id),
+        }
+    }
+
+    fn reply(&self, token: u64, started: bool) -> Reply {
+        Reply { token, sender: Some(self.sender.clone()), waker: Arc::clone(&self.waker), started }
+    }
+
+    // 提交一个读取下一段响应体的任务；读取期间不关注任何事件，也不受写超时限制
+    fn fetch(&mut self, token: u64, mut body: Option<Pending>, mut on_complete: Option<OnComplete>, keep_alive: bool) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+        connection.state = State::Processing;
+        if self.epoll.modify(connection.stream.as_raw_fd(), token, 0).is_err() {
+            return self.close(token);
+        }
+        let reply = self.reply(token, true);
+        let job = move || {
+            let mut bytes = Vec::new();
+            let keep_alive = read_piece(&mut body, &mut bytes, &mut on_complete) && keep_alive;
+            reply.send(bytes, keep_alive, None, body, on_complete);
+        };
+        // 被拒绝的任务由Reply关闭连接
+        if let Ok(Submitted::Discarded) | Err(_) = self.pool.try_execute(job) {
+            println!("[Reactor(id = {})] thread pool queue is full, response aborted", self.id);
+        }
+    }
+
+    // 处理线程池中完成的响应
+    fn complete(&mut self) {
+        self.waker.drain();
+        while let Ok(completion) = self.completions.try_recv() {
+            self.respond(completion);
+        }
+    }
+
+    fn respond(&mut self, completion: Completion) {
+        let Completion { token, bytes, keep_alive, upgrade, body, on_complete } = completion;
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            // 连接在处理期间已经被关闭
+            None => return,
+        };
+        connection.state = State::Writing;
+        connection.write_buf = bytes;
+        connection.written = 0;
+        connection.keep_alive = keep_alive;
+        connection.upgrade = upgrade;
+        connection.body = body;
+        connection.on_complete = on_complete;
+        connection.last_active = Instant::now();
+        self.flush(token);
+    }
+
+    // 尽可能多地写出响应，写不完时关注可写事件等待下一次写出
+    fn flush(&mut self, token: u64) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+        while connection.written < connection.write_buf.len() {
+            match connection.stream.write(&connection.write_buf[connection.written..]) {
+                Ok(0) => return self.close(token),
+                Ok(n) => {
+                    connection.written += n;
+                    connection.last_active = Instant::now();
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    if self.epoll.modify(connection.stream.as_raw_fd(), token, libc::EPOLLOUT as u32).is_err() {
+                        self.close(token);
+                    }
+                    return;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return self.close(token),
+            }
+        }
+
+        // 写缓冲写完了，响应体还有后续的段
+        if connection.body.is_some() {
+            let (body, on_complete) = (connection.body.take(), connection.on_complete.take());
+            let keep_alive = connection.keep_alive;
+            return self.fetch(token, body, on_complete, keep_alive);
+        }
+        // 响应已经完整写出
+        connection.on_complete = None;
+
+        // 协议升级：连接从epoll中移除，交给独立的线程处理
+        if connection.upgrade.is_some() {
+            if let Some(mut connection) = self.connections.remove(&token) {
+                let _ = self.epoll.delete(connection.stream.as_raw_fd());
+                if let Some(on_upgrade) = connection.upgrade.take() {
+                    let buffered = std::mem::take(&mut connection.read_buf);
+                    upgrade::spawn(on_upgrade, Upgraded::new(connection.stream, buffered));
+                }
+            }
+            return;
+        }
+        if !connection.keep_alive {
+            return self.close(token);
+        }
+        // 响应写完，回到读取状态继续处理同一连接上的后续请求
+        connection.state = State::Reading;
+        connection.write_buf = Vec::new();
+        connection.written = 0;
+        connection.last_active = Instant::now();
+        if self.epoll.modify(connection.stream.as_raw_fd(), token, READ_INTEREST).is_err() {
+            return self.close(token);
+        }
+        if !connection.read_buf.is_empty() || connection.read_closed {
+            self.dispatch(token);
+        }
+    }
+
+    // 单个连接最多缓冲的未处理字节数：一个完整请求再加上流水线中后续请求的头部
+    fn max_buffered(&self) -> usize {
+        let limits = self.options.limits;
+        limits.max_head_bytes * 2 + limits.max_body_bytes
+    }
+
+    // 关闭空闲超时的连接以及写出停滞的连接
+    fn sweep(&mut self) {
+        let options = self.options;
+        let expired: Vec<u64> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| {
+                let elapsed = connection.last_active.elapsed();
+                match connection.state {
+                    State::Reading => elapsed > options.keep_alive_timeout,
+                    State::Writing => elapsed > options.write_timeout,
+                    State::Processing => false,
+                }
+            })
+            .map(|(token, _)| *token)
+            .collect();
+        for token in expired {
+            self.close(token);
+        }
+    }
+
+    // 停机时关闭没有未处理数据的空闲连接，处理中的连接在写完响应回到读取状态后关闭
+    fn close_idle(&mut self) {
+        let idle: Vec<u64> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| connection.state == State::Reading && connection.read_buf.is_empty())
+            .map(|(token, _)| *token)
+            .collect();
+        for token in idle {
+            self.close(token);
+        }
+    }
+
+    fn close(&mut self, token: u64) {
+        if let Some(connection) = self.connections.remove(&token) {
+            let _ = self.epoll.delete(connection.stream.as_raw_fd());
+        }
+    }
+}
+
+const READ_INTEREST: u32 = (libc::EPOLLIN | libc::EPOLLRDHUP) as u32;
+
+// 将libc的返回值转换为io::Result
+fn cvt(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/*
+    epoll系统调用的简单封装
+ */
+struct Epoll {
+    fd: RawFd,
+}
+
+impl Epoll {
+    fn new() -> io::Result<Epoll> {
+        let fd = cvt(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+        Ok(Epoll { fd })
+    }
+
+    fn add(&self, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        self.ctl(libc::EPOLL_CTL_ADD, fd, token, events)
+    }
+
+    fn modify(&self, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        self.ctl(libc::EPOLL_CTL_MOD, fd, token, events)
+    }
+
+    fn delete(&self, fd: RawFd) -> io::Result<()> {
+        self.ctl(libc::EPOLL_CTL_DEL, fd, 0, 0)
+    }
+
+    fn ctl(&self, op: libc::c_int, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        let mut event = libc::epoll_event { events, u64: token };
+        cvt(unsafe { libc::epoll_ctl(self.fd, op, fd, &mut event) })?;
+        Ok(())
+    }
+
+    fn wait(&self, events: &mut [libc::epoll_event], timeout_ms: libc::c_int) -> io::Result<usize> {
+        let result = unsafe { libc::epoll_wait(self.fd, events.as_mut_ptr(), events.len() as libc::c_int, timeout_ms) };
+        match cvt(result) {
+            Ok(n) => Ok(n as usize),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl Drop for Epoll {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/*
+    基于eventfd的唤醒器，Worker线程处理完请求后通过它唤醒阻塞在epoll_wait上的Reactor
+ */
+struct Waker {
+    fd: RawFd,
+}
+
+impl Waker {
+    fn new() -> io::Result<Waker> {
+        let fd = cvt(unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) })?;
+        Ok(Waker { fd })
+    }
+
+    fn wake(&self) {
+        let value = 1u64.to_ne_bytes();
+        unsafe { libc::write(self.fd, value.as_ptr() as *const libc::c_void, value.len()) };
+    }
+
+    fn drain(&self) {
+        let mut value = [0u8; 8];
+        unsafe { libc::read(self.fd, value.as_mut_ptr() as *mut libc::c_void, value.len()) };
+    }
+}
+
+impl Drop for Waker {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::{Parsed, Parser};
+    use crate::http::Limits;
+
+    #[test]
+    fn wait_for_split_chunked_trailer() {
+        // trailer分两次到达：第一次只有半行，不能当作请求已经结束
+        let mut parser = Parser::default();
+        let mut buffer = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\nX-Check".to_vec();
+        assert!(matches!(parser.parse(&mut buffer, Limits::default()), Parsed::Incomplete));
+        buffer.extend_from_slice(b"sum: 1\r\n");
+        assert!(matches!(parser.parse(&mut buffer, Limits::default()), Parsed::Incomplete));
+
+        buffer.extend_from_slice(b"\r\nGET /next HTTP/1.1\r\n\r\n");
+        match parser.parse(&mut buffer, Limits::default()) {
+            Parsed::Request(request) => assert_eq!(b"ok".to_vec(), request.body),
+            _ => panic!("request should be complete"),
+        }
+        // 只消费了第一个请求，流水线中的下一个请求留在缓冲中
+        assert_eq!(b"GET /next HTTP/1.1\r\n\r\n".to_vec(), buffer);
+    }
+    #[test]
+    fn parse_incrementally() {
+        // 每次只到达一个字节：块大小行、块数据之后的CRLF以及trailer都可能被截断在任意位置
+        let raw: &[u8] =
+            b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nX-Sum: 1\r\n\r\n";
+        let (mut parser, mut buffer) = (Parser::default(), Vec::new());
+        for (i, byte) in raw.iter().enumerate() {
+            buffer.push(*byte);
+            match parser.parse(&mut buffer, Limits::default()) {
+                Parsed::Incomplete => assert!(i < raw.len() - 1, "request should be complete"),
+                Parsed::Request(request) => {
+                    assert_eq!(i, raw.len() - 1);
+                    assert_eq!(b"hello world".to_vec(), request.body);
+                }
+                Parsed::Invalid(err) => panic!("{} at byte {}", err, i),
+            }
+        }
+        assert!(buffer.is_empty());
+
+        // 大的请求体分多次到达，已经解码的部分不会被重新扫描
+        let body = vec![b'x'; 200 * 1024];
+        let mut raw = format!("PUT /file HTTP/1.1\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes();
+        raw.extend_from_slice(&body);
+        let limits = Limits { max_body_bytes: body.len(), ..Limits::default() };
+        for piece in raw.chunks(1000) {
+            buffer.extend_from_slice(piece);
+            if let Parsed::Request(request) = parser.parse(&mut buffer, limits) {
+                assert_eq!(body, request.body);
+                assert!(buffer.is_empty());
+                return;
+            }
+            assert_eq!(parser.consumed, buffer.len());
+        }
+        panic!("request should be complete");
+    }
+}
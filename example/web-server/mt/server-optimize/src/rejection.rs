@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, PoisonError};
+
+/*
+    队列已满时的拒绝策略
+
+    - Block       阻塞提交者直到队列有空位；在线程池的Worker中提交时改为CallerRuns，避免Worker互相等待
+    - CallerRuns  在提交者的线程中直接执行任务，提交者因此被减速
+    - DropNewest  丢弃新提交的任务
+    - DropOldest  丢弃队列中等待最久的任务，为新任务腾出空位
+    - Abort       不执行任务，由 `try_execute` 以 `Err(任务)` 交还给提交者
+
+    被丢弃的任务不会执行而是直接释放：`spawn` 提交的任务在 `TaskHandle::join` 中得到 `JoinError::Cancelled`
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RejectionPolicy {
+    #[default]
+    Block,
+    CallerRuns,
+    DropNewest,
+    DropOldest,
+    Abort,
+}
+
+/*
+    `try_execute` 成功时任务的去向
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Submitted {
+    // 任务进入队列
+    Queued,
+    // 队列已满，任务已经在提交者的线程中执行完毕
+    RanOnCaller,
+    // 队列已满，丢弃了等待最久的任务之后进入队列
+    DisplacedOldest,
+    // 队列已满，任务被丢弃
+    Discarded,
+}
+
+/*
+    队列容量
+
+    排队任务数即线程池状态中的queued计数：提交时先占用一个位置，Worker取走任务时归还
+ */
+pub(crate) struct Capacity {
+    limit: Option<usize>,
+    // 等待空位的提交者数，没有等待者时归还位置不需要获取锁
+    waiting: AtomicUsize,
+    closed: AtomicBool,
+    lock: Mutex<()>,
+    space: Condvar,
+}
+
+impl Capacity {
+    pub(crate) fn new(limit: Option<usize>) -> Capacity {
+        Capacity { limit, waiting: AtomicUsize::new(0), closed: AtomicBool::new(false), lock: Mutex::new(()), space: Condvar::new() }
+    }
+
+    /// 尝试占用一个位置，队列已满时返回false。
+    pub(crate) fn reserve(&self, queued: &AtomicUsize) -> bool {
+        match self.limit {
+            None => {
+                queued.fetch_add(1, Ordering::SeqCst);
+                true
+            }
+            Some(limit) => queued.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < limit).then_some(n + 1)).is_ok(),
+        }
+    }
+
+    /// 阻塞直到占用一个位置，线程池关闭时放弃等待并返回false。
+    pub(crate) fn wait(&self, queued: &AtomicUsize) -> bool {
+        while !self.reserve(queued) {
+            let guard = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+            self.waiting.fetch_add(1, Ordering::SeqCst);
+            // 在锁内再次检查：归还位置时先减少queued再检查waiting，两者至少有一方能看到对方
+            if self.closed.load(Ordering::SeqCst) {
+                self.waiting.fetch_sub(1, Ordering::SeqCst);
+                return false;
+            }
+            if self.reserve(queued) {
+                self.waiting.fetch_sub(1, Ordering::SeqCst);
+                return true;
+            }
+            drop(self.space.wait(guard).unwrap_or_else(PoisonError::into_inner));
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+        }
+        true
+    }
+
+    /// 线程池关闭，唤醒所有等待空位的提交者。
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let _guard = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        self.space.notify_all();
+    }
+
+    /// queued计数减少之后调用，唤醒一个等待空位的提交者。
+    pub(crate) fn release(&self) {
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            let _guard = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+            self.space.notify_one();
+        }
+    }
+}
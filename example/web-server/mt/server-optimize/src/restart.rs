@@ -0,0 +1,152 @@
+use std::env;
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+
+// 新进程继承的监听套接字，逗号分隔的文件描述符
+pub const LISTEN_FDS_ENV: &str = "SERVER_OPTIMIZE_LISTEN_FDS";
+// 新进程完成启动后写入一个字节通知旧进程的文件描述符
+pub const READY_FD_ENV: &str = "SERVER_OPTIMIZE_READY_FD";
+// 等待新进程就绪的最长时间，超时后杀掉新进程，旧进程继续服务
+pub const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/*
+    从旧进程继承的监听套接字
+
+    热升级时旧进程通过环境变量告知新进程哪些文件描述符是监听套接字，新进程按照地址认领这些套接字，
+    不需要重新bind，同一个端口在升级过程中始终有进程在accept，不会出现连接被拒绝的窗口
+ */
+#[derive(Debug, Default)]
+pub struct Inherited {
+    listeners: Vec<TcpListener>,
+    ready: Option<UnixStream>,
+}
+
+impl Inherited {
+    /// 读取并清除环境变量中的文件描述符，不是由旧进程启动时返回空集合。
+    ///
+    /// 必须在进程启动时调用一次，文件描述符的所有权随之转移给返回值。
+    pub fn from_env() -> io::Result<Inherited> {
+        let listen_fds = env::var(LISTEN_FDS_ENV).ok();
+        let ready_fd = env::var(READY_FD_ENV).ok();
+        env::remove_var(LISTEN_FDS_ENV);
+        env::remove_var(READY_FD_ENV);
+
+        let mut inherited = Inherited::default();
+        for fd in listen_fds.iter().flat_map(|fds| fds.split(',')).filter(|fd| !fd.is_empty()) {
+            let fd = parse_fd(LISTEN_FDS_ENV, fd)?;
+            inherited.listeners.push(unsafe { TcpListener::from_raw_fd(fd) });
+        }
+        if let Some(fd) = ready_fd {
+            let fd = parse_fd(READY_FD_ENV, &fd)?;
+            inherited.ready = Some(unsafe { UnixStream::from_raw_fd(fd) });
+        }
+        Ok(inherited)
+    }
+
+    pub fn len(&self) -> usize {
+        self.listeners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+
+    /// 取出监听在 `addr` 上的套接字；`addr` 的端口为0时只比较IP地址。
+    pub fn take_listener(&mut self, addr: SocketAddr) -> Option<TcpListener> {
+        let index = self.listeners.iter().position(|listener| match listener.local_addr() {
+            Ok(local) if addr.port() == 0 => local.ip() == addr.ip(),
+            Ok(local) => local == addr,
+            Err(_) => false,
+        })?;
+        Some(self.listeners.remove(index))
+    }
+
+    /// 通知旧进程已经可以开始服务，并关闭配置中不再使用的监听套接字。
+    pub fn ready(&mut self) -> io::Result<()> {
+        self.listeners.clear();
+        match self.ready.take() {
+            Some(mut stream) => stream.write_all(&[1]),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_fd(name: &str, value: &str) -> io::Result<RawFd> {
+    let fd: RawFd = value
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid file descriptor in {}: {:?}", name, value)))?;
+    // 确认文件描述符有效，并且在之后再次执行exec时不会泄漏给其他程序
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+/*
+    热升级
+
+    启动时记录可执行文件路径与命令行参数，收到升级信号后以相同的参数启动（可能已经被替换的）新二进制，
+    把监听套接字交给新进程，等到新进程通知就绪后旧进程才停止accept并排空线程池
+ */
+#[derive(Debug, Clone)]
+pub struct Upgrader {
+    program: PathBuf,
+    args: Vec<OsString>,
+}
+
+impl Upgrader {
+    /// 必须在进程启动时调用：二进制被替换之后当前进程的可执行文件路径会失效。
+    pub fn new() -> io::Result<Upgrader> {
+        Ok(Upgrader { program: env::current_exe()?, args: env::args_os().skip(1).collect() })
+    }
+
+    /// 启动新进程并等待它就绪，返回新进程的pid；新进程启动失败或者超时未就绪时返回错误。
+    pub fn spawn(&self, listeners: &[RawFd]) -> io::Result<u32> {
+        let (mut parent, child) = UnixStream::pair()?;
+        let ready_fd = child.as_raw_fd();
+        let listen_fds = listeners.iter().map(|fd| fd.to_string()).collect::<Vec<_>>().join(",");
+
+        let mut command = Command::new(&self.program);
+        command.args(&self.args).env(LISTEN_FDS_ENV, listen_fds).env(READY_FD_ENV, ready_fd.to_string());
+        let inherit: Vec<RawFd> = listeners.iter().copied().chain([ready_fd]).collect();
+        unsafe {
+            command.pre_exec(move || {
+                // fork之后、exec之前：让这些文件描述符跨过exec，并恢复默认的信号屏蔽字
+                for &fd in &inherit {
+                    if libc::fcntl(fd, libc::F_SETFD, 0) < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                let mut empty = std::mem::MaybeUninit::<libc::sigset_t>::uninit();
+                libc::sigemptyset(empty.as_mut_ptr());
+                libc::sigprocmask(libc::SIG_SETMASK, empty.as_ptr(), std::ptr::null_mut());
+                Ok(())
+            });
+        }
+        let mut process = command.spawn()?;
+        // 关闭本进程中新进程那一端，新进程退出时读取会立即得到EOF
+        drop(child);
+
+        parent.set_read_timeout(Some(READY_TIMEOUT))?;
+        let mut byte = [0; 1];
+        let result = match parent.read(&mut byte) {
+            Ok(1) => return Ok(process.id()),
+            Ok(_) => io::Error::new(io::ErrorKind::UnexpectedEof, "new process exited before it was ready"),
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                io::Error::new(io::ErrorKind::TimedOut, "new process did not become ready in time")
+            }
+            Err(err) => err,
+        };
+        let _ = process.kill();
+        let _ = process.wait();
+        Err(result)
+    }
+}
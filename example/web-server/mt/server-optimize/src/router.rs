@@ -0,0 +1,153 @@
+use crate::http::{Request, Response, StatusCode};
+use crate::middleware::Handler;
+
+/*
+    路由表
+
+    按照方法与路径把请求分发给不同的处理器，按照添加顺序匹配，先添加的路由优先；
+    路径匹配但方法不匹配时返回405并带上Allow头部，都不匹配时交给fallback处理器，没有fallback则返回404。
+    匹配到路由时（包括405）把路由的路径写入 `Response::route`，嵌套的Router以最内层的路由为准
+ */
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Option<Box<dyn Handler>>,
+}
+
+struct Route {
+    method: String,
+    // 添加路由时的路径，例如 `/api/*`
+    path: String,
+    pattern: Pattern,
+    handler: Box<dyn Handler>,
+}
+
+enum Pattern {
+    Exact(String),
+    Prefix(String),
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router { routes: Vec::new(), fallback: None }
+    }
+
+    /// 添加一条路由，方法不区分大小写，`*` 匹配任意方法。
+    ///
+    /// 路径支持两种写法：`/about` 精确匹配；`/static/*` 前缀匹配，同时匹配 `/static` 与 `/static/` 下的任意路径。
+    pub fn route<H: Handler + 'static>(mut self, method: &str, path: &str, handler: H) -> Router {
+        let pattern = match path.strip_suffix("/*") {
+            Some(prefix) => Pattern::Prefix(prefix.to_string()),
+            None => Pattern::Exact(path.to_string()),
+        };
+        let method = method.to_ascii_uppercase();
+        self.routes.push(Route { method, path: path.to_string(), pattern, handler: Box::new(handler) });
+        self
+    }
+
+    pub fn get<H: Handler + 'static>(self, path: &str, handler: H) -> Router {
+        self.route("GET", path, handler)
+    }
+
+    pub fn post<H: Handler + 'static>(self, path: &str, handler: H) -> Router {
+        self.route("POST", path, handler)
+    }
+
+    /// 设置没有任何路由匹配时使用的处理器。
+    pub fn fallback<H: Handler + 'static>(mut self, handler: H) -> Router {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    pub fn has_fallback(&self) -> bool {
+        self.fallback.is_some()
+    }
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+impl Pattern {
+    fn matches(&self, path: &str) -> bool {
+        match self {
+            Pattern::Exact(exact) => path == exact,
+            Pattern::Prefix(prefix) => match path.strip_prefix(prefix.as_str()) {
+                Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'),
+                None => false,
+            },
+        }
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, request: Request) -> Response {
+        let mut allowed: Vec<&str> = Vec::new();
+        let mut matched = None;
+        for route in self.routes.iter().filter(|route| route.pattern.matches(&request.path)) {
+            if route.method == "*" || route.method == request.method {
+                let mut response = route.handler.handle(request);
+                response.route.get_or_insert_with(|| route.path.clone());
+                return response;
+            }
+            if !allowed.contains(&route.method.as_str()) {
+                allowed.push(&route.method);
+            }
+            matched.get_or_insert(&route.path);
+        }
+        if !allowed.is_empty() {
+            let mut response = Response::from(StatusCode::METHOD_NOT_ALLOWED).with_header("Allow", allowed.join(", "));
+            response.route = matched.cloned();
+            return response;
+        }
+        match &self.fallback {
+            Some(fallback) => fallback.handle(request),
+            None => Response::from(StatusCode::NOT_FOUND),
+        }
+    }
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(body: &'static str) -> impl Handler {
+        move |_: Request| Response::text(StatusCode::OK, body)
+    }
+
+    fn body(response: Response) -> String {
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        text.split_once("\r\n\r\n").unwrap().1.to_string()
+    }
+
+    #[test]
+    fn dispatch_by_method_and_path() {
+        let router = Router::new()
+            .get("/", text("index"))
+            .post("/", text("created"))
+            .get("/static/*", text("static"))
+            .fallback(text("fallback"));
+
+        assert_eq!(body(router.handle(Request::new("GET", "/"))), "index");
+        assert_eq!(body(router.handle(Request::new("POST", "/"))), "created");
+        assert_eq!(body(router.handle(Request::new("GET", "/static"))), "static");
+        assert_eq!(body(router.handle(Request::new("GET", "/static/css/site.css"))), "static");
+        assert_eq!(body(router.handle(Request::new("GET", "/statics"))), "fallback");
+    }
+
+    #[test]
+    fn method_not_allowed_and_not_found() {
+        let router = Router::new().get("/ws", text("ws")).route("head", "/ws", text("ws"));
+
+        let response = router.handle(Request::new("DELETE", "/ws"));
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers.get("Allow"), Some("GET, HEAD"));
+        assert_eq!(router.handle(Request::new("GET", "/missing")).status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,370 @@
+use std::cell::Cell;
+use std::collections::{BTreeMap, VecDeque};
+use std::hint;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{Job, Message, Priority};
+
+// 从全局队列一次最多搬运到本地队列的任务数
+const MAX_BATCH: usize = 32;
+// 找不到任务时休眠之前的自旋轮数：前几轮忙等，之后让出CPU；休眠与唤醒都需要系统调用
+const SPIN_ROUNDS: u32 = 10;
+const YIELD_ROUNDS: u32 = 6;
+
+thread_local! {
+    // 当前线程所属的调度器（地址）以及Worker编号，Worker中提交的任务放入自己的本地队列
+    static CURRENT: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+/*
+    工作窃取调度器
+
+    - 全局队列（injector）：线程池外部提交的任务以及终止消息，先进先出
+    - 本地队列：每个Worker一个，Worker内部提交的任务（例如作用域任务）直接放入自己的本地队列；
+      Worker从全局队列取任务时顺带搬运一批到本地队列，之后不再争用全局队列的锁
+    - 窃取：本地队列与全局队列都为空时，从其他Worker的本地队列尾部窃取一半
+
+    Worker按照 本地队列 -> 全局队列 -> 窃取 的顺序取任务，都取不到时在条件变量上休眠；
+    终止消息只存在于全局队列中，排在它之前提交的任务都会先被执行
+
+    优先级队列：高、低优先级的任务按照 (入队时间 + (High - 优先级) * 老化间隔) 排序，
+    相当于任务每等待一个老化间隔优先级提升一级。排在最前的任务的有效优先级不低于Normal时
+    （高优先级任务，或者已经等待了一个老化间隔的低优先级任务），Worker优先执行它，
+    否则在其他队列都为空时再执行，低优先级任务因此不会一直被普通任务压住
+ */
+pub(crate) struct Scheduler {
+    injector: Mutex<VecDeque<Message>>,
+    locals: Box<[Mutex<VecDeque<Message>>]>,
+    // 键为（排序时间，序号），排序时间为相对于epoch的微秒数
+    prioritized: Mutex<BTreeMap<(u64, u64), Job>>,
+    // 优先级队列中的任务数，为0时取任务不需要获取它的锁
+    prioritized_len: AtomicUsize,
+    next_seq: AtomicUsize,
+    epoch: Instant,
+    aging_interval: u64,
+    // 所有队列中的消息总数，休眠之前检查，避免错过唤醒
+    pending: AtomicUsize,
+    // 正在休眠（或者准备休眠）的Worker数，没有休眠的Worker时提交任务不需要获取全局队列的锁
+    sleeping: AtomicUsize,
+    available: Condvar,
+}
+
+impl Scheduler {
+    pub(crate) fn new(workers: usize, aging_interval: Duration) -> Scheduler {
+        Scheduler {
+            injector: Mutex::new(VecDeque::new()),
+            locals: (0..workers).map(|_| Mutex::new(VecDeque::new())).collect(),
+            prioritized: Mutex::new(BTreeMap::new()),
+            prioritized_len: AtomicUsize::new(0),
+            next_seq: AtomicUsize::new(0),
+            epoch: Instant::now(),
+            aging_interval: aging_interval.as_micros() as u64,
+            pending: AtomicUsize::new(0),
+            sleeping: AtomicUsize::new(0),
+            available: Condvar::new(),
+        }
+    }
+
+    /// 把当前线程登记为第 `index` 个Worker，之后在该线程中提交的任务放入它的本地队列。
+    pub(crate) fn register(&self, index: usize) {
+        CURRENT.with(|current| current.set(Some((self.address(), index))));
+    }
+
+    pub(crate) fn push(&self, message: Message) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        let local = match (&message, CURRENT.with(Cell::get)) {
+            (Message::NEW_JOB(_), Some((address, index))) if address == self.address() => Some(index),
+            _ => None,
+        };
+        match local {
+            // 自己马上会处理本地队列，唤醒的Worker只能来窃取
+            Some(index) => lock(&self.locals[index]).push_back(message),
+            None => lock(&self.injector).push_back(message),
+        }
+        self.wake_one();
+    }
+
+    /// 按照优先级提交任务，普通优先级的任务与 `push` 相同。
+    pub(crate) fn push_prioritized(&self, job: Job, priority: Priority) {
+        let levels = match priority {
+            Priority::Normal => return self.push(Message::NEW_JOB(job)),
+            Priority::High => 0,
+            Priority::Low => 2,
+        };
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        let key = self.now() + levels * self.aging_interval;
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed) as u64;
+        lock(&self.prioritized).insert((key, seq), job);
+        self.prioritized_len.fetch_add(1, Ordering::SeqCst);
+        self.wake_one();
+    }
+
+    /// 当前线程是否为该调度器的Worker。
+    pub(crate) fn is_worker(&self) -> bool {
+        self.current().is_some()
+    }
+
+    /// 当前线程是该调度器的Worker时返回它的编号。
+    pub(crate) fn current(&self) -> Option<usize> {
+        match CURRENT.with(Cell::get) {
+            Some((address, index)) if address == self.address() => Some(index),
+            _ => None,
+        }
+    }
+
+    /// 不阻塞地取一个任务，只从第 `index` 个Worker的本地队列以及其他Worker的本地队列中取，
+    /// 供等待fork-join任务的Worker帮忙执行；本地队列中没有终止消息。
+    pub(crate) fn try_pop_local(&self, index: usize) -> Option<Job> {
+        let message = self.pop_local(index).or_else(|| self.steal(index))?;
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+        match message {
+            Message::NEW_JOB(job) => Some(job),
+            Message::TERMINATE => unreachable!("terminate message in a local queue"),
+        }
+    }
+
+    /// 取出等待最久的任务（全局队列优先，其次各个本地队列的队首，最后是优先级最低的任务），不交给任何Worker执行。
+    pub(crate) fn evict_oldest(&self) -> Option<Job> {
+        let evicted = take_job(&mut lock(&self.injector))
+            .or_else(|| self.locals.iter().find_map(|local| take_job(&mut lock(local))))
+            .or_else(|| self.pop_prioritized(None, true));
+        if evicted.is_some() {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+        }
+        evicted
+    }
+
+    /// 取出所有队列中的任务（保留终止消息），按照全局队列、各个本地队列、优先级队列的顺序排列。
+    pub(crate) fn drain(&self) -> Vec<Job> {
+        let mut jobs = Vec::new();
+        for queue in std::iter::once(&self.injector).chain(self.locals.iter()) {
+            let mut queue = lock(queue);
+            let mut rest = VecDeque::new();
+            for message in queue.drain(..) {
+                match message {
+                    Message::NEW_JOB(job) => jobs.push(job),
+                    terminate => rest.push_back(terminate),
+                }
+            }
+            *queue = rest;
+        }
+        let prioritized = mem::take(&mut *lock(&self.prioritized));
+        self.prioritized_len.fetch_sub(prioritized.len(), Ordering::SeqCst);
+        jobs.extend(prioritized.into_values());
+        self.pending.fetch_sub(jobs.len(), Ordering::SeqCst);
+        jobs
+    }
+
+    /// 第 `index` 个Worker取下一个消息，所有队列都为空时阻塞；
+    /// 指定了 `idle_timeout` 时，空闲超过该时长仍然没有消息则返回 `None`。
+    pub(crate) fn pop(&self, index: usize, idle_timeout: Option<Duration>) -> Option<Message> {
+        let deadline = idle_timeout.map(|timeout| Instant::now() + timeout);
+        let mut round = 0;
+        loop {
+            let urgent = Some(self.now() + self.aging_interval);
+            if let Some(message) = self
+                .pop_prioritized(urgent, false)
+                .map(Message::NEW_JOB)
+                .or_else(|| self.pop_local(index))
+                .or_else(|| self.pop_injector(index))
+                .or_else(|| self.steal(index))
+                .or_else(|| self.pop_prioritized(None, false).map(Message::NEW_JOB))
+            {
+                self.pending.fetch_sub(1, Ordering::SeqCst);
+                return Some(message);
+            }
+            round += 1;
+            if round <= SPIN_ROUNDS {
+                for _ in 0..1 << round.min(6) {
+                    hint::spin_loop();
+                }
+                continue;
+            }
+            if round <= SPIN_ROUNDS + YIELD_ROUNDS {
+                thread::yield_now();
+                continue;
+            }
+            round = 0;
+
+            let injector = lock(&self.injector);
+            self.sleeping.fetch_add(1, Ordering::SeqCst);
+            let mut timed_out = false;
+            // 在全局队列的锁内再次检查：提交任务时先增加pending再检查sleeping，两者至少有一方能看到对方
+            if injector.is_empty() && self.pending.load(Ordering::SeqCst) == 0 {
+                match deadline.map(|deadline| deadline.saturating_duration_since(Instant::now())) {
+                    None => drop(self.available.wait(injector).unwrap_or_else(PoisonError::into_inner)),
+                    // 只有确认所有队列都为空时才算超时
+                    Some(Duration::ZERO) => {
+                        timed_out = true;
+                        drop(injector);
+                    }
+                    Some(remaining) => {
+                        drop(self.available.wait_timeout(injector, remaining).unwrap_or_else(PoisonError::into_inner))
+                    }
+                }
+            } else {
+                drop(injector);
+            }
+            self.sleeping.fetch_sub(1, Ordering::SeqCst);
+            if timed_out {
+                return None;
+            }
+        }
+    }
+
+    // 取出优先级队列中排在最前（`last` 为true时排在最后）的任务，`before` 限制排序时间的上限
+    fn pop_prioritized(&self, before: Option<u64>, last: bool) -> Option<Job> {
+        if self.prioritized_len.load(Ordering::SeqCst) == 0 {
+            return None;
+        }
+        let mut prioritized = lock(&self.prioritized);
+        let entry = if last { prioritized.last_entry() } else { prioritized.first_entry() }?;
+        if before.is_some_and(|before| entry.key().0 > before) {
+            return None;
+        }
+        let job = entry.remove();
+        self.prioritized_len.fetch_sub(1, Ordering::SeqCst);
+        Some(job)
+    }
+
+    fn pop_local(&self, index: usize) -> Option<Message> {
+        lock(&self.locals[index]).pop_front()
+    }
+
+    // 取出全局队列的第一个消息，并把之后的一批任务搬运到本地队列
+    fn pop_injector(&self, index: usize) -> Option<Message> {
+        let mut injector = lock(&self.injector);
+        // 终止之前先执行完优先级队列中的任务
+        if matches!(injector.front(), Some(Message::TERMINATE)) && self.prioritized_len.load(Ordering::SeqCst) > 0 {
+            return None;
+        }
+        let message = injector.pop_front()?;
+        if matches!(message, Message::TERMINATE) {
+            return Some(message);
+        }
+        // 按Worker数平分，给其他Worker留下任务
+        let batch = (injector.len() / self.locals.len()).min(MAX_BATCH);
+        let count = injector.iter().take(batch).take_while(|message| matches!(message, Message::NEW_JOB(_))).count();
+        let moved: Vec<Message> = injector.drain(..count).collect();
+        drop(injector);
+        if !moved.is_empty() {
+            lock(&self.locals[index]).extend(moved);
+            self.wake_one();
+        }
+        Some(message)
+    }
+
+    // 从其他Worker的本地队列尾部窃取一半，返回其中一个，其余放入自己的本地队列
+    fn steal(&self, index: usize) -> Option<Message> {
+        let workers = self.locals.len();
+        for offset in 1..workers {
+            let mut victim = lock(&self.locals[(index + offset) % workers]);
+            let len = victim.len();
+            if len == 0 {
+                continue;
+            }
+            let mut stolen = victim.split_off(len - len.div_ceil(2));
+            drop(victim);
+            let message = stolen.pop_front();
+            if !stolen.is_empty() {
+                lock(&self.locals[index]).extend(stolen);
+                self.wake_one();
+            }
+            return message;
+        }
+        None
+    }
+
+    // 只在有Worker休眠时才获取全局队列的锁并唤醒，唤醒是一次系统调用
+    fn wake_one(&self) {
+        if self.sleeping.load(Ordering::SeqCst) > 0 {
+            let _injector = lock(&self.injector);
+            self.available.notify_one();
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.epoch.elapsed().as_micros() as u64
+    }
+
+    fn address(&self) -> usize {
+        self as *const Scheduler as usize
+    }
+}
+
+// 取出队列中第一个任务，跳过终止消息
+fn take_job(queue: &mut VecDeque<Message>) -> Option<Job> {
+    let position = queue.iter().position(|message| matches!(message, Message::NEW_JOB(_)))?;
+    match queue.remove(position) {
+        Some(Message::NEW_JOB(job)) => Some(job),
+        _ => None,
+    }
+}
+
+// 队列中的消息在持有锁时整体入队出队，锁中毒时队列仍然是完整的
+fn lock<T>(queue: &Mutex<T>) -> MutexGuard<'_, T> {
+    queue.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::ThreadPool;
+
+    #[test]
+    fn steal_jobs_submitted_by_worker() {
+        let pool = Arc::new(ThreadPool::new(4));
+        let (sender, threads) = mpsc::channel();
+        {
+            let inner = Arc::clone(&pool);
+            // 全部任务都由一个Worker提交到它自己的本地队列，其他Worker只能通过窃取拿到
+            pool.execute(move || {
+                for _ in 0..64 {
+                    let sender = sender.clone();
+                    inner.execute(move || {
+                        thread::sleep(Duration::from_millis(2));
+                        sender.send(thread::current().id()).unwrap();
+                    });
+                }
+            });
+        }
+        let threads: HashSet<_> = (0..64).map(|_| threads.recv_timeout(Duration::from_secs(10)).unwrap()).collect();
+        assert!(threads.len() > 1, "only {} worker executed the jobs", threads.len());
+    }
+
+    #[test]
+    fn drain_all_queues_on_drop() {
+        let done = Arc::new(AtomicUsize::new(0));
+        let pool = Arc::new(ThreadPool::new(3));
+        for _ in 0..10 {
+            let (pool_ref, done) = (Arc::clone(&pool), Arc::clone(&done));
+            pool.execute(move || {
+                for _ in 0..10 {
+                    let done = Arc::clone(&done);
+                    pool_ref.execute(move || {
+                        done.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        // 等待外层任务都已经提交完内层任务（外层任务持有的线程池引用随之释放）
+        while Arc::strong_count(&pool) > 1 {
+            thread::sleep(Duration::from_millis(5));
+        }
+        drop(Arc::try_unwrap(pool).ok().unwrap());
+        assert_eq!(done.load(Ordering::SeqCst), 110);
+    }
+}
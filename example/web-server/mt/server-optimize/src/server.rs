@@ -0,0 +1,357 @@
+use std::io::{self, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crate::http::body::Decoder;
+use crate::http::{upgrade, BodyReader, Limits, OnUpgrade, ParseError, Request, Response, Upgraded};
+use crate::limiter::{self, ConnectionGuard, Limiter, Rejection};
+use crate::middleware::Handler;
+use crate::{PoolMonitor, Submitted, ThreadPool};
+
+// 保持连接（keep-alive）时等待下一个请求的最长时间
+pub const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+// 写出响应时单次写操作的最长等待时间
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+// accept循环检查停机信号的间隔
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/*
+    连接处理选项
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    // 空闲连接（以及等待请求数据时）的超时时间
+    pub keep_alive_timeout: Duration,
+    // 写出响应的超时时间，防止客户端不读取响应时长期占用连接
+    pub write_timeout: Duration,
+    // 请求大小限制
+    pub limits: Limits,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { keep_alive_timeout: KEEP_ALIVE_TIMEOUT, write_timeout: WRITE_TIMEOUT, limits: Limits::default() }
+    }
+}
+
+/*
+    停机信号
+
+    可以被克隆并在多个线程之间共享，触发之后各个accept循环不再接受新连接并返回，已经接受的连接由线程池继续处理完毕：
+    - 正在处理请求的连接写完当前响应后关闭，响应带有 `Connection: close`
+    - 空闲的keep-alive连接在事件驱动模式下立即关闭，阻塞模式下最多再等待keep_alive_timeout，
+      期间到达的请求仍然会得到处理（同样以 `Connection: close` 结束）
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    pub fn trigger(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// 阻塞模式：每接受一个连接就提交给线程池，由一个Worker负责该连接的整个生命周期。
+///
+/// 连接先经过 `limiter` 的准入检查，被拒绝的连接直接在accept线程中得到503/429响应，不会进入线程池队列。
+pub fn serve(listener: TcpListener, pool: &ThreadPool, handler: Arc<dyn Handler>, options: Options, limiter: &Limiter) {
+    serve_until(listener, pool, handler, options, limiter, &Shutdown::new())
+}
+
+/// 与 `serve` 相同，`shutdown` 触发后停止接受新连接并返回。
+pub fn serve_until(
+    listener: TcpListener,
+    pool: &ThreadPool,
+    handler: Arc<dyn Handler>,
+    options: Options,
+    limiter: &Limiter,
+    shutdown: &Shutdown,
+) {
+    let monitor = pool.monitor();
+    while let Some(result) = accept(&listener, shutdown) { // result: Result<TcpStream, Error>
+        let stream = match result {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("[Server] accept failed: {}", err);
+                continue;
+            }
+        };
+        let guard = match admit(&stream, &monitor, limiter) {
+            Ok(guard) => guard,
+            Err(rejection) => {
+                limiter::refuse(stream, rejection);
+                continue;
+            }
+        };
+        let (handler, shutdown) = (Arc::clone(&handler), shutdown.clone());
+        submit(pool, move || {
+            // 连接处理结束（包括协议升级之前）时归还连接数
+            let _guard = guard;
+            handle_connection(stream, handler.as_ref(), options, &shutdown)
+        });
+    }
+}
+
+/// 把连接交给线程池，线程池队列已满时按照它的拒绝策略处理并输出日志。
+///
+/// 被丢弃的任务连同其中的连接一起释放，连接随之关闭；连接数在释放时归还。
+pub fn submit<F>(pool: &ThreadPool, job: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    match pool.try_execute(job) {
+        Ok(Submitted::Queued) | Ok(Submitted::RanOnCaller) => {}
+        Ok(Submitted::DisplacedOldest) => println!("[Server] thread pool queue is full, dropped the oldest queued connection"),
+        Ok(Submitted::Discarded) | Err(_) => println!("[Server] thread pool queue is full, connection closed"),
+    }
+}
+
+/// 等待并接受一个新连接，`shutdown` 触发后返回None。
+///
+/// 监听套接字可能与热升级后的新进程共享（非阻塞标志也随之共享），所以先poll再accept，
+/// 连接被另一个进程抢先接受时（WouldBlock）继续等待。
+pub fn accept(listener: &TcpListener, shutdown: &Shutdown) -> Option<io::Result<TcpStream>> {
+    let mut poll = libc::pollfd { fd: listener.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+    while !shutdown.is_triggered() {
+        let ready = unsafe { libc::poll(&mut poll, 1, SHUTDOWN_POLL_INTERVAL.as_millis() as libc::c_int) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Some(Err(err));
+        }
+        // 等待期间可能已经触发停机，此时不再接受新连接，留给新进程处理
+        if ready == 0 || shutdown.is_triggered() {
+            continue;
+        }
+        match listener.accept() {
+            // 在部分平台上非阻塞监听套接字接受的连接也是非阻塞的
+            Ok((stream, _)) => return Some(stream.set_nonblocking(false).map(|_| stream)),
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => continue,
+            Err(err) => return Some(Err(err)),
+        }
+    }
+    None
+}
+
+/// 准入检查：线程池队列长度以及连接数上限，通过时返回连接守卫。
+pub fn admit(stream: &TcpStream, pool: &PoolMonitor, limiter: &Limiter) -> Result<ConnectionGuard, Rejection> {
+    limiter.check_queue(pool.stats().queued)?;
+    // 取不到对端地址的连接已经断开，很快就会在处理时失败
+    let ip = stream.peer_addr().map(|addr| addr.ip()).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    limiter.connect(ip)
+}
+
+/// 处理一个TCP连接：解析请求，交给处理器生成响应，再将响应写回TCP流。
+///
+/// 客户端要求保持连接时会在同一个连接上继续处理后续请求，直到客户端关闭连接、空闲超时或者 `shutdown` 触发。
+pub fn handle_connection(stream: TcpStream, handler: &dyn Handler, options: Options, shutdown: &Shutdown) {
+    if let Err(err) = set_timeouts(&stream, &options) {
+        println!("[Connection] set timeouts failed: {}", err);
+        return;
+    }
+    let remote_addr = stream.peer_addr().ok();
+
+    // 协议升级：将连接（以及已经读入缓冲但尚未处理的数据）交给升级回调
+    let (stream, upgrade) = serve_stream(stream, remote_addr, handler, options, true, shutdown);
+    if let Some((on_upgrade, buffered)) = upgrade {
+        upgrade::spawn(on_upgrade, Upgraded::new(stream, buffered));
+    }
+}
+
+/// 在任意可读写的字节流上（例如明文TCP流或者TLS流）处理HTTP请求，结束后交还字节流。
+///
+/// 处理器通过 `Request::take_body` 直接从连接中读取请求体；处理器没有读完的部分在响应写出之后被丢弃，
+/// 剩余部分超过 `Limits::max_body_bytes` 时关闭连接。
+///
+/// `allow_upgrade` 为true时，处理器返回协议升级响应后会写出101响应并返回升级回调以及已缓冲的数据；
+/// 为false时协议升级请求会得到501响应。`shutdown` 触发之后写出的响应带有 `Connection: close`，写完即结束。
+pub fn serve_stream<S>(
+    stream: S,
+    remote_addr: Option<SocketAddr>,
+    handler: &dyn Handler,
+    options: Options,
+    allow_upgrade: bool,
+    shutdown: &Shutdown,
+) -> (S, Option<(OnUpgrade, Vec<u8>)>)
+where
+    S: Read + Write + Send + 'static,
+{
+    let mut incoming = Arc::new(Mutex::new(Incoming { reader: BufReader::new(stream), body: Decoder::Done, generation: 0 }));
+    let upgrade = serve_requests(&incoming, remote_addr, handler, options, allow_upgrade, shutdown);
+
+    // 处理器可能仍然持有已经失效的请求体读取器，它们只在一次read调用期间短暂持有连接
+    lock(&incoming).generation += 1;
+    let incoming = loop {
+        match Arc::try_unwrap(incoming) {
+            Ok(incoming) => break incoming.into_inner().unwrap_or_else(PoisonError::into_inner),
+            Err(shared) => {
+                incoming = shared;
+                thread::yield_now();
+            }
+        }
+    };
+    let buffered = incoming.reader.buffer().to_vec();
+    (incoming.reader.into_inner(), upgrade.map(|on_upgrade| (on_upgrade, buffered)))
+}
+
+fn serve_requests<S>(
+    incoming: &Arc<Mutex<Incoming<S>>>,
+    remote_addr: Option<SocketAddr>,
+    handler: &dyn Handler,
+    options: Options,
+    allow_upgrade: bool,
+    shutdown: &Shutdown,
+) -> Option<OnUpgrade>
+where
+    S: Read + Write + Send + 'static,
+{
+    loop {
+        let head = Request::read_head(&mut lock(incoming).reader, options.limits);
+        let (mut response, keep_alive, head_only) = match head {
+            Ok(Some((mut request, decoder))) => {
+                let generation = {
+                    let mut incoming = lock(incoming);
+                    incoming.body = decoder;
+                    incoming.generation
+                };
+                let body = StreamBody { incoming: Arc::downgrade(incoming), generation };
+                request.set_body(BodyReader::new(body, decoder.length()));
+                request.remote_addr = remote_addr;
+                let keep_alive = request.keep_alive();
+                let head_only = request.method == "HEAD";
+                (handler.handle(request), keep_alive, head_only)
+            }
+            // 客户端关闭了连接
+            Ok(None) => return None,
+            // 空闲超时，关闭连接
+            Err(ParseError::Io(err)) if is_timeout(&err) => return None,
+            Err(ParseError::Io(err)) => {
+                println!("[Connection] read request failed: {}", err);
+                return None;
+            }
+            Err(err) => {
+                println!("[Connection] bad request: {}", err);
+                (Response::from(err.status()), false, false)
+            }
+        };
+
+        if let Some(on_upgrade) = response.upgrade.take() {
+            if allow_upgrade {
+                if let Err(err) = response.write_to(&mut Outgoing(incoming)) {
+                    println!("[Connection] write response failed: {}", err);
+                    return None;
+                }
+                return finish_body(incoming, options.limits.max_body_bytes).then_some(on_upgrade);
+            }
+            response = Response::from(crate::http::StatusCode::NOT_IMPLEMENTED);
+        }
+
+        // 处理器也可以通过响应头主动要求关闭连接；停机期间写完当前响应就关闭
+        let keep_alive = keep_alive && !closes_connection(&response) && !shutdown.is_triggered();
+        let response = response.with_header("Connection", if keep_alive { "keep-alive" } else { "close" });
+        // HEAD请求的响应与GET相同，只是不写出响应体
+        let written = if head_only {
+            response.write_head_to(&mut Outgoing(incoming))
+        } else {
+            response.write_to(&mut Outgoing(incoming)).map(drop)
+        };
+        if let Err(err) = written {
+            println!("[Connection] write response failed: {}", err);
+            return None;
+        }
+        if !keep_alive || !finish_body(incoming, options.limits.max_body_bytes) {
+            return None;
+        }
+    }
+}
+
+/*
+    阻塞模式下连接的读取端
+
+    处理器通过请求体读取器直接从这里读取请求体，处理结束之后连接丢弃剩余的请求体并继续读取下一个请求
+ */
+struct Incoming<S> {
+    reader: BufReader<S>,
+    // 当前请求的请求体解码状态
+    body: Decoder,
+    // 每处理完一个请求加1，使仍然被持有的旧读取器失效
+    generation: u64,
+}
+
+// 交给处理器的请求体读取器，不阻止连接在请求结束之后收回字节流
+struct StreamBody<S> {
+    incoming: Weak<Mutex<Incoming<S>>>,
+    generation: u64,
+}
+
+impl<S: Read> Read for StreamBody<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let stale = || io::Error::other("request has already been answered");
+        let incoming = self.incoming.upgrade().ok_or_else(stale)?;
+        let mut incoming = lock(&incoming);
+        if incoming.generation != self.generation {
+            return Err(stale());
+        }
+        let Incoming { reader, body, .. } = &mut *incoming;
+        body.read(reader, buf)
+    }
+}
+
+// 写出响应时每次写操作单独加锁，响应体可以是同一个连接上的请求体读取器（例如原样回显请求体）
+struct Outgoing<'a, S>(&'a Mutex<Incoming<S>>);
+
+impl<S: Write> Write for Outgoing<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        lock(self.0).reader.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        lock(self.0).reader.get_mut().flush()
+    }
+}
+
+// 丢弃处理器没有读完的请求体，剩余部分超过limit或者读取失败时返回false，连接不能继续使用
+fn finish_body<S: Read>(incoming: &Mutex<Incoming<S>>, limit: usize) -> bool {
+    let mut incoming = lock(incoming);
+    incoming.generation += 1;
+    let Incoming { reader, body, .. } = &mut *incoming;
+    let drained = io::copy(&mut body.reader(reader).take(limit as u64), &mut io::sink());
+    drained.is_ok() && body.is_done()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// 响应是否声明了 `Connection: close`。
+pub fn closes_connection(response: &Response) -> bool {
+    response
+        .headers
+        .get("Connection")
+        .map(|value| value.eq_ignore_ascii_case("close"))
+        .unwrap_or(false)
+}
+
+/// 按照选项设置TCP流的读写超时。
+pub fn set_timeouts(stream: &TcpStream, options: &Options) -> io::Result<()> {
+    stream.set_read_timeout(Some(options.keep_alive_timeout))?;
+    stream.set_write_timeout(Some(options.write_timeout))
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
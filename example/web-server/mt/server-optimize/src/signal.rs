@@ -0,0 +1,76 @@
+use std::io;
+use std::mem::MaybeUninit;
+use std::ptr;
+
+/*
+    进程控制信号
+
+    - Reload    SIGHUP：重新读取配置文件，替换处理器，已有连接不受影响
+    - Upgrade   SIGUSR2：启动新的二进制并把监听套接字交给它，旧进程排空后退出
+    - Terminate SIGTERM/SIGINT：停止接受新连接，处理完已接受的连接后退出
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Reload,
+    Upgrade,
+    Terminate,
+}
+
+impl Signal {
+    fn from_raw(signo: libc::c_int) -> Option<Signal> {
+        match signo {
+            libc::SIGHUP => Some(Signal::Reload),
+            libc::SIGUSR2 => Some(Signal::Upgrade),
+            libc::SIGTERM | libc::SIGINT => Some(Signal::Terminate),
+            _ => None,
+        }
+    }
+}
+
+/*
+    同步接收信号
+
+    屏蔽控制信号后由一个专门的线程通过sigwait逐个取出，信号处理不再受异步信号安全的限制；
+    信号屏蔽字会被之后创建的线程继承，所以必须在创建任何线程之前调用 `Signals::block`
+ */
+pub struct Signals {
+    set: libc::sigset_t,
+}
+
+// sigset_t只是一个位图
+unsafe impl Send for Signals {}
+unsafe impl Sync for Signals {}
+
+impl Signals {
+    /// 在当前线程（以及之后创建的所有线程）中屏蔽控制信号。
+    pub fn block() -> io::Result<Signals> {
+        let set = unsafe {
+            let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+            libc::sigemptyset(set.as_mut_ptr());
+            for signo in [libc::SIGHUP, libc::SIGUSR2, libc::SIGTERM, libc::SIGINT] {
+                libc::sigaddset(set.as_mut_ptr(), signo);
+            }
+            set.assume_init()
+        };
+        match unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) } {
+            0 => Ok(Signals { set }),
+            errno => Err(io::Error::from_raw_os_error(errno)),
+        }
+    }
+
+    /// 阻塞等待下一个控制信号。
+    pub fn wait(&self) -> io::Result<Signal> {
+        loop {
+            let mut signo = 0;
+            match unsafe { libc::sigwait(&self.set, &mut signo) } {
+                0 => {
+                    if let Some(signal) = Signal::from_raw(signo) {
+                        return Ok(signal);
+                    }
+                }
+                libc::EINTR => continue,
+                errno => return Err(io::Error::from_raw_os_error(errno)),
+            }
+        }
+    }
+}
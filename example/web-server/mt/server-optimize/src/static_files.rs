@@ -0,0 +1,160 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::http::{mime, Request, Response, StatusCode};
+use crate::middleware::compression::{self, Encoding};
+use crate::middleware::Handler;
+
+/*
+    静态文件处理器
+
+    把请求路径映射到文档根目录下的文件，目录返回其中的首页文件；
+    路径中的 ".." 以及编码后的分隔符会被拒绝，保证不会读取到文档根目录之外的文件。
+    HEAD请求与GET得到相同的响应，由服务端在写出时丢弃响应体
+ */
+pub struct StaticFiles {
+    root: PathBuf,
+    index: String,
+}
+
+impl StaticFiles {
+    pub fn new<P: Into<PathBuf>>(root: P) -> StaticFiles {
+        StaticFiles { root: root.into(), index: String::from("index.html") }
+    }
+
+    /// 设置目录的首页文件名，默认为index.html。
+    pub fn index(mut self, index: &str) -> StaticFiles {
+        self.index = index.to_string();
+        self
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: Request) -> Response {
+        if request.method != "GET" && request.method != "HEAD" {
+            return Response::from(StatusCode::METHOD_NOT_ALLOWED).with_header("Allow", "GET, HEAD");
+        }
+        let mut path = match resolve(&self.root, &request.path) {
+            Some(path) => path,
+            None => return Response::from(StatusCode::FORBIDDEN),
+        };
+        if path.is_dir() {
+            path.push(&self.index);
+        }
+        if !path.is_file() {
+            return Response::from(StatusCode::NOT_FOUND);
+        }
+        serve_file(&request, StatusCode::OK, &path).unwrap_or_else(|_| Response::from(StatusCode::INTERNAL_SERVER_ERROR))
+    }
+}
+
+/// 把请求路径解析为文档根目录下的文件路径。
+///
+/// 路径会先进行百分号解码，解码后包含 `..`、反斜杠、NUL字符或者隐藏文件（以 `.` 开头）时返回None。
+pub fn resolve(root: &Path, request_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode(request_path)?;
+    if decoded.contains(['\\', '\0']) {
+        return None;
+    }
+    let mut path = root.to_path_buf();
+    for component in Path::new(decoded.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) if part.to_string_lossy().starts_with('.') => return None,
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(path)
+}
+
+// 百分号解码，编码不合法或者解码结果不是UTF-8时返回None
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = text.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// 返回静态文件响应。
+///
+/// 如果客户端接受gzip编码并且文件旁边存在预压缩的 `.gz` 文件（例如 `index.html.gz`），
+/// 则直接返回预压缩文件，Content-Type仍然按照原始文件推断，避免每次请求都重新压缩。
+pub fn serve_file<P: AsRef<Path>>(request: &Request, status: StatusCode, path: P) -> io::Result<Response> {
+    let path = path.as_ref();
+    let content_type = mime::from_path(path);
+
+    let accept_gzip = request
+        .header("Accept-Encoding")
+        .and_then(|accept| compression::negotiate(accept, &[Encoding::Gzip]))
+        .is_some();
+    if accept_gzip && compression::is_text_like(content_type) {
+        if let Ok(response) = Response::file(status, gz_sibling(path)) {
+            let mut response = response
+                .with_header("Content-Type", content_type)
+                .with_header("Content-Encoding", Encoding::Gzip.as_str());
+            compression::add_vary_accept_encoding(&mut response.headers);
+            return Ok(response);
+        }
+    }
+
+    Response::file(status, path)
+}
+
+// index.html -> index.html.gz
+fn gz_sibling(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_stays_inside_root() {
+        let root = Path::new("/srv/www");
+        assert_eq!(resolve(root, "/"), Some(PathBuf::from("/srv/www")));
+        assert_eq!(resolve(root, "/css/site.css"), Some(PathBuf::from("/srv/www/css/site.css")));
+        assert_eq!(resolve(root, "/a%20b.html"), Some(PathBuf::from("/srv/www/a b.html")));
+        assert_eq!(resolve(root, "/./index.html"), Some(PathBuf::from("/srv/www/index.html")));
+        assert_eq!(resolve(root, "/../etc/passwd"), None);
+        assert_eq!(resolve(root, "/.git/config"), None);
+        assert_eq!(resolve(root, "/%2e%2e/etc/passwd"), None);
+        assert_eq!(resolve(root, "/..%5cetc"), None);
+        assert_eq!(resolve(root, "/bad%zz"), None);
+    }
+
+    #[test]
+    fn head_and_disallowed_methods() {
+        let root = std::env::temp_dir().join(format!("server-optimize-static-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("index.html"), "<h1>home</h1>").unwrap();
+        let files = StaticFiles::new(&root);
+
+        // HEAD与GET得到同样的响应，响应体由服务端丢弃
+        let response = files.handle(Request::new("HEAD", "/"));
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body.len(), Some(13));
+
+        let response = files.handle(Request::new("POST", "/"));
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers.get("Allow"), Some("GET, HEAD"));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,482 @@
+use std::any::Any;
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use crate::{panic_message, Job, ThreadPool};
+
+/*
+    任务句柄
+
+    由 `ThreadPool::spawn`、`ThreadPool::spawn_future` 或者 `Scope::spawn` 返回，用于等待任务的返回值、
+    或者取消尚未开始执行的任务；也可以在异步任务中 `.await`。丢弃句柄不会影响任务的执行
+ */
+pub struct TaskHandle<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> TaskHandle<T> {
+    /// 阻塞等待任务结束并返回结果；任务panic或者被取消时返回 `JoinError`。
+    ///
+    /// # Panics
+    ///
+    /// 结果已经被 `try_join` 或者 `join_timeout` 取走时会 panic。
+    pub fn join(self) -> Result<T, JoinError> {
+        let state = self.shared.lock();
+        let mut state = self.shared.finished.wait_while(state, |state| state.is_pending()).unwrap_or_else(PoisonError::into_inner);
+        state.take()
+    }
+
+    /// 任务已经结束时取走结果，否则立即返回None。
+    ///
+    /// # Panics
+    ///
+    /// 结果已经被取走时会 panic。
+    pub fn try_join(&mut self) -> Option<Result<T, JoinError>> {
+        let mut state = self.shared.lock();
+        if state.is_pending() {
+            return None;
+        }
+        Some(state.take())
+    }
+
+    /// 最多等待 `timeout`，任务在此期间结束时取走结果，否则返回None。
+    ///
+    /// # Panics
+    ///
+    /// 结果已经被取走时会 panic。
+    pub fn join_timeout(&mut self, timeout: Duration) -> Option<Result<T, JoinError>> {
+        let state = self.shared.lock();
+        let (mut state, _) = self
+            .shared
+            .finished
+            .wait_timeout_while(state, timeout, |state| state.is_pending())
+            .unwrap_or_else(PoisonError::into_inner);
+        if state.is_pending() {
+            return None;
+        }
+        Some(state.take())
+    }
+
+    /// 取消尚未开始执行的任务，返回是否取消成功；已经开始执行或者已经结束的任务不受影响。
+    ///
+    /// 被取消的任务仍然留在队列中，轮到它时Worker直接丢弃它而不执行。
+    pub fn cancel(&self) -> bool {
+        self.shared.finish_if_queued(Err(JoinError::Cancelled))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !self.shared.lock().is_pending()
+    }
+}
+
+// 在异步任务中等待另一个任务的结果，不阻塞Worker
+impl<T> Future for TaskHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    /// # Panics
+    ///
+    /// 结果已经被取走时会 panic。
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.lock();
+        if !state.is_pending() {
+            return Poll::Ready(state.take());
+        }
+        // 持有状态锁时登记：结束任务的一方先修改状态再取走waker，不会错过唤醒
+        let mut waker = self.shared.waker.lock().unwrap_or_else(PoisonError::into_inner);
+        if !waker.as_ref().is_some_and(|waker| waker.will_wake(cx.waker())) {
+            *waker = Some(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> fmt::Debug for TaskHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskHandle").field("finished", &self.is_finished()).finish()
+    }
+}
+
+/*
+    任务未能产生返回值的原因
+ */
+pub enum JoinError {
+    // 任务在开始执行之前被取消（或者被线程池丢弃）
+    Cancelled,
+    // 任务panic，保存panic的payload
+    Panicked(Box<dyn Any + Send + 'static>),
+}
+
+impl JoinError {
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, JoinError::Cancelled)
+    }
+
+    pub fn is_panic(&self) -> bool {
+        matches!(self, JoinError::Panicked(_))
+    }
+
+    /// 取出panic的payload，可以交给 `std::panic::resume_unwind` 在当前线程继续panic。
+    ///
+    /// # Panics
+    ///
+    /// 任务是被取消的时会 panic。
+    pub fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        match self {
+            JoinError::Panicked(payload) => payload,
+            JoinError::Cancelled => panic!("task was cancelled, not panicked"),
+        }
+    }
+}
+
+impl fmt::Debug for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => f.write_str("Cancelled"),
+            JoinError::Panicked(payload) => f.debug_tuple("Panicked").field(&panic_message(payload.as_ref())).finish(),
+        }
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => f.write_str("task was cancelled"),
+            JoinError::Panicked(payload) => write!(f, "task panicked: {}", panic_message(payload.as_ref())),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+// 任务状态：排队中 -> 执行中 -> 已结束 -> 结果已取走；排队中的任务也可能直接变为已结束（被取消）
+enum State<T> {
+    Queued,
+    Running,
+    Finished(Result<T, JoinError>),
+    Taken,
+}
+
+impl<T> State<T> {
+    fn is_pending(&self) -> bool {
+        matches!(self, State::Queued | State::Running)
+    }
+
+    fn take(&mut self) -> Result<T, JoinError> {
+        match mem::replace(self, State::Taken) {
+            State::Finished(result) => result,
+            State::Taken => panic!("task result already taken"),
+            State::Queued | State::Running => unreachable!("task is still pending"),
+        }
+    }
+}
+
+// 任务与句柄共享的状态
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    finished: Condvar,
+    // 在异步任务中等待该任务的句柄
+    waker: Mutex<Option<Waker>>,
+}
+
+impl<T> Shared<T> {
+    fn new() -> Arc<Shared<T>> {
+        Arc::new(Shared { state: Mutex::new(State::Queued), finished: Condvar::new(), waker: Mutex::new(None) })
+    }
+
+    // 状态只在持有锁时整体替换，锁中毒时状态仍然是完整的
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // 任务还在排队时直接结束它，返回是否成功
+    fn finish_if_queued(&self, result: Result<T, JoinError>) -> bool {
+        self.finish_if(result, |state| matches!(state, State::Queued))
+    }
+
+    fn finish_if(&self, result: Result<T, JoinError>, condition: impl FnOnce(&State<T>) -> bool) -> bool {
+        let mut state = self.lock();
+        if !condition(&state) {
+            return false;
+        }
+        *state = State::Finished(result);
+        drop(state);
+        self.notify();
+        true
+    }
+
+    // 状态变为已结束之后通知阻塞等待以及异步等待的句柄
+    fn notify(&self) {
+        self.finished.notify_all();
+        let waker = self.waker.lock().unwrap_or_else(PoisonError::into_inner).take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/*
+    提交给Worker执行的任务
+
+    任务没有执行就被丢弃时（例如被取消，或者被线程池丢弃），等待中的句柄得到 `JoinError::Cancelled`
+ */
+pub(crate) struct Task<F, T> {
+    f: Option<F>,
+    shared: Arc<Shared<T>>,
+}
+
+pub(crate) fn task<F, T>(f: F) -> (Task<F, T>, TaskHandle<T>)
+where
+    F: FnOnce() -> T,
+{
+    let shared = Shared::new();
+    (Task { f: Some(f), shared: Arc::clone(&shared) }, TaskHandle { shared })
+}
+
+impl<F, T> Task<F, T>
+where
+    F: FnOnce() -> T,
+{
+    pub(crate) fn run(mut self) {
+        {
+            let mut state = self.shared.lock();
+            // 已经被取消
+            if !matches!(*state, State::Queued) {
+                return;
+            }
+            *state = State::Running;
+        }
+        let f = self.f.take().unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(f)).map_err(JoinError::Panicked);
+        *self.shared.lock() = State::Finished(result);
+        self.shared.notify();
+    }
+}
+
+impl<F, T> Drop for Task<F, T> {
+    fn drop(&mut self) {
+        // 先释放闭包（以及它借用的数据），再通知句柄
+        drop(self.f.take());
+        self.shared.finish_if_queued(Err(JoinError::Cancelled));
+    }
+}
+
+/*
+    异步任务的结果写入端
+
+    第一次轮询之前句柄可以取消任务；没有写入结果就被丢弃时（例如线程池丢弃了排队中的异步任务），
+    等待中的句柄得到 `JoinError::Cancelled`
+ */
+pub(crate) struct Promise<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub(crate) fn promise<T>() -> (Promise<T>, TaskHandle<T>) {
+    let shared = Shared::new();
+    (Promise { shared: Arc::clone(&shared) }, TaskHandle { shared })
+}
+
+impl<T> Promise<T> {
+    /// 开始（或者继续）执行，任务已经被取消时返回false。
+    pub(crate) fn start(&self) -> bool {
+        let mut state = self.shared.lock();
+        match *state {
+            State::Queued => {
+                *state = State::Running;
+                true
+            }
+            State::Running => true,
+            State::Finished(_) | State::Taken => false,
+        }
+    }
+
+    pub(crate) fn complete(&self, result: Result<T, JoinError>) {
+        self.shared.finish_if(result, State::is_pending);
+    }
+}
+
+impl<T> Drop for Promise<T> {
+    fn drop(&mut self) {
+        self.shared.finish_if(Err(JoinError::Cancelled), State::is_pending);
+    }
+}
+
+/*
+    任务作用域
+
+    由 `ThreadPool::scope` 创建，作用域内提交的任务可以借用 'env 生命周期内的数据；
+    任务的panic通过各自的 `TaskHandle::join` 返回，作用域本身不会因此panic
+ */
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'env ThreadPool,
+    pending: Arc<Pending>,
+    // 与 std::thread::Scope 相同：'scope 不变，'env 不变
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// 在线程池中执行一个可以借用作用域外数据的任务。
+    pub fn spawn<F, T>(&'scope self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let (task, handle) = task(f);
+        let job = ScopedJob { task: Some(task), pending: Arc::clone(&self.pending) };
+        *self.pending.lock() += 1;
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            let mut job = job;
+            job.task.take().unwrap().run();
+        });
+        // SAFETY: `scope` 返回之前等待所有任务结束（ScopedJob被释放），任务借用的数据在此之前一直有效
+        let job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        self.pool.execute_job(job);
+        handle
+    }
+}
+
+pub(crate) fn scope<'env, F, R>(pool: &'env ThreadPool, f: F) -> R
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+{
+    let scope = Scope { pool, pending: Arc::new(Pending::default()), scope: PhantomData, env: PhantomData };
+    // f panic时同样要等待已经提交的任务结束，之后再继续panic
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+    let pending = scope.pending.lock();
+    drop(scope.pending.done.wait_while(pending, |pending| *pending > 0).unwrap_or_else(PoisonError::into_inner));
+    match result {
+        Ok(result) => result,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+// 作用域中尚未结束的任务数；由任务共同持有，避免作用域返回后任务还在访问它
+#[derive(Default)]
+struct Pending {
+    count: Mutex<usize>,
+    done: Condvar,
+}
+
+impl Pending {
+    fn lock(&self) -> MutexGuard<'_, usize> {
+        self.count.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// 作用域中的任务，无论执行完毕还是没有执行就被丢弃，都在任务本身释放之后才减少计数
+struct ScopedJob<F, T> {
+    task: Option<Task<F, T>>,
+    pending: Arc<Pending>,
+}
+
+impl<F, T> Drop for ScopedJob<F, T> {
+    fn drop(&mut self) {
+        drop(self.task.take());
+        let mut count = self.pending.lock();
+        *count -= 1;
+        if *count == 0 {
+            self.pending.done.notify_all();
+        }
+    }
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn join_result_and_panic() {
+        let pool = ThreadPool::new(2);
+        assert_eq!(pool.spawn(|| 6 * 7).join().unwrap(), 42);
+
+        let err = pool.spawn(|| -> i32 { panic!("boom") }).join().unwrap_err();
+        assert!(err.is_panic());
+        assert_eq!(err.to_string(), "task panicked: boom");
+        assert_eq!(*err.into_panic().downcast::<&str>().unwrap(), "boom");
+        // panic被句柄接收，不计入线程池的panic次数
+        assert_eq!(pool.monitor().stats().panicked, 0);
+    }
+
+    #[test]
+    fn try_join_and_timeout() {
+        let pool = ThreadPool::new(1);
+        let (release, gate) = mpsc::channel::<()>();
+        let mut handle = pool.spawn(move || {
+            gate.recv().unwrap();
+            "done"
+        });
+        assert!(handle.try_join().is_none());
+        assert!(handle.join_timeout(Duration::from_millis(50)).is_none());
+        assert!(!handle.is_finished());
+
+        release.send(()).unwrap();
+        assert_eq!(handle.join_timeout(Duration::from_secs(10)).unwrap().unwrap(), "done");
+        assert!(handle.is_finished());
+    }
+
+    #[test]
+    fn cancel_queued_task() {
+        let pool = ThreadPool::new(1);
+        let (release, gate) = mpsc::channel::<()>();
+        let (started, start) = mpsc::channel();
+        let running = pool.spawn(move || {
+            started.send(()).unwrap();
+            gate.recv().unwrap()
+        });
+        start.recv().unwrap();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let queued = {
+            let runs = Arc::clone(&runs);
+            pool.spawn(move || runs.fetch_add(1, Ordering::SeqCst))
+        };
+
+        assert!(queued.cancel());
+        // 已经开始执行的任务不能取消
+        assert!(!running.cancel());
+        release.send(()).unwrap();
+        running.join().unwrap();
+        assert!(queued.join().unwrap_err().is_cancelled());
+
+        // 等到队列中被取消的任务也被Worker取走
+        pool.spawn(|| ()).join().unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn scoped_tasks_borrow_stack() {
+        let pool = ThreadPool::new(3);
+        let mut numbers: Vec<u64> = (1..=100).collect();
+        let total = AtomicUsize::new(0);
+        let sums = pool.scope(|scope| {
+            let handles: Vec<_> = numbers
+                .chunks_mut(30)
+                .map(|chunk| {
+                    let total = &total;
+                    scope.spawn(move || {
+                        chunk.iter_mut().for_each(|n| *n *= 2);
+                        total.fetch_add(chunk.len(), Ordering::SeqCst);
+                        chunk.iter().sum::<u64>()
+                    })
+                })
+                .collect();
+            // 不join的任务同样会在scope返回前结束
+            scope.spawn(|| total.fetch_add(1000, Ordering::SeqCst));
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Vec<_>>()
+        });
+        assert_eq!(sums.iter().sum::<u64>(), 10100);
+        assert_eq!(numbers[99], 200);
+        assert_eq!(total.load(Ordering::SeqCst), 1100);
+    }
+}
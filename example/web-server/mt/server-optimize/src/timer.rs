@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{Job, Priority, WorkerContext};
+
+// 定时任务的状态
+const PENDING: u8 = 0;
+const FIRED: u8 = 1;
+const CANCELLED: u8 = 2;
+
+/*
+    定时任务句柄
+
+    由 `ThreadPool::schedule` 或者 `ThreadPool::schedule_at_fixed_rate` 返回，可以克隆；
+    丢弃句柄不会取消任务，线程池关闭后尚未到期的定时任务不再执行
+ */
+#[derive(Clone)]
+pub struct ScheduledHandle {
+    shared: Arc<Scheduled>,
+}
+
+struct Scheduled {
+    state: AtomicU8,
+    // 任务交给线程池执行的次数
+    runs: AtomicUsize,
+}
+
+impl ScheduledHandle {
+    /// 取消任务：一次性任务不再执行，周期任务不再触发之后的执行，已经交给线程池的那一次不受影响。
+    ///
+    /// 一次性任务已经交给线程池、或者任务已经被取消时返回false。
+    pub fn cancel(&self) -> bool {
+        self.shared.state.compare_exchange(PENDING, CANCELLED, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.shared.state.load(Ordering::SeqCst) == CANCELLED
+    }
+
+    /// 任务到期后交给线程池执行的次数；周期任务上一次执行尚未结束时跳过的触发不计入。
+    pub fn runs(&self) -> usize {
+        self.shared.runs.load(Ordering::SeqCst)
+    }
+}
+
+// 到期时的动作
+enum Action {
+    Once(Job),
+    // 在定时器线程中直接调用，只用于很快就能返回的回调（例如唤醒异步任务）
+    Call(Job),
+    Repeat {
+        job: Arc<dyn Fn() + Send + Sync>,
+        period: Duration,
+        // 上一次执行尚未结束，用于避免同一个周期任务并发执行
+        running: Arc<AtomicBool>,
+    },
+}
+
+struct Entry {
+    shared: Arc<Scheduled>,
+    action: Action,
+}
+
+impl Entry {
+    // 到期时把任务交给线程池，周期任务返回下一次到期的时间
+    fn fire(self, context: &Arc<WorkerContext>, due: Instant) -> Option<(Instant, Entry)> {
+        match self.action {
+            Action::Once(job) => {
+                if self.shared.state.compare_exchange(PENDING, FIRED, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                    self.shared.runs.fetch_add(1, Ordering::SeqCst);
+                    let _ = context.submit(Priority::Normal, job);
+                }
+                None
+            }
+            Action::Call(f) => {
+                if self.shared.state.compare_exchange(PENDING, FIRED, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                    self.shared.runs.fetch_add(1, Ordering::SeqCst);
+                    f();
+                }
+                None
+            }
+            Action::Repeat { ref job, period, ref running } => {
+                if self.shared.state.load(Ordering::SeqCst) != PENDING {
+                    return None;
+                }
+                // 上一次执行尚未结束时跳过本次
+                if !running.swap(true, Ordering::SeqCst) {
+                    self.shared.runs.fetch_add(1, Ordering::SeqCst);
+                    let (job, reset) = (Arc::clone(job), Reset(Arc::clone(running)));
+                    let _ = context.submit(Priority::Normal, move || {
+                        let _reset = reset;
+                        job()
+                    });
+                }
+                // 固定频率：下一次到期时间从本次的到期时间算起，错过的周期（例如线程池过载时）直接跳过
+                let now = Instant::now();
+                let mut next = due + period;
+                if next <= now {
+                    let missed = (now - due).as_nanos() / period.as_nanos();
+                    next = due + Duration::from_nanos((period.as_nanos() * (missed + 1)) as u64);
+                }
+                Some((next, self))
+            }
+        }
+    }
+}
+
+// 周期任务执行结束（或者被拒绝策略丢弃）时清除执行中标志
+struct Reset(Arc<AtomicBool>);
+
+impl Drop for Reset {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/*
+    定时器线程
+
+    所有定时任务按照到期时间保存在有序表中，定时器线程等待最早的任务到期后把它交给线程池执行，
+    自身从不执行任务（只负责唤醒等待定时器的异步任务）；线程池第一次提交定时任务时才启动
+ */
+pub(crate) struct Timer {
+    shared: Arc<TimerShared>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+struct TimerShared {
+    entries: Mutex<Entries>,
+    // 新任务排到了最前面或者线程池正在关闭
+    changed: Condvar,
+}
+
+struct Entries {
+    // 键为（到期时间，序号），到期时间相同的任务按照提交顺序执行
+    queue: BTreeMap<(Instant, u64), Entry>,
+    next_seq: u64,
+    shutdown: bool,
+}
+
+impl Timer {
+    pub(crate) fn start(context: Weak<WorkerContext>, name: String) -> io::Result<Timer> {
+        let shared = Arc::new(TimerShared {
+            entries: Mutex::new(Entries { queue: BTreeMap::new(), next_seq: 0, shutdown: false }),
+            changed: Condvar::new(),
+        });
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::Builder::new().name(name).spawn(move || run(shared, context))?
+        };
+        Ok(Timer { shared, thread: Mutex::new(Some(thread)) })
+    }
+
+    /// 在 `at` 执行一次。
+    pub(crate) fn once(&self, at: Instant, job: Job) -> ScheduledHandle {
+        self.insert(at, Action::Once(job))
+    }
+
+    /// 在 `at` 由定时器线程直接调用 `f`。
+    pub(crate) fn call(&self, at: Instant, f: Job) -> ScheduledHandle {
+        self.insert(at, Action::Call(f))
+    }
+
+    /// 首次在 `at` 执行，之后每隔 `period` 执行一次。
+    pub(crate) fn repeat(&self, at: Instant, period: Duration, job: Arc<dyn Fn() + Send + Sync>) -> ScheduledHandle {
+        self.insert(at, Action::Repeat { job, period, running: Arc::new(AtomicBool::new(false)) })
+    }
+
+    fn insert(&self, at: Instant, action: Action) -> ScheduledHandle {
+        let shared = Arc::new(Scheduled { state: AtomicU8::new(PENDING), runs: AtomicUsize::new(0) });
+        let mut entries = self.shared.lock();
+        let seq = entries.next_seq;
+        entries.next_seq += 1;
+        entries.queue.insert((at, seq), Entry { shared: Arc::clone(&shared), action });
+        // 只有最早到期的任务变化时才需要唤醒定时器线程重新计算等待时间
+        if entries.queue.first_key_value().is_some_and(|(key, _)| *key == (at, seq)) {
+            self.shared.changed.notify_one();
+        }
+        ScheduledHandle { shared }
+    }
+
+    /// 停止定时器线程，尚未到期的任务随之丢弃。
+    pub(crate) fn shutdown(&self) {
+        self.shared.lock().shutdown = true;
+        self.shared.changed.notify_one();
+        if let Some(thread) = self.thread.lock().unwrap_or_else(PoisonError::into_inner).take() {
+            let _ = thread.join();
+        }
+        // 任务在锁外释放
+        let queue = mem::take(&mut self.shared.lock().queue);
+        drop(queue);
+    }
+}
+
+impl TimerShared {
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn run(shared: Arc<TimerShared>, context: Weak<WorkerContext>) {
+    let mut entries = shared.lock();
+    loop {
+        if entries.shutdown {
+            return;
+        }
+        let now = Instant::now();
+        match entries.queue.first_key_value().map(|(key, _)| key.0) {
+            None => entries = shared.changed.wait(entries).unwrap_or_else(PoisonError::into_inner),
+            Some(due) if due > now => {
+                entries = shared.changed.wait_timeout(entries, due - now).unwrap_or_else(PoisonError::into_inner).0
+            }
+            Some(_) => {
+                let ((due, _), entry) = entries.queue.pop_first().unwrap();
+                // 提交时可能因为队列已满而阻塞，不能持有锁
+                drop(entries);
+                let context = match context.upgrade() {
+                    Some(context) => context,
+                    None => return,
+                };
+                let next = entry.fire(&context, due);
+                drop(context);
+                entries = shared.lock();
+                if let Some((due, entry)) = next {
+                    let seq = entries.next_seq;
+                    entries.next_seq += 1;
+                    entries.queue.insert((due, seq), entry);
+                }
+            }
+        }
+    }
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::ThreadPool;
+
+    #[test]
+    fn run_delayed_jobs_in_order() {
+        let pool = ThreadPool::new(2);
+        let (sender, fired) = mpsc::channel();
+        let start = Instant::now();
+        for (n, delay) in [(2, 60), (0, 20), (1, 40)] {
+            let sender = sender.clone();
+            pool.schedule(Duration::from_millis(delay), move || sender.send(n).unwrap());
+        }
+        let order: Vec<i32> = (0..3).map(|_| fired.recv_timeout(Duration::from_secs(10)).unwrap()).collect();
+        assert_eq!(order, [0, 1, 2]);
+        assert!(start.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn cancel_scheduled_jobs() {
+        let pool = ThreadPool::new(1);
+        let (sender, fired) = mpsc::channel();
+        let cancelled = pool.schedule(Duration::from_millis(20), move || sender.send(()).unwrap());
+        assert!(cancelled.cancel());
+        assert!(!cancelled.cancel());
+        assert!(fired.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(cancelled.runs(), 0);
+
+        // 已经执行过的一次性任务无法取消
+        let (sender, fired) = mpsc::channel();
+        let done = pool.schedule(Duration::ZERO, move || sender.send(()).unwrap());
+        fired.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(!done.cancel());
+        assert_eq!(done.runs(), 1);
+    }
+
+    #[test]
+    fn repeat_at_fixed_rate_until_cancelled() {
+        let pool = ThreadPool::new(2);
+        let count = Arc::new(AtomicUsize::new(0));
+        let handle = {
+            let count = Arc::clone(&count);
+            pool.schedule_at_fixed_rate(Duration::ZERO, Duration::from_millis(10), move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while count.load(Ordering::SeqCst) < 3 {
+            assert!(Instant::now() < deadline, "periodic job did not repeat");
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(handle.cancel());
+        // 取消时可能有一次已经交给线程池
+        thread::sleep(Duration::from_millis(30));
+        let runs = count.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(count.load(Ordering::SeqCst), runs);
+        assert_eq!(handle.runs(), runs);
+    }
+
+    #[test]
+    fn skip_overlapping_runs() {
+        let pool = ThreadPool::new(4);
+        let (running, max_running) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let handle = {
+            let (running, max_running) = (Arc::clone(&running), Arc::clone(&max_running));
+            pool.schedule_at_fixed_rate(Duration::ZERO, Duration::from_millis(5), move || {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(30));
+                running.fetch_sub(1, Ordering::SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(150));
+        handle.cancel();
+        assert_eq!(max_running.load(Ordering::SeqCst), 1);
+        assert!(handle.runs() >= 2);
+    }
+}
@@ -0,0 +1,129 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::limiter::Limiter;
+use crate::middleware::Handler;
+use crate::server::{self, Options, Shutdown};
+use crate::ThreadPool;
+
+/// 从PEM文件中加载证书链与私钥，构建TLS服务端配置。
+///
+/// 证书文件中可以包含多个证书（服务端证书在前，中间证书在后），私钥支持PKCS#8、PKCS#1以及SEC1格式。
+pub fn load_config<P: AsRef<Path>, Q: AsRef<Path>>(cert_path: P, key_path: Q) -> io::Result<Arc<ServerConfig>> {
+    let certs = load_certs(cert_path.as_ref())?;
+    let key = load_key(key_path.as_ref())?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    Ok(Arc::new(config))
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no certificate found in {}", path.display()),
+        ));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("no private key found in {}", path.display()))
+    })
+}
+
+/// HTTPS阻塞模式：与 `server::serve` 相同，只是在TCP连接之上先完成TLS握手。
+pub fn serve(
+    listener: TcpListener,
+    pool: &ThreadPool,
+    handler: Arc<dyn Handler>,
+    config: Arc<ServerConfig>,
+    options: Options,
+    limiter: &Limiter,
+) {
+    serve_until(listener, pool, handler, config, options, limiter, &Shutdown::new())
+}
+
+/// 与 `serve` 相同，`shutdown` 触发后停止接受新连接并返回。
+pub fn serve_until(
+    listener: TcpListener,
+    pool: &ThreadPool,
+    handler: Arc<dyn Handler>,
+    config: Arc<ServerConfig>,
+    options: Options,
+    limiter: &Limiter,
+    shutdown: &Shutdown,
+) {
+    let monitor = pool.monitor();
+    while let Some(result) = server::accept(&listener, shutdown) { // result: Result<TcpStream, Error>
+        let stream = match result {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("[Server] accept failed: {}", err);
+                continue;
+            }
+        };
+        // 握手之前无法写出HTTP响应，被拒绝的连接直接关闭
+        let guard = match server::admit(&stream, &monitor, limiter) {
+            Ok(guard) => guard,
+            Err(_) => continue,
+        };
+        let handler = Arc::clone(&handler);
+        let config = Arc::clone(&config);
+        let shutdown = shutdown.clone();
+        server::submit(pool, move || {
+            let _guard = guard;
+            handle_connection(stream, handler.as_ref(), config, options, &shutdown)
+        });
+    }
+}
+
+/// 处理一个TLS连接，握手在第一次读取时完成，之后的请求处理与明文HTTP完全相同。
+///
+/// 升级后的连接需要可克隆的TCP流，所以TLS连接上的协议升级请求会得到501响应。
+pub fn handle_connection(
+    stream: TcpStream,
+    handler: &dyn Handler,
+    config: Arc<ServerConfig>,
+    options: Options,
+    shutdown: &Shutdown,
+) {
+    // 读超时同时限制了TLS握手与keep-alive空闲的时间
+    if let Err(err) = server::set_timeouts(&stream, &options) {
+        println!("[Connection] set timeouts failed: {}", err);
+        return;
+    }
+    let remote_addr = stream.peer_addr().ok();
+    let connection = match ServerConnection::new(config) {
+        Ok(connection) => connection,
+        Err(err) => {
+            println!("[Connection] create tls connection failed: {}", err);
+            return;
+        }
+    };
+    let stream = StreamOwned::new(connection, stream);
+    let (mut tls, _) = server::serve_stream(stream, remote_addr, handler, options, false, shutdown);
+
+    // 关闭前发送close_notify，让客户端能够区分正常结束与连接被截断；
+    // 这里直接写出待发送的TLS记录，而不是调用flush，因为握手失败时flush会继续等待读取握手数据
+    tls.conn.send_close_notify();
+    while tls.conn.wants_write() {
+        if tls.conn.write_tls(&mut tls.sock).is_err() {
+            break;
+        }
+    }
+}
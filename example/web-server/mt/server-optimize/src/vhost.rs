@@ -0,0 +1,265 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::cgi::Cgi;
+use crate::config::{self, Config, RouteAction};
+use crate::http::{Body, Request, Response, StatusCode};
+use crate::middleware::Handler;
+use crate::proxy::Proxy;
+use crate::router::Router;
+use crate::static_files::{self, StaticFiles};
+
+/*
+    虚拟主机
+
+    按照请求的Host头部把请求分发给不同站点的处理器，主机名不区分大小写并忽略端口；
+    以 "*." 开头的名称匹配该域名下的任意子域名（不包括域名本身），精确匹配优先于通配符，
+    多个通配符都匹配时后缀最长的优先；没有Host头部或者都不匹配时交给默认站点
+ */
+pub struct VirtualHosts {
+    hosts: HashMap<String, Arc<dyn Handler>>,
+    // (".example.com", handler)，按照后缀长度从长到短排序
+    wildcards: Vec<(String, Arc<dyn Handler>)>,
+    default: Box<dyn Handler>,
+}
+
+impl VirtualHosts {
+    pub fn new<H: Handler + 'static>(default: H) -> VirtualHosts {
+        VirtualHosts { hosts: HashMap::new(), wildcards: Vec::new(), default: Box::new(default) }
+    }
+
+    /// 添加一个站点，同一个站点可以对应多个主机名。
+    pub fn host<H: Handler + 'static>(mut self, names: &[&str], handler: H) -> VirtualHosts {
+        let handler: Arc<dyn Handler> = Arc::new(handler);
+        for name in names {
+            let name = name.trim().trim_end_matches('.').to_ascii_lowercase();
+            match name.strip_prefix('*') {
+                Some(suffix) => self.wildcards.push((suffix.to_string(), Arc::clone(&handler))),
+                None => {
+                    self.hosts.insert(name, Arc::clone(&handler));
+                }
+            }
+        }
+        self.wildcards.sort_by_key(|(suffix, _)| std::cmp::Reverse(suffix.len()));
+        self
+    }
+
+    /// 按照配置组装全部站点：默认站点使用 `router` 中的路由，虚拟主机只使用各自配置中的路由。
+    pub fn from_config(config: &Config, router: Router) -> VirtualHosts {
+        let mut hosts = VirtualHosts::new(SiteHandler::new(&config.site, router));
+        for host in &config.virtual_hosts {
+            let names: Vec<&str> = host.names.iter().map(String::as_str).collect();
+            hosts = hosts.host(&names, SiteHandler::new(&host.site, Router::new()));
+        }
+        hosts
+    }
+
+    fn lookup(&self, host: &str) -> Option<&dyn Handler> {
+        if let Some(handler) = self.hosts.get(host) {
+            return Some(handler.as_ref());
+        }
+        self.wildcards
+            .iter()
+            .find(|(suffix, _)| host.len() > suffix.len() && host.ends_with(suffix.as_str()))
+            .map(|(_, handler)| handler.as_ref())
+    }
+}
+
+impl Handler for VirtualHosts {
+    fn handle(&self, request: Request) -> Response {
+        let handler = host_name(&request).and_then(|host| self.lookup(&host)).unwrap_or(self.default.as_ref());
+        handler.handle(request)
+    }
+}
+
+/// 从Host头部取出主机名：转为小写，去掉端口以及末尾的点。
+pub fn host_name(request: &Request) -> Option<String> {
+    let host = request.header("Host")?.trim();
+    let name = if host.starts_with('[') {
+        // IPv6地址：[::1]:8080
+        &host[..host.find(']')? + 1]
+    } else {
+        match host.rsplit_once(':') {
+            Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+            _ => host,
+        }
+    };
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/*
+    站点处理器
+
+    先按照路由表处理，没有匹配的路由时从文档根目录返回静态文件；
+    响应为错误状态并且站点为该状态码配置了错误页面时，用错误页面替换响应体，
+    但流式响应体（例如反向代理转发的上游响应、CGI脚本的输出）保持不变
+ */
+pub struct SiteHandler {
+    router: Router,
+    error_pages: BTreeMap<u16, PathBuf>,
+}
+
+impl SiteHandler {
+    /// 在 `router` 已有路由之后追加站点配置中的路由。
+    pub fn new(site: &config::Site, router: Router) -> SiteHandler {
+        let mut router = router;
+        for route in &site.routes {
+            // 同一条路由的多个方法共享一个处理器，代理的轮询与健康状态也是共享的
+            let handler: Arc<dyn Handler> = match &route.action {
+                RouteAction::File(path) => {
+                    let path = path.clone();
+                    Arc::new(move |request: Request| {
+                        static_files::serve_file(&request, StatusCode::OK, &path)
+                            .unwrap_or_else(|_| Response::from(StatusCode::INTERNAL_SERVER_ERROR))
+                    })
+                }
+                RouteAction::Redirect { location, status } => {
+                    let (location, status) = (location.clone(), *status);
+                    Arc::new(move |_: Request| Response::new(status).with_header("Location", location.as_str()))
+                }
+                RouteAction::Proxy { upstreams, strip_prefix } => {
+                    let proxy = Proxy::new(upstreams);
+                    Arc::new(match strip_prefix {
+                        Some(prefix) => proxy.strip_prefix(prefix),
+                        None => proxy,
+                    })
+                }
+                RouteAction::Cgi { program, timeout, max_output } => Arc::new(
+                    Cgi::new(program)
+                        .script_name(route.path.trim_end_matches('*'))
+                        .timeout(*timeout)
+                        .max_output(*max_output),
+                ),
+            };
+            for method in &route.methods {
+                let handler = Arc::clone(&handler);
+                router = router.route(method, &route.path, move |request: Request| handler.handle(request));
+            }
+        }
+        if !router.has_fallback() {
+            router = router.fallback(StaticFiles::new(&site.document_root).index(&site.index));
+        }
+        SiteHandler { router, error_pages: site.error_pages.clone() }
+    }
+}
+
+impl Handler for SiteHandler {
+    fn handle(&self, request: Request) -> Response {
+        let mut response = self.router.handle(request);
+        if !response.status.is_error() || matches!(response.body, Body::Stream(_)) {
+            return response;
+        }
+        // 保留原响应的头部（例如405的Allow），只替换内容
+        if let Some(page) = self.error_pages.get(&response.status.as_u16()) {
+            if let Ok(page) = Response::file(response.status, page) {
+                for (name, value) in page.headers.iter() {
+                    response.headers.insert(name, value);
+                }
+                response.body = page.body;
+            }
+        }
+        response
+    }
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn text(body: &'static str) -> impl Handler {
+        move |_: Request| Response::text(StatusCode::OK, body)
+    }
+
+    fn request(host: Option<&str>, path: &str) -> Request {
+        let mut request = Request::new("GET", path);
+        if let Some(host) = host {
+            request.headers.insert("Host", host);
+        }
+        request
+    }
+
+    fn body(response: Response) -> String {
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        text.split_once("\r\n\r\n").unwrap().1.to_string()
+    }
+
+    #[test]
+    fn normalize_host_header() {
+        assert_eq!(host_name(&request(Some("Example.COM:8080"), "/")), Some("example.com".into()));
+        assert_eq!(host_name(&request(Some("example.com."), "/")), Some("example.com".into()));
+        assert_eq!(host_name(&request(Some("[::1]:7878"), "/")), Some("[::1]".into()));
+        assert_eq!(host_name(&request(Some("127.0.0.1"), "/")), Some("127.0.0.1".into()));
+        assert_eq!(host_name(&request(None, "/")), None);
+    }
+
+    #[test]
+    fn dispatch_by_host() {
+        let hosts = VirtualHosts::new(text("default"))
+            .host(&["blog.example.com", "www.blog.example.com"], text("blog"))
+            .host(&["*.example.com"], text("wildcard"))
+            .host(&["*.api.example.com"], text("api"));
+
+        assert_eq!(body(hosts.handle(request(Some("BLOG.example.com:80"), "/"))), "blog");
+        assert_eq!(body(hosts.handle(request(Some("www.blog.example.com"), "/"))), "blog");
+        assert_eq!(body(hosts.handle(request(Some("shop.example.com"), "/"))), "wildcard");
+        assert_eq!(body(hosts.handle(request(Some("v1.api.example.com"), "/"))), "api");
+        // 通配符不匹配域名本身
+        assert_eq!(body(hosts.handle(request(Some("example.com"), "/"))), "default");
+        assert_eq!(body(hosts.handle(request(Some("unknown.org"), "/"))), "default");
+        assert_eq!(body(hosts.handle(request(None, "/"))), "default");
+    }
+
+    #[test]
+    fn site_routes_and_error_pages() {
+        let root = std::env::temp_dir().join(format!("server-optimize-vhost-{}", std::process::id()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("index.html"), "home").unwrap();
+        fs::write(root.join("docs/guide.html"), "guide").unwrap();
+        fs::write(root.join("missing.html"), "custom 404").unwrap();
+        let site = config::Site {
+            document_root: root.clone(),
+            index: "index.html".into(),
+            error_pages: BTreeMap::from([(404, root.join("missing.html"))]),
+            routes: vec![
+                config::RouteConfig {
+                    methods: vec!["GET".into()],
+                    path: "/about".into(),
+                    action: RouteAction::File(root.join("docs/guide.html")),
+                },
+                config::RouteConfig {
+                    methods: vec!["GET".into()],
+                    path: "/old/*".into(),
+                    action: RouteAction::Redirect { location: "/docs/guide.html".into(), status: StatusCode::MOVED_PERMANENTLY },
+                },
+            ],
+        };
+        let handler = SiteHandler::new(&site, Router::new().get("/ping", text("pong")));
+
+        assert_eq!(body(handler.handle(request(None, "/ping"))), "pong");
+        assert_eq!(body(handler.handle(request(None, "/"))), "home");
+        assert_eq!(body(handler.handle(request(None, "/about"))), "guide");
+        assert_eq!(body(handler.handle(request(None, "/docs/guide.html"))), "guide");
+
+        let response = handler.handle(request(None, "/old/page"));
+        assert_eq!(response.status, StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers.get("Location"), Some("/docs/guide.html"));
+
+        let response = handler.handle(request(None, "/nope"));
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert_eq!(body(response), "custom 404");
+        assert_eq!(handler.handle(request(None, "/../etc/passwd")).status, StatusCode::FORBIDDEN);
+    }
+}
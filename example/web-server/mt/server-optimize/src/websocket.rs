@@ -0,0 +1,357 @@
+use std::io::{self, BufReader};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::http::{Request, Response, Upgraded};
+
+pub mod frame;
+pub mod handshake;
+
+use frame::{Frame, OpCode, TooLarge};
+
+/// 注册WebSocket处理器：校验握手请求，成功时返回101响应，响应写出后在独立线程中调用 `handler`。
+///
+/// ```no_run
+/// use server_optimize::http::Request;
+/// use server_optimize::websocket::{self, Message};
+///
+/// fn echo(request: Request) -> server_optimize::http::Response {
+///     websocket::upgrade(&request, |mut socket| {
+///         let sender = socket.sender();
+///         for message in socket.messages() {
+///             let _ = sender.send(message);
+///         }
+///     })
+/// }
+/// ```
+pub fn upgrade<F>(request: &Request, handler: F) -> Response
+where
+    F: FnOnce(WebSocket) + Send + 'static,
+{
+    let response = match handshake::validate(request) {
+        Ok(response) => response,
+        Err(response) => return response,
+    };
+    response.with_upgrade(Box::new(move |upgraded: Upgraded| {
+        let stream = upgraded.stream().try_clone();
+        match WebSocket::new(upgraded, Role::Server) {
+            Ok(socket) => {
+                let sender = socket.sender();
+                handler(socket);
+                // 处理器返回时如果还没有发送Close帧则正常关闭
+                let _ = sender.close(CloseCode::NORMAL, "");
+            }
+            Err(err) => println!("[WebSocket] setup failed: {}", err),
+        }
+        if let Ok(stream) = stream {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }))
+}
+
+/*
+    连接的角色：客户端发送的帧必须带掩码，服务端发送的帧不能带掩码
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Server,
+    Client,
+}
+
+/*
+    关闭状态码
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CloseCode(pub u16);
+
+impl CloseCode {
+    pub const NORMAL: CloseCode = CloseCode(1000);
+    pub const GOING_AWAY: CloseCode = CloseCode(1001);
+    pub const PROTOCOL_ERROR: CloseCode = CloseCode(1002);
+    pub const UNSUPPORTED_DATA: CloseCode = CloseCode(1003);
+    pub const INVALID_PAYLOAD: CloseCode = CloseCode(1007);
+    pub const POLICY_VIOLATION: CloseCode = CloseCode(1008);
+    pub const MESSAGE_TOO_BIG: CloseCode = CloseCode(1009);
+    pub const INTERNAL_ERROR: CloseCode = CloseCode(1011);
+
+    /// 是否允许出现在Close帧中，1005、1006、1015只能在本地使用，1016-2999为保留值。
+    pub fn is_sendable(&self) -> bool {
+        matches!(self.0, 1000..=1003 | 1007..=1014 | 3000..=4999)
+    }
+}
+
+/*
+    关闭帧的内容
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: CloseCode,
+    pub reason: String,
+}
+
+/*
+    WebSocket消息，分片的数据帧会被合并为一条完整的消息
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<CloseFrame>),
+}
+
+/*
+    消息发送端
+
+    可以被克隆并在其他线程中使用，例如由后台线程向浏览器推送实时更新，
+    所有克隆共享同一个写端，每一帧在锁内一次性写出保证不会交错
+ */
+#[derive(Clone)]
+pub struct WebSocketSender {
+    writer: Arc<Mutex<TcpStream>>,
+    role: Role,
+    // 是否已经发送过Close帧，发送Close帧之后不能再发送任何数据帧
+    close_sent: Arc<AtomicBool>,
+}
+
+impl WebSocketSender {
+    pub fn send(&self, message: Message) -> io::Result<()> {
+        match message {
+            Message::Text(text) => self.send_frame(Frame::new(true, OpCode::Text, text.into_bytes())),
+            Message::Binary(bytes) => self.send_frame(Frame::new(true, OpCode::Binary, bytes)),
+            Message::Ping(payload) => self.send_frame(Frame::new(true, OpCode::Ping, payload)),
+            Message::Pong(payload) => self.send_frame(Frame::new(true, OpCode::Pong, payload)),
+            Message::Close(Some(frame)) => self.close(frame.code, &frame.reason),
+            Message::Close(None) => self.close(CloseCode::NORMAL, ""),
+        }
+    }
+
+    pub fn send_text(&self, text: impl Into<String>) -> io::Result<()> {
+        self.send(Message::Text(text.into()))
+    }
+
+    pub fn send_binary(&self, bytes: impl Into<Vec<u8>>) -> io::Result<()> {
+        self.send(Message::Binary(bytes.into()))
+    }
+
+    /// 发送Close帧，重复调用时只有第一次会真正发送。
+    pub fn close(&self, code: CloseCode, reason: &str) -> io::Result<()> {
+        if self.close_sent.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let mut payload = code.0.to_be_bytes().to_vec();
+        // 控制帧负载不超过125字节，扣除2字节状态码后截断原因（保证不截断在UTF-8字符中间）
+        let mut end = reason.len().min(123);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        self.write(Frame::new(true, OpCode::Close, payload))
+    }
+
+    /// 是否已经发送过Close帧。
+    pub fn is_closed(&self) -> bool {
+        self.close_sent.load(Ordering::SeqCst)
+    }
+
+    fn send_frame(&self, frame: Frame) -> io::Result<()> {
+        if self.is_closed() {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "websocket is closed"));
+        }
+        self.write(frame)
+    }
+
+    fn write(&self, frame: Frame) -> io::Result<()> {
+        let mask = match self.role {
+            Role::Client => Some(frame::mask_key()),
+            Role::Server => None,
+        };
+        let mut writer = self.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        frame.write_to(&mut *writer, mask)
+    }
+}
+
+/*
+    WebSocket连接
+
+    recv在读取到Ping时会自动回复Pong，读取到Close时会自动回复Close，
+    协议错误时会以对应的状态码关闭连接并返回InvalidData错误
+ */
+pub struct WebSocket {
+    reader: BufReader<Upgraded>,
+    sender: WebSocketSender,
+    role: Role,
+    max_message_size: u64,
+    // 正在接收的分片消息：(首帧操作码, 已接收的负载)
+    fragments: Option<(OpCode, Vec<u8>)>,
+    // 是否已经收到Close帧或者连接已断开
+    closed: bool,
+}
+
+impl WebSocket {
+    pub fn new(upgraded: Upgraded, role: Role) -> io::Result<WebSocket> {
+        let writer = upgraded.stream().try_clone()?;
+        Ok(WebSocket {
+            reader: BufReader::new(upgraded),
+            sender: WebSocketSender {
+                writer: Arc::new(Mutex::new(writer)),
+                role,
+                close_sent: Arc::new(AtomicBool::new(false)),
+            },
+            role,
+            max_message_size: 16 * 1024 * 1024,
+            fragments: None,
+            closed: false,
+        })
+    }
+
+    /// 设置单条消息（合并分片之后）的最大字节数，默认为16MB，超过时以1009关闭连接。
+    pub fn max_message_size(mut self, bytes: u64) -> WebSocket {
+        self.max_message_size = bytes;
+        self
+    }
+
+    pub fn sender(&self) -> WebSocketSender {
+        self.sender.clone()
+    }
+
+    pub fn send(&self, message: Message) -> io::Result<()> {
+        self.sender.send(message)
+    }
+
+    pub fn close(&self, code: CloseCode, reason: &str) -> io::Result<()> {
+        self.sender.close(code, reason)
+    }
+
+    /// 读取下一条消息，连接关闭后返回 `Ok(None)`。
+    pub fn recv(&mut self) -> io::Result<Option<Message>> {
+        loop {
+            if self.closed {
+                return Ok(None);
+            }
+            let buffered = self.fragments.as_ref().map(|(_, payload)| payload.len() as u64).unwrap_or(0);
+            let limit = self.max_message_size.saturating_sub(buffered);
+            let frame = match Frame::read_from(&mut self.reader, self.role == Role::Server, limit) {
+                Ok(frame) => frame,
+                // 对方没有发送Close帧就断开了连接
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    self.closed = true;
+                    return Ok(None);
+                }
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    let code = if err.get_ref().map(|inner| inner.is::<TooLarge>()).unwrap_or(false) {
+                        CloseCode::MESSAGE_TOO_BIG
+                    } else {
+                        CloseCode::PROTOCOL_ERROR
+                    };
+                    return Err(self.fail(code, err));
+                }
+                Err(err) => return Err(err),
+            };
+
+            match frame.opcode {
+                OpCode::Ping => {
+                    if !self.sender.is_closed() {
+                        self.sender.send_frame(Frame::new(true, OpCode::Pong, frame.payload.clone()))?;
+                    }
+                    return Ok(Some(Message::Ping(frame.payload)));
+                }
+                OpCode::Pong => return Ok(Some(Message::Pong(frame.payload))),
+                OpCode::Close => return self.on_close(frame.payload).map(Some),
+                OpCode::Text | OpCode::Binary => {
+                    if self.fragments.is_some() {
+                        return Err(self.fail(CloseCode::PROTOCOL_ERROR, invalid("expected continuation frame")));
+                    }
+                    if frame.fin {
+                        return self.finish(frame.opcode, frame.payload).map(Some);
+                    }
+                    self.fragments = Some((frame.opcode, frame.payload));
+                }
+                OpCode::Continuation => {
+                    let (opcode, mut payload) = match self.fragments.take() {
+                        Some(fragments) => fragments,
+                        None => return Err(self.fail(CloseCode::PROTOCOL_ERROR, invalid("unexpected continuation frame"))),
+                    };
+                    payload.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        return self.finish(opcode, payload).map(Some);
+                    }
+                    self.fragments = Some((opcode, payload));
+                }
+            }
+        }
+    }
+
+    /// 数据消息的迭代器，忽略Ping/Pong，在连接关闭或者出错时结束。
+    pub fn messages(&mut self) -> Messages<'_> {
+        Messages { socket: self }
+    }
+
+    // 合并完成的消息，文本消息必须是合法的UTF-8
+    fn finish(&mut self, opcode: OpCode, payload: Vec<u8>) -> io::Result<Message> {
+        match opcode {
+            OpCode::Text => match String::from_utf8(payload) {
+                Ok(text) => Ok(Message::Text(text)),
+                Err(_) => Err(self.fail(CloseCode::INVALID_PAYLOAD, invalid("text message is not valid utf-8"))),
+            },
+            _ => Ok(Message::Binary(payload)),
+        }
+    }
+
+    // 收到Close帧：校验状态码与原因后回复Close帧完成关闭握手
+    fn on_close(&mut self, payload: Vec<u8>) -> io::Result<Message> {
+        let frame = match payload.len() {
+            0 => None,
+            1 => return Err(self.fail(CloseCode::PROTOCOL_ERROR, invalid("invalid close payload"))),
+            _ => {
+                let code = CloseCode(u16::from_be_bytes([payload[0], payload[1]]));
+                if !code.is_sendable() {
+                    return Err(self.fail(CloseCode::PROTOCOL_ERROR, invalid("invalid close code")));
+                }
+                let reason = match String::from_utf8(payload[2..].to_vec()) {
+                    Ok(reason) => reason,
+                    Err(_) => return Err(self.fail(CloseCode::INVALID_PAYLOAD, invalid("close reason is not valid utf-8"))),
+                };
+                Some(CloseFrame { code, reason })
+            }
+        };
+        self.closed = true;
+        let code = frame.as_ref().map(|frame| frame.code).unwrap_or(CloseCode::NORMAL);
+        self.sender.close(code, "")?;
+        Ok(Message::Close(frame))
+    }
+
+    // 以指定状态码关闭连接并返回错误
+    fn fail(&mut self, code: CloseCode, err: io::Error) -> io::Error {
+        let _ = self.sender.close(code, "");
+        self.closed = true;
+        err
+    }
+}
+
+fn invalid(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+/*
+    数据消息迭代器
+ */
+pub struct Messages<'a> {
+    socket: &'a mut WebSocket,
+}
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = Message;
+
+    fn next(&mut self) -> Option<Message> {
+        loop {
+            match self.socket.recv() {
+                Ok(Some(message @ Message::Text(_))) | Ok(Some(message @ Message::Binary(_))) => return Some(message),
+                Ok(Some(Message::Ping(_))) | Ok(Some(Message::Pong(_))) => continue,
+                Ok(Some(Message::Close(_))) | Ok(None) | Err(_) => return None,
+            }
+        }
+    }
+}
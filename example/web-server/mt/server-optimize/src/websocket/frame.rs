@@ -0,0 +1,229 @@
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/*
+    帧操作码
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpCode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl OpCode {
+    fn from_u8(value: u8) -> Option<OpCode> {
+        match value {
+            0x0 => Some(OpCode::Continuation),
+            0x1 => Some(OpCode::Text),
+            0x2 => Some(OpCode::Binary),
+            0x8 => Some(OpCode::Close),
+            0x9 => Some(OpCode::Ping),
+            0xA => Some(OpCode::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(&self) -> u8 {
+        match self {
+            OpCode::Continuation => 0x0,
+            OpCode::Text => 0x1,
+            OpCode::Binary => 0x2,
+            OpCode::Close => 0x8,
+            OpCode::Ping => 0x9,
+            OpCode::Pong => 0xA,
+        }
+    }
+
+    /// 控制帧（Close、Ping、Pong）不能分片，负载不超过125字节。
+    pub fn is_control(&self) -> bool {
+        matches!(self, OpCode::Close | OpCode::Ping | OpCode::Pong)
+    }
+}
+
+/*
+    WebSocket数据帧
+
+     0                   1                   2                   3
+     0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+    +-+-+-+-+-------+-+-------------+-------------------------------+
+    |F|R|R|R| opcode|M| Payload len |    Extended payload length    |
+    |I|S|S|S|  (4)  |A|     (7)     |             (16/64)           |
+    |N|V|V|V|       |S|             |                               |
+    +-+-+-+-+-------+-+-------------+-------------------------------+
+    |     Masking-key (if MASK is set)  |        Payload Data       |
+    +-----------------------------------+---------------------------+
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: OpCode,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(fin: bool, opcode: OpCode, payload: Vec<u8>) -> Frame {
+        Frame { fin, opcode, payload }
+    }
+
+    /// 读取一帧。
+    ///
+    /// `expect_masked` 为true时要求帧带有掩码（服务端接收客户端的帧），为false时要求不带掩码；
+    /// 负载超过 `max_payload` 字节时返回 `InvalidData` 错误。
+    pub fn read_from<R: Read>(reader: &mut R, expect_masked: bool, max_payload: u64) -> io::Result<Frame> {
+        let mut head = [0; 2];
+        reader.read_exact(&mut head)?;
+
+        let fin = head[0] & 0x80 != 0;
+        if head[0] & 0x70 != 0 {
+            return Err(invalid("reserved bits must be zero"));
+        }
+        let opcode = OpCode::from_u8(head[0] & 0x0F).ok_or_else(|| invalid("unknown opcode"))?;
+        let masked = head[1] & 0x80 != 0;
+        if masked != expect_masked {
+            return Err(invalid(if expect_masked { "client frames must be masked" } else { "server frames must not be masked" }));
+        }
+
+        let length = match head[1] & 0x7F {
+            126 => {
+                let mut bytes = [0; 2];
+                reader.read_exact(&mut bytes)?;
+                u16::from_be_bytes(bytes) as u64
+            }
+            127 => {
+                let mut bytes = [0; 8];
+                reader.read_exact(&mut bytes)?;
+                u64::from_be_bytes(bytes)
+            }
+            length => length as u64,
+        };
+        if opcode.is_control() && (!fin || length > 125) {
+            return Err(invalid("invalid control frame"));
+        }
+        if length > max_payload {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, TooLarge));
+        }
+
+        let mut mask = [0; 4];
+        if masked {
+            reader.read_exact(&mut mask)?;
+        }
+        let mut payload = vec![0; length as usize];
+        reader.read_exact(&mut payload)?;
+        if masked {
+            apply_mask(&mut payload, mask);
+        }
+        Ok(Frame { fin, opcode, payload })
+    }
+
+    /// 写出一帧，客户端发送的帧需要设置掩码。
+    pub fn write_to<W: Write>(&self, writer: &mut W, mask: Option<[u8; 4]>) -> io::Result<()> {
+        let mut head = Vec::with_capacity(14);
+        head.push(if self.fin { 0x80 } else { 0 } | self.opcode.as_u8());
+
+        let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+        let length = self.payload.len();
+        if length < 126 {
+            head.push(mask_bit | length as u8);
+        } else if length <= u16::MAX as usize {
+            head.push(mask_bit | 126);
+            head.extend_from_slice(&(length as u16).to_be_bytes());
+        } else {
+            head.push(mask_bit | 127);
+            head.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+
+        match mask {
+            Some(mask) => {
+                head.extend_from_slice(&mask);
+                let mut payload = self.payload.clone();
+                apply_mask(&mut payload, mask);
+                head.extend_from_slice(&payload);
+            }
+            None => head.extend_from_slice(&self.payload),
+        }
+        // 一次性写出整帧，避免多个线程并发写时帧被拆散
+        writer.write_all(&head)?;
+        writer.flush()
+    }
+}
+
+/// 负载超过限制的错误标记，用于区分应该以1009关闭连接的情况。
+#[derive(Debug)]
+pub struct TooLarge;
+
+impl std::fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "frame payload too large")
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+fn invalid(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+// 掩码处理：负载的第i个字节与掩码的第i % 4个字节异或，加掩码与去掩码是同一个操作
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+/// 生成掩码，掩码只用于防止中间代理的缓存污染，不需要密码学强度的随机数。
+pub fn mask_key() -> [u8; 4] {
+    static SEED: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+    // xorshift64*
+    let mut x = nanos ^ SEED.fetch_add(0x9E3779B97F4A7C15, Ordering::Relaxed) | 1;
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    let value = x.wrapping_mul(0x2545F4914F6CDD1D);
+    (value as u32).to_be_bytes()
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_masked_and_extended_length() {
+        for length in [0, 125, 126, 70_000] {
+            let frame = Frame::new(true, OpCode::Binary, vec![7; length]);
+            let mut bytes = Vec::new();
+            frame.write_to(&mut bytes, Some(mask_key())).unwrap();
+            let decoded = Frame::read_from(&mut &bytes[..], true, u64::MAX).unwrap();
+            assert_eq!(frame, decoded);
+        }
+    }
+
+    // RFC 6455 第5.7节中的示例
+    #[test]
+    fn decode_rfc_examples() {
+        let unmasked = [0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f];
+        let frame = Frame::read_from(&mut &unmasked[..], false, 125).unwrap();
+        assert_eq!(Frame::new(true, OpCode::Text, b"Hello".to_vec()), frame);
+
+        let masked = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let frame = Frame::read_from(&mut &masked[..], true, 125).unwrap();
+        assert_eq!(b"Hello".to_vec(), frame.payload);
+
+        assert!(Frame::read_from(&mut &masked[..], false, 125).is_err());
+        assert!(Frame::read_from(&mut &masked[..], true, 4).is_err());
+    }
+
+    #[test]
+    fn reject_fragmented_control_frame() {
+        let ping = [0x09, 0x00];
+        assert!(Frame::read_from(&mut &ping[..], false, 125).is_err());
+    }
+}
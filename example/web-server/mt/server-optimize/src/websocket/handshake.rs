@@ -0,0 +1,127 @@
+use crate::http::{Request, Response, StatusCode};
+
+// RFC 6455中规定的固定GUID，与客户端的Sec-WebSocket-Key拼接后计算SHA-1
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// 根据客户端的 `Sec-WebSocket-Key` 计算 `Sec-WebSocket-Accept`。
+pub fn accept_key(key: &str) -> String {
+    let mut input = String::with_capacity(key.len() + GUID.len());
+    input.push_str(key.trim());
+    input.push_str(GUID);
+    base64_encode(&sha1(input.as_bytes()))
+}
+
+/// 校验WebSocket握手请求，成功时返回101响应（尚未设置升级回调），失败时返回对应的错误响应。
+pub fn validate(request: &Request) -> Result<Response, Response> {
+    let has_token = |name: &str, token: &str| {
+        request
+            .headers
+            .get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    };
+
+    if request.method != "GET" {
+        return Err(Response::from(StatusCode::METHOD_NOT_ALLOWED).with_header("Allow", "GET"));
+    }
+    if !has_token("Upgrade", "websocket") || !has_token("Connection", "upgrade") {
+        return Err(Response::text(StatusCode::BAD_REQUEST, "expected websocket upgrade"));
+    }
+    if request.header("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+        return Err(Response::from(StatusCode(426)).with_header("Sec-WebSocket-Version", "13"));
+    }
+    // Sec-WebSocket-Key是16字节随机数的base64编码，长度固定为24
+    let key = match request.header("Sec-WebSocket-Key").map(str::trim) {
+        Some(key) if key.len() == 24 && key.ends_with("==") => key,
+        _ => return Err(Response::text(StatusCode::BAD_REQUEST, "invalid Sec-WebSocket-Key")),
+    };
+
+    Ok(Response::new(StatusCode(101))
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", accept_key(key)))
+}
+
+pub(crate) fn base64_encode(input: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        output.push(TABLE[(n >> 18) as usize & 63] as char);
+        output.push(TABLE[(n >> 12) as usize & 63] as char);
+        output.push(if chunk.len() > 1 { TABLE[(n >> 6) as usize & 63] as char } else { '=' });
+        output.push(if chunk.len() > 2 { TABLE[n as usize & 63] as char } else { '=' });
+    }
+    output
+}
+
+// SHA-1摘要（RFC 3174），握手只需要这一处，所以不引入额外的依赖
+fn sha1(input: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    // 填充：追加0x80，再补0直到长度模64余56，最后追加64位大端的原始比特长度
+    let mut message = input.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((input.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (i, word) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6455 第1.3节中的示例
+    #[test]
+    fn compute_accept_key() {
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept_key("dGhlIHNhbXBsZSBub25jZQ=="));
+    }
+
+    #[test]
+    fn encode_base64_with_padding() {
+        assert_eq!("", base64_encode(b""));
+        assert_eq!("Zg==", base64_encode(b"f"));
+        assert_eq!("Zm8=", base64_encode(b"fo"));
+        assert_eq!("Zm9vYmFy", base64_encode(b"foobar"));
+    }
+}
@@ -0,0 +1,77 @@
+use std::fs;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use server_optimize::http::{Body, Request, Response, StatusCode};
+use server_optimize::limiter::Limiter;
+use server_optimize::logfile::RotatingFile;
+use server_optimize::middleware::{AccessLog, Chain, Handler, LogFormat};
+use server_optimize::server::Options;
+
+mod common;
+
+// 在写出过程中逐渐产生的响应体，读取完成之前客户端已经收到了头部
+struct Slow {
+    remaining: usize,
+}
+
+impl Read for Slow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        thread::sleep(Duration::from_millis(10));
+        let n = buf.len().min(self.remaining).min(10000);
+        buf[..n].fill(b'x');
+        self.remaining -= n;
+        Ok(n)
+    }
+}
+
+fn app(path: &Path) -> Arc<dyn Handler> {
+    let file = RotatingFile::open(path, 1 << 20, 1).unwrap();
+    let handler = |_request: Request| {
+        Response::new(StatusCode::OK).with_body(Body::Stream(Box::new(Slow { remaining: 50000 })))
+    };
+    Arc::new(Chain::new(handler).with(AccessLog::new(LogFormat::Common, file)))
+}
+
+fn log_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("server-optimize-access-{}-{}.log", name, std::process::id()))
+}
+
+#[test]
+fn logs_bytes_and_duration_after_body_is_written() {
+    let path = log_path("blocking");
+    logs_after_body_with(common::serve_blocking(2, app(&path), Options::default(), Limiter::unlimited()), &path);
+    fs::remove_file(path).unwrap();
+
+    let path = log_path("event");
+    logs_after_body_with(common::serve_event(2, app(&path), |event_loop| event_loop.threads(1)), &path);
+    fs::remove_file(path).unwrap();
+}
+
+fn logs_after_body_with(addr: SocketAddr, path: &Path) {
+    let reply = common::get(addr, "/stream");
+    assert_eq!(reply.status, 200);
+    assert!(reply.is_chunked());
+    assert_eq!(reply.body.len(), 50000);
+
+    // 日志行在响应体写完之后写入：字节数是chunked响应体的实际长度，耗时包括逐段读取的时间
+    let deadline = Instant::now() + common::READ_TIMEOUT;
+    let line = loop {
+        let content = fs::read_to_string(path).unwrap();
+        if let Some(line) = content.lines().next() {
+            break line.to_string();
+        }
+        assert!(Instant::now() < deadline, "access log was not written");
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert!(line.contains("\"GET /stream HTTP/1.1\" 200 50000 "), "{}", line);
+    let micros: u128 = line.rsplit(' ').next().unwrap().parse().unwrap();
+    assert!(micros >= 50000, "{}", line);
+}
@@ -0,0 +1,186 @@
+use std::fs;
+use std::io::{self, Read};
+use std::mem;
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use server_optimize::cgi::Cgi;
+use server_optimize::http::{Body, Request};
+use server_optimize::middleware::Handler;
+use server_optimize::limiter::Limiter;
+use server_optimize::router::Router;
+use server_optimize::server::Options;
+
+mod common;
+
+use common::{get, send};
+
+// 把脚本写入临时目录并加上可执行权限
+fn script(name: &str, source: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("server-optimize-cgi-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    fs::write(&path, format!("#!/bin/sh\n{}", source)).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+fn start(router: Router) -> SocketAddr {
+    common::start(router)
+}
+
+#[test]
+fn pass_environment_and_stream_body() {
+    let env = script(
+        "env.sh",
+        "printf 'Content-Type: text/plain\\r\\nX-Script: env\\r\\n\\r\\n'\n\
+         echo \"$REQUEST_METHOD $SCRIPT_NAME $PATH_INFO $QUERY_STRING\"\n\
+         echo \"$CONTENT_LENGTH $CONTENT_TYPE $HTTP_X_TOKEN $GATEWAY_INTERFACE\"\n\
+         cat\n",
+    );
+    let addr = start(Router::new().route("*", "/cgi/env/*", Cgi::new(env).script_name("/cgi/env")));
+
+    let reply = send(
+        addr,
+        "POST /cgi/env/a/b?x=1&y=2 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+         Content-Type: text/plain\r\nX-Token: secret\r\nContent-Length: 11\r\n\r\nhello world",
+    );
+    assert_eq!(reply.status, 200);
+    assert_eq!(reply.header("X-Script"), Some("env"), "{:?}", reply.headers);
+    assert!(reply.is_chunked(), "{:?}", reply.headers);
+    assert!(reply.complete);
+    let body = reply.text();
+    assert!(body.contains("POST /cgi/env /a/b x=1&y=2\n"), "{}", body);
+    assert!(body.contains("11 text/plain secret CGI/1.1\n"), "{}", body);
+    assert!(body.contains("hello world"), "{}", body);
+}
+
+#[test]
+fn status_and_location_headers() {
+    let missing = script("missing.sh", "printf 'Status: 404 Not Found\\nContent-Type: text/plain\\n\\ngone'\n");
+    let redirect = script("redirect.sh", "printf 'Location: /elsewhere\\n\\n'\n");
+    let broken = script("broken.sh", "echo 'no header block here'\n");
+    let failing = script("failing.sh", "exit 1\n");
+    let addr = start(
+        Router::new()
+            .get("/missing", Cgi::new(missing))
+            .get("/redirect", Cgi::new(redirect))
+            .get("/broken", Cgi::new(broken))
+            .get("/failing", Cgi::new(failing))
+            .get("/absent", Cgi::new("/nonexistent/script.cgi")),
+    );
+
+    let reply = get(addr, "/missing");
+    assert_eq!(reply.status, 404);
+    assert_eq!(reply.text(), "gone");
+
+    let reply = get(addr, "/redirect");
+    assert_eq!(reply.status, 302);
+    assert_eq!(reply.header("Location"), Some("/elsewhere"), "{:?}", reply.headers);
+
+    assert_eq!(get(addr, "/broken").status, 500);
+    assert_eq!(get(addr, "/failing").status, 500);
+    assert_eq!(get(addr, "/absent").status, 500);
+}
+
+#[test]
+fn kill_runaway_scripts() {
+    let sleepy = script("sleepy.sh", "sleep 30\n");
+    let slow_body = script("slow-body.sh", "printf 'Content-Type: text/plain\\n\\nstarted\\n'\nsleep 30\n");
+    let chatty = script("chatty.sh", "printf 'Content-Type: text/plain\\n\\n'\nwhile :; do echo 0123456789abcdef; done\n");
+    let timeout = Duration::from_millis(300);
+    let addr = start(
+        Router::new()
+            .get("/sleepy", Cgi::new(sleepy).timeout(timeout))
+            .get("/slow-body", Cgi::new(slow_body).timeout(timeout))
+            .get("/chatty", Cgi::new(chatty).max_output(4096)),
+    );
+
+    // 头部之前超时返回504
+    let started = Instant::now();
+    assert_eq!(get(addr, "/sleepy").status, 504);
+    assert!(started.elapsed() < Duration::from_secs(10), "{:?}", started.elapsed());
+
+    // 响应体输出期间超时：已经发出的部分保留，但没有结束块，客户端能发现响应不完整
+    let started = Instant::now();
+    let reply = get(addr, "/slow-body");
+    assert_eq!(reply.status, 200);
+    assert_eq!(reply.text(), "started\n");
+    assert!(!reply.complete);
+    assert!(started.elapsed() < Duration::from_secs(10), "{:?}", started.elapsed());
+
+    // 输出超过上限
+    let reply = get(addr, "/chatty");
+    assert_eq!(reply.status, 200);
+    assert!(reply.body.len() < 64 * 1024, "{}", reply.body.len());
+    assert!(!reply.complete);
+}
+
+#[test]
+fn finish_output_after_script_exits() {
+    // 脚本很快写完输出并退出，但响应体在超时之后才被读取（相当于客户端读得慢）：不能被当作超时而中断
+    let quick = script("quick.sh", "printf 'Content-Type: text/plain\\n\\ndone\\n'\n");
+    let quick = Cgi::new(quick).timeout(Duration::from_millis(200));
+    let delayed = move |request: Request| {
+        let mut response = quick.handle(request);
+        if let Body::Stream(output) = mem::replace(&mut response.body, Body::Empty) {
+            response.body = Body::Stream(Box::new(Delayed { delay: Some(Duration::from_millis(600)), inner: output }));
+        }
+        response
+    };
+    let addr = start(Router::new().get("/quick", delayed));
+
+    let reply = get(addr, "/quick");
+    assert_eq!(reply.status, 200);
+    assert!(reply.complete);
+    assert_eq!(reply.text(), "done\n");
+}
+
+// 第一次读取之前先等待一段时间的响应体
+struct Delayed {
+    delay: Option<Duration>,
+    inner: Box<dyn Read + Send>,
+}
+
+impl Read for Delayed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(delay) = self.delay.take() {
+            thread::sleep(delay);
+        }
+        self.inner.read(buf)
+    }
+}
+
+#[test]
+fn stream_request_body_to_stdin() {
+    let count = script("count.sh", "printf 'Content-Type: text/plain\\n\\n'\necho \"$CONTENT_LENGTH\"\nwc -c\n");
+    let mut options = Options::default();
+    options.limits.max_body_bytes = 1024;
+    let router = Router::new().post("/count", Cgi::new(count));
+    let addr = common::serve_blocking(2, Arc::new(router), options, Limiter::unlimited());
+
+    // 超过max_body_bytes的请求体直接从连接复制到脚本的标准输入
+    let mut request = b"POST /count HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 262144\r\n\r\n".to_vec();
+    request.resize(request.len() + 256 * 1024, b'x');
+    let reply = send(addr, &request);
+    assert_eq!(reply.status, 200);
+    let lines: Vec<&str> = reply.body.split(|b| *b == b'\n').map(|line| std::str::from_utf8(line).unwrap().trim()).collect();
+    assert_eq!(lines[..2], ["262144", "262144"]);
+
+    // chunked请求体需要先读入内存才能得到CONTENT_LENGTH
+    let reply = send(
+        addr,
+        "POST /count HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+    );
+    assert_eq!(reply.status, 200);
+    assert_eq!(reply.text().split_whitespace().collect::<Vec<_>>(), ["5", "5"]);
+    let chunked = format!(
+        "POST /count HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n800\r\n{}\r\n0\r\n\r\n",
+        "x".repeat(2048)
+    );
+    assert_eq!(send(addr, chunked).status, 413);
+}
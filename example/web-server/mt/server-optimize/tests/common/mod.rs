@@ -0,0 +1,194 @@
+// 集成测试共用的服务端启动方法与HTTP响应解析，每个测试文件只用到其中一部分
+#![allow(dead_code)]
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use server_optimize::limiter::Limiter;
+use server_optimize::middleware::Handler;
+use server_optimize::reactor::EventLoop;
+use server_optimize::server::{self, Options};
+use server_optimize::ThreadPool;
+
+// 客户端等待响应的最长时间
+pub const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 在随机端口上以阻塞模式启动服务端：4个Worker，默认选项，不限流。
+pub fn start<H: Handler + 'static>(handler: H) -> SocketAddr {
+    serve_blocking(4, Arc::new(handler), Options::default(), Limiter::unlimited())
+}
+
+pub fn serve_blocking(workers: usize, handler: Arc<dyn Handler>, options: Options, limiter: Limiter) -> SocketAddr {
+    let (listener, addr) = bind();
+    thread::spawn(move || {
+        let pool = ThreadPool::new(workers);
+        server::serve(listener, &pool, handler, options, &limiter);
+    });
+    addr
+}
+
+/// 在随机端口上以事件驱动模式启动服务端，`configure` 用来设置Reactor线程数、限流等。
+pub fn serve_event<F>(workers: usize, handler: Arc<dyn Handler>, configure: F) -> SocketAddr
+where
+    F: FnOnce(EventLoop) -> EventLoop + Send + 'static,
+{
+    let (listener, addr) = bind();
+    thread::spawn(move || {
+        let pool = ThreadPool::new(workers);
+        configure(EventLoop::new(listener)).run(&pool, handler).unwrap();
+    });
+    addr
+}
+
+pub fn bind() -> (TcpListener, SocketAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    (listener, addr)
+}
+
+pub fn connect(addr: SocketAddr) -> BufReader<TcpStream> {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+    BufReader::new(stream)
+}
+
+/// 在新连接上发送原始请求，读取一个响应（没有长度信息时读到连接关闭为止）。
+pub fn send(addr: SocketAddr, request: impl AsRef<[u8]>) -> Reply {
+    let mut connection = connect(addr);
+    connection.get_mut().write_all(request.as_ref()).unwrap();
+    Reply::read(&mut connection)
+}
+
+pub fn get(addr: SocketAddr, path: &str) -> Reply {
+    send(addr, format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path))
+}
+
+/*
+    从连接中解析出的响应
+
+    响应体按照Content-Length或者chunked编码读取并解码；连接提前关闭（或者被重置）时保留已经读到的部分，
+    `complete` 为false，用于验证被中断的响应
+ */
+#[derive(Debug)]
+pub struct Reply {
+    pub version: String,
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub complete: bool,
+}
+
+impl Reply {
+    pub fn read<R: BufRead>(reader: &mut R) -> Reply {
+        Reply::read_response(reader, false)
+    }
+
+    /// 读取HEAD请求的响应：只有头部，没有响应体。
+    pub fn read_head<R: BufRead>(reader: &mut R) -> Reply {
+        Reply::read_response(reader, true)
+    }
+
+    /// 解析一段完整的原始响应。
+    pub fn parse(bytes: &[u8]) -> Reply {
+        Reply::read(&mut &bytes[..])
+    }
+
+    fn read_response<R: BufRead>(reader: &mut R, head_only: bool) -> Reply {
+        let status_line = read_line(reader).unwrap().expect("connection closed before the status line");
+        let mut parts = status_line.splitn(3, ' ');
+        let version = parts.next().unwrap().to_string();
+        assert!(version.starts_with("HTTP/1."), "invalid status line: {:?}", status_line);
+        let status = parts
+            .next()
+            .filter(|code| code.len() == 3)
+            .and_then(|code| code.parse().ok())
+            .unwrap_or_else(|| panic!("invalid status line: {:?}", status_line));
+        let reason = parts.next().unwrap_or_default().to_string();
+
+        let mut headers = Vec::new();
+        loop {
+            let line = read_line(reader).unwrap().expect("connection closed inside the headers");
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').unwrap_or_else(|| panic!("invalid header line: {:?}", line));
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        let mut reply = Reply { version, status, reason, headers, body: Vec::new(), complete: true };
+        if head_only || matches!(status, 100..=199 | 204 | 304) {
+            return reply;
+        }
+        reply.complete = if reply.is_chunked() {
+            read_chunked(reader, &mut reply.body)
+        } else if let Some(length) = reply.header("Content-Length") {
+            let length: u64 = length.parse().unwrap();
+            let _ = reader.by_ref().take(length).read_to_end(&mut reply.body);
+            reply.body.len() as u64 == length
+        } else {
+            // 被重置的连接同样视为结束
+            let _ = reader.read_to_end(&mut reply.body);
+            true
+        };
+        reply
+    }
+
+    /// 第一个同名头部的值，名称不区分大小写。
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    pub fn has_header(&self, name: &str, value: &str) -> bool {
+        self.headers.iter().any(|(key, v)| key.eq_ignore_ascii_case(name) && v == value)
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        self.header("Transfer-Encoding").is_some_and(|value| value.to_ascii_lowercase().contains("chunked"))
+    }
+
+    /// 以UTF-8解码响应体，无效的字节替换为U+FFFD。
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+// 读取一行并去掉CRLF，行首就遇到连接关闭时返回None
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+// 解码chunked响应体，返回是否读到了结束块以及trailer之后的空行
+fn read_chunked<R: BufRead>(reader: &mut R, body: &mut Vec<u8>) -> bool {
+    loop {
+        let size = match read_line(reader) {
+            Ok(Some(line)) => line,
+            _ => return false,
+        };
+        let size = usize::from_str_radix(size.split(';').next().unwrap().trim(), 16).unwrap();
+        if size == 0 {
+            loop {
+                match read_line(reader) {
+                    Ok(Some(line)) if line.is_empty() => return true,
+                    Ok(Some(_)) => continue,
+                    _ => return false,
+                }
+            }
+        }
+        let start = body.len();
+        let _ = reader.by_ref().take(size as u64).read_to_end(body);
+        if body.len() - start < size || !matches!(read_line(reader), Ok(Some(line)) if line.is_empty()) {
+            return false;
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use server_optimize::form::Form;
+use server_optimize::http::{Request, Response, StatusCode};
+use server_optimize::limiter::Limiter;
+use server_optimize::router::Router;
+use server_optimize::server::Options;
+
+mod common;
+
+use common::Reply;
+
+// 回显表单中的字段与文件：每行一个 name=value 或者 name:filename:size:content
+fn echo(mut request: Request) -> Response {
+    let form = match Form::parse(&mut request) {
+        Ok(form) => form,
+        Err(err) => return err.response(),
+    };
+    let mut body = String::new();
+    for (name, value) in form.fields() {
+        body.push_str(&format!("{}={}\n", name, value));
+    }
+    for file in form.files() {
+        let mut content = String::new();
+        file.open().unwrap().read_to_string(&mut content).unwrap();
+        body.push_str(&format!("{}:{}:{}:{}\n", file.name(), file.filename().unwrap_or("-"), file.size(), content));
+    }
+    Response::text(StatusCode::OK, body)
+}
+
+fn start() -> SocketAddr {
+    start_with(Options::default())
+}
+
+fn start_with(options: Options) -> SocketAddr {
+    common::serve_blocking(2, Arc::new(Router::new().post("/form", echo)), options, Limiter::unlimited())
+}
+
+fn post(addr: SocketAddr, content_type: &str, body: &str) -> Reply {
+    common::send(
+        addr,
+        format!(
+            "POST /form HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+            content_type,
+            body.len(),
+            body
+        ),
+    )
+}
+
+#[test]
+fn urlencoded_form() {
+    let addr = start();
+    let reply = post(addr, "application/x-www-form-urlencoded", "name=a+b&city=%E5%8C%97%E4%BA%AC");
+    assert_eq!(reply.status, 200);
+    assert_eq!(reply.text(), "name=a b\ncity=北京\n");
+}
+
+#[test]
+fn multipart_upload() {
+    let addr = start();
+    let body = "--boundary42\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        report\r\n\
+        --boundary42\r\n\
+        Content-Disposition: form-data; name=\"doc\"; filename=\"../../etc/passwd\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        file content\r\n\
+        --boundary42--\r\n";
+    let reply = post(addr, "multipart/form-data; boundary=boundary42", body);
+    assert_eq!(reply.status, 200);
+    assert_eq!(reply.text(), "title=report\ndoc:passwd:12:file content\n");
+}
+
+#[test]
+fn reject_bad_forms() {
+    let addr = start();
+    assert_eq!(post(addr, "application/json", "{}").status, 415);
+    assert_eq!(post(addr, "multipart/form-data", "").status, 400);
+    assert_eq!(post(addr, "multipart/form-data; boundary=x", "--x\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nno end").status, 400);
+}
+
+#[test]
+fn stream_uploads_larger_than_body_limit() {
+    // 请求体上限只有1KiB：multipart请求体直接写入临时文件，不受它的限制
+    let mut options = Options::default();
+    options.limits.max_body_bytes = 1024;
+    let addr = start_with(options);
+
+    let content = "0123456789abcdef".repeat(16 * 1024);
+    let body = format!(
+        "--b\r\nContent-Disposition: form-data; name=\"big\"; filename=\"big.txt\"\r\n\r\n{}\r\n--b--\r\n",
+        content
+    );
+    let reply = post(addr, "multipart/form-data; boundary=b", &body);
+    assert_eq!(reply.status, 200);
+    assert_eq!(reply.text(), format!("big:big.txt:{}:{}\n", content.len(), content));
+
+    // 需要整个读入内存的urlencoded请求体仍然受上限限制
+    let field = format!("a={}", "x".repeat(2048));
+    assert_eq!(post(addr, "application/x-www-form-urlencoded", &field).status, 413);
+}
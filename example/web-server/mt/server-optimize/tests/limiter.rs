@@ -0,0 +1,110 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use server_optimize::http::{Request, Response, StatusCode};
+use server_optimize::limiter::{Limiter, Policy, Rate};
+use server_optimize::middleware::{Chain, Handler};
+use server_optimize::server::Options;
+
+mod common;
+
+use common::Reply;
+
+fn hello(_: Request) -> Response {
+    Response::text(StatusCode::OK, "hello")
+}
+
+fn start_blocking(workers: usize, handler: Arc<dyn Handler>, limiter: Limiter) -> SocketAddr {
+    common::serve_blocking(workers, handler, Options::default(), limiter)
+}
+
+fn get(addr: SocketAddr) -> Reply {
+    common::get(addr, "/")
+}
+
+// 被拒绝的连接不需要发送请求，直接读取服务端写出的响应
+fn rejected(addr: SocketAddr) -> Reply {
+    Reply::read(&mut common::connect(addr))
+}
+
+#[test]
+fn per_ip_connection_cap() {
+    let limiter = Limiter::new(Policy { max_connections_per_ip: Some(1), ..Policy::default() });
+    let addr = start_blocking(2, Arc::new(hello), limiter.clone());
+
+    // 第一个连接保持打开：先完成一个keep-alive请求，确保它已经被接受
+    let mut first = TcpStream::connect(addr).unwrap();
+    first.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut buffer = [0; 1024];
+    assert!(first.read(&mut buffer).unwrap() > 0);
+
+    let reply = rejected(addr);
+    assert_eq!(reply.status, 429);
+    assert_eq!(reply.header("Retry-After"), Some("1"), "{:?}", reply.headers);
+
+    // 第一个连接关闭后名额归还
+    drop(first);
+    let mut status = 0;
+    for _ in 0..50 {
+        status = get(addr).status;
+        if status == 200 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(status, 200);
+    assert!(limiter.stats().rejected_per_ip >= 1);
+}
+
+#[test]
+fn full_queue_returns_service_unavailable() {
+    // 唯一的Worker被第一个请求占住，第二个连接在队列中等待，第三个连接超出队列上限
+    let (started, wait_started) = mpsc::channel::<()>();
+    let (release, wait_release) = mpsc::channel::<()>();
+    let (started, wait_release) = (Mutex::new(started), Mutex::new(wait_release));
+    let handler = move |_: Request| {
+        started.lock().unwrap().send(()).unwrap();
+        let _ = wait_release.lock().unwrap().recv_timeout(Duration::from_secs(10));
+        Response::text(StatusCode::OK, "hello")
+    };
+    let limiter = Limiter::new(Policy { max_queue: Some(1), ..Policy::default() });
+    let addr = start_blocking(1, Arc::new(handler), limiter.clone());
+
+    let busy = thread::spawn(move || get(addr).status);
+    wait_started.recv_timeout(Duration::from_secs(10)).unwrap();
+    let queued = thread::spawn(move || get(addr).status);
+    thread::sleep(Duration::from_millis(200));
+
+    let reply = rejected(addr);
+    assert_eq!(reply.status, 503);
+    assert_eq!(reply.header("Retry-After"), Some("1"), "{:?}", reply.headers);
+    assert_eq!(limiter.stats().rejected_queue_full, 1);
+
+    // 队列中的连接在Worker空闲后正常得到处理
+    release.send(()).unwrap();
+    wait_started.recv_timeout(Duration::from_secs(10)).unwrap();
+    release.send(()).unwrap();
+    assert_eq!(busy.join().unwrap(), 200);
+    assert_eq!(queued.join().unwrap(), 200);
+}
+
+#[test]
+fn rate_limit_requests_in_event_mode() {
+    let limiter = Limiter::new(Policy { rate: Some(Rate { per_second: 1, burst: 2 }), ..Policy::default() });
+    let handler: Arc<dyn Handler> = Arc::new(Chain::new(hello).with(limiter.clone()));
+    let event_limiter = limiter.clone();
+    let addr = common::serve_event(2, handler, move |event_loop| event_loop.threads(1).limiter(event_limiter));
+
+    assert_eq!(get(addr).status, 200);
+    assert_eq!(get(addr).status, 200);
+    let reply = get(addr);
+    assert_eq!(reply.status, 429);
+    assert_eq!(reply.header("Retry-After"), Some("1"), "{:?}", reply.headers);
+
+    let stats = limiter.stats();
+    assert_eq!(stats.rate_limited, 1);
+    assert_eq!(stats.accepted, 3);
+}
@@ -0,0 +1,105 @@
+use std::io::{BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use server_optimize::http::{Request, Response, StatusCode};
+use server_optimize::limiter::Limiter;
+use server_optimize::middleware::Handler;
+use server_optimize::server::Options;
+
+mod common;
+
+use common::{connect, Reply};
+
+#[derive(Clone, Copy, Debug)]
+enum Mode {
+    Blocking,
+    Event,
+}
+
+fn app(_: Request) -> Response {
+    Response::text(StatusCode::OK, "Hi from Rust")
+}
+
+// 在随机端口上启动服务端，线程池固定为4个Worker
+fn start(mode: Mode) -> SocketAddr {
+    let handler: Arc<dyn Handler> = Arc::new(app);
+    match mode {
+        Mode::Blocking => common::serve_blocking(4, handler, Options::default(), Limiter::unlimited()),
+        Mode::Event => common::serve_event(4, handler, |event_loop| event_loop.threads(2)),
+    }
+}
+
+// 在keep-alive连接上发送一个请求并读取完整响应
+fn request(reader: &mut BufReader<TcpStream>) -> Reply {
+    reader.get_mut().write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    Reply::read(reader)
+}
+
+// 大量空闲连接以及只发送了一半请求的慢客户端不应该影响新的请求
+#[test]
+fn event_mode_serves_while_holding_idle_connections() {
+    let addr = start(Mode::Event);
+
+    let mut idle: Vec<TcpStream> = (0..1000).map(|_| TcpStream::connect(addr).unwrap()).collect();
+    for stream in idle.iter_mut().take(4) {
+        stream.write_all(b"GET /sleep HTTP/1.1\r\nHost: local").unwrap();
+    }
+
+    let start = Instant::now();
+    let mut reader = connect(addr);
+    for _ in 0..10 {
+        assert_eq!(200, request(&mut reader).status);
+    }
+    assert!(start.elapsed() < Duration::from_secs(2), "took {:?}", start.elapsed());
+}
+
+// 对比两种模式：先占用8个空闲keep-alive连接，再用8个客户端并发请求2秒
+// 运行方式：cargo test --release --test load -- --ignored --nocapture
+#[test]
+#[ignore]
+fn compare_blocking_and_event_mode() {
+    for mode in [Mode::Blocking, Mode::Event] {
+        let addr = start(mode);
+        let idle: Vec<BufReader<TcpStream>> = (0..8)
+            .map(|_| {
+                let mut reader = connect(addr);
+                request(&mut reader);
+                reader
+            })
+            .collect();
+
+        let duration = Duration::from_secs(2);
+        let clients: Vec<_> = (0..8)
+            .map(|_| {
+                thread::spawn(move || {
+                    let start = Instant::now();
+                    let mut reader = connect(addr);
+                    let mut latencies = Vec::new();
+                    while start.elapsed() < duration {
+                        let begin = Instant::now();
+                        request(&mut reader);
+                        latencies.push(begin.elapsed());
+                    }
+                    latencies
+                })
+            })
+            .collect();
+        let mut latencies: Vec<Duration> = clients.into_iter().flat_map(|client| client.join().unwrap()).collect();
+        latencies.sort();
+        drop(idle);
+
+        let p99 = latencies.get(latencies.len() * 99 / 100).copied().unwrap_or_default();
+        println!(
+            "{:?}: {} requests in {:?}, {:.0} req/s, max latency {:?}, p99 {:?}",
+            mode,
+            latencies.len(),
+            duration,
+            latencies.len() as f64 / duration.as_secs_f64(),
+            latencies.last().copied().unwrap_or_default(),
+            p99
+        );
+    }
+}
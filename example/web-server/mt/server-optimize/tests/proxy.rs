@@ -0,0 +1,253 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use server_optimize::proxy::Proxy;
+use server_optimize::router::Router;
+
+mod common;
+
+use common::{get, send};
+
+/*
+    上游替身：在临时端口上监听，每个连接读取完整的请求后交给respond处理
+ */
+fn upstream<F>(respond: F) -> SocketAddr
+where
+    F: Fn(String, Vec<u8>, &mut TcpStream) + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let respond = Arc::new(respond);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let respond = Arc::clone(&respond);
+            thread::spawn(move || {
+                let (head, body) = read_request(&mut stream);
+                respond(head, body, &mut stream);
+            });
+        }
+    });
+    addr
+}
+
+// 读取请求头部以及按照Content-Length或者chunked编码的请求体
+fn read_request(stream: &mut TcpStream) -> (String, Vec<u8>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut head = String::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" || line.is_empty() {
+            break;
+        }
+        head.push_str(&line);
+    }
+    if head.contains("Transfer-Encoding: chunked\r\n") {
+        let mut body = Vec::new();
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).unwrap();
+            let size = usize::from_str_radix(size.trim(), 16).unwrap();
+            let mut chunk = vec![0; size + 2];
+            reader.read_exact(&mut chunk).unwrap();
+            if size == 0 {
+                return (head, body);
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    }
+    let length = head
+        .lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+        .map(|value| value.trim().parse().unwrap())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    (head, body)
+}
+
+// 返回固定文本的上游
+fn named(name: &'static str) -> SocketAddr {
+    upstream(move |_, _, stream| {
+        let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", name.len(), name);
+        stream.write_all(response.as_bytes()).unwrap();
+    })
+}
+
+// 一个已经关闭的端口，连接会被拒绝
+fn closed_port() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+fn start(proxy: Proxy) -> SocketAddr {
+    common::start(Router::new().route("*", "/api/*", proxy))
+}
+
+fn start_event(proxy: Proxy) -> SocketAddr {
+    common::serve_event(4, Arc::new(Router::new().route("*", "/api/*", proxy)), |event_loop| event_loop.threads(1))
+}
+
+#[test]
+fn forward_request_and_rewrite_headers() {
+    // 上游把收到的请求头部与请求体原样作为响应体返回
+    let echo = upstream(|head, body, stream| {
+        let body = format!("{}\n{}", head, String::from_utf8(body).unwrap());
+        let response = format!(
+            "HTTP/1.1 201 Created\r\nContent-Length: {}\r\nConnection: close, X-Upstream-Secret\r\n\
+             X-Upstream-Secret: 1\r\nKeep-Alive: timeout=5\r\nX-Backend: echo\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).unwrap();
+    });
+    let addr = start(Proxy::new(&[echo]).strip_prefix("/api"));
+
+    let reply = send(
+        addr,
+        "POST /api/users?page=2 HTTP/1.1\r\nHost: example.com\r\nX-Forwarded-For: 10.0.0.1\r\n\
+         Connection: close, X-Client-Secret\r\nX-Client-Secret: 1\r\nKeep-Alive: timeout=5\r\n\
+         Content-Length: 5\r\n\r\nhello",
+    );
+    assert_eq!(reply.status, 201);
+    assert_eq!(reply.header("X-Backend"), Some("echo"), "{:?}", reply.headers);
+    assert_eq!(reply.header("X-Upstream-Secret"), None, "{:?}", reply.headers);
+    assert_eq!(reply.header("Keep-Alive"), None, "{:?}", reply.headers);
+
+    let body = reply.text();
+    let (upstream_head, upstream_body) = body.rsplit_once('\n').unwrap();
+    assert!(upstream_head.starts_with("POST /users?page=2 HTTP/1.1\r\n"), "{}", upstream_head);
+    assert!(upstream_head.contains("Host: example.com\r\n"), "{}", upstream_head);
+    assert!(upstream_head.contains("X-Forwarded-For: 10.0.0.1, 127.0.0.1\r\n"), "{}", upstream_head);
+    assert!(upstream_head.contains("X-Forwarded-Host: example.com\r\n"), "{}", upstream_head);
+    assert!(upstream_head.contains("Connection: close\r\n"), "{}", upstream_head);
+    assert!(!upstream_head.contains("X-Client-Secret"), "{}", upstream_head);
+    assert!(!upstream_head.contains("Keep-Alive"), "{}", upstream_head);
+    assert_eq!(upstream_body, "hello");
+}
+
+#[test]
+fn round_robin_across_upstreams() {
+    let addr = start(Proxy::new(&[named("a"), named("b"), named("c")]));
+    let mut bodies: Vec<String> = (0..6).map(|_| get(addr, "/api/").text()).collect();
+    // 连续的3个请求依次落在3个上游上
+    assert_ne!(bodies[0], bodies[1]);
+    assert_ne!(bodies[1], bodies[2]);
+    assert_eq!(bodies[0..3], bodies[3..6]);
+    bodies.sort();
+    assert_eq!(bodies, ["a", "a", "b", "b", "c", "c"]);
+}
+
+#[test]
+fn passive_health_check_skips_failed_upstreams() {
+    // 连接被拒绝时换下一个上游重试，客户端感知不到失败
+    let addr = start(Proxy::new(&[closed_port(), named("ok")]));
+    for _ in 0..4 {
+        assert_eq!(get(addr, "/api/").text(), "ok");
+    }
+
+    // 接受连接后立即关闭的上游：请求已经发出，不重试，返回502，随后该上游被摘除
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&accepted);
+    let broken = upstream(move |_, _, _| {
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+    let addr = start(Proxy::new(&[broken, named("ok")]).fail_timeout(Duration::from_secs(60)));
+    let statuses: Vec<u16> = (0..6).map(|_| get(addr, "/api/").status).collect();
+    assert_eq!(statuses.iter().filter(|status| **status == 502).count(), 1, "{:?}", statuses);
+    assert_eq!(statuses.iter().filter(|status| **status == 200).count(), 5, "{:?}", statuses);
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn bad_gateway_and_gateway_timeout() {
+    let addr = start(Proxy::new(&[closed_port(), closed_port()]));
+    assert_eq!(get(addr, "/api/").status, 502);
+
+    // 接受连接但一直不响应的上游
+    let (hold, release) = mpsc::channel::<()>();
+    let release = Arc::new(std::sync::Mutex::new(release));
+    let silent = upstream(move |_, _, _| {
+        let _ = release.lock().unwrap().recv_timeout(Duration::from_secs(5));
+    });
+    let addr = start(Proxy::new(&[silent]).timeout(Duration::from_millis(200)));
+    assert_eq!(get(addr, "/api/").status, 504);
+    drop(hold);
+}
+
+#[test]
+fn stream_response_body() {
+    // 阻塞模式与事件驱动模式都不能把响应体收集完整之后再写出
+    stream_response_body_with(start);
+    stream_response_body_with(start_event);
+}
+
+fn stream_response_body_with(start: fn(Proxy) -> SocketAddr) {
+    // 上游先发送第一个块，等客户端收到之后才发送剩余部分，证明响应体没有在代理中缓冲
+    let (received, wait) = mpsc::channel::<()>();
+    let wait = Arc::new(std::sync::Mutex::new(wait));
+    let slow = upstream(move |_, _, stream| {
+        stream.write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nfirst \r\n").unwrap();
+        stream.flush().unwrap();
+        wait.lock().unwrap().recv_timeout(Duration::from_secs(5)).unwrap();
+        stream.write_all(b"6\r\nsecond\r\n0\r\n\r\n").unwrap();
+    });
+    let addr = start(Proxy::new(&[slow]));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(b"GET /api/stream HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut received_first = String::new();
+    while !received_first.contains("first ") {
+        let mut line = String::new();
+        assert!(reader.read_line(&mut line).unwrap() > 0, "connection closed before the first chunk");
+        received_first.push_str(&line);
+    }
+    received.send(()).unwrap();
+
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert!(rest.contains("second"), "{}", rest);
+    assert!(rest.ends_with("0\r\n\r\n"), "{:?}", rest);
+}
+
+#[test]
+fn stream_request_body() {
+    // 上游返回收到的请求体长度与校验和，以及转发时使用的分帧方式
+    let counter = upstream(|head, body, stream| {
+        let framing = if head.contains("Transfer-Encoding: chunked\r\n") { "chunked" } else { "length" };
+        let sum: u64 = body.iter().map(|b| *b as u64).sum();
+        let body = format!("{} {} {}", framing, body.len(), sum);
+        let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+        stream.write_all(response.as_bytes()).unwrap();
+    });
+    let addr = start(Proxy::new(&[counter]));
+
+    // 请求体超过默认的max_body_bytes（1MiB），只有直接从客户端连接复制到上游才能转发成功
+    let payload: Vec<u8> = (0..3 * 1024 * 1024).map(|n| (n % 251) as u8).collect();
+    let sum: u64 = payload.iter().map(|b| *b as u64).sum();
+
+    let mut request = format!("PUT /api/blob HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n", payload.len())
+        .into_bytes();
+    request.extend_from_slice(&payload);
+    let reply = send(addr, &request);
+    assert_eq!(reply.status, 200);
+    assert_eq!(reply.text(), format!("length {} {}", payload.len(), sum));
+
+    // 长度未知的chunked请求体以chunked编码转发
+    let mut request = b"POST /api/blob HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+    for chunk in payload.chunks(64 * 1024) {
+        request.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+        request.extend_from_slice(chunk);
+        request.extend_from_slice(b"\r\n");
+    }
+    request.extend_from_slice(b"0\r\n\r\n");
+    let reply = send(addr, &request);
+    assert_eq!(reply.status, 200);
+    assert_eq!(reply.text(), format!("chunked {} {}", payload.len(), sum));
+}
@@ -0,0 +1,218 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+
+use common::Reply;
+
+/*
+    运行中的服务端进程，标准输出逐行转发到通道中，热升级后新进程的输出同样会出现在这里
+ */
+struct Server {
+    child: Child,
+    lines: Receiver<String>,
+    addr: String,
+    // 热升级后的新进程，测试结束时一并清理
+    upgraded: Option<u32>,
+}
+
+impl Server {
+    fn start(config: &Path) -> Server {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_main"))
+            .arg("--config")
+            .arg(config)
+            .args(["--listen", "127.0.0.1:0", "--access-log", "off"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let (sender, lines) = mpsc::channel();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                if sender.send(line.unwrap()).is_err() {
+                    return;
+                }
+            }
+        });
+        let mut server = Server { child, lines, addr: String::new(), upgraded: None };
+        let line = server.wait_for("listening: http on ");
+        server.addr = line.split_whitespace().next().unwrap().to_string();
+        server
+    }
+
+    // 等待以 `prefix` 开头的一行输出，返回去掉前缀之后的部分
+    fn wait_for(&self, prefix: &str) -> String {
+        let deadline = Instant::now() + Duration::from_secs(20);
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let line = self.lines.recv_timeout(timeout).unwrap_or_else(|_| panic!("no output line starting with {:?}", prefix));
+            if let Some(rest) = line.strip_prefix(prefix) {
+                return rest.to_string();
+            }
+        }
+    }
+
+    fn signal(&self, signal: libc::c_int) {
+        kill(self.child.id(), signal);
+    }
+
+    fn connect(&self) -> BufReader<TcpStream> {
+        let stream = TcpStream::connect(&self.addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        BufReader::new(stream)
+    }
+
+    fn get(&self, path: &str) -> String {
+        get(&mut self.connect(), path)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        if let Some(pid) = self.upgraded {
+            unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+        }
+    }
+}
+
+fn kill(pid: u32, signal: libc::c_int) {
+    assert_eq!(unsafe { libc::kill(pid as libc::pid_t, signal) }, 0);
+}
+
+// 在保持的连接上发送一个GET请求，返回响应体
+fn get(connection: &mut BufReader<TcpStream>, path: &str) -> String {
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+    connection.get_mut().write_all(request.as_bytes()).unwrap();
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        connection.read_line(&mut line).unwrap();
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+    }
+    let mut body = vec![0; length];
+    connection.read_exact(&mut body).unwrap();
+    String::from_utf8(body).unwrap()
+}
+
+// 创建包含两个文档根目录的临时站点，配置文件指向第一个
+fn site(name: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("server-optimize-restart-{}-{}", name, std::process::id()));
+    for (root, content) in [("a", "site a"), ("b", "site b")] {
+        fs::create_dir_all(dir.join(root)).unwrap();
+        fs::write(dir.join(root).join("index.html"), content).unwrap();
+        fs::write(dir.join(root).join("404.html"), "not found").unwrap();
+    }
+    let config = dir.join("server.toml");
+    fs::write(&config, "document_root = \"a\"\n").unwrap();
+    (dir, config)
+}
+
+#[test]
+fn reload_configuration() {
+    let (dir, config) = site("reload");
+    let server = Server::start(&config);
+    let mut connection = server.connect();
+    assert_eq!(get(&mut connection, "/"), "site a");
+
+    fs::write(&config, "document_root = \"b\"\nworkers = 2\n").unwrap();
+    server.signal(libc::SIGHUP);
+    server.wait_for("[Reload] configuration reloaded");
+    assert_eq!(server.wait_for("[Reload] changes to "), "workers take effect after a restart or upgrade");
+    // 已有的keep-alive连接不受影响，并且使用新的配置
+    assert_eq!(get(&mut connection, "/"), "site b");
+    assert_eq!(server.get("/"), "site b");
+
+    // 无效的配置不会替换当前配置
+    fs::write(&config, "document_root = \"missing\"\n").unwrap();
+    server.signal(libc::SIGHUP);
+    server.wait_for("[Reload] failed");
+    assert_eq!(get(&mut connection, "/"), "site b");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn upgrade_without_downtime() {
+    let (dir, config) = site("upgrade");
+    let mut server = Server::start(&config);
+    let mut connection = server.connect();
+    assert_eq!(get(&mut connection, "/"), "site a");
+
+    fs::write(&config, "document_root = \"b\"\n").unwrap();
+    server.signal(libc::SIGUSR2);
+    let pid: u32 = server.wait_for("[Upgrade] new process ").split_whitespace().next().unwrap().parse().unwrap();
+    server.upgraded = Some(pid);
+    // 新连接由新进程处理，旧进程中已经接受的连接继续由旧进程处理完毕
+    for _ in 0..10 {
+        assert_eq!(server.get("/"), "site b");
+    }
+    assert_eq!(get(&mut connection, "/"), "site a");
+    drop(connection);
+
+    // 连接关闭后旧进程排空线程池并退出
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while server.child.try_wait().unwrap().is_none() {
+        assert!(Instant::now() < deadline, "old process did not exit");
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(server.child.try_wait().unwrap().unwrap().success());
+    assert_eq!(server.get("/"), "site b");
+
+    kill(pid, libc::SIGTERM);
+    server.wait_for("[Shutdown] draining");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn close_busy_connections_on_upgrade() {
+    close_busy_connections_on_upgrade_with("blocking");
+    close_busy_connections_on_upgrade_with("event");
+}
+
+fn close_busy_connections_on_upgrade_with(mode: &str) {
+    let (dir, config) = site(&format!("busy-{}", mode));
+    // 空闲超时足够长，旧进程能够及时退出只能是因为连接被主动关闭
+    fs::write(&config, format!("document_root = \"a\"\nmode = \"{}\"\n[timeouts]\nkeep_alive = \"60s\"\n", mode)).unwrap();
+    let mut server = Server::start(&config);
+    let mut connection = server.connect();
+    assert_eq!(get(&mut connection, "/"), "site a");
+
+    server.signal(libc::SIGUSR2);
+    let pid: u32 = server.wait_for("[Upgrade] new process ").split_whitespace().next().unwrap().parse().unwrap();
+    server.upgraded = Some(pid);
+
+    // 排空期间仍然在使用的连接：请求照常处理，但响应要求客户端关闭连接，随后旧进程关闭连接
+    connection.get_mut().write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let reply = Reply::read(&mut connection);
+    assert_eq!(reply.text(), "site a");
+    assert_eq!(reply.header("Connection"), Some("close"), "{:?}", reply.headers);
+    assert_eq!(connection.read(&mut [0; 1]).unwrap(), 0);
+
+    // 客户端一直没有关闭连接，旧进程同样能够退出
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while server.child.try_wait().unwrap().is_none() {
+        assert!(Instant::now() < deadline, "old process did not exit");
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(server.child.try_wait().unwrap().unwrap().success());
+    assert_eq!(server.get("/"), "site a");
+    drop(connection);
+
+    kill(pid, libc::SIGTERM);
+    server.wait_for("[Shutdown] draining");
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,50 @@
+use std::fs;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use server_optimize::limiter::Limiter;
+use server_optimize::server::Options;
+use server_optimize::static_files::StaticFiles;
+
+mod common;
+
+use common::Reply;
+
+// 在临时目录中创建只有首页的站点
+fn site(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("server-optimize-static-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("index.html"), "<h1>home</h1>").unwrap();
+    root
+}
+
+#[test]
+fn head_requests() {
+    let root = site("head");
+    let files = Arc::new(StaticFiles::new(&root));
+    head_requests_with(common::serve_blocking(2, files.clone(), Options::default(), Limiter::unlimited()));
+    head_requests_with(common::serve_event(2, files, |event_loop| event_loop.threads(1)));
+    fs::remove_dir_all(root).unwrap();
+}
+
+fn head_requests_with(addr: SocketAddr) {
+    // HEAD响应的头部与GET相同但没有响应体：同一个连接上紧接着的GET响应能够被正确解析
+    let mut connection = common::connect(addr);
+    connection
+        .get_mut()
+        .write_all(b"HEAD / HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let head = Reply::read_head(&mut connection);
+    assert_eq!(head.status, 200);
+    assert_eq!(head.header("Content-Length"), Some("13"), "{:?}", head.headers);
+    assert_eq!(head.header("Content-Type"), Some("text/html; charset=utf-8"), "{:?}", head.headers);
+    let get = Reply::read(&mut connection);
+    assert_eq!(get.status, 200);
+    assert_eq!(get.text(), "<h1>home</h1>");
+
+    let reply = common::send(addr, "DELETE / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert_eq!(reply.status, 405);
+    assert_eq!(reply.header("Allow"), Some("GET, HEAD"), "{:?}", reply.headers);
+}
@@ -0,0 +1,112 @@
+use std::fs;
+use std::io::{BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+
+use rcgen::CertifiedKey;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use server_optimize::http::{Request, Response, StatusCode};
+use server_optimize::limiter::Limiter;
+use server_optimize::middleware::{Chain, Handler, RequestId};
+use server_optimize::{server, tls, ThreadPool};
+
+mod common;
+
+use common::{Reply, READ_TIMEOUT};
+
+// 生成一次性的自签名证书，写入临时目录并返回(证书路径, 私钥路径, 证书DER)
+fn self_signed(name: &str) -> (PathBuf, PathBuf, CertificateDer<'static>) {
+    let CertifiedKey { cert, key_pair } = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let dir = std::env::temp_dir().join(format!("tls-test-{}-{}", std::process::id(), name));
+    fs::create_dir_all(&dir).unwrap();
+    let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+    fs::write(&cert_path, cert.pem()).unwrap();
+    fs::write(&key_path, key_pair.serialize_pem()).unwrap();
+    (cert_path, key_path, cert.der().clone())
+}
+
+// 与明文HTTP共用的处理器
+fn app() -> Arc<dyn Handler> {
+    Arc::new(Chain::new(|request: Request| Response::text(StatusCode::OK, format!("secure {}", request.path))).with(RequestId::new()))
+}
+
+fn start(cert_path: &PathBuf, key_path: &PathBuf) -> SocketAddr {
+    let config = tls::load_config(cert_path, key_path).unwrap();
+    let (listener, addr) = common::bind();
+    thread::spawn(move || {
+        let pool = ThreadPool::new(2);
+        tls::serve(listener, &pool, app(), config, server::Options::default(), &Limiter::unlimited());
+    });
+    addr
+}
+
+fn client_config(trusted: CertificateDer<'static>) -> Arc<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    roots.add(trusted).unwrap();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+// 握手失败时返回错误，握手成功之后按照HTTP解析响应
+fn https_get(addr: SocketAddr, config: Arc<ClientConfig>, path: &str) -> std::io::Result<Reply> {
+    let stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let connection = ClientConnection::new(config, ServerName::try_from("localhost").unwrap()).unwrap();
+    let mut tls = StreamOwned::new(connection, stream);
+    while tls.conn.is_handshaking() {
+        tls.conn.complete_io(&mut tls.sock)?;
+    }
+    write!(tls, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path)?;
+    Ok(Reply::read(&mut BufReader::new(tls)))
+}
+
+#[test]
+fn serve_https_with_self_signed_certificate() {
+    let (cert_path, key_path, cert) = self_signed("ok");
+    let addr = start(&cert_path, &key_path);
+
+    let reply = https_get(addr, client_config(cert), "/hello").unwrap();
+    assert_eq!(reply.status, 200);
+    // 中间件链在HTTPS上同样生效
+    assert!(reply.header("X-Request-Id").is_some(), "{:?}", reply.headers);
+    assert_eq!(reply.text(), "secure /hello");
+}
+
+#[test]
+fn reject_untrusted_certificate_and_plaintext_clients() {
+    let (cert_path, key_path, _) = self_signed("server");
+    let (_, _, other) = self_signed("other");
+    let addr = start(&cert_path, &key_path);
+
+    // 客户端不信任服务端证书时握手失败
+    assert!(https_get(addr, client_config(other), "/").is_err());
+
+    // 明文HTTP请求无法完成握手，服务端关闭连接而不是返回HTTP响应
+    let mut plain = TcpStream::connect(addr).unwrap();
+    plain.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+    plain.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = Vec::new();
+    let _ = plain.read_to_end(&mut response);
+    assert!(!response.starts_with(b"HTTP/1.1 200"));
+
+    // 之前失败的连接不影响后续的正常请求
+    let pem = fs::read(&cert_path).unwrap();
+    let cert = rustls_pemfile::certs(&mut &pem[..]).next().unwrap().unwrap();
+    assert_eq!(https_get(addr, client_config(cert), "/").unwrap().status, 200);
+}
+
+#[test]
+fn load_config_reports_missing_key() {
+    let (cert_path, _, _) = self_signed("missing");
+    let err = tls::load_config(&cert_path, &cert_path).unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+}
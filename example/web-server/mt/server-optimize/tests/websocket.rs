@@ -0,0 +1,114 @@
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+
+use server_optimize::http::{Request, Response, StatusCode, Upgraded};
+use server_optimize::limiter::Limiter;
+use server_optimize::server::Options;
+use server_optimize::websocket::frame::{self, Frame, OpCode};
+use server_optimize::websocket::{self, CloseCode, CloseFrame, Message, Role, WebSocket};
+
+mod common;
+
+use common::Reply;
+
+fn app(request: Request) -> Response {
+    match request.path.as_str() {
+        "/ws" => websocket::upgrade(&request, |mut socket| {
+            let sender = socket.sender();
+            for message in socket.messages() {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        }),
+        _ => Response::text(StatusCode::OK, "Hi from Rust"),
+    }
+}
+
+// 线程池只有1个Worker，用来验证长连接不会占用Worker
+fn start(event: bool) -> SocketAddr {
+    if event {
+        common::serve_event(1, Arc::new(app), |event_loop| event_loop.threads(1))
+    } else {
+        common::serve_blocking(1, Arc::new(app), Options::default(), Limiter::unlimited())
+    }
+}
+
+// 完成握手并返回客户端的WebSocket，以及可以直接写出原始帧的底层连接
+fn connect(addr: SocketAddr) -> (WebSocket, TcpStream) {
+    let mut reader = common::connect(addr);
+    reader
+        .get_mut()
+        .write_all(
+            b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .unwrap();
+
+    let reply = Reply::read_head(&mut reader);
+    assert_eq!(101, reply.status);
+    assert_eq!(Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="), reply.header("Sec-WebSocket-Accept"));
+
+    let buffered = reader.buffer().to_vec();
+    let stream = reader.into_inner();
+    let raw = stream.try_clone().unwrap();
+    (WebSocket::new(Upgraded::new(stream, buffered), Role::Client).unwrap(), raw)
+}
+
+fn echo_session(addr: SocketAddr) {
+    let (mut socket, _) = connect(addr);
+
+    socket.send(Message::Text(String::from("hello"))).unwrap();
+    assert_eq!(Some(Message::Text(String::from("hello"))), socket.recv().unwrap());
+
+    // 服务端收到Ping后自动回复Pong
+    socket.send(Message::Ping(b"beat".to_vec())).unwrap();
+    assert_eq!(Some(Message::Pong(b"beat".to_vec())), socket.recv().unwrap());
+
+    socket.close(CloseCode::NORMAL, "bye").unwrap();
+    assert_eq!(
+        Some(Message::Close(Some(CloseFrame { code: CloseCode::NORMAL, reason: String::new() }))),
+        socket.recv().unwrap()
+    );
+    assert_eq!(None, socket.recv().unwrap());
+}
+
+#[test]
+fn echo_in_blocking_and_event_mode() {
+    echo_session(start(false));
+    echo_session(start(true));
+}
+
+#[test]
+fn reassemble_fragmented_message() {
+    let (mut socket, mut stream) = connect(start(false));
+    // 直接在底层连接上写出分片帧：Text(非FIN) + Ping + Continuation(FIN)
+    let frames = [
+        Frame::new(false, OpCode::Text, b"Hello, ".to_vec()),
+        Frame::new(true, OpCode::Ping, Vec::new()),
+        Frame::new(true, OpCode::Continuation, b"World".to_vec()),
+    ];
+    for frame in frames {
+        frame.write_to(&mut stream, Some(frame::mask_key())).unwrap();
+    }
+    assert_eq!(Some(Message::Pong(Vec::new())), socket.recv().unwrap());
+    assert_eq!(Some(Message::Text(String::from("Hello, World"))), socket.recv().unwrap());
+
+    // 未加掩码的客户端帧属于协议错误，服务端以1002关闭连接
+    Frame::new(true, OpCode::Text, b"oops".to_vec()).write_to(&mut stream, None).unwrap();
+    match socket.recv().unwrap() {
+        Some(Message::Close(Some(frame))) => assert_eq!(CloseCode::PROTOCOL_ERROR, frame.code),
+        other => panic!("unexpected message {:?}", other),
+    }
+}
+
+#[test]
+fn websocket_sessions_do_not_starve_http_workers() {
+    for event in [false, true] {
+        let addr = start(event);
+        let sessions: Vec<(WebSocket, TcpStream)> = (0..3).map(|_| connect(addr)).collect();
+        assert_eq!(200, common::get(addr, "/").status);
+        drop(sessions);
+    }
+}
@@ -1,4 +1,4 @@
-use std::{fs, thread};
+use std::{env, fs, thread};
 use std::io::prelude::*;
 use std::net::{TcpListener, TcpStream};
 use std::time::Duration;
@@ -9,7 +9,10 @@ use server::ThreadPool;
     多线程WebServer-服务端
  */
 fn main() {
-    let listner = TcpListener::bind("127.0.0.1:7878").unwrap(); // listner: TcpListener
+    // 监听地址可以通过第一个命令行参数指定，默认为127.0.0.1:7878
+    let addr = env::args().nth(1).unwrap_or_else(|| String::from("127.0.0.1:7878"));
+    let listner = TcpListener::bind(&addr).unwrap(); // listner: TcpListener
+    println!("listening on {}", listner.local_addr().unwrap());
     // 初始化线程池，默认设置4个初始线程，这样可以同时接受4个请求处理
     let pool = ThreadPool::new(4);
 
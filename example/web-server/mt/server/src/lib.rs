@@ -1,4 +1,4 @@
-use std::{thread::{JoinHandle, self}, sync::{mpsc, Arc, Mutex}};
+use std::{any::Any, panic::{self, AssertUnwindSafe}, thread::{JoinHandle, self}, sync::{mpsc, Arc, Mutex, PoisonError}};
 
 /*
     线程池结构体
@@ -19,6 +19,22 @@ impl ThreadPool {
     ///
     /// `new` 函数在 size 为 0 时会 panic。
     pub fn new(size: usize) -> ThreadPool {
+        ThreadPool::with_panic_handler(size, |panic: &JobPanic| {
+            println!("[Worker(id = {})] job panicked: {}", panic.worker, panic.message)
+        })
+    }
+
+    /// 创建线程池，任务panic时在执行该任务的Worker线程中调用 `handler`。
+    ///
+    /// panic不会影响其他任务：执行该任务的Worker线程会被一个新的线程替换，线程池的容量保持不变。
+    ///
+    /// # Panics
+    ///
+    /// size 为 0 时会 panic。
+    pub fn with_panic_handler<F>(size: usize, handler: F) -> ThreadPool
+    where
+        F: Fn(&JobPanic) + Send + Sync + 'static,
+    {
         assert!(size > 0);
 
         // 根据指定大小创建线程列表
@@ -27,13 +43,12 @@ impl ThreadPool {
         // 创建channel管道用于Job和Worker的任务收发
         let (sender, receiver) = mpsc::channel();
 
-        // 创建线程安全并且支持多所有权共享的智能指针来解决下面循环初始化线程时receiver move移入问题
-        // receiver: Arc<Mutex<Receiver<Job>>>
-        let receiver  = Arc::new(Mutex::new(receiver));
+        // 所有Worker（包括替换panic线程的新Worker）共享同一个接收端与panic处理函数
+        let context = Arc::new(WorkerContext { receiver: Mutex::new(receiver), panic_handler: Box::new(handler) });
 
         // 初始化线程，即Worker对象
         for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+            workers.push(Worker::new(id, Arc::clone(&context)));
         }
 
         ThreadPool { workers, sender }
@@ -64,25 +79,101 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+    fn new(id: usize, context: Arc<WorkerContext>) -> Worker {
         println!("[Worker(id = {})] startup", id);
         let thread = thread::spawn(move || {
             // 为了让每个Worker能够不断的执行请求，这里需要将整个逻辑让入死循环中
             // 如果从channel中能够recv到任务就是执行，否则就阻塞等待
             loop {
-                // 从通道中获取执行请求进行执行
+                // 从通道中获取执行请求进行执行；锁中毒不影响通道本身，忽略中毒标记继续使用
                 // result: Receiver<Box<..>>
-                let func = receiver.lock().unwrap().recv().unwrap();
+                let func = match context.receiver.lock().unwrap_or_else(PoisonError::into_inner).recv() {
+                    Ok(func) => func,
+                    // 线程池已经被销毁
+                    Err(_) => return,
+                };
 
                 println!("[Worker(id = {})] execute job", id);
 
-                // 这里是封装的代码，然后进行真正的执行
-                func();
+                // 这里是封装的代码，然后进行真正的执行；任务panic时在这里捕获，不会波及线程池
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(func)) {
+                    let report = JobPanic { worker: id, message: panic_message(payload.as_ref()) };
+                    // 处理函数本身panic时同样不能让线程池失去Worker
+                    if panic::catch_unwind(AssertUnwindSafe(|| (context.panic_handler)(&report))).is_err() {
+                        println!("[Worker(id = {})] panic handler panicked", id);
+                    }
+                    // 任务可能留下了不一致的线程局部状态，换一个新的线程继续工作
+                    Worker::new(id, context);
+                    return;
+                }
             }
         });
         Worker { id, thread }
     }
 }
 
+/*
+    任务panic的信息，交给线程池的panic处理函数
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobPanic {
+    // 执行该任务的Worker编号
+    pub worker: usize,
+    // panic消息，payload不是字符串时为 "Box<dyn Any>"
+    pub message: String,
+}
+
+// Worker之间共享的上下文
+struct WorkerContext {
+    receiver: Mutex<mpsc::Receiver<Job>>,
+    panic_handler: Box<dyn Fn(&JobPanic) + Send + Sync>,
+}
+
+// panic的payload通常是 &str 或者 String
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("Box<dyn Any>")
+    }
+}
+
 // 定义请求提交任务类型别名，用于代表存储执行请求的闭包类型
-type Job = Box<dyn FnOnce() + Send + 'static>;
\ No newline at end of file
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/*
+    单元测试
+ */
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn survive_panicking_jobs() {
+        let (reports, received) = mpsc::channel();
+        let reports = Mutex::new(reports);
+        let pool = ThreadPool::with_panic_handler(2, move |panic: &JobPanic| {
+            reports.lock().unwrap().send(panic.clone()).unwrap();
+        });
+        for n in 0..4 {
+            pool.execute(move || panic!("job {} failed", n));
+        }
+
+        // 所有Worker都经历过panic之后仍然能够执行任务
+        let (sender, results) = mpsc::channel();
+        for n in 0..8 {
+            let sender = sender.clone();
+            pool.execute(move || sender.send(n).unwrap());
+        }
+        let mut done: Vec<i32> = (0..8).map(|_| results.recv_timeout(Duration::from_secs(10)).unwrap()).collect();
+        done.sort();
+        assert_eq!(done, (0..8).collect::<Vec<_>>());
+
+        let mut messages: Vec<String> = (0..4).map(|_| received.recv_timeout(Duration::from_secs(10)).unwrap().message).collect();
+        messages.sort();
+        assert_eq!(messages, ["job 0 failed", "job 1 failed", "job 2 failed", "job 3 failed"]);
+    }
+}
@@ -1,3 +1,4 @@
+use std::env;
 use std::fs;
 use std::io::prelude::*;
 use std::net::{TcpListener, TcpStream};
@@ -8,7 +9,10 @@ use std::net::{TcpListener, TcpStream};
 fn main() {
     // 通过TCP协议绑定IP地址和端口号，进行对7878这个端口的监听
     // 因为绑定过程中可能发生异常，例如端口冲突等，所以采用unwrap方法做简单的异常处理，在一般生产环境中需要更加复杂异常处理来保证代码的健壮性
-    let listner = TcpListener::bind("127.0.0.1:7878").unwrap(); // listner: TcpListener
+    // 也可以通过第一个命令行参数指定监听地址，例如127.0.0.1:0表示由系统分配一个空闲端口（集成测试中使用）
+    let addr = env::args().nth(1).unwrap_or_else(|| String::from("127.0.0.1:7878"));
+    let listner = TcpListener::bind(&addr).unwrap(); // listner: TcpListener
+    println!("listening on {}", listner.local_addr().unwrap());
 
     // incoming方法用于获取建立连接的迭代器，这里对迭代器中的每个Result进行循环
     for result in listner.incoming() { // result: Result<TcpStream, Error>